@@ -80,6 +80,10 @@ export class TilemapComponent implements OnInit, AfterViewInit {
               drawPixel(x, y, 150, 50, 200, 100);
             }
 
+            if (classData[y][x] == 'STREAM') {
+              drawPixel(x, y, 60, 120, 220, 200);
+            }
+
             if (classData[y][x] == 'BUILDING') {
               drawPixel(x, y, 255, 180, 230, 255);
 
@@ -0,0 +1,152 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use map_tool::geometry::bbox::{Bbox, BboxTree, HasCoords};
+use map_tool::geometry::point::Point;
+use map_tool::geometry::rtree::{HasBbox, RTree};
+
+#[derive(Debug, Clone)]
+struct Item {
+    coords: Point,
+}
+
+impl HasCoords for Item {
+    fn fits_into(&self, bbox: &Bbox) -> bool {
+        bbox.contains(&self.coords)
+    }
+}
+
+impl HasBbox for Item {
+    fn bbox(&self) -> Bbox {
+        Bbox::from_point(&self.coords)
+    }
+}
+
+/// Points scattered over the map area with a fixed linear congruential
+/// generator, so every run measures the same data.
+fn items(count: usize) -> Vec<Item> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    };
+    (0..count)
+        .map(|_| Item {
+            coords: Point {
+                x: 23.75 + next() * 0.05,
+                y: 61.47 + next() * 0.05,
+            },
+        })
+        .collect()
+}
+
+fn bbox_tree(items: &[Item]) -> BboxTree<Item> {
+    let mut tree = BboxTree::Leaf {
+        bbox: Bbox {
+            a: Point { x: 23.0, y: 60.0 },
+            b: Point { x: 24.0, y: 62.0 },
+        },
+        items: Vec::new(),
+    };
+    for item in items {
+        tree = tree.add_item(item, 0);
+    }
+    tree
+}
+
+fn query_boxes() -> Vec<Bbox> {
+    items(1000)
+        .into_iter()
+        .map(|i| Bbox {
+            a: Point {
+                x: i.coords.x - 0.00003,
+                y: i.coords.y - 0.00003,
+            },
+            b: Point {
+                x: i.coords.x + 0.00003,
+                y: i.coords.y + 0.00003,
+            },
+        })
+        .collect()
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    for count in [1_000, 10_000, 50_000] {
+        let data = items(count);
+        group.bench_with_input(BenchmarkId::new("bbox_tree", count), &data, |b, data| {
+            b.iter(|| bbox_tree(black_box(data)))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("rtree_bulk_load", count),
+            &data,
+            |b, data| b.iter(|| RTree::bulk_load(black_box(data.clone()))),
+        );
+        group.bench_with_input(BenchmarkId::new("rtree_insert", count), &data, |b, data| {
+            b.iter(|| {
+                let mut tree = RTree::new();
+                for item in black_box(data) {
+                    tree.insert(item.clone());
+                }
+                tree
+            })
+        });
+    }
+    group.finish();
+}
+
+fn query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("query");
+    let data = items(50_000);
+    let boxes = query_boxes();
+
+    let old = bbox_tree(&data);
+    group.bench_function("bbox_tree", |b| {
+        b.iter(|| {
+            let mut found = 0;
+            for query in boxes.iter() {
+                for leaf in old.find_boxes_overlapping(query) {
+                    if let BboxTree::Leaf { items, .. } = leaf {
+                        found += items.iter().filter(|i| query.contains(&i.coords)).count();
+                    }
+                }
+            }
+            found
+        })
+    });
+
+    let rtree = RTree::bulk_load(data.clone());
+    group.bench_function("rtree", |b| {
+        b.iter(|| {
+            boxes
+                .iter()
+                .map(|query| rtree.query_bbox(query).count())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+fn remove(c: &mut Criterion) {
+    let data = items(10_000);
+    c.bench_function("rtree_remove_half", |b| {
+        b.iter_batched(
+            || {
+                let mut tree = RTree::new();
+                let ids: Vec<_> = data.iter().map(|i| tree.insert(i.clone())).collect();
+                (tree, ids)
+            },
+            |(mut tree, ids)| {
+                for id in ids.into_iter().step_by(2) {
+                    tree.remove(id);
+                }
+                tree
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, build, query, remove);
+criterion_main!(benches);
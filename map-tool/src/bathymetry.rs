@@ -0,0 +1,245 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::delaunay::Triangulation;
+use crate::geometry::point::{LineSegment, Point, Polygon};
+use crate::geometry::projection::LocalProjection;
+use crate::geometry::rtree::RTree;
+
+/// Options for the lake bed model.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct BathymetryConfig {
+    /// Metres per unit of the depth contour `syvyysarvo` and the lake
+    /// `keskikorkeus` water level.
+    pub value_scale: f64,
+}
+
+impl Default for BathymetryConfig {
+    fn default() -> BathymetryConfig {
+        // The topographic database stores both in millimetres.
+        BathymetryConfig { value_scale: 0.001 }
+    }
+}
+
+/// Line of equal depth in metres below the water surface.
+#[derive(Debug, Clone)]
+pub struct DepthContour {
+    pub depth: f64,
+    pub points: Vec<Point>,
+}
+
+/// Lake polygon with the mean height of its surface in metres, if known.
+/// `Map::build_lake_beds` fills in the surface of lakes without one.
+#[derive(Clone)]
+pub struct Lake {
+    pub polygon: Polygon,
+    pub surface: Option<f64>,
+}
+
+/// Longest shore edge in metres left unsplit, so that the triangulation
+/// follows the shore instead of cutting across bays.
+const SHORE_SPACING: f64 = 10.0;
+
+/// Distance in metres within which a triangle corner lies on the shore.
+const ON_SHORE: f64 = 1e-6;
+
+/// Depth below the surface inside one lake, interpolated linearly over a
+/// Delaunay triangulation of the shore at depth zero and the depth contour
+/// vertices inside the lake. Islands count as shore. Triangles whose middle
+/// is on land or whose edges cross the shore, spanning a peninsula or an
+/// island, are left out.
+pub struct LakeBed {
+    projection: LocalProjection,
+    triangulation: Triangulation,
+    depths: Vec<f64>,
+    /// Vertices of the triangles on land, in increasing order.
+    on_land: HashSet<[usize; 3]>,
+}
+
+impl LakeBed {
+    pub fn new(lake: &Polygon, contours: &[DepthContour]) -> LakeBed {
+        let projection = LocalProjection::new(&lake.bbox().center());
+        let mut points = Vec::new();
+        let mut depths = Vec::new();
+        let mut shore = Vec::new();
+
+        let rings =
+            std::iter::once(lake.exterior()).chain(lake.holes().iter().map(|hole| hole.exterior()));
+        for ring in rings {
+            let ring: Vec<Point> = ring.iter().map(|p| projection.to_metres(p)).collect();
+            for (i, a) in ring.iter().enumerate() {
+                let b = &ring[(i + 1) % ring.len()];
+                shore.push(LineSegment {
+                    a: a.clone(),
+                    b: b.clone(),
+                });
+                let steps = (a.distance(b) / SHORE_SPACING).ceil().max(1.0) as usize;
+                for step in 0..steps {
+                    let t = step as f64 / steps as f64;
+                    points.push(Point {
+                        x: a.x + (b.x - a.x) * t,
+                        y: a.y + (b.y - a.y) * t,
+                    });
+                    depths.push(0.0);
+                }
+            }
+        }
+
+        for contour in contours {
+            for point in contour.points.iter() {
+                if lake.contains_point(point) {
+                    points.push(projection.to_metres(point));
+                    depths.push(contour.depth.max(0.0));
+                }
+            }
+        }
+
+        let shore = RTree::bulk_load(shore);
+        let triangulation = Triangulation::new(&points);
+        let on_land = triangulation
+            .triangles()
+            .into_iter()
+            .filter(|t| {
+                let [a, b, c] = t.map(|v| &points[v]);
+                let middle = Point {
+                    x: (a.x + b.x + c.x) / 3.0,
+                    y: (a.y + b.y + c.y) / 3.0,
+                };
+                let crosses_shore = [(a, b), (b, c), (c, a)].into_iter().any(|(a, b)| {
+                    shore.crosses(
+                        &LineSegment {
+                            a: a.clone(),
+                            b: b.clone(),
+                        },
+                        ON_SHORE,
+                    )
+                });
+                crosses_shore || !lake.contains_point(&projection.to_degrees(&middle))
+            })
+            .map(|mut t| {
+                t.sort_unstable();
+                t
+            })
+            .collect();
+
+        LakeBed {
+            triangulation,
+            projection,
+            depths,
+            on_land,
+        }
+    }
+
+    /// Depth in metres at a point, never above the surface. `None` outside
+    /// the shoreline's convex hull and on triangles left out as land.
+    pub fn depth(&self, point: &Point) -> Option<f64> {
+        let vertices = self
+            .triangulation
+            .barycentric(&self.projection.to_metres(point))?;
+        let mut triangle = vertices.map(|(v, _)| v);
+        triangle.sort_unstable();
+        if self.on_land.contains(&triangle) {
+            return None;
+        }
+        let depth: f64 = vertices.iter().map(|(v, w)| self.depths[*v] * w).sum();
+        Some(depth.max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depths_do_not_reach_across_a_peninsula() {
+        let projection = LocalProjection::new(&Point { x: 23.75, y: 61.5 });
+        let degrees = |x: f64, y: f64| projection.to_degrees(&Point { x, y });
+        // A square bay 400 m across with a peninsula 80 m wide reaching
+        // from the north shore most of the way down.
+        let lake = Polygon::new(
+            [
+                (0.0, 0.0),
+                (400.0, 0.0),
+                (400.0, 400.0),
+                (240.0, 400.0),
+                (240.0, 120.0),
+                (160.0, 120.0),
+                (160.0, 400.0),
+                (0.0, 400.0),
+            ]
+            .into_iter()
+            .map(|(x, y)| degrees(x, y))
+            .collect(),
+        );
+        // Deep channels down the middle of both arms.
+        let contours: Vec<DepthContour> = [80.0, 320.0]
+            .into_iter()
+            .map(|x| DepthContour {
+                depth: 10.0,
+                points: (0..=8)
+                    .map(|i| degrees(x, 150.0 + 25.0 * i as f64))
+                    .collect(),
+            })
+            .collect();
+        let bed = LakeBed::new(&lake, &contours);
+
+        let depth = |x: f64, y: f64| bed.depth(&degrees(x, y));
+        assert!((depth(80.0, 250.0).unwrap() - 10.0).abs() < 1e-6);
+        // Ten metres off the peninsula, an eighth of the way from its shore
+        // to the channel.
+        let off_peninsula = depth(150.0, 300.0).unwrap();
+        assert!((off_peninsula - 1.25).abs() < 0.5, "{off_peninsula}");
+        let off_west_shore = depth(10.0, 300.0).unwrap();
+        assert!((off_west_shore - 1.25).abs() < 0.5, "{off_west_shore}");
+        // Nothing is interpolated over the peninsula itself.
+        assert_eq!(depth(200.0, 300.0), None);
+        // South of the peninsula both channels meet the open water.
+        assert!(depth(200.0, 60.0).unwrap() < 10.0);
+    }
+
+    #[test]
+    fn depths_do_not_reach_across_a_narrow_spit() {
+        let projection = LocalProjection::new(&Point { x: 23.75, y: 61.5 });
+        let degrees = |x: f64, y: f64| projection.to_degrees(&Point { x, y });
+        // A spit 2 m wide, narrower than the shore spacing, slanting from
+        // the north shore to the middle of a lake 200 m across.
+        let lake = Polygon::new(
+            [
+                (-100.0, -100.0),
+                (100.0, -100.0),
+                (100.0, 100.0),
+                (85.3, 100.0),
+                (1.3, 0.0),
+                (-1.3, 0.0),
+                (82.7, 100.0),
+                (-100.0, 100.0),
+            ]
+            .into_iter()
+            .map(|(x, y)| degrees(x, y))
+            .collect(),
+        );
+        // A one metre contour along both sides of the spit.
+        let contours: Vec<DepthContour> = [-6.0, 6.0]
+            .into_iter()
+            .map(|side| DepthContour {
+                depth: 1.0,
+                points: (0..10)
+                    .map(|i| {
+                        let y = 5.0 + 10.0 * i as f64;
+                        degrees(0.84 * y + side, y)
+                    })
+                    .collect(),
+            })
+            .collect();
+        let bed = LakeBed::new(&lake, &contours);
+
+        for y in 1..100 {
+            let depth = bed.depth(&degrees(0.84 * y as f64, y as f64));
+            assert_eq!(depth, None, "lake bed at {y} m");
+        }
+        let beside = bed.depth(&degrees(0.84 * 45.0 - 6.0, 45.0)).unwrap();
+        assert!((beside - 1.0).abs() < 1e-6);
+    }
+}
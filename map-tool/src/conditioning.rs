@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::point::{LineSegment, Point, Polygon};
+use crate::raster::Raster;
+use crate::road_mesh::Ribbon;
+
+/// Distance in metres between the samples of a road's height profile.
+const PROFILE_SPACING: f64 = 1.0;
+
+/// Options for shaping the terrain under buildings and roads.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConditioningConfig {
+    /// Whether to level a pad under each building.
+    pub buildings: bool,
+    /// Distance in metres around a building over which its pad blends into
+    /// the terrain.
+    pub building_blend_radius: f64,
+    /// Whether to level roads across their width.
+    pub roads: bool,
+    /// Distance in metres beyond the road edge over which the road blends
+    /// into the terrain.
+    pub road_blend_radius: f64,
+    /// Length in metres of the moving average over a road's height profile.
+    pub road_smoothing: f64,
+}
+
+impl Default for ConditioningConfig {
+    fn default() -> ConditioningConfig {
+        ConditioningConfig {
+            buildings: true,
+            building_blend_radius: 3.0,
+            roads: true,
+            road_blend_radius: 2.0,
+            road_smoothing: 20.0,
+        }
+    }
+}
+
+/// Heights with building pads and roads levelled. Building pads
+/// are at the mean height of the tiles under the footprint. Roads are level
+/// across their width at the height of a smoothed profile along the centre
+/// line. Around both the terrain eases back to its own height. Where several
+/// features reach a tile, the one with the most weight there wins, buildings
+/// over roads on ties. Missing tiles stay missing and are not sampled.
+///
+/// Footprint rings and road lines are in tile coordinates, `tile_size` is
+/// the tile size in metres.
+pub fn condition_terrain(
+    heights: &Raster,
+    buildings: &[Vec<Vec<(f64, f64)>>],
+    roads: &[Ribbon],
+    tile_size: (f64, f64),
+    config: &ConditioningConfig,
+) -> Raster {
+    let mut targets = Targets {
+        heights: Raster::new(heights.width(), heights.height()),
+        weights: Raster::filled(heights.width(), heights.height(), 0.0),
+        tile_size,
+    };
+    if config.roads {
+        for road in roads {
+            targets.add_road(heights, road, config);
+        }
+    }
+    if config.buildings {
+        for rings in buildings {
+            targets.add_pad(heights, rings, config.building_blend_radius);
+        }
+    }
+
+    let mut conditioned = heights.clone();
+    for y in 0..heights.height() {
+        for x in 0..heights.width() {
+            let (Some(height), Some(target)) = (heights.get(x, y), targets.heights.get(x, y))
+            else {
+                continue;
+            };
+            let weight = targets.weights.get(x, y).unwrap_or(0.0);
+            conditioned.set(x, y, Some(height + weight * (target - height)));
+        }
+    }
+    conditioned
+}
+
+/// Height each tile is pulled towards and how strongly.
+struct Targets {
+    heights: Raster,
+    weights: Raster,
+    tile_size: (f64, f64),
+}
+
+impl Targets {
+    fn to_metres(&self, p: (f64, f64)) -> Point {
+        Point {
+            x: p.0 * self.tile_size.0,
+            y: p.1 * self.tile_size.1,
+        }
+    }
+
+    /// Calls `f` with each tile within `reach` metres of a bounding box in
+    /// tile coordinates, and the tile's position in metres.
+    fn for_tiles_near(
+        &self,
+        min: (f64, f64),
+        max: (f64, f64),
+        reach: f64,
+        mut f: impl FnMut(usize, usize, Point),
+    ) {
+        let (rx, ry) = (reach / self.tile_size.0, reach / self.tile_size.1);
+        let x0 = (min.0 - rx).floor().max(0.0) as usize;
+        let y0 = (min.1 - ry).floor().max(0.0) as usize;
+        let x1 =
+            ((max.0 + rx).ceil().max(0.0) as usize).min(self.weights.width().saturating_sub(1));
+        let y1 =
+            ((max.1 + ry).ceil().max(0.0) as usize).min(self.weights.height().saturating_sub(1));
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                f(x, y, self.to_metres((x as f64, y as f64)));
+            }
+        }
+    }
+
+    fn pull(&mut self, x: usize, y: usize, height: f64, weight: f64) {
+        if weight > 0.0 && weight >= self.weights.get(x, y).unwrap_or(0.0) {
+            self.weights.set(x, y, Some(weight));
+            self.heights.set(x, y, Some(height));
+        }
+    }
+
+    fn add_pad(&mut self, heights: &Raster, rings: &[Vec<(f64, f64)>], blend_radius: f64) {
+        let Some(exterior) = rings.first() else {
+            return;
+        };
+        if exterior.len() < 3 {
+            return;
+        }
+        let ring = |ring: &Vec<(f64, f64)>| ring.iter().map(|p| self.to_metres(*p)).collect();
+        let polygon = Polygon::with_holes(ring(exterior), rings[1..].iter().map(ring).collect());
+        let (min, max) = bounds(exterior);
+
+        let mut inside = Vec::new();
+        self.for_tiles_near(min, max, 0.0, |x, y, p| {
+            if let Some(height) = heights.get(x, y) {
+                if polygon.contains_point(&p) {
+                    inside.push(height);
+                }
+            }
+        });
+        // Footprints smaller than a tile take the height at their middle.
+        let level = if inside.is_empty() {
+            bilinear(heights, (min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0)
+        } else {
+            Some(inside.iter().sum::<f64>() / inside.len() as f64)
+        };
+        let Some(level) = level else {
+            return;
+        };
+
+        let mut pulls = Vec::new();
+        self.for_tiles_near(min, max, blend_radius, |x, y, p| {
+            let distance = polygon.distance_squared_to_point(&p).sqrt();
+            pulls.push((x, y, falloff(distance, 0.0, blend_radius)));
+        });
+        for (x, y, weight) in pulls {
+            self.pull(x, y, level, weight);
+        }
+    }
+
+    fn add_road(&mut self, heights: &Raster, road: &Ribbon, config: &ConditioningConfig) {
+        let line: Vec<Point> = road.points.iter().map(|p| self.to_metres(*p)).collect();
+        if line.len() < 2 {
+            return;
+        }
+        let profile = self.profile(heights, &line, config.road_smoothing);
+        if profile.is_empty() {
+            return;
+        }
+        let half_width = road.width / 2.0;
+        let reach = half_width + config.road_blend_radius;
+
+        // Distance from each tile near the road to the closest point of the
+        // line and how far along the line that point is.
+        let mut closest: HashMap<(usize, usize), (f64, f64)> = HashMap::new();
+        let mut start = 0.0;
+        for (pair, tiles) in line.windows(2).zip(road.points.windows(2)) {
+            let (min, max) = bounds(tiles);
+            let segment = LineSegment {
+                a: pair[0].clone(),
+                b: pair[1].clone(),
+            };
+            let length = pair[0].distance(&pair[1]);
+            self.for_tiles_near(min, max, reach, |x, y, p| {
+                let distance = segment.distance_squared_to_point(&p).sqrt();
+                if distance > reach || closest.get(&(x, y)).is_some_and(|c| c.0 <= distance) {
+                    return;
+                }
+                let t = if length > 0.0 {
+                    ((p.x - pair[0].x) * (pair[1].x - pair[0].x)
+                        + (p.y - pair[0].y) * (pair[1].y - pair[0].y))
+                        / (length * length)
+                } else {
+                    0.0
+                };
+                closest.insert((x, y), (distance, start + t.clamp(0.0, 1.0) * length));
+            });
+            start += length;
+        }
+
+        for ((x, y), (distance, along)) in closest {
+            let i = ((along / PROFILE_SPACING).round() as usize).min(profile.len() - 1);
+            if let Some(height) = profile[i] {
+                let weight = falloff(distance, half_width, config.road_blend_radius);
+                self.pull(x, y, height, weight);
+            }
+        }
+    }
+
+    /// Terrain heights every `PROFILE_SPACING` metres along the line,
+    /// averaged over `smoothing` metres. The window shrinks near the ends so
+    /// that it stays centred.
+    fn profile(&self, heights: &Raster, line: &[Point], smoothing: f64) -> Vec<Option<f64>> {
+        let mut samples = Vec::new();
+        let mut carried = 0.0;
+        for pair in line.windows(2) {
+            let length = pair[0].distance(&pair[1]);
+            let mut along = carried;
+            while along < length {
+                let t = along / length;
+                let x = pair[0].x + (pair[1].x - pair[0].x) * t;
+                let y = pair[0].y + (pair[1].y - pair[0].y) * t;
+                samples.push(bilinear(
+                    heights,
+                    x / self.tile_size.0,
+                    y / self.tile_size.1,
+                ));
+                along += PROFILE_SPACING;
+            }
+            carried = along - length;
+        }
+        let end = line.last().unwrap();
+        samples.push(bilinear(
+            heights,
+            end.x / self.tile_size.0,
+            end.y / self.tile_size.1,
+        ));
+
+        let radius = (smoothing / PROFILE_SPACING / 2.0).round() as usize;
+        (0..samples.len())
+            .map(|i| {
+                let r = radius.min(i).min(samples.len() - 1 - i);
+                let window: Vec<f64> = samples[i - r..=i + r].iter().flatten().copied().collect();
+                if window.is_empty() {
+                    None
+                } else {
+                    Some(window.iter().sum::<f64>() / window.len() as f64)
+                }
+            })
+            .collect()
+    }
+}
+
+fn bounds(points: &[(f64, f64)]) -> ((f64, f64), (f64, f64)) {
+    points.iter().fold(
+        (
+            (f64::INFINITY, f64::INFINITY),
+            (f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(min, max), p| {
+            (
+                (min.0.min(p.0), min.1.min(p.1)),
+                (max.0.max(p.0), max.1.max(p.1)),
+            )
+        },
+    )
+}
+
+/// Full weight up to `inner` metres, easing to none over `blend` more.
+fn falloff(distance: f64, inner: f64, blend: f64) -> f64 {
+    if distance <= inner {
+        return 1.0;
+    }
+    if blend <= 0.0 || distance >= inner + blend {
+        return 0.0;
+    }
+    let t = 1.0 - (distance - inner) / blend;
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Bilinear height at a position in tiles, over the corners that have
+/// data.
+fn bilinear(heights: &Raster, x: f64, y: f64) -> Option<f64> {
+    let max_x = heights.width().checked_sub(1)? as f64;
+    let max_y = heights.height().checked_sub(1)? as f64;
+    let (x, y) = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as usize, y0 as usize);
+    let (x1, y1) = ((x0 + 1).min(max_x as usize), (y0 + 1).min(max_y as usize));
+    let corners = [
+        (x0, y0, (1.0 - tx) * (1.0 - ty)),
+        (x1, y0, tx * (1.0 - ty)),
+        (x0, y1, (1.0 - tx) * ty),
+        (x1, y1, tx * ty),
+    ];
+    let (sum, weight) = corners
+        .iter()
+        .filter_map(|(x, y, w)| heights.get(*x, *y).map(|h| (h * w, *w)))
+        .fold((0.0, 0.0), |(s, t), (h, w)| (s + h, t + w));
+    if weight > 1e-12 {
+        Some(sum / weight)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Heights rising one metre per tile along x.
+    fn slope(size: usize) -> Raster {
+        let mut raster = Raster::new(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                raster.set(x, y, Some(x as f64));
+            }
+        }
+        raster
+    }
+
+    #[test]
+    fn building_pads_are_level_and_blend_out() {
+        let heights = slope(40);
+        let footprint = vec![vec![(10.0, 10.0), (20.0, 10.0), (20.0, 20.0), (10.0, 20.0)]];
+        let config = ConditioningConfig::default();
+        let conditioned = condition_terrain(&heights, &[footprint], &[], (1.0, 1.0), &config);
+        for y in 10..=20 {
+            for x in 10..=20 {
+                assert!((conditioned.get(x, y).unwrap() - 15.0).abs() < 1e-9);
+            }
+        }
+        // Halfway through the blend and beyond it.
+        let halfway = conditioned.get(21, 15).unwrap();
+        assert!(halfway > 15.0 && halfway < 21.0);
+        assert_eq!(conditioned.get(24, 15), Some(24.0));
+        assert_eq!(conditioned.get(15, 30), Some(15.0));
+    }
+
+    #[test]
+    fn roads_are_level_across_and_keep_their_grade() {
+        let heights = slope(60);
+        // Across the slope, and along it.
+        let across = Ribbon {
+            points: vec![(30.0, 5.0), (30.0, 55.0)],
+            width: 6.0,
+        };
+        let along = Ribbon {
+            points: vec![(5.0, 40.0), (55.0, 40.0)],
+            width: 4.0,
+        };
+        let config = ConditioningConfig {
+            buildings: false,
+            ..ConditioningConfig::default()
+        };
+        let conditioned = condition_terrain(&heights, &[], &[across], (1.0, 1.0), &config);
+        for x in 27..=33 {
+            assert!((conditioned.get(x, 20).unwrap() - 30.0).abs() < 1e-9);
+        }
+        assert_eq!(conditioned.get(36, 20), Some(36.0));
+
+        let conditioned = condition_terrain(&heights, &[], &[along], (1.0, 1.0), &config);
+        for x in 10..50 {
+            assert!((conditioned.get(x, 40).unwrap() - x as f64).abs() < 1e-9);
+        }
+    }
+}
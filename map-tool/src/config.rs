@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bathymetry::BathymetryConfig;
+use crate::conditioning::ConditioningConfig;
+use crate::contour::ContourConfig;
+use crate::geometry::point::{Point, Polygon};
+use crate::geometry::simplify::{densify, Simplification};
+use crate::height_image::HeightImageConfig;
+use crate::hydrology::HydrologyConfig;
+use crate::land_cover::LandCoverConfig;
+use crate::pyramid::PyramidConfig;
+use crate::quantized_mesh::QuantizedMeshConfig;
+use crate::raster::Resampler;
+use crate::road_mesh::RoadMeshConfig;
+use crate::roads::RoadConfig;
+use crate::terrain::TerrainFilter;
+use crate::terrain_analysis::AnalysisConfig;
+use crate::texture::TextureConfig;
+use crate::visibility::ViewshedConfig;
+use crate::water::WaterConfig;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub layers: LayersConfig,
+    pub height: HeightConfig,
+    pub tiles: PyramidConfig,
+    pub mesh: MeshConfig,
+    pub quantized_mesh: QuantizedMeshConfig,
+    pub height_image: HeightImageConfig,
+    pub analysis: AnalysisConfig,
+    pub hydrology: HydrologyConfig,
+    pub viewshed: ViewshedConfig,
+    pub contours: ContourConfig,
+    pub bathymetry: BathymetryConfig,
+    pub land_cover: LandCoverConfig,
+    pub roads: RoadConfig,
+    pub road_mesh: RoadMeshConfig,
+    pub conditioning: ConditioningConfig,
+    pub water: WaterConfig,
+    pub textures: TextureConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct MeshConfig {
+    /// Largest vertical distance in metres between the adaptive surface mesh
+    /// and the height map.
+    pub max_error: f64,
+}
+
+impl Default for MeshConfig {
+    fn default() -> MeshConfig {
+        MeshConfig { max_error: 0.5 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HeightConfig {
+    /// How the height grid is sampled onto the map tiles.
+    pub resampling: Resampler,
+    /// Filters run over the tile heights after sampling, in order.
+    pub filters: Vec<TerrainFilter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct LayersConfig {
+    pub roads: LayerConfig,
+    pub lakes: LayerConfig,
+    pub buildings: LayerConfig,
+}
+
+/// Geometry clean up for one layer. Simplification runs first, so
+/// densification only adds points along the already simplified lines.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct LayerConfig {
+    pub simplify: Option<Simplification>,
+    /// Maximum segment length in metres.
+    pub densify: Option<f64>,
+}
+
+impl LayerConfig {
+    pub fn apply_to_line(&self, points: Vec<Point>) -> Vec<Point> {
+        let points = match &self.simplify {
+            Some(s) => s.apply(&points),
+            None => points,
+        };
+        match self.densify {
+            Some(d) => densify(&points, d),
+            None => points,
+        }
+    }
+
+    pub fn apply_to_polygon(&self, polygon: Polygon) -> Polygon {
+        let polygon = match &self.simplify {
+            Some(s) => polygon.simplified(s),
+            None => polygon,
+        };
+        match self.densify {
+            Some(d) => polygon.densified(d),
+            None => polygon,
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::raster::Raster;
+
+/// Options for contour lines.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ContourConfig {
+    /// Height difference between contours in metres.
+    pub interval: f64,
+    /// Height of one of the contours, the others are whole intervals away.
+    pub base: f64,
+    /// Rounds of Chaikin corner cutting run on every line.
+    pub smoothing: usize,
+    /// Distance in metres between labels along a line. Lines shorter than
+    /// half of it get no label.
+    pub label_spacing: Option<f64>,
+}
+
+impl Default for ContourConfig {
+    fn default() -> ContourConfig {
+        ContourConfig {
+            interval: 5.0,
+            base: 0.0,
+            smoothing: 0,
+            label_spacing: Some(500.0),
+        }
+    }
+}
+
+/// Contour line in raster cells.
+#[derive(Debug, Clone)]
+pub struct Contour {
+    pub elevation: f64,
+    /// For closed lines the last point repeats the first one.
+    pub points: Vec<(f64, f64)>,
+    pub closed: bool,
+}
+
+/// Place for an elevation label on a contour, in the coordinates of the
+/// contour.
+#[derive(Debug, Clone)]
+pub struct ContourLabel {
+    pub elevation: f64,
+    pub position: (f64, f64),
+    /// Direction of the line in degrees counterclockwise from east, turned
+    /// so that text along it is never upside down.
+    pub angle: f64,
+}
+
+/// Contour heights between the lowest and highest cell.
+pub fn levels(raster: &Raster, config: &ContourConfig) -> Vec<f64> {
+    let mut min = f64::INFINITY;
+    let mut max = f64::NEG_INFINITY;
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            if let Some(h) = raster.get(x, y) {
+                min = min.min(h);
+                max = max.max(h);
+            }
+        }
+    }
+    if !min.is_finite() || config.interval <= 0.0 {
+        return Vec::new();
+    }
+    let first = ((min - config.base) / config.interval).ceil() as i64;
+    let last = ((max - config.base) / config.interval).floor() as i64;
+    (first..=last)
+        .map(|k| config.base + k as f64 * config.interval)
+        .collect()
+}
+
+/// Crossing point of a contour on a grid edge. Horizontal edges run from
+/// cell (x, y) to (x + 1, y) and vertical ones from (x, y) to (x, y + 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    Horizontal(usize, usize),
+    Vertical(usize, usize),
+}
+
+/// Traces the contours of `raster` at `level` with marching squares over
+/// the cell centers. Squares touching a missing cell are skipped, so lines
+/// end there like at the edge of the raster. Saddles are split by the
+/// average of the four corners.
+pub fn trace(raster: &Raster, level: f64) -> Vec<Contour> {
+    let mut links: HashMap<Edge, Vec<Edge>> = HashMap::new();
+    let mut link = |a: Edge, b: Edge| {
+        links.entry(a).or_default().push(b);
+        links.entry(b).or_default().push(a);
+    };
+
+    for y in 0..raster.height().saturating_sub(1) {
+        for x in 0..raster.width().saturating_sub(1) {
+            let corners = match (
+                raster.get(x, y),
+                raster.get(x + 1, y),
+                raster.get(x + 1, y + 1),
+                raster.get(x, y + 1),
+            ) {
+                (Some(a), Some(b), Some(c), Some(d)) => [a, b, c, d],
+                _ => continue,
+            };
+            let inside = corners.map(|h| h >= level);
+            let (top, right, bottom, left) = (
+                Edge::Horizontal(x, y),
+                Edge::Vertical(x + 1, y),
+                Edge::Horizontal(x, y + 1),
+                Edge::Vertical(x, y),
+            );
+
+            match inside {
+                [true, true, true, true] | [false, false, false, false] => {}
+                [true, false, true, false] | [false, true, false, true] => {
+                    let center = corners.iter().sum::<f64>() / 4.0 >= level;
+                    // Cut off the two corners that are on the other side of
+                    // the level than the middle of the square.
+                    if inside[0] != center {
+                        link(left, top);
+                        link(right, bottom);
+                    } else {
+                        link(top, right);
+                        link(bottom, left);
+                    }
+                }
+                _ => {
+                    let edges = [top, right, bottom, left];
+                    let crossed: Vec<Edge> = (0..4)
+                        .filter(|i| inside[*i] != inside[(i + 1) % 4])
+                        .map(|i| edges[i])
+                        .collect();
+                    link(crossed[0], crossed[1]);
+                }
+            }
+        }
+    }
+
+    let point = |edge: Edge| {
+        let ((x0, y0), (x1, y1)) = match edge {
+            Edge::Horizontal(x, y) => ((x, y), (x + 1, y)),
+            Edge::Vertical(x, y) => ((x, y), (x, y + 1)),
+        };
+        let a = raster.get(x0, y0).unwrap();
+        let b = raster.get(x1, y1).unwrap();
+        let t = (level - a) / (b - a);
+        (
+            x0 as f64 + (x1 as f64 - x0 as f64) * t,
+            y0 as f64 + (y1 as f64 - y0 as f64) * t,
+        )
+    };
+
+    // Open lines start from their ends, what is left after them are loops.
+    let mut starts: Vec<Edge> = links.keys().copied().collect();
+    starts.sort_by_key(|edge| (links[edge].len(), sort_key(edge)));
+
+    let mut visited = HashSet::new();
+    let mut contours = Vec::new();
+    for start in starts {
+        if visited.contains(&start) {
+            continue;
+        }
+        let mut edges = vec![start];
+        visited.insert(start);
+        let mut current = start;
+        let closed = loop {
+            let next = links[&current]
+                .iter()
+                .find(|next| !visited.contains(next))
+                .copied();
+            match next {
+                Some(next) => {
+                    visited.insert(next);
+                    edges.push(next);
+                    current = next;
+                }
+                None => break edges.len() > 2 && links[&current].contains(&start),
+            }
+        };
+        if closed {
+            edges.push(start);
+        }
+        let points: Vec<(f64, f64)> = edges.into_iter().map(point).collect();
+        // A level that only touches a peak or a pit exactly gives a loop
+        // around a single point.
+        if points.windows(2).all(|pair| pair[0] == pair[1]) {
+            continue;
+        }
+        contours.push(Contour {
+            elevation: level,
+            points,
+            closed,
+        });
+    }
+    contours
+}
+
+fn sort_key(edge: &Edge) -> (usize, usize, usize) {
+    match edge {
+        Edge::Horizontal(x, y) => (*y, *x, 0),
+        Edge::Vertical(x, y) => (*y, *x, 1),
+    }
+}
+
+/// Contours of every level in `config`, smoothed.
+pub fn contours(raster: &Raster, config: &ContourConfig) -> Vec<Contour> {
+    levels(raster, config)
+        .into_iter()
+        .flat_map(|level| trace(raster, level))
+        .map(|mut contour| {
+            for _ in 0..config.smoothing {
+                contour.points = chaikin(&contour.points, contour.closed);
+            }
+            contour
+        })
+        .collect()
+}
+
+/// One round of Chaikin corner cutting. Open lines keep their end points.
+pub fn chaikin(points: &[(f64, f64)], closed: bool) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut smoothed = Vec::with_capacity(points.len() * 2);
+    if !closed {
+        smoothed.push(points[0]);
+    }
+    for pair in points.windows(2) {
+        let (p, q) = (pair[0], pair[1]);
+        smoothed.push((0.75 * p.0 + 0.25 * q.0, 0.75 * p.1 + 0.25 * q.1));
+        smoothed.push((0.25 * p.0 + 0.75 * q.0, 0.25 * p.1 + 0.75 * q.1));
+    }
+    if closed {
+        smoothed.push(smoothed[0]);
+    } else {
+        smoothed.push(points[points.len() - 1]);
+    }
+    smoothed
+}
+
+/// Raster of `width` by `height` cells holding the elevation of the contour
+/// passing through each cell, the higher one where several do, and missing
+/// where none does.
+pub fn layer(contours: &[Contour], width: usize, height: usize) -> Raster {
+    let mut raster = Raster::new(width, height);
+    for contour in contours {
+        for pair in contour.points.windows(2) {
+            let (p, q) = (pair[0], pair[1]);
+            // Steps of at most half a cell reach every cell the segment
+            // crosses.
+            let steps = ((q.0 - p.0).abs().max((q.1 - p.1).abs()) * 2.0).ceil() as usize;
+            for step in 0..=steps {
+                let t = step as f64 / steps.max(1) as f64;
+                let (x, y) = (p.0 + (q.0 - p.0) * t, p.1 + (q.1 - p.1) * t);
+                let (x, y) = (x.round(), y.round());
+                if x < 0.0 || y < 0.0 || x >= width as f64 || y >= height as f64 {
+                    continue;
+                }
+                let (x, y) = (x as usize, y as usize);
+                if raster.get(x, y).is_none_or(|e| e < contour.elevation) {
+                    raster.set(x, y, Some(contour.elevation));
+                }
+            }
+        }
+    }
+    raster
+}
+
+/// Label positions every `label_spacing` metres along the contours,
+/// starting half a spacing from the start of each line. `spacing` is the
+/// cell size in metres, with rows running from north to south.
+pub fn labels(contours: &[Contour], label_spacing: f64, spacing: (f64, f64)) -> Vec<ContourLabel> {
+    let mut labels = Vec::new();
+    for contour in contours {
+        let mut next = label_spacing / 2.0;
+        let mut walked = 0.0;
+        for pair in contour.points.windows(2) {
+            let (p, q) = (pair[0], pair[1]);
+            let (east, north) = ((q.0 - p.0) * spacing.0, -(q.1 - p.1) * spacing.1);
+            let length = east.hypot(north);
+            while length > 0.0 && walked + length >= next {
+                let t = (next - walked) / length;
+                let mut angle = north.atan2(east).to_degrees();
+                if angle > 90.0 {
+                    angle -= 180.0;
+                } else if angle <= -90.0 {
+                    angle += 180.0;
+                }
+                labels.push(ContourLabel {
+                    elevation: contour.elevation,
+                    position: (p.0 + (q.0 - p.0) * t, p.1 + (q.1 - p.1) * t),
+                    angle,
+                });
+                next += label_spacing;
+            }
+            walked += length;
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster<F: Fn(f64, f64) -> f64>(width: usize, height: usize, h: F) -> Raster {
+        let rows: Vec<Vec<f64>> = (0..height)
+            .map(|y| (0..width).map(|x| h(x as f64, y as f64)).collect())
+            .collect();
+        Raster::from_rows(&rows, None)
+    }
+
+    fn cone() -> Raster {
+        raster(21, 21, |x, y| 10.0 - (x - 10.0).hypot(y - 10.0))
+    }
+
+    #[test]
+    fn cone_gives_closed_rings() {
+        let cone = cone();
+        for level in [2.0, 4.0, 6.0, 8.0] {
+            let contours = trace(&cone, level);
+            assert_eq!(contours.len(), 1);
+            let ring = &contours[0];
+            assert!(ring.closed);
+            assert_eq!(ring.points.first(), ring.points.last());
+            let radius = 10.0 - level;
+            for (x, y) in ring.points.iter() {
+                assert!(((x - 10.0).hypot(y - 10.0) - radius).abs() < 0.1);
+            }
+        }
+        // The peak itself is a single point and gives no line.
+        assert!(trace(&cone, 10.0).is_empty());
+
+        let config = ContourConfig {
+            interval: 2.0,
+            ..ContourConfig::default()
+        };
+        assert_eq!(
+            levels(&cone, &config),
+            vec![-4.0, -2.0, 0.0, 2.0, 4.0, 6.0, 8.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn lines_end_at_the_edges() {
+        // Two branches of a hyperbola on both sides of a saddle.
+        let saddle = raster(11, 11, |x, y| (x - 5.0).powi(2) - (y - 5.0).powi(2));
+        let contours = trace(&saddle, 1.0);
+        assert_eq!(contours.len(), 2);
+        let on_edge = |(x, y): (f64, f64)| x == 0.0 || y == 0.0 || x == 10.0 || y == 10.0;
+        for line in contours.iter() {
+            assert!(!line.closed);
+            assert!(on_edge(line.points[0]) && on_edge(*line.points.last().unwrap()));
+            let side = line.points[0].0 < 5.0;
+            assert!(line.points.iter().all(|p| (p.0 < 5.0) == side));
+        }
+    }
+
+    #[test]
+    fn saddle_squares_follow_their_middle() {
+        let saddle = raster(2, 2, |x, y| if x == y { 1.0 } else { 0.0 });
+        let ends = |level: f64| {
+            let mut ends: Vec<[(f64, f64); 2]> = trace(&saddle, level)
+                .into_iter()
+                .map(|c| {
+                    assert_eq!(c.points.len(), 2);
+                    let mut ends = [c.points[0], c.points[1]];
+                    ends.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    ends
+                })
+                .collect();
+            ends.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            ends
+        };
+        // The middle is at 0.5, above 0.4: the low corners are cut off.
+        assert_eq!(
+            ends(0.4),
+            vec![[(0.0, 0.6), (0.4, 1.0)], [(0.6, 0.0), (1.0, 0.4)]]
+        );
+        // Below 0.6 the middle is low and the high corners are cut off.
+        assert_eq!(
+            ends(0.6),
+            vec![[(0.0, 0.4), (0.4, 0.0)], [(0.6, 1.0), (1.0, 0.6)]]
+        );
+    }
+
+    #[test]
+    fn chaikin_cuts_corners() {
+        let square = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0), (0.0, 0.0)];
+        let smoothed = chaikin(&square, true);
+        assert_eq!(smoothed.len(), 9);
+        assert_eq!(smoothed.first(), smoothed.last());
+        assert_eq!(&smoothed[..2], &[(1.0, 0.0), (3.0, 0.0)]);
+
+        let line = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0)];
+        let smoothed = chaikin(&line, false);
+        assert_eq!(smoothed.first(), Some(&(0.0, 0.0)));
+        assert_eq!(smoothed.last(), Some(&(4.0, 4.0)));
+        assert_eq!(smoothed.len(), 6);
+        assert_eq!(chaikin(&line[..2], false), line[..2].to_vec());
+    }
+
+    #[test]
+    fn labels_are_spaced_along_lines_and_upright() {
+        let line = |points: Vec<(f64, f64)>| Contour {
+            elevation: 5.0,
+            points,
+            closed: false,
+        };
+        let east = labels(&[line(vec![(0.0, 0.0), (10.0, 0.0)])], 8.0, (2.0, 2.0));
+        let positions: Vec<(f64, f64)> = east.iter().map(|l| l.position).collect();
+        assert_eq!(positions, vec![(2.0, 0.0), (6.0, 0.0), (10.0, 0.0)]);
+        assert!(east.iter().all(|l| l.angle == 0.0 && l.elevation == 5.0));
+
+        let west = labels(&[line(vec![(10.0, 0.0), (0.0, 0.0)])], 8.0, (2.0, 2.0));
+        assert!(west.iter().all(|l| l.angle == 0.0));
+        // Rows run south, so a line down the raster reads from south to
+        // north.
+        let south = labels(&[line(vec![(0.0, 0.0), (0.0, 10.0)])], 8.0, (2.0, 2.0));
+        assert!(south.iter().all(|l| l.angle == 90.0));
+        // Too short for its first label.
+        assert!(labels(&[line(vec![(0.0, 0.0), (1.0, 0.0)])], 8.0, (2.0, 2.0)).is_empty());
+    }
+
+    #[test]
+    fn layer_marks_the_cells_lines_pass() {
+        let cone = cone();
+        let config = ContourConfig {
+            interval: 4.0,
+            base: 2.0,
+            ..ContourConfig::default()
+        };
+        let layer = layer(&contours(&cone, &config), 21, 21);
+        assert_eq!(layer.get(10, 10), None);
+        // Rings of radius 4 and 8 cross the row through the peak.
+        assert_eq!(layer.get(14, 10), Some(6.0));
+        assert_eq!(layer.get(2, 10), Some(2.0));
+        assert_eq!(layer.get(12, 10), None);
+    }
+}
@@ -0,0 +1,336 @@
+/// Fractions of the cells of a window of a raster covered by a shape.
+/// Coordinates are in raster cells with cell centers on whole numbers, so
+/// cell (x, y) spans half a cell in every direction around (x, y).
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    x0: usize,
+    y0: usize,
+    width: usize,
+    values: Vec<f64>,
+}
+
+impl Coverage {
+    fn empty() -> Coverage {
+        Coverage {
+            x0: 0,
+            y0: 0,
+            width: 0,
+            values: Vec::new(),
+        }
+    }
+
+    /// Raster coordinates and coverage of every cell the shape touches.
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v > 0.0)
+            .map(|(i, v)| (self.x0 + i % self.width, self.y0 + i / self.width, *v))
+    }
+}
+
+/// Exact area of each cell inside a polygon, for a raster of `width` by
+/// `height` cells. The first ring is the exterior and the rest are holes,
+/// in either winding. Edges are accumulated as signed areas along each row
+/// and summed from the left, as in font rasterisers.
+pub fn polygon_coverage(rings: &[Vec<(f64, f64)>], width: usize, height: usize) -> Coverage {
+    // Shift to coordinates where cell (x, y) spans [x, x + 1].
+    let rings: Vec<Vec<(f64, f64)>> = rings
+        .iter()
+        .map(|ring| ring.iter().map(|(x, y)| (x + 0.5, y + 0.5)).collect())
+        .collect();
+    let points = rings.iter().flatten();
+    let (min_x, max_x) = points
+        .clone()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| {
+            (lo.min(p.0), hi.max(p.0))
+        });
+    let (min_y, max_y) = points.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| {
+        (lo.min(p.1), hi.max(p.1))
+    });
+    if !min_x.is_finite() {
+        return Coverage::empty();
+    }
+    let x0 = min_x.floor().clamp(0.0, width as f64) as usize;
+    let x1 = max_x.ceil().clamp(0.0, width as f64) as usize;
+    let y0 = min_y.floor().clamp(0.0, height as f64) as usize;
+    let y1 = max_y.ceil().clamp(0.0, height as f64) as usize;
+    if x0 >= x1 || y0 >= y1 {
+        return Coverage::empty();
+    }
+
+    let (columns, rows) = (x1 - x0, y1 - y0);
+    // Two spare columns take what edges on the right border add.
+    let stride = columns + 2;
+    let mut accumulation = vec![0.0; stride * rows];
+    let exterior_sign = ring_area(&rings[0]).signum();
+    for (i, ring) in rings.iter().enumerate() {
+        let reverse = i > 0 && ring_area(ring).signum() == exterior_sign;
+        let n = ring.len();
+        for k in 0..n {
+            let (mut a, mut b) = (ring[k], ring[(k + 1) % n]);
+            if reverse {
+                std::mem::swap(&mut a, &mut b);
+            }
+            add_edge(
+                &mut accumulation,
+                stride,
+                rows,
+                columns as f64,
+                (a.0 - x0 as f64, a.1 - y0 as f64),
+                (b.0 - x0 as f64, b.1 - y0 as f64),
+            );
+        }
+    }
+
+    let mut values = Vec::with_capacity(columns * rows);
+    for row in accumulation.chunks(stride) {
+        let mut sum = 0.0;
+        for a in row.iter().take(columns) {
+            sum += a;
+            values.push(f64::min(f64::abs(sum), 1.0));
+        }
+    }
+    Coverage {
+        x0,
+        y0,
+        width: columns,
+        values,
+    }
+}
+
+/// Twice the signed area of a ring.
+fn ring_area(ring: &[(f64, f64)]) -> f64 {
+    let n = ring.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % n]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+/// Splits an edge where it leaves the columns and moves the outside parts
+/// onto the border, where they still open or close the rows they span.
+fn add_edge(
+    accumulation: &mut [f64],
+    stride: usize,
+    rows: usize,
+    columns: f64,
+    a: (f64, f64),
+    b: (f64, f64),
+) {
+    let mut cuts = vec![0.0, 1.0];
+    for border in [0.0, columns] {
+        if (a.0 - border) * (b.0 - border) < 0.0 {
+            cuts.push((border - a.0) / (b.0 - a.0));
+        }
+    }
+    cuts.sort_by(f64::total_cmp);
+    let at = |t: f64| {
+        (
+            (a.0 + (b.0 - a.0) * t).clamp(0.0, columns),
+            a.1 + (b.1 - a.1) * t,
+        )
+    };
+    for pair in cuts.windows(2) {
+        accumulate_line(accumulation, stride, rows, at(pair[0]), at(pair[1]));
+    }
+}
+
+/// Adds the signed area a line leaves to its right in every row it spans,
+/// split between the cells it crosses and the cell after them.
+fn accumulate_line(
+    accumulation: &mut [f64],
+    stride: usize,
+    rows: usize,
+    p0: (f64, f64),
+    p1: (f64, f64),
+) {
+    if p0.1 == p1.1 {
+        return;
+    }
+    let (direction, p0, p1) = if p0.1 < p1.1 {
+        (1.0, p0, p1)
+    } else {
+        (-1.0, p1, p0)
+    };
+    if p1.1 <= 0.0 || p0.1 >= rows as f64 {
+        return;
+    }
+    let dxdy = (p1.0 - p0.0) / (p1.1 - p0.1);
+    let mut x = p0.0;
+    if p0.1 < 0.0 {
+        x -= p0.1 * dxdy;
+    }
+    let first_row = p0.1.max(0.0) as usize;
+    let end_row = (p1.1.ceil() as usize).min(rows);
+
+    for y in first_row..end_row {
+        let row = &mut accumulation[y * stride..(y + 1) * stride];
+        let dy = ((y + 1) as f64).min(p1.1) - (y as f64).max(p0.1);
+        let x_next = x + dxdy * dy;
+        let d = dy * direction;
+        let (left, right) = if x < x_next { (x, x_next) } else { (x_next, x) };
+        let left_floor = left.floor();
+        let right_ceil = right.ceil();
+        let (l, r) = (left_floor as usize, right_ceil as usize);
+
+        if r <= l + 1 {
+            // Within one cell: the part right of the line's mean position
+            // goes to the cell, the rest starts with the next one.
+            let middle = 0.5 * (x + x_next) - left_floor;
+            row[l] += d - d * middle;
+            row[l + 1] += d * middle;
+        } else {
+            let s = 1.0 / (right - left);
+            let left_fraction = left - left_floor;
+            let first = 0.5 * s * (1.0 - left_fraction) * (1.0 - left_fraction);
+            let right_fraction = right - right_ceil + 1.0;
+            let last = 0.5 * s * right_fraction * right_fraction;
+            row[l] += d * first;
+            if r == l + 2 {
+                row[l + 1] += d * (1.0 - first - last);
+            } else {
+                let second = s * (1.5 - left_fraction);
+                row[l + 1] += d * (second - first);
+                for cell in row.iter_mut().take(r - 1).skip(l + 2) {
+                    *cell += d * s;
+                }
+                let before_last = second + (r - l - 3) as f64 * s;
+                row[r - 1] += d * (1.0 - before_last - last);
+            }
+            row[r] += d * last;
+        }
+        x = x_next;
+    }
+}
+
+/// Coverage of a line segment buffered to `line_width` metres with round
+/// ends. `cell_size` is the width and height of a cell in metres. Each cell
+/// is treated as its extent across the line, so coverage is exact for lines
+/// along the rows or columns and close to it otherwise.
+pub fn segment_coverage(
+    a: (f64, f64),
+    b: (f64, f64),
+    line_width: f64,
+    cell_size: (f64, f64),
+    width: usize,
+    height: usize,
+) -> Coverage {
+    let half = line_width / 2.0;
+    let margin = (half / cell_size.0 + 1.0, half / cell_size.1 + 1.0);
+    let clamp = |v: f64, max: usize| v.clamp(0.0, max as f64 - 1.0) as usize;
+    if width == 0 || height == 0 {
+        return Coverage::empty();
+    }
+    let x0 = clamp((a.0.min(b.0) - margin.0).floor(), width);
+    let x1 = clamp((a.0.max(b.0) + margin.0).ceil(), width);
+    let y0 = clamp((a.1.min(b.1) - margin.1).floor(), height);
+    let y1 = clamp((a.1.max(b.1) + margin.1).ceil(), height);
+
+    // Work in metres from `a`.
+    let segment = ((b.0 - a.0) * cell_size.0, (b.1 - a.1) * cell_size.1);
+    let length_squared = segment.0 * segment.0 + segment.1 * segment.1;
+    let columns = x1 - x0 + 1;
+    let mut values = Vec::with_capacity(columns * (y1 - y0 + 1));
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let p = (
+                (x as f64 - a.0) * cell_size.0,
+                (y as f64 - a.1) * cell_size.1,
+            );
+            let t = if length_squared > 0.0 {
+                ((p.0 * segment.0 + p.1 * segment.1) / length_squared).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let offset = (p.0 - segment.0 * t, p.1 - segment.1 * t);
+            let distance = offset.0.hypot(offset.1);
+            let normal = if distance > 1e-9 {
+                (offset.0 / distance, offset.1 / distance)
+            } else if length_squared > 0.0 {
+                let length = length_squared.sqrt();
+                (-segment.1 / length, segment.0 / length)
+            } else {
+                (1.0, 0.0)
+            };
+            let extent = normal.0.abs() * cell_size.0 + normal.1.abs() * cell_size.1;
+            let overlap =
+                (distance + extent / 2.0).min(half) - (distance - extent / 2.0).max(-half);
+            values.push((overlap / extent).clamp(0.0, 1.0));
+        }
+    }
+    Coverage {
+        x0,
+        y0,
+        width: columns,
+        values,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(coverage: &Coverage) -> f64 {
+        coverage.cells().map(|(_, _, v)| v).sum()
+    }
+
+    #[test]
+    fn polygon_coverage_is_the_area_in_each_cell() {
+        // Triangle over cells, with half and quarter cells on its edges.
+        let triangle = vec![(-0.5, -0.5), (3.5, -0.5), (-0.5, 3.5)];
+        let coverage = polygon_coverage(&[triangle], 10, 10);
+        assert!((total(&coverage) - 8.0).abs() < 1e-9);
+        let cell = |x: usize, y: usize| {
+            coverage
+                .cells()
+                .find(|c| c.0 == x && c.1 == y)
+                .map_or(0.0, |c| c.2)
+        };
+        assert!((cell(0, 0) - 1.0).abs() < 1e-9);
+        assert!((cell(3, 0) - 0.5).abs() < 1e-9);
+        assert!((cell(1, 2) - 0.5).abs() < 1e-9);
+        assert_eq!(cell(3, 3), 0.0);
+    }
+
+    #[test]
+    fn holes_and_borders_are_cut_out() {
+        // Square reaching past the left and top of the raster with a hole
+        // wound the same way as the exterior.
+        let exterior = vec![(-3.0, -3.0), (4.5, -3.0), (4.5, 4.5), (-3.0, 4.5)];
+        let hole = vec![(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (1.0, 3.0)];
+        let coverage = polygon_coverage(&[exterior, hole], 10, 10);
+        assert!((total(&coverage) - (25.0 - 4.0)).abs() < 1e-9);
+        assert!(coverage.cells().all(|(_, _, v)| v <= 1.0));
+    }
+
+    #[test]
+    fn slanted_edges_keep_the_area() {
+        let quad = vec![(0.3, 0.1), (7.7, 2.2), (6.1, 8.9), (1.4, 5.5)];
+        let coverage = polygon_coverage(std::slice::from_ref(&quad), 20, 20);
+        assert!((total(&coverage) - ring_area(&quad).abs() / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn buffered_lines_cover_their_width() {
+        // Two metres wide along a row of four metre cells.
+        let coverage = segment_coverage((2.0, 5.0), (12.0, 5.0), 2.0, (4.0, 4.0), 20, 20);
+        let middle: Vec<f64> = coverage
+            .cells()
+            .filter(|(x, _, _)| *x == 7)
+            .map(|c| c.2)
+            .collect();
+        assert_eq!(middle.len(), 1);
+        assert!((middle[0] - 0.5).abs() < 1e-9);
+        // Wider than a cell.
+        let coverage = segment_coverage((2.0, 5.0), (12.0, 5.0), 6.0, (4.0, 4.0), 20, 20);
+        let middle: f64 = coverage
+            .cells()
+            .filter(|(x, _, _)| *x == 7)
+            .map(|c| c.2)
+            .sum();
+        assert!((middle - 1.5).abs() < 1e-9);
+    }
+}
@@ -1,3 +1,4 @@
+use std::fs;
 use std::fs::write;
 use std::io::{BufRead, BufWriter, Write};
 use std::vec;
@@ -5,10 +6,26 @@ use std::{collections::HashMap, error::Error, fs::File, io::BufReader, path::Pat
 
 use serde::{Deserialize, Serialize};
 
+use crate::config::Config;
+use crate::contour::ContourConfig;
 use crate::geojson::GeoJSON;
-use crate::geometry::point::Vec3;
-use crate::map::Map;
+use crate::geometry::bbox::Bbox;
+use crate::geometry::point::{Point, Vec3};
+use crate::height_image::{
+    encode_gray16, encode_terrain_rgb, HeightImageConfig, ImageGeoreference, HEIGHT_IMAGE_NODATA,
+    TERRAIN_RGB_OFFSET, TERRAIN_RGB_SCALE,
+};
+use crate::map::{Map, HEIGHT_MAP_PER_METRE, TILE_HEIGHT_PER_METRE};
+use crate::pyramid::{BoundingVolume, PyramidConfig, TerrainTile, TilePyramid};
+use crate::quantized_mesh::{QuantizedMesh, QuantizedMeshConfig, TileId};
+use crate::raster::{Raster, Resampler};
+use crate::road_mesh::RoadMeshConfig;
+use crate::rtin::Rtin;
+use crate::terrain_analysis::{analyse, AnalysisConfig};
+use crate::texture::{bake_colour, bake_splat, Texture, TextureConfig};
+use crate::tile_layers::coverage_layer_name;
 use crate::ui;
+use crate::water::WaterConfig;
 
 pub fn read_json_from_file<P: AsRef<Path>>(
     path: P,
@@ -45,6 +62,17 @@ pub fn read_height_data_from_file<P: AsRef<Path>>(
     Ok(rows)
 }
 
+/// Reads the map config. A missing file gives the default config.
+pub fn read_config_from_file<P: AsRef<Path>>(path: P) -> Result<Config, Box<dyn Error>> {
+    if !path.as_ref().exists() {
+        return Ok(Config::default());
+    }
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let u = serde_json::from_reader(reader)?;
+    Ok(u)
+}
+
 pub fn read_geojson_from_file<P: AsRef<Path>>(path: P) -> Result<GeoJSON, Box<dyn Error>> {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
@@ -52,6 +80,24 @@ pub fn read_geojson_from_file<P: AsRef<Path>>(path: P) -> Result<GeoJSON, Box<dy
     Ok(u)
 }
 
+/// Reads the pages `download_collection` saved for a collection from
+/// `directory`. A collection that was never downloaded has no pages.
+pub fn read_collection_from_files<P: AsRef<Path>>(
+    directory: P,
+    collection_name: &str,
+) -> Result<Vec<GeoJSON>, Box<dyn Error>> {
+    let mut pages = Vec::new();
+    loop {
+        let path = directory
+            .as_ref()
+            .join(format!("{collection_name}-{}.json", pages.len() + 1));
+        if !path.exists() {
+            return Ok(pages);
+        }
+        pages.push(read_geojson_from_file(path)?);
+    }
+}
+
 pub fn write_height_map<P: AsRef<Path>>(path: P, map: &Map) -> Result<(), Box<dyn Error>> {
     let json = serde_json::to_string(&(map.height_map(), map.class_map()))?;
     write(path, json).expect("Unable to write file");
@@ -61,6 +107,16 @@ pub fn write_height_map<P: AsRef<Path>>(path: P, map: &Map) -> Result<(), Box<dy
 #[derive(Serialize, Deserialize, Debug)]
 struct ModelOutput {
     vertices: Vec<Vec<f64>>,
+    /// Texture coordinates of the vertices, left out of normal lists.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    uvs: Vec<[f64; 2]>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct WaterModelOutput {
+    vertices: Vec<Vec<f64>>,
+    uvs: Vec<[f64; 2]>,
+    shore_distances: Vec<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -122,7 +178,915 @@ pub fn write_surface_model<P: AsRef<Path>>(
             ]);
         }
     }
-    let output = ModelOutput { vertices };
+    let uvs = vertices
+        .iter()
+        .map(|v| map.texture_coords(v[0], v[1]))
+        .collect();
+    let output = ModelOutput { vertices, uvs };
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+/// Writes an adaptive surface mesh within `max_error` metres of the height
+/// map, as triangle lists in the same format as `write_surface_model` and
+/// `write_surface_normals`. Prints how the triangle count scales with the
+/// error threshold.
+pub fn write_adaptive_surface_model<P: AsRef<Path>>(
+    model_path: P,
+    normals_path: P,
+    map: &Map,
+    max_error: f64,
+) -> Result<(), Box<dyn Error>> {
+    println!("Building adaptive surface mesh");
+    let height_map = map.height_map();
+    let rtin = Rtin::new(&Raster::from_rows(&height_map, None));
+
+    for threshold in [0.25, 0.5, 1.0, 2.0, 4.0].map(|f| f * max_error) {
+        let report = rtin.report(threshold * HEIGHT_MAP_PER_METRE);
+        println!(
+            "max error {:.2} m: {} triangles, {} vertices ({:.1}% of the {} grid triangles)",
+            threshold,
+            report.triangles,
+            report.vertices,
+            100.0 * report.triangles as f64 / report.grid_triangles as f64,
+            report.grid_triangles
+        );
+    }
+
+    let mesh = rtin.mesh(max_error * HEIGHT_MAP_PER_METRE);
+    let average = 1.6;
+    let distance_diff = 0.1;
+    let mut vertices: Vec<Vec<f64>> = Vec::new();
+    let mut uvs: Vec<[f64; 2]> = Vec::new();
+    let mut normals: Vec<Vec<f64>> = Vec::new();
+    for index in mesh.indices.iter() {
+        let [x, y, z] = mesh.positions[*index as usize];
+        vertices.push(vec![x, y, z, 1.0]);
+        uvs.push(mesh.uvs[*index as usize]);
+        let normal = normal_for_point(
+            &height_map,
+            distance_diff,
+            1,
+            x as usize,
+            y as usize,
+            average,
+        );
+        normals.push(vec![normal.x, normal.y, normal.z]);
+    }
+
+    let f = BufWriter::new(File::create(model_path)?);
+    serde_json::to_writer(f, &ModelOutput { vertices, uvs })?;
+    let f = BufWriter::new(File::create(normals_path)?);
+    let normals = ModelOutput {
+        vertices: normals,
+        uvs: Vec::new(),
+    };
+    serde_json::to_writer(f, &normals)?;
+    Ok(())
+}
+
+/// Writes the road ribbon mesh as a triangle list in the same format as
+/// `write_surface_model`.
+pub fn write_road_model<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    config: &RoadMeshConfig,
+) -> Result<(), Box<dyn Error>> {
+    println!("Building road mesh");
+    let mesh = map.road_mesh(config);
+    let vertices: Vec<Vec<f64>> = mesh
+        .indices
+        .iter()
+        .map(|i| {
+            let [x, y, z] = mesh.positions[*i as usize];
+            vec![x, y, z * HEIGHT_MAP_PER_METRE, 1.0]
+        })
+        .collect();
+    let uvs = mesh.indices.iter().map(|i| mesh.uvs[*i as usize]).collect();
+    let f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(f, &ModelOutput { vertices, uvs })?;
+    Ok(())
+}
+
+pub fn write_water_model<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    config: &WaterConfig,
+) -> Result<(), Box<dyn Error>> {
+    println!("Building water meshes");
+    let mut output = WaterModelOutput {
+        vertices: Vec::new(),
+        uvs: Vec::new(),
+        shore_distances: Vec::new(),
+    };
+    for water in map.water_meshes(config) {
+        for v in water.triangles.iter().flatten() {
+            let p = &water.points[*v];
+            output
+                .vertices
+                .push(vec![p.x, p.y, water.level * HEIGHT_MAP_PER_METRE, 1.0]);
+            output.uvs.push(map.texture_coords(p.x, p.y));
+            output.shore_distances.push(water.shore_distances[*v]);
+        }
+    }
+    let f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TilesetOutput {
+    tile_size: usize,
+    levels: usize,
+    root: TilesetNode,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TilesetNode {
+    level: usize,
+    x: usize,
+    y: usize,
+    geometric_error: f64,
+    bounds: BoundingVolume,
+    uri: String,
+    children: Vec<TilesetNode>,
+}
+
+fn tileset_node(pyramid: &TilePyramid, tile: &TerrainTile) -> TilesetNode {
+    let children = match pyramid.levels.get(tile.level + 1) {
+        Some(level) => level
+            .iter()
+            .filter(|t| t.x / 2 == tile.x && t.y / 2 == tile.y)
+            .map(|t| tileset_node(pyramid, t))
+            .collect(),
+        None => Vec::new(),
+    };
+    TilesetNode {
+        level: tile.level,
+        x: tile.x,
+        y: tile.y,
+        geometric_error: tile.geometric_error,
+        bounds: tile.bounds.clone(),
+        uri: format!("{}/{}/{}.json", tile.level, tile.x, tile.y),
+        children,
+    }
+}
+
+/// Writes the height map as a tile pyramid, one mesh per tile in
+/// `{level}/{x}/{y}.json` and the tile tree with bounding volumes in
+/// `tileset.json`.
+pub fn write_terrain_tiles<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    config: &PyramidConfig,
+) -> Result<(), Box<dyn Error>> {
+    println!("Writing terrain tiles");
+    let raster = Raster::from_rows(&map.height_map(), None);
+    let pyramid = TilePyramid::build(&raster, config)?;
+
+    let tile_count = pyramid.tiles().count();
+    for (i, tile) in pyramid.tiles().enumerate() {
+        let directory = path
+            .as_ref()
+            .join(tile.level.to_string())
+            .join(tile.x.to_string());
+        fs::create_dir_all(&directory)?;
+        let f = BufWriter::new(File::create(directory.join(format!("{}.json", tile.y)))?);
+        serde_json::to_writer(f, &tile.mesh)?;
+        ui::print_progress_bar(i as f64 / tile_count as f64);
+    }
+    ui::print_progress_bar_completed();
+
+    let output = TilesetOutput {
+        tile_size: pyramid.tile_size,
+        levels: pyramid.levels.len(),
+        root: tileset_node(&pyramid, &pyramid.levels[0][0]),
+    };
+    let f = BufWriter::new(File::create(path.as_ref().join("tileset.json"))?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+fn write_png<P: AsRef<Path>>(
+    path: P,
+    (width, height): (usize, usize),
+    color: png::ColorType,
+    depth: png::BitDepth,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let f = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(f, width as u32, height as u32);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    Ok(())
+}
+
+/// Writes the georeference of an image at `path` next to it, with the
+/// extension replaced by `.json`.
+fn write_image_georeference<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    (width, height): (usize, usize),
+    encoding: &str,
+    scale: f64,
+    offset: f64,
+    nodata: Option<u32>,
+) -> Result<(), Box<dyn Error>> {
+    let extent = map.extent();
+    let output = ImageGeoreference {
+        encoding: String::from(encoding),
+        width,
+        height,
+        crs: String::from("EPSG:4326"),
+        bounds: [extent.a.x, extent.a.y, extent.b.x, extent.b.y],
+        pixel_size: [
+            (extent.b.x - extent.a.x) / width as f64,
+            (extent.b.y - extent.a.y) / height as f64,
+        ],
+        scale,
+        offset,
+        nodata,
+    };
+    let f = BufWriter::new(File::create(path.as_ref().with_extension("json"))?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+/// Writes the height map as a 16-bit grayscale PNG, north up, with the
+/// georeference and height scale in a JSON file next to it.
+pub fn write_height_png<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    config: &HeightImageConfig,
+) -> Result<(), Box<dyn Error>> {
+    write_gray16_png(path, map, &map.height_raster_in_metres(), config)
+}
+
+/// Writes the lake bed heights like `write_height_png`. Tiles outside lakes
+/// have no data.
+pub fn write_lake_bed_png<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    config: &HeightImageConfig,
+) -> Result<(), Box<dyn Error>> {
+    write_gray16_png(path, map, &map.lake_bed_raster_in_metres(), config)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LandCoverClassOutput {
+    id: u16,
+    /// Pixel value of the class in `land_cover.png`.
+    value: u16,
+    name: String,
+    colour: [u8; 3],
+    priority: i32,
+}
+
+/// Writes the tile class ids as a 16-bit grayscale PNG with a georeference
+/// file next to it, and the id, pixel value, name, colour and priority of
+/// every class in `classes.json` in the same directory. Pixels hold the id
+/// plus one, as 0 is the no-data value.
+pub fn write_land_cover<P: AsRef<Path>>(path: P, map: &Map) -> Result<(), Box<dyn Error>> {
+    let raster = map.class_raster();
+    let ids = land_cover_encoding();
+    write_gray16_png(&path, map, &raster, &ids)?;
+
+    let classes: Vec<LandCoverClassOutput> = map
+        .classes
+        .iter()
+        .map(|class| LandCoverClassOutput {
+            id: class.id,
+            value: ids.encode(Some(class.id as f64)),
+            name: class.name.clone(),
+            colour: class.colour,
+            priority: class.priority,
+        })
+        .collect();
+    let classes_path = path
+        .as_ref()
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join("classes.json");
+    let f = BufWriter::new(File::create(classes_path)?);
+    serde_json::to_writer(f, &classes)?;
+    Ok(())
+}
+
+/// Class id `v - 1` for pixel value `v`, keeping EMPTY (0) apart from the
+/// no-data value.
+fn land_cover_encoding() -> HeightImageConfig {
+    HeightImageConfig {
+        scale: 1.0,
+        offset: -1.0,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TileLayerOutput {
+    name: String,
+    labels: Option<Vec<String>>,
+    rows: Vec<Vec<Option<f64>>>,
+}
+
+/// Writes one tile layer as rows like the height map, with `null` for
+/// tiles without a value. Categorical layers include the names of their
+/// values.
+pub fn write_tile_layer<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let layer = map
+        .layers
+        .get(name)
+        .ok_or_else(|| format!("No tile layer named {name}"))?;
+    let output = TileLayerOutput {
+        name: layer.name.clone(),
+        labels: layer.labels.clone(),
+        rows: raster_rows(&layer.values),
+    };
+    let f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+/// Writes every tile layer into `directory` as `{name}.json`.
+pub fn write_tile_layers<P: AsRef<Path>>(directory: P, map: &Map) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(&directory)?;
+    for name in map.layers.names() {
+        write_tile_layer(directory.as_ref().join(format!("{name}.json")), map, name)?;
+    }
+    Ok(())
+}
+
+/// Writes the coverage layers into `directory` as 8-bit grayscale PNGs named
+/// like the layers, 255 where the class covers the whole tile, for blending
+/// textures at class borders.
+pub fn write_coverage_masks<P: AsRef<Path>>(directory: P, map: &Map) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(&directory)?;
+    for layer in map
+        .layers
+        .iter()
+        .filter(|layer| layer.name.starts_with("coverage_"))
+    {
+        let raster = &layer.values;
+        let mut data = Vec::with_capacity(raster.width() * raster.height());
+        for y in 0..raster.height() {
+            for x in 0..raster.width() {
+                let value = raster.get(x, y).unwrap_or(0.0).clamp(0.0, 1.0);
+                data.push((value * 255.0).round() as u8);
+            }
+        }
+        let path = directory.as_ref().join(format!("{}.png", layer.name));
+        write_png(
+            &path,
+            raster.size(),
+            png::ColorType::Grayscale,
+            png::BitDepth::Eight,
+            &data,
+        )?;
+        write_image_georeference(&path, map, raster.size(), "gray8", 1.0 / 255.0, 0.0, None)?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SplatMapOutput {
+    image: String,
+    /// Class names in the red, green, blue and alpha channels.
+    classes: Vec<String>,
+}
+
+/// Writes the textures for the meshes' texture coordinates into
+/// `directory`: `colour.png` with the class colours shaded by the
+/// hillshade, and `splat_{n}.png` splat maps with the coverage of four
+/// classes each, listed in `splat.json`. All have georeference files.
+pub fn write_terrain_textures<P: AsRef<Path>>(
+    directory: P,
+    map: &Map,
+    config: &TextureConfig,
+    analysis: &AnalysisConfig,
+) -> Result<(), Box<dyn Error>> {
+    println!("Baking terrain textures");
+    fs::create_dir_all(&directory)?;
+    let hillshade = analyse(
+        &map.height_raster_in_metres(),
+        map.tile_size_in_metres(),
+        analysis,
+    )
+    .hillshade;
+    let colours: HashMap<u16, [u8; 3]> = map
+        .classes
+        .iter()
+        .map(|class| (class.id, class.colour))
+        .collect();
+    let colour = bake_colour(&map.class_raster(), &colours, &hillshade, config);
+    write_texture(
+        directory.as_ref().join("colour.png"),
+        map,
+        &colour,
+        png::ColorType::Rgb,
+        "rgb8",
+    )?;
+
+    let layers: Vec<(&String, &Raster)> = map
+        .classes
+        .iter()
+        .filter_map(|class| {
+            let layer = map.layers.get(&coverage_layer_name(&class.name))?;
+            Some((&class.name, &layer.values))
+        })
+        .collect();
+    let mut splat_maps = Vec::new();
+    for (n, group) in layers.chunks(4).enumerate() {
+        let image = format!("splat_{n}.png");
+        let coverage: Vec<&Raster> = group.iter().map(|(_, raster)| *raster).collect();
+        write_texture(
+            directory.as_ref().join(&image),
+            map,
+            &bake_splat(&coverage, config),
+            png::ColorType::Rgba,
+            "rgba8",
+        )?;
+        splat_maps.push(SplatMapOutput {
+            image,
+            classes: group.iter().map(|(name, _)| (*name).clone()).collect(),
+        });
+    }
+    let f = BufWriter::new(File::create(directory.as_ref().join("splat.json"))?);
+    serde_json::to_writer(f, &splat_maps)?;
+    Ok(())
+}
+
+fn write_texture<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    texture: &Texture,
+    color: png::ColorType,
+    encoding: &str,
+) -> Result<(), Box<dyn Error>> {
+    let size = (texture.width, texture.height);
+    write_png(&path, size, color, png::BitDepth::Eight, &texture.data)?;
+    write_image_georeference(&path, map, size, encoding, 1.0 / 255.0, 0.0, None)
+}
+
+fn write_gray16_png<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    raster: &Raster,
+    config: &HeightImageConfig,
+) -> Result<(), Box<dyn Error>> {
+    write_png(
+        &path,
+        raster.size(),
+        png::ColorType::Grayscale,
+        png::BitDepth::Sixteen,
+        &encode_gray16(raster, config),
+    )?;
+    write_image_georeference(
+        &path,
+        map,
+        raster.size(),
+        "gray16",
+        config.scale,
+        config.offset,
+        Some(HEIGHT_IMAGE_NODATA),
+    )
+}
+
+/// Writes the height map as a Mapbox Terrain-RGB PNG, north up, with the
+/// georeference in a JSON file next to it.
+pub fn write_terrain_rgb_png<P: AsRef<Path>>(path: P, map: &Map) -> Result<(), Box<dyn Error>> {
+    let raster = map.height_raster_in_metres();
+    write_png(
+        &path,
+        raster.size(),
+        png::ColorType::Rgb,
+        png::BitDepth::Eight,
+        &encode_terrain_rgb(&raster),
+    )?;
+    write_image_georeference(
+        &path,
+        map,
+        raster.size(),
+        "terrain-rgb",
+        TERRAIN_RGB_SCALE,
+        TERRAIN_RGB_OFFSET,
+        Some(HEIGHT_IMAGE_NODATA),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TerrainLayersOutput {
+    slope: Vec<Vec<Option<f64>>>,
+    aspect: Vec<Vec<Option<f64>>>,
+    hillshade: Vec<Vec<Option<f64>>>,
+    plan_curvature: Vec<Vec<Option<f64>>>,
+    profile_curvature: Vec<Vec<Option<f64>>>,
+}
+
+fn raster_rows(raster: &Raster) -> Vec<Vec<Option<f64>>> {
+    (0..raster.height())
+        .map(|y| (0..raster.width()).map(|x| raster.get(x, y)).collect())
+        .collect()
+}
+
+/// Writes slope, aspect, hillshade and curvature of the height map into
+/// `directory`, as 16-bit grayscale PNGs with georeference files and as
+/// rows like the height map in `terrain_layers.json`.
+pub fn write_terrain_analysis<P: AsRef<Path>>(
+    directory: P,
+    map: &Map,
+    config: &AnalysisConfig,
+) -> Result<(), Box<dyn Error>> {
+    println!("Writing terrain analysis");
+    fs::create_dir_all(&directory)?;
+    let heights = map.height_raster_in_metres();
+    let derivatives = analyse(&heights, map.tile_size_in_metres(), config);
+
+    let curvature = config.curvature_range;
+    let layers = [
+        ("slope", &derivatives.slope, 0.0, 90.0),
+        ("aspect", &derivatives.aspect, 0.0, 360.0),
+        ("hillshade", &derivatives.hillshade, 0.0, 1.0),
+        (
+            "plan_curvature",
+            &derivatives.plan_curvature,
+            -curvature,
+            curvature,
+        ),
+        (
+            "profile_curvature",
+            &derivatives.profile_curvature,
+            -curvature,
+            curvature,
+        ),
+    ];
+    for (name, raster, min, max) in layers {
+        let image = HeightImageConfig::spanning(min, max);
+        let path = directory.as_ref().join(format!("{}.png", name));
+        write_png(
+            &path,
+            raster.size(),
+            png::ColorType::Grayscale,
+            png::BitDepth::Sixteen,
+            &encode_gray16(raster, &image),
+        )?;
+        write_image_georeference(
+            &path,
+            map,
+            raster.size(),
+            "gray16",
+            image.scale,
+            image.offset,
+            Some(HEIGHT_IMAGE_NODATA),
+        )?;
+    }
+
+    let output = TerrainLayersOutput {
+        slope: raster_rows(&derivatives.slope),
+        aspect: raster_rows(&derivatives.aspect),
+        hillshade: raster_rows(&derivatives.hillshade),
+        plan_curvature: raster_rows(&derivatives.plan_curvature),
+        profile_curvature: raster_rows(&derivatives.profile_curvature),
+    };
+    let f = BufWriter::new(File::create(
+        directory.as_ref().join("terrain_layers.json"),
+    )?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+/// Writes a viewshed as an 8-bit grayscale PNG with alpha, white where
+/// the tile is visible, black where it is hidden and transparent out of
+/// range, with the georeference in a JSON file next to it.
+pub fn write_viewshed<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    viewshed: &Raster,
+) -> Result<(), Box<dyn Error>> {
+    let mut data = Vec::with_capacity(viewshed.width() * viewshed.height() * 2);
+    for y in 0..viewshed.height() {
+        for x in 0..viewshed.width() {
+            match viewshed.get(x, y) {
+                Some(visible) => data.extend([(visible * 255.0).round() as u8, 255]),
+                None => data.extend([0, 0]),
+            }
+        }
+    }
+    write_png(
+        &path,
+        viewshed.size(),
+        png::ColorType::GrayscaleAlpha,
+        png::BitDepth::Eight,
+        &data,
+    )?;
+    write_image_georeference(&path, map, viewshed.size(), "gray8", 1.0 / 255.0, 0.0, None)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FeatureCollectionOutput {
+    #[serde(rename = "type")]
+    kind: String,
+    features: Vec<FeatureOutput>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FeatureOutput {
+    #[serde(rename = "type")]
+    kind: String,
+    geometry: GeometryOutput,
+    properties: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum GeometryOutput {
+    LineString { coordinates: Vec<(f64, f64)> },
+    Point { coordinates: (f64, f64) },
+}
+
+impl FeatureCollectionOutput {
+    fn new(features: Vec<FeatureOutput>) -> FeatureCollectionOutput {
+        FeatureCollectionOutput {
+            kind: String::from("FeatureCollection"),
+            features,
+        }
+    }
+}
+
+impl FeatureOutput {
+    fn new(geometry: GeometryOutput, properties: serde_json::Value) -> FeatureOutput {
+        FeatureOutput {
+            kind: String::from("Feature"),
+            geometry,
+            properties: match properties {
+                serde_json::Value::Object(properties) => properties,
+                _ => serde_json::Map::new(),
+            },
+        }
+    }
+}
+
+/// Writes the stream lines of the map as a GeoJSON feature collection.
+pub fn write_streams<P: AsRef<Path>>(path: P, map: &Map) -> Result<(), Box<dyn Error>> {
+    let output = FeatureCollectionOutput::new(
+        map.streams
+            .iter()
+            .map(|line| {
+                FeatureOutput::new(
+                    GeometryOutput::LineString {
+                        coordinates: line.iter().map(|p| (p.x, p.y)).collect(),
+                    },
+                    serde_json::json!({ "class": "stream" }),
+                )
+            })
+            .collect(),
+    );
+    let f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+/// Writes the roads of the map with their attributes as a GeoJSON feature
+/// collection.
+pub fn write_roads<P: AsRef<Path>>(path: P, map: &Map) -> Result<(), Box<dyn Error>> {
+    let output = FeatureCollectionOutput::new(
+        map.roads
+            .iter()
+            .map(|road| {
+                FeatureOutput::new(
+                    GeometryOutput::LineString {
+                        coordinates: road.points.iter().map(|p| (p.x, p.y)).collect(),
+                    },
+                    serde_json::json!({
+                        "class": road.class,
+                        "code": road.code,
+                        "width": road.width,
+                        "surface": road.surface,
+                        "direction": road.direction,
+                        "level": road.level,
+                        "name": road.name,
+                    }),
+                )
+            })
+            .collect(),
+    );
+    let f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RoadNodeOutput {
+    /// Longitude and latitude.
+    coordinates: (f64, f64),
+    tile: (f64, f64),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RoadEdgeOutput {
+    from: usize,
+    to: usize,
+    road: usize,
+    length: f64,
+    one_way: bool,
+    coordinates: Vec<(f64, f64)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RoadGraphOutput {
+    nodes: Vec<RoadNodeOutput>,
+    edges: Vec<RoadEdgeOutput>,
+}
+
+/// Writes the road graph as lists of nodes and edges, edges referring to
+/// nodes and roads by their index.
+pub fn write_road_graph<P: AsRef<Path>>(path: P, map: &Map) -> Result<(), Box<dyn Error>> {
+    let graph = &map.road_graph;
+    let output = RoadGraphOutput {
+        nodes: graph
+            .nodes
+            .iter()
+            .map(|node| RoadNodeOutput {
+                coordinates: (node.point.x, node.point.y),
+                tile: map.to_tile_coords(node.point.x, node.point.y),
+            })
+            .collect(),
+        edges: graph
+            .edges
+            .iter()
+            .map(|edge| RoadEdgeOutput {
+                from: edge.from,
+                to: edge.to,
+                road: edge.road,
+                length: edge.length,
+                one_way: edge.one_way,
+                coordinates: edge.points.iter().map(|p| (p.x, p.y)).collect(),
+            })
+            .collect(),
+    };
+    let f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+/// Writes contour lines with their elevation and label points with the
+/// elevation and text angle as a GeoJSON feature collection.
+pub fn write_contours<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    config: &ContourConfig,
+) -> Result<(), Box<dyn Error>> {
+    println!("Writing contours");
+    let (lines, labels) = map.contours(config);
+    let lines = lines.iter().map(|line| {
+        FeatureOutput::new(
+            GeometryOutput::LineString {
+                coordinates: line.points.iter().map(|p| (p.x, p.y)).collect(),
+            },
+            serde_json::json!({
+                "class": "contour",
+                "elevation": line.elevation,
+                "closed": line.closed,
+            }),
+        )
+    });
+    let labels = labels.iter().map(|label| {
+        FeatureOutput::new(
+            GeometryOutput::Point {
+                coordinates: label.position,
+            },
+            serde_json::json!({
+                "class": "contour_label",
+                "elevation": label.elevation,
+                "angle": label.angle,
+            }),
+        )
+    });
+    let output = FeatureCollectionOutput::new(lines.chain(labels).collect());
+    let f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(f, &output)?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LayerOutput {
+    tilejson: String,
+    name: String,
+    version: String,
+    format: String,
+    scheme: String,
+    tiles: Vec<String>,
+    projection: String,
+    bounds: [f64; 4],
+    minzoom: u32,
+    maxzoom: u32,
+    extensions: Vec<String>,
+    available: Vec<Vec<TileRange>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TileRange {
+    start_x: u32,
+    start_y: u32,
+    end_x: u32,
+    end_y: u32,
+}
+
+/// Writes the height map as quantized-mesh terrain, one tile per
+/// `{level}/{x}/{y}.terrain` and the tile availability in `layer.json`.
+pub fn write_quantized_mesh<P: AsRef<Path>>(
+    path: P,
+    map: &Map,
+    config: &QuantizedMeshConfig,
+) -> Result<(), Box<dyn Error>> {
+    write_quantized_mesh_tiles(path, map_height(map), &map.extent(), config)
+}
+
+/// Heights in metres at global coordinates, interpolated between the tiles.
+/// Tile `(x, y)` is sampled at tile coordinates `(x, y)`, the middle of its
+/// raster cell.
+fn map_height(map: &Map) -> impl Fn(f64, f64) -> Option<f64> + '_ {
+    let raster = map.height_raster();
+    let resampler = Resampler::default();
+    let extent = map.extent();
+    move |lon: f64, lat: f64| {
+        if !extent.contains(&Point { x: lon, y: lat }) {
+            return None;
+        }
+        let (x, y) = map.to_tile_coords(lon, lat);
+        resampler
+            .sample(&raster, x, y, (1.0, 1.0))
+            .map(|h| h / TILE_HEIGHT_PER_METRE)
+    }
+}
+
+/// Writes quantized-mesh tiles over `extent` for heights in metres given by
+/// `height(longitude, latitude)`.
+pub fn write_quantized_mesh_tiles<P: AsRef<Path>, F: Fn(f64, f64) -> Option<f64>>(
+    path: P,
+    height: F,
+    extent: &Bbox,
+    config: &QuantizedMeshConfig,
+) -> Result<(), Box<dyn Error>> {
+    println!("Writing quantized mesh tiles");
+    let ranges: Vec<_> = (0..=config.max_level)
+        .map(|level| TileId::range(extent, level))
+        .collect();
+    let tile_count: u32 = ranges
+        .iter()
+        .map(|((x0, x1), (y0, y1))| (x1 - x0 + 1) * (y1 - y0 + 1))
+        .sum();
+
+    let mut written = 0;
+    for (level, ((x0, x1), (y0, y1))) in ranges.iter().enumerate() {
+        for x in *x0..=*x1 {
+            let directory = path.as_ref().join(level.to_string()).join(x.to_string());
+            fs::create_dir_all(&directory)?;
+            for y in *y0..=*y1 {
+                let tile = TileId {
+                    level: level as u32,
+                    x,
+                    y,
+                };
+                let mesh = QuantizedMesh::build(tile, &height, config);
+                write(directory.join(format!("{}.terrain", y)), mesh.encode())?;
+                written += 1;
+                ui::print_progress_bar(written as f64 / tile_count as f64);
+            }
+        }
+    }
+    ui::print_progress_bar_completed();
+
+    let output = LayerOutput {
+        tilejson: String::from("2.1.0"),
+        name: String::from("map-tool"),
+        version: String::from("1.0.0"),
+        format: String::from("quantized-mesh-1.0"),
+        scheme: String::from("tms"),
+        tiles: vec![String::from("{z}/{x}/{y}.terrain?v={version}")],
+        projection: String::from("EPSG:4326"),
+        bounds: [extent.a.x, extent.a.y, extent.b.x, extent.b.y],
+        minzoom: 0,
+        maxzoom: config.max_level,
+        extensions: vec![String::from("octvertexnormals")],
+        available: ranges
+            .iter()
+            .map(|((x0, x1), (y0, y1))| {
+                vec![TileRange {
+                    start_x: *x0,
+                    start_y: *y0,
+                    end_x: *x1,
+                    end_y: *y1,
+                }]
+            })
+            .collect(),
+    };
+    let f = BufWriter::new(File::create(path.as_ref().join("layer.json"))?);
     serde_json::to_writer(f, &output)?;
     Ok(())
 }
@@ -371,161 +1335,48 @@ fn normal_for_point(
     y: usize,
     average: f64,
 ) -> Vec3 {
-    let x = x as i64;
-    let y = y as i64;
-    let scale = scale as i64;
-    let normals = vec![
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64 - distance_diff,
-                y: y as f64,
-                z: get_height_or_average(height_map, x - scale, y, average),
-            },
-            &Vec3 {
-                x: x as f64 - distance_diff,
-                y: y as f64 - distance_diff,
-                z: get_height_or_average(height_map, x - scale, y - scale, average),
-            },
-        ),
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64 - distance_diff,
-                y: y as f64 - distance_diff,
-                z: get_height_or_average(height_map, x - scale, y - scale, average),
-            },
-            &Vec3 {
-                x: x as f64,
-                y: y as f64 - distance_diff,
-                z: get_height_or_average(height_map, x, y - scale, average),
-            },
-        ),
-        //
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64,
-                y: y as f64 - distance_diff,
-                z: get_height_or_average(height_map, x, y - scale, average),
-            },
-            &Vec3 {
-                x: x as f64 + distance_diff,
-                y: y as f64 - distance_diff,
-                z: get_height_or_average(height_map, x + scale, y - scale, average),
-            },
-        ),
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64 + distance_diff,
-                y: y as f64 - distance_diff,
-                z: get_height_or_average(height_map, x + scale, y - scale, average),
-            },
-            &Vec3 {
-                x: x as f64 + distance_diff,
-                y: y as f64,
-                z: get_height_or_average(height_map, x + scale, y, average),
-            },
-        ),
-        //
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64 + distance_diff,
-                y: y as f64,
-                z: get_height_or_average(height_map, x + scale, y, average),
-            },
-            &Vec3 {
-                x: x as f64 + distance_diff,
-                y: y as f64 + distance_diff,
-                z: get_height_or_average(height_map, x + scale, y + scale, average),
-            },
-        ),
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64 + distance_diff,
-                y: y as f64 + distance_diff,
-                z: get_height_or_average(height_map, x + scale, y + scale, average),
-            },
-            &Vec3 {
-                x: x as f64,
-                y: y as f64 + distance_diff,
-                z: get_height_or_average(height_map, x, y + scale, average),
-            },
-        ),
-        //
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64,
-                y: y as f64 + distance_diff,
-                z: get_height_or_average(height_map, x, y + scale, average),
-            },
-            &Vec3 {
-                x: x as f64 - distance_diff,
-                y: y as f64 + distance_diff,
-                z: get_height_or_average(height_map, x - scale, y + scale, average),
-            },
-        ),
-        surface_normal_from_vectors(
-            &Vec3 {
-                x: x as f64,
-                y: y as f64,
-                z: get_height_or_average(height_map, x, y, average),
-            },
-            &Vec3 {
-                x: x as f64 - distance_diff,
-                y: y as f64 + distance_diff,
-                z: get_height_or_average(height_map, x - scale, y + scale, average),
-            },
-            &Vec3 {
-                x: x as f64 - distance_diff,
-                y: y as f64,
-                z: get_height_or_average(height_map, x - scale, y, average),
-            },
-        ),
+    let (x, y, scale) = (x as i64, y as i64, scale as i64);
+    normal_from_neighbours(
+        |dx, dy| get_height_or_average(height_map, x + dx * scale, y + dy * scale, average),
+        (distance_diff, distance_diff),
+    )
+}
+
+/// Average of the normals of the eight triangles fanning around a point.
+/// `height(dx, dy)` gives the height of the neighbour `dx` steps along x and
+/// `dy` along y, and `spacing` the horizontal length of one step.
+pub fn normal_from_neighbours<F: Fn(i64, i64) -> f64>(height: F, spacing: (f64, f64)) -> Vec3 {
+    let ring = [
+        (-1, 0),
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (1, 0),
+        (1, 1),
+        (0, 1),
+        (-1, 1),
     ];
+    let point = |(dx, dy): (i64, i64)| Vec3 {
+        x: dx as f64 * spacing.0,
+        y: dy as f64 * spacing.1,
+        z: height(dx, dy),
+    };
 
+    let origin = point((0, 0));
     let mut sum = Vec3 {
         x: 0.0,
         y: 0.0,
         z: 0.0,
     };
-    for n in normals.iter() {
-        sum = &sum + n;
+    for i in 0..ring.len() {
+        let n = surface_normal_from_vectors(
+            &origin,
+            &point(ring[i]),
+            &point(ring[(i + 1) % ring.len()]),
+        );
+        sum = &sum + &n;
     }
-    sum = &sum * (1.0 / normals.len() as f64);
-    sum
+    &sum * (1.0 / ring.len() as f64)
 }
 
 pub fn write_surface_normals<P: AsRef<Path>>(
@@ -675,3 +1526,53 @@ pub async fn download_collection(
     // rakennus
     Ok(responses)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MapTileClass;
+
+    #[test]
+    fn quantized_mesh_heights_line_up_with_the_tiles() {
+        let mut map = Map::new(
+            vec![vec![0.0; 2]; 2],
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            &Config::default(),
+        );
+        // A plane rising to the east and the south, so a shift along
+        // either axis changes the heights.
+        for (y, row) in map.tiles.iter_mut().enumerate() {
+            for (x, tile) in row.iter_mut().enumerate() {
+                tile.height = ((1 + x + 1000 * y) as f64 * TILE_HEIGHT_PER_METRE) as i64;
+            }
+        }
+        let extent = map.extent();
+        let size = map.tiles.len() as f64;
+        let height = map_height(&map);
+        for (x, y) in [(1, 1), (100, 7), (300, 450), (510, 510)] {
+            let lon = extent.a.x + (extent.b.x - extent.a.x) * x as f64 / size;
+            let lat = extent.b.y - (extent.b.y - extent.a.y) * y as f64 / size;
+            let tile = map.to_tile_coords(lon, lat);
+            assert!((tile.0 - x as f64).abs() < 1e-6 && (tile.1 - y as f64).abs() < 1e-6);
+            let expected = map.tiles[y][x].height as f64 / TILE_HEIGHT_PER_METRE;
+            assert!((height(lon, lat).unwrap() - expected).abs() < 1e-3);
+        }
+        assert!(height(extent.a.x - 0.001, extent.b.y).is_none());
+    }
+
+    #[test]
+    fn land_cover_ids_stay_apart_from_nodata() {
+        let ids = land_cover_encoding();
+        let empty = ids.encode(Some(MapTileClass::EMPTY.0 as f64));
+        let water = ids.encode(Some(MapTileClass::WATER.0 as f64));
+        assert_ne!(empty as u32, HEIGHT_IMAGE_NODATA);
+        assert_ne!(empty, water);
+        assert_eq!(ids.decode(empty), Some(0.0));
+        assert_eq!(ids.decode(water), Some(1.0));
+        assert_eq!(ids.encode(None) as u32, HEIGHT_IMAGE_NODATA);
+    }
+}
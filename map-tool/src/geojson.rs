@@ -17,6 +17,13 @@ pub struct GeoJSONProperties {
     pub korkeusarvo: Option<i64>,
     pub syvyysarvo: Option<i64>,
     pub pohjankorkeus: Option<i64>,
+    pub keskikorkeus: Option<i64>,
+    pub kohdeluokka: Option<i64>,
+    pub paallyste: Option<i64>,
+    pub yksisuuntaisuus: Option<i64>,
+    pub tasosijainti: Option<i64>,
+    pub kerrosluku: Option<i64>,
+    pub nimi_suomi: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -1,2 +1,8 @@
 pub mod bbox;
+pub mod boolean;
+pub mod delaunay;
 pub mod point;
+pub mod predicates;
+pub mod projection;
+pub mod rtree;
+pub mod simplify;
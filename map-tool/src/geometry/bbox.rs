@@ -1,4 +1,4 @@
-use super::point::Point;
+use super::point::{Point, Polygon};
 
 #[derive(Debug, Clone)]
 pub struct Bbox {
@@ -7,6 +7,61 @@ pub struct Bbox {
 }
 
 impl Bbox {
+    /// A box containing nothing, the identity for `union`.
+    pub fn empty() -> Bbox {
+        Bbox {
+            a: Point {
+                x: f64::INFINITY,
+                y: f64::INFINITY,
+            },
+            b: Point {
+                x: -f64::INFINITY,
+                y: -f64::INFINITY,
+            },
+        }
+    }
+
+    pub fn from_point(point: &Point) -> Bbox {
+        Bbox {
+            a: point.clone(),
+            b: point.clone(),
+        }
+    }
+
+    pub fn union(self: &Bbox, other: &Bbox) -> Bbox {
+        Bbox {
+            a: Point {
+                x: self.a.x.min(other.a.x),
+                y: self.a.y.min(other.a.y),
+            },
+            b: Point {
+                x: self.b.x.max(other.b.x),
+                y: self.b.y.max(other.b.y),
+            },
+        }
+    }
+
+    pub fn area(self: &Bbox) -> f64 {
+        if self.a.x > self.b.x || self.a.y > self.b.y {
+            return 0.0;
+        }
+        (self.b.x - self.a.x) * (self.b.y - self.a.y)
+    }
+
+    pub fn center(self: &Bbox) -> Point {
+        Point {
+            x: (self.a.x + self.b.x) / 2.0,
+            y: (self.a.y + self.b.y) / 2.0,
+        }
+    }
+
+    pub fn contains_bbox(self: &Bbox, other: &Bbox) -> bool {
+        self.a.x <= other.a.x
+            && other.b.x <= self.b.x
+            && self.a.y <= other.a.y
+            && other.b.y <= self.b.y
+    }
+
     pub fn contains(self: &Bbox, point: &Point) -> bool {
         self.a.x <= point.x && point.x <= self.b.x && self.a.y <= point.y && point.y <= self.b.y
     }
@@ -18,6 +73,95 @@ impl Bbox {
             || self.a.y > other.b.y)
     }
 
+    /// Squared distance from the point to the closest point of the box, zero
+    /// when the point is inside.
+    pub fn distance_squared_to_point(self: &Bbox, point: &Point) -> f64 {
+        let dx = (self.a.x - point.x).max(point.x - self.b.x).max(0.0);
+        let dy = (self.a.y - point.y).max(point.y - self.b.y).max(0.0);
+        dx * dx + dy * dy
+    }
+
+    /// Parts of a line inside the box, split where the line leaves it.
+    pub fn clip_line(self: &Bbox, points: &[Point]) -> Vec<Vec<Point>> {
+        let mut parts = Vec::new();
+        let mut part: Vec<Point> = Vec::new();
+        for segment in points.windows(2) {
+            let (a, b) = (&segment[0], &segment[1]);
+            let Some((t0, t1)) = self.clip_segment(a, b) else {
+                continue;
+            };
+            let at = |t: f64| Point {
+                x: a.x + (b.x - a.x) * t,
+                y: a.y + (b.y - a.y) * t,
+            };
+            if part.is_empty() || t0 > 0.0 {
+                if part.len() >= 2 {
+                    parts.push(std::mem::take(&mut part));
+                }
+                part = vec![at(t0)];
+            }
+            part.push(at(t1));
+            if t1 < 1.0 {
+                if part.len() >= 2 {
+                    parts.push(std::mem::take(&mut part));
+                }
+                part.clear();
+            }
+        }
+        if part.len() >= 2 {
+            parts.push(part);
+        }
+        parts
+    }
+
+    /// Start and end of the part of the segment from `a` to `b` inside the
+    /// box as fractions of the segment, after Liang and Barsky.
+    fn clip_segment(self: &Bbox, a: &Point, b: &Point) -> Option<(f64, f64)> {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let (mut t0, mut t1) = (0.0, 1.0);
+        for (p, q) in [
+            (-dx, a.x - self.a.x),
+            (dx, self.b.x - a.x),
+            (-dy, a.y - self.a.y),
+            (dy, self.b.y - a.y),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+                continue;
+            }
+            let t = q / p;
+            if p < 0.0 {
+                t0 = f64::max(t0, t);
+            } else {
+                t1 = f64::min(t1, t);
+            }
+        }
+        (t0 < t1).then_some((t0, t1))
+    }
+
+    pub fn to_polygon(self: &Bbox) -> Polygon {
+        Polygon::new(vec![
+            Point {
+                x: self.a.x,
+                y: self.a.y,
+            },
+            Point {
+                x: self.b.x,
+                y: self.a.y,
+            },
+            Point {
+                x: self.b.x,
+                y: self.b.y,
+            },
+            Point {
+                x: self.a.x,
+                y: self.b.y,
+            },
+        ])
+    }
+
     pub fn split(self: &Bbox) -> (Bbox, Bbox, Bbox, Bbox) {
         let half_width = (self.b.x - self.a.x) / 2.0;
         let half_height = (self.b.y - self.a.y) / 2.0;
@@ -227,3 +371,53 @@ impl<T: HasCoords + Clone> BboxTree<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(points: &[(f64, f64)]) -> Vec<Point> {
+        points.iter().map(|&(x, y)| Point { x, y }).collect()
+    }
+
+    fn coords(parts: &[Vec<Point>]) -> Vec<Vec<(f64, f64)>> {
+        parts
+            .iter()
+            .map(|part| part.iter().map(|p| (p.x, p.y)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn lines_are_split_where_they_leave_the_box() {
+        let bbox = Bbox {
+            a: Point { x: 0.0, y: 0.0 },
+            b: Point { x: 10.0, y: 10.0 },
+        };
+        // In from the west, out to the north, back in and out to the east.
+        let parts = bbox.clip_line(&line(&[
+            (-5.0, 5.0),
+            (5.0, 5.0),
+            (5.0, 15.0),
+            (8.0, 15.0),
+            (8.0, 5.0),
+            (20.0, 5.0),
+        ]));
+        assert_eq!(
+            coords(&parts),
+            vec![
+                vec![(0.0, 5.0), (5.0, 5.0), (5.0, 10.0)],
+                vec![(8.0, 10.0), (8.0, 5.0), (10.0, 5.0)],
+            ]
+        );
+
+        let inside = line(&[(1.0, 1.0), (2.0, 3.0), (4.0, 4.0)]);
+        assert_eq!(coords(&bbox.clip_line(&inside)), coords(&[inside]));
+        assert!(bbox
+            .clip_line(&line(&[(-1.0, -1.0), (-1.0, 20.0)]))
+            .is_empty());
+        // Touching a corner leaves nothing.
+        assert!(bbox
+            .clip_line(&line(&[(-1.0, 1.0), (1.0, -1.0)]))
+            .is_empty());
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::{HashMap, HashSet};
+
+use super::bbox::Bbox;
+use super::point::{signed_area, LineSegment, Point, PointLocation, Polygon, Vec2};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Intersection,
+    Union,
+    Difference,
+}
+
+impl Polygon {
+    pub fn intersection(&self, other: &Polygon) -> Vec<Polygon> {
+        overlay(self, other, Operation::Intersection)
+    }
+
+    pub fn union(&self, other: &Polygon) -> Vec<Polygon> {
+        overlay(self, other, Operation::Union)
+    }
+
+    pub fn difference(&self, other: &Polygon) -> Vec<Polygon> {
+        overlay(self, other, Operation::Difference)
+    }
+
+    pub fn clip_to_bbox(&self, bbox: &Bbox) -> Vec<Polygon> {
+        if bbox.contains(&self.bbox().a) && bbox.contains(&self.bbox().b) {
+            return vec![self.clone()];
+        }
+        if !bbox.overlaps(self.bbox()) {
+            return Vec::new();
+        }
+
+        self.intersection(&bbox.to_polygon())
+    }
+}
+
+/// Merges every group of overlapping or edge sharing polygons into one.
+pub fn union_all(polygons: Vec<Polygon>) -> Vec<Polygon> {
+    let mut merged: Vec<Polygon> = Vec::new();
+    for polygon in polygons {
+        let mut current = vec![polygon];
+        let mut i = 0;
+        while i < merged.len() {
+            if current.len() == 1 && merged[i].bbox().overlaps(current[0].bbox()) {
+                let result = merged[i].union(&current[0]);
+                if result.len() == 1 {
+                    merged.swap_remove(i);
+                    current = result;
+                    i = 0;
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        merged.append(&mut current);
+    }
+    merged
+}
+
+/// Boolean overlay of two polygons with holes.
+///
+/// Both boundaries are split at every point where they meet, each piece is
+/// classified against the other polygon and the pieces the operation keeps
+/// are linked back into rings. Rings are oriented so that the interior is on
+/// the left, which makes shared edges distinguishable by direction.
+pub fn overlay(subject: &Polygon, clip: &Polygon, operation: Operation) -> Vec<Polygon> {
+    let subject_edges = oriented_edges(subject);
+    let clip_edges = oriented_edges(clip);
+
+    let (subject_pieces, clip_pieces) = split_edges(&subject_edges, &clip_edges);
+
+    let clip_keys: HashSet<(Key, Key)> =
+        clip_pieces.iter().map(|(a, b)| (key(a), key(b))).collect();
+    let subject_keys: HashSet<(Key, Key)> = subject_pieces
+        .iter()
+        .map(|(a, b)| (key(a), key(b)))
+        .collect();
+
+    let mut selected: Vec<Edge> = Vec::new();
+    for (a, b) in subject_pieces.iter() {
+        let keep = match classify(a, b, clip, &clip_keys) {
+            EdgeClass::Inside => operation == Operation::Intersection,
+            EdgeClass::Outside => operation != Operation::Intersection,
+            EdgeClass::SharedSame => operation != Operation::Difference,
+            EdgeClass::SharedOpposite => operation == Operation::Difference,
+        };
+        if keep {
+            selected.push((a.clone(), b.clone()));
+        }
+    }
+    for (a, b) in clip_pieces.iter() {
+        // Shared pieces are taken from the subject only.
+        match (classify(a, b, subject, &subject_keys), operation) {
+            (EdgeClass::Inside, Operation::Intersection) => selected.push((a.clone(), b.clone())),
+            (EdgeClass::Outside, Operation::Union) => selected.push((a.clone(), b.clone())),
+            (EdgeClass::Inside, Operation::Difference) => selected.push((b.clone(), a.clone())),
+            _ => {}
+        }
+    }
+
+    assemble_polygons(link_rings(selected))
+}
+
+type Edge = (Point, Point);
+
+type Key = (u64, u64);
+
+fn key(point: &Point) -> Key {
+    // Adding zero folds -0.0 into 0.0 so both hash the same.
+    ((point.x + 0.0).to_bits(), (point.y + 0.0).to_bits())
+}
+
+enum EdgeClass {
+    Inside,
+    Outside,
+    SharedSame,
+    SharedOpposite,
+}
+
+fn classify(a: &Point, b: &Point, other: &Polygon, other_keys: &HashSet<(Key, Key)>) -> EdgeClass {
+    // Shared pieces have identical endpoints after splitting. Their midpoint
+    // is rarely exactly on the other boundary, so it can't be used for them.
+    if other_keys.contains(&(key(a), key(b))) {
+        return EdgeClass::SharedSame;
+    }
+    if other_keys.contains(&(key(b), key(a))) {
+        return EdgeClass::SharedOpposite;
+    }
+
+    let midpoint = Point {
+        x: (a.x + b.x) / 2.0,
+        y: (a.y + b.y) / 2.0,
+    };
+    match other.locate_point(&midpoint) {
+        PointLocation::Inside => EdgeClass::Inside,
+        PointLocation::Outside => EdgeClass::Outside,
+        // On the other boundary without matching endpoints, as inexact
+        // crossing points can leave it. The interior is on the left of the
+        // piece, so the other polygon just left of it tells which way the
+        // shared boundary runs.
+        PointLocation::Boundary => {
+            let length = a.distance(b);
+            let offset = length * 1e-6;
+            let left = Point {
+                x: midpoint.x - (b.y - a.y) / length * offset,
+                y: midpoint.y + (b.x - a.x) / length * offset,
+            };
+            if other.locate_point(&left) == PointLocation::Inside {
+                EdgeClass::SharedSame
+            } else {
+                EdgeClass::SharedOpposite
+            }
+        }
+    }
+}
+
+/// Closed rings without repeated vertices, exterior counterclockwise and
+/// holes clockwise.
+fn normalized_ring(ring: &[Point], counterclockwise: bool) -> Vec<Point> {
+    let mut points: Vec<Point> = Vec::with_capacity(ring.len());
+    for p in ring {
+        if points.last().map(|l| key(l) != key(p)).unwrap_or(true) {
+            points.push(p.clone());
+        }
+    }
+    while points.len() > 1 && key(&points[0]) == key(points.last().unwrap()) {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return Vec::new();
+    }
+    if (signed_area(&points) > 0.0) != counterclockwise {
+        points.reverse();
+    }
+    points
+}
+
+fn ring_edges(ring: &[Point]) -> Vec<Edge> {
+    (0..ring.len())
+        .map(|i| (ring[i].clone(), ring[(i + 1) % ring.len()].clone()))
+        .collect()
+}
+
+fn oriented_edges(polygon: &Polygon) -> Vec<Edge> {
+    let mut edges = ring_edges(&normalized_ring(polygon.exterior(), true));
+    for hole in polygon.holes() {
+        edges.append(&mut ring_edges(&normalized_ring(hole.exterior(), false)));
+    }
+    edges
+}
+
+/// Splits both edge sets at their mutual intersections. Crossing points are
+/// computed once and shared, so the pieces of both sets meet exactly.
+fn split_edges(first: &[Edge], second: &[Edge]) -> (Vec<Edge>, Vec<Edge>) {
+    let mut first_splits: Vec<Vec<Point>> = vec![Vec::new(); first.len()];
+    let mut second_splits: Vec<Vec<Point>> = vec![Vec::new(); second.len()];
+    let second_segments: Vec<LineSegment> = second
+        .iter()
+        .map(|(a, b)| LineSegment {
+            a: a.clone(),
+            b: b.clone(),
+        })
+        .collect();
+    let second_boxes: Vec<Bbox> = second_segments.iter().map(|s| s.bbox()).collect();
+
+    for (i, (p1, p2)) in first.iter().enumerate() {
+        let first_segment = LineSegment {
+            a: p1.clone(),
+            b: p2.clone(),
+        };
+        let first_box = first_segment.bbox();
+        for (j, second_segment) in second_segments.iter().enumerate() {
+            if !first_box.overlaps(&second_boxes[j]) {
+                continue;
+            }
+
+            let (q1, q2) = (&second_segment.a, &second_segment.b);
+            if first_segment.crosses_segment(second_segment) {
+                let point = crossing_point(p1, p2, q1, q2);
+                first_splits[i].push(point.clone());
+                second_splits[j].push(point);
+                continue;
+            }
+
+            for q in [q1, q2] {
+                if first_segment.contains_point(q) {
+                    first_splits[i].push(q.clone());
+                }
+            }
+            for p in [p1, p2] {
+                if second_segment.contains_point(p) {
+                    second_splits[j].push(p.clone());
+                }
+            }
+        }
+    }
+
+    (
+        apply_splits(first, first_splits),
+        apply_splits(second, second_splits),
+    )
+}
+
+fn crossing_point(p1: &Point, p2: &Point, q1: &Point, q2: &Point) -> Point {
+    let r = p2 - p1;
+    let s = q2 - q1;
+    let qp = q1 - p1;
+    let denominator = r.x * s.y - r.y * s.x;
+    let t = ((qp.x * s.y - qp.y * s.x) / denominator).clamp(0.0, 1.0);
+    p1 + &(&r * t)
+}
+
+fn apply_splits(edges: &[Edge], splits: Vec<Vec<Point>>) -> Vec<Edge> {
+    let mut pieces = Vec::new();
+    for ((a, b), mut points) in edges.iter().zip(splits) {
+        points.sort_by(|p, q| {
+            a.distance_squared(p)
+                .partial_cmp(&a.distance_squared(q))
+                .unwrap()
+        });
+        let mut start = a.clone();
+        for point in points.into_iter().chain([b.clone()]) {
+            if key(&point) != key(&start) {
+                pieces.push((start, point.clone()));
+                start = point;
+            }
+        }
+    }
+    pieces
+}
+
+/// Walks the selected edges into closed rings. Where several edges leave the
+/// same vertex the walk takes the leftmost turn, which keeps rings that only
+/// touch at a vertex apart.
+fn link_rings(edges: Vec<Edge>) -> Vec<Vec<Point>> {
+    let mut outgoing: HashMap<Key, Vec<usize>> = HashMap::new();
+    for (i, (a, _)) in edges.iter().enumerate() {
+        outgoing.entry(key(a)).or_default().push(i);
+    }
+
+    let mut used = vec![false; edges.len()];
+    let mut rings = Vec::new();
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+
+        let start = key(&edges[first].0);
+        let mut ring = Vec::new();
+        let mut current = first;
+        let closed = loop {
+            used[current] = true;
+            let (a, b) = &edges[current];
+            ring.push(a.clone());
+            if key(b) == start {
+                break true;
+            }
+
+            let incoming = b - a;
+            let next = outgoing
+                .get(&key(b))
+                .into_iter()
+                .flatten()
+                .filter(|i| !used[**i])
+                .max_by(|i, j| {
+                    let turn_i = turn_angle(&incoming, &(&edges[**i].1 - &edges[**i].0));
+                    let turn_j = turn_angle(&incoming, &(&edges[**j].1 - &edges[**j].0));
+                    turn_i.partial_cmp(&turn_j).unwrap()
+                });
+            match next {
+                Some(i) => current = *i,
+                None => break false,
+            }
+        };
+
+        if closed && ring.len() >= 3 && signed_area(&ring) != 0.0 {
+            rings.push(ring);
+        }
+    }
+    rings
+}
+
+fn turn_angle(incoming: &Vec2, outgoing: &Vec2) -> f64 {
+    let cross = incoming.x * outgoing.y - incoming.y * outgoing.x;
+    cross.atan2(incoming.dot(outgoing))
+}
+
+/// Groups rings into polygons. Counterclockwise rings are exteriors and each
+/// clockwise ring becomes a hole of the smallest exterior containing it.
+fn assemble_polygons(rings: Vec<Vec<Point>>) -> Vec<Polygon> {
+    let (exteriors, holes): (Vec<_>, Vec<_>) =
+        rings.into_iter().partition(|r| signed_area(r) > 0.0);
+    let shells: Vec<Polygon> = exteriors.iter().map(|r| Polygon::new(r.clone())).collect();
+
+    let mut shell_holes: Vec<Vec<Vec<Point>>> = vec![Vec::new(); shells.len()];
+    for hole in holes {
+        let sample = hole_sample_point(&hole);
+        let owner = shells
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.locate_point(&sample) == PointLocation::Inside)
+            .min_by(|(_, a), (_, b)| a.area().partial_cmp(&b.area()).unwrap())
+            .map(|(i, _)| i);
+        if let Some(i) = owner {
+            shell_holes[i].push(hole);
+        }
+    }
+
+    exteriors
+        .into_iter()
+        .zip(shell_holes)
+        .map(|(exterior, holes)| Polygon::with_holes(exterior, holes))
+        .collect()
+}
+
+/// A point just inside the hole, next to the middle of its first edge. The
+/// interior of a clockwise ring is on the right of its edges.
+fn hole_sample_point(hole: &[Point]) -> Point {
+    let a = &hole[0];
+    let b = &hole[1];
+    let length = a.distance(b);
+    let offset = length * 1e-6;
+    Point {
+        x: (a.x + b.x) / 2.0 + (b.y - a.y) / length * offset,
+        y: (a.y + b.y) / 2.0 - (b.x - a.x) / length * offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x0: f64, y0: f64, x1: f64, y1: f64) -> Vec<Point> {
+        vec![
+            Point { x: x0, y: y0 },
+            Point { x: x1, y: y0 },
+            Point { x: x1, y: y1 },
+            Point { x: x0, y: y1 },
+        ]
+    }
+
+    fn square(x0: f64, y0: f64, x1: f64, y1: f64) -> Polygon {
+        Polygon::new(rect(x0, y0, x1, y1))
+    }
+
+    fn area(polygons: &[Polygon]) -> f64 {
+        polygons.iter().map(|p| p.area()).sum()
+    }
+
+    /// Checks the count and total area of each operation's result.
+    fn check(
+        a: &Polygon,
+        b: &Polygon,
+        union: (usize, f64),
+        intersection: (usize, f64),
+        difference: (usize, f64),
+    ) {
+        for (operation, (count, expected)) in [
+            (Operation::Union, union),
+            (Operation::Intersection, intersection),
+            (Operation::Difference, difference),
+        ] {
+            let result = overlay(a, b, operation);
+            assert_eq!(result.len(), count, "{operation:?}");
+            assert!(
+                (area(&result) - expected).abs() < 1e-9,
+                "{operation:?}: area {} instead of {expected}",
+                area(&result)
+            );
+        }
+    }
+
+    #[test]
+    fn overlapping_squares() {
+        let a = square(0.0, 0.0, 4.0, 4.0);
+        let b = square(2.0, 2.0, 6.0, 6.0);
+        check(&a, &b, (1, 28.0), (1, 4.0), (1, 12.0));
+    }
+
+    #[test]
+    fn squares_sharing_an_edge() {
+        let a = square(0.0, 0.0, 2.0, 2.0);
+        check(
+            &a,
+            &square(2.0, 0.0, 4.0, 2.0),
+            (1, 8.0),
+            (0, 0.0),
+            (1, 4.0),
+        );
+        check(
+            &a,
+            &square(2.0, 1.0, 4.0, 3.0),
+            (1, 8.0),
+            (0, 0.0),
+            (1, 4.0),
+        );
+        check(
+            &a,
+            &square(0.0, 0.0, 2.0, 2.0),
+            (1, 4.0),
+            (1, 4.0),
+            (0, 0.0),
+        );
+    }
+
+    #[test]
+    fn triangles_sharing_a_diagonal() {
+        let a = Polygon::new(vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 3.0, y: 3.0 },
+            Point { x: 0.0, y: 3.0 },
+        ]);
+        let b = Polygon::new(vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 3.0, y: 0.0 },
+            Point { x: 3.0, y: 3.0 },
+        ]);
+        check(&a, &b, (1, 9.0), (0, 0.0), (1, 4.5));
+        // Touches part of the diagonal from below.
+        let c = Polygon::new(vec![
+            Point { x: 1.0, y: 1.0 },
+            Point { x: 2.0, y: 1.0 },
+            Point { x: 2.0, y: 2.0 },
+        ]);
+        check(&a, &c, (1, 5.0), (0, 0.0), (1, 4.5));
+    }
+
+    #[test]
+    fn squares_touching_at_a_vertex() {
+        let a = square(0.0, 0.0, 2.0, 2.0);
+        let b = square(2.0, 2.0, 4.0, 4.0);
+        check(&a, &b, (2, 8.0), (0, 0.0), (1, 4.0));
+    }
+
+    #[test]
+    fn disjoint_squares() {
+        let a = square(0.0, 0.0, 2.0, 2.0);
+        let b = square(5.0, 0.0, 7.0, 2.0);
+        check(&a, &b, (2, 8.0), (0, 0.0), (1, 4.0));
+    }
+
+    #[test]
+    fn nested_squares() {
+        let outer = square(0.0, 0.0, 10.0, 10.0);
+        let inner = square(2.0, 2.0, 4.0, 4.0);
+        check(&outer, &inner, (1, 100.0), (1, 4.0), (1, 96.0));
+        check(&inner, &outer, (1, 100.0), (1, 4.0), (0, 0.0));
+        let difference = outer.difference(&inner);
+        assert_eq!(difference[0].holes().len(), 1);
+    }
+
+    #[test]
+    fn polygon_with_a_hole() {
+        let a = Polygon::with_holes(rect(0.0, 0.0, 10.0, 10.0), vec![rect(3.0, 3.0, 7.0, 7.0)]);
+        // Crosses the hole from side to side.
+        let b = square(2.0, 4.0, 8.0, 6.0);
+        check(&a, &b, (1, 92.0), (2, 4.0), (1, 80.0));
+        // Fills the hole exactly.
+        let plug = square(3.0, 3.0, 7.0, 7.0);
+        check(&a, &plug, (1, 100.0), (0, 0.0), (1, 84.0));
+        // Sits inside the hole.
+        let island = square(4.0, 4.0, 6.0, 6.0);
+        check(&a, &island, (2, 88.0), (0, 0.0), (1, 84.0));
+    }
+
+    #[test]
+    fn pieces_on_the_other_boundary_are_shared() {
+        let other = square(0.0, 0.0, 4.0, 4.0);
+        let a = Point { x: 1.0, y: 0.0 };
+        let b = Point { x: 3.0, y: 0.0 };
+        // No key matches, as if the other square's bottom edge had been
+        // split elsewhere.
+        let keys = HashSet::new();
+        assert!(matches!(
+            classify(&a, &b, &other, &keys),
+            EdgeClass::SharedSame
+        ));
+        assert!(matches!(
+            classify(&b, &a, &other, &keys),
+            EdgeClass::SharedOpposite
+        ));
+    }
+
+    #[test]
+    fn rotated_square() {
+        let a = square(0.0, 0.0, 1.0, 1.0);
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        let b = Polygon::new(vec![
+            Point {
+                x: 0.5,
+                y: 0.5 - half,
+            },
+            Point {
+                x: 0.5 + half,
+                y: 0.5,
+            },
+            Point {
+                x: 0.5,
+                y: 0.5 + half,
+            },
+            Point {
+                x: 0.5 - half,
+                y: 0.5,
+            },
+        ]);
+        // The overlap is a regular octagon.
+        let octagon = 2.0 * (std::f64::consts::SQRT_2 - 1.0);
+        check(&a, &b, (1, 2.0 - octagon), (1, octagon), (4, 1.0 - octagon));
+    }
+}
@@ -0,0 +1,292 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+
+use super::bbox::Bbox;
+use super::point::Point;
+use super::predicates::{in_circle, orient2d};
+
+/// Number of vertices of the enclosing triangle, which come before the input
+/// points.
+const SUPER_VERTICES: usize = 3;
+
+/// Delaunay triangulation of a point set, built by inserting the points one
+/// at a time (Bowyer-Watson). Points that repeat an earlier one are left
+/// out. Vertices are numbered like the input points.
+pub struct Triangulation {
+    points: Vec<Point>,
+    /// Counterclockwise vertices of each triangle.
+    triangles: Vec<[usize; 3]>,
+    /// Neighbour across the edge opposite each vertex.
+    neighbours: Vec<[Option<usize>; 3]>,
+    /// Triangle the next walk starts from.
+    last: Cell<usize>,
+}
+
+impl Triangulation {
+    pub fn new(points: &[Point]) -> Triangulation {
+        let bbox = points
+            .iter()
+            .fold(Bbox::empty(), |bbox, p| bbox.union(&Bbox::from_point(p)));
+        let center = if points.is_empty() {
+            Point { x: 0.0, y: 0.0 }
+        } else {
+            bbox.center()
+        };
+        let size = if points.is_empty() {
+            1.0
+        } else {
+            (bbox.b.x - bbox.a.x).max(bbox.b.y - bbox.a.y).max(1.0)
+        };
+
+        let mut vertices = vec![
+            Point {
+                x: center.x - 100.0 * size,
+                y: center.y - 100.0 * size,
+            },
+            Point {
+                x: center.x + 100.0 * size,
+                y: center.y - 100.0 * size,
+            },
+            Point {
+                x: center.x,
+                y: center.y + 100.0 * size,
+            },
+        ];
+        vertices.extend(points.iter().cloned());
+
+        let mut triangulation = Triangulation {
+            points: vertices,
+            triangles: vec![[0, 1, 2]],
+            neighbours: vec![[None; 3]],
+            last: Cell::new(0),
+        };
+
+        // Inserting along a space filling curve keeps the walks short.
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        order.sort_by_key(|i| morton_code(&points[*i], &bbox, size));
+        for i in order {
+            triangulation.insert(i + SUPER_VERTICES);
+        }
+        triangulation
+    }
+
+    /// Triangles with all vertices among the input points, counterclockwise.
+    pub fn triangles(&self) -> Vec<[usize; 3]> {
+        self.triangles
+            .iter()
+            .filter(|t| t.iter().all(|v| *v >= SUPER_VERTICES))
+            .map(|t| t.map(|v| v - SUPER_VERTICES))
+            .collect()
+    }
+
+    /// Vertices of the triangle under `point` with the barycentric weight of
+    /// each, or `None` outside the triangulation.
+    pub fn barycentric(&self, point: &Point) -> Option<[(usize, f64); 3]> {
+        let t = self.walk(point);
+        let [a, b, c] = self.triangles[t];
+        if [a, b, c].iter().any(|v| *v < SUPER_VERTICES) {
+            return None;
+        }
+        let (pa, pb, pc) = (&self.points[a], &self.points[b], &self.points[c]);
+        let area = orient2d(pa, pb, pc);
+        let weights = [
+            orient2d(pb, pc, point) / area,
+            orient2d(pc, pa, point) / area,
+            orient2d(pa, pb, point) / area,
+        ];
+        if weights.iter().any(|w| *w < -1e-9) {
+            return None;
+        }
+        Some([
+            (a - SUPER_VERTICES, weights[0]),
+            (b - SUPER_VERTICES, weights[1]),
+            (c - SUPER_VERTICES, weights[2]),
+        ])
+    }
+
+    /// Linear interpolation of per point `values` over the triangles.
+    pub fn interpolate(&self, point: &Point, values: &[f64]) -> Option<f64> {
+        self.barycentric(point)
+            .map(|vertices| vertices.iter().map(|(v, w)| values[*v] * w).sum())
+    }
+
+    fn insert(&mut self, vertex: usize) {
+        let point = self.points[vertex].clone();
+        let start = self.walk(&point);
+        if self.triangles[start].iter().any(|v| {
+            let p = &self.points[*v];
+            p.x == point.x && p.y == point.y
+        }) {
+            return;
+        }
+
+        // Triangles whose circumcircle holds the point form a star shaped
+        // cavity around it.
+        let mut cavity = vec![start];
+        let mut in_cavity = HashSet::from([start]);
+        let mut i = 0;
+        while i < cavity.len() {
+            for neighbour in self.neighbours[cavity[i]].into_iter().flatten() {
+                if !in_cavity.contains(&neighbour) && self.circumcircle_contains(neighbour, &point)
+                {
+                    in_cavity.insert(neighbour);
+                    cavity.push(neighbour);
+                }
+            }
+            i += 1;
+        }
+
+        let mut boundary = Vec::new();
+        for t in cavity.iter() {
+            for k in 0..3 {
+                let outside = self.neighbours[*t][k];
+                if outside.is_none_or(|n| !in_cavity.contains(&n)) {
+                    let tri = self.triangles[*t];
+                    boundary.push((tri[(k + 1) % 3], tri[(k + 2) % 3], outside));
+                }
+            }
+        }
+
+        // The cavity always has two triangles fewer than its boundary edges.
+        let mut slots = cavity;
+        while slots.len() < boundary.len() {
+            slots.push(self.triangles.len());
+            self.triangles.push([0; 3]);
+            self.neighbours.push([None; 3]);
+        }
+
+        let mut by_start = HashMap::new();
+        let mut by_end = HashMap::new();
+        for ((a, b, outside), slot) in boundary.iter().zip(slots.iter()) {
+            self.triangles[*slot] = [*a, *b, vertex];
+            self.neighbours[*slot][2] = *outside;
+            if let Some(outside) = outside {
+                let k = (0..3)
+                    .find(|k| {
+                        let v = self.triangles[*outside][*k];
+                        v != *a && v != *b
+                    })
+                    .unwrap();
+                self.neighbours[*outside][k] = Some(*slot);
+            }
+            by_start.insert(*a, *slot);
+            by_end.insert(*b, *slot);
+        }
+        for (a, b, _) in boundary.iter() {
+            let slot = by_start[a];
+            self.neighbours[slot][0] = Some(by_start[b]);
+            self.neighbours[slot][1] = Some(by_end[a]);
+        }
+        self.last.set(slots[0]);
+    }
+
+    fn circumcircle_contains(&self, triangle: usize, point: &Point) -> bool {
+        let [a, b, c] = self.triangles[triangle].map(|v| &self.points[v]);
+        in_circle(a, b, c, point) > 0.0
+    }
+
+    /// Triangle containing `point`, found by stepping across edges that
+    /// have the point on their outer side. Points outside the enclosing
+    /// triangle end at a triangle on its edge.
+    fn walk(&self, point: &Point) -> usize {
+        let mut t = self.last.get().min(self.triangles.len() - 1);
+        for _ in 0..self.triangles.len() {
+            let tri = self.triangles[t];
+            let next = (0..3).find_map(|k| {
+                let a = &self.points[tri[(k + 1) % 3]];
+                let b = &self.points[tri[(k + 2) % 3]];
+                if orient2d(a, b, point) < 0.0 {
+                    self.neighbours[t][k]
+                } else {
+                    None
+                }
+            });
+            match next {
+                Some(next) => t = next,
+                None => {
+                    self.last.set(t);
+                    return t;
+                }
+            }
+        }
+
+        // Walks only cycle on inconsistent input, look through every
+        // triangle instead.
+        (0..self.triangles.len())
+            .find(|t| {
+                let [a, b, c] = self.triangles[*t].map(|v| &self.points[v]);
+                orient2d(a, b, point) >= 0.0
+                    && orient2d(b, c, point) >= 0.0
+                    && orient2d(c, a, point) >= 0.0
+            })
+            .unwrap_or(t)
+    }
+}
+
+/// Position of a point along a Z-order curve over the bounding box.
+fn morton_code(point: &Point, bbox: &Bbox, size: f64) -> u64 {
+    let scale = (u16::MAX as f64) / size;
+    let x = ((point.x - bbox.a.x) * scale).clamp(0.0, u16::MAX as f64) as u64;
+    let y = ((point.y - bbox.a.y) * scale).clamp(0.0, u16::MAX as f64) as u64;
+    let spread = |mut v: u64| {
+        v = (v | (v << 8)) & 0x00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333;
+        (v | (v << 1)) & 0x5555_5555
+    };
+    spread(x) | (spread(y) << 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_with_jitter() -> Vec<Point> {
+        let mut points = Vec::new();
+        for i in 0..30 {
+            for j in 0..20 {
+                let jitter = ((i * 7 + j * 13) % 11) as f64 / 11.0;
+                points.push(Point {
+                    x: i as f64 * 10.0 + jitter,
+                    y: j as f64 * 5.0 - jitter,
+                });
+            }
+        }
+        // A repeated point is left out.
+        points.push(points[42].clone());
+        points
+    }
+
+    #[test]
+    fn triangles_have_empty_circumcircles() {
+        let points = grid_with_jitter();
+        let triangulation = Triangulation::new(&points);
+        let triangles = triangulation.triangles();
+        assert!(!triangles.is_empty());
+        for triangle in triangles {
+            let [a, b, c] = triangle.map(|v| &points[v]);
+            assert!(orient2d(a, b, c) > 0.0);
+            for p in points.iter() {
+                assert!(in_circle(a, b, c, p) <= 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation_is_exact_on_planes() {
+        let points = grid_with_jitter();
+        let plane = |p: &Point| 0.5 * p.x - 2.0 * p.y + 3.0;
+        let values: Vec<f64> = points.iter().map(plane).collect();
+        let triangulation = Triangulation::new(&points);
+        for i in 0..50 {
+            let p = Point {
+                x: 20.0 + i as f64 * 4.9,
+                y: 10.0 + i as f64 * 1.3,
+            };
+            let value = triangulation.interpolate(&p, &values).unwrap();
+            assert!((value - plane(&p)).abs() < 1e-9);
+        }
+        let outside = Point { x: -50.0, y: 0.0 };
+        assert!(triangulation.interpolate(&outside, &values).is_none());
+    }
+}
@@ -1,6 +1,7 @@
 use std::ops;
 
 use super::bbox;
+use super::predicates::{on_segment, orientation, Orientation};
 
 #[derive(Debug, Clone)]
 pub struct Vec2 {
@@ -122,12 +123,6 @@ impl ops::Sub<&Point> for &Point {
     }
 }
 
-pub struct Line {
-    pub a: f64,
-    pub b: f64,
-    pub c: f64,
-}
-
 #[derive(Clone)]
 pub struct LineSegment {
     pub a: Point,
@@ -135,22 +130,41 @@ pub struct LineSegment {
 }
 
 impl LineSegment {
+    /// Tells whether the closed segments share at least one point. Touching
+    /// endpoints and overlapping collinear segments count as intersecting.
     pub fn intersects_segment(&self, line: &LineSegment) -> bool {
-        self.intersects_line(&line.to_line()) && line.intersects_line(&self.to_line())
-    }
+        let o1 = orientation(&self.a, &self.b, &line.a);
+        let o2 = orientation(&self.a, &self.b, &line.b);
+        let o3 = orientation(&line.a, &line.b, &self.a);
+        let o4 = orientation(&line.a, &line.b, &self.b);
 
-    pub fn intersects_line(&self, line: &Line) -> bool {
-        let d1 = (line.a * self.a.x) + (line.b * self.a.y) + line.c;
-        let d2 = (line.a * self.b.x) + (line.b * self.b.y) + line.c;
-
-        if d1 > 0.0 && d2 > 0.0 {
-            return false;
-        }
-        if d1 < 0.0 && d2 < 0.0 {
-            return false;
+        if o1 != o2 && o3 != o4 {
+            return true;
         }
 
-        true
+        on_segment(&self.a, &self.b, &line.a)
+            || on_segment(&self.a, &self.b, &line.b)
+            || on_segment(&line.a, &line.b, &self.a)
+            || on_segment(&line.a, &line.b, &self.b)
+    }
+
+    /// Tells whether the segments cross at a single point interior to both.
+    pub fn crosses_segment(&self, line: &LineSegment) -> bool {
+        let o1 = orientation(&self.a, &self.b, &line.a);
+        let o2 = orientation(&self.a, &self.b, &line.b);
+        let o3 = orientation(&line.a, &line.b, &self.a);
+        let o4 = orientation(&line.a, &line.b, &self.b);
+
+        o1 != Orientation::Collinear
+            && o2 != Orientation::Collinear
+            && o3 != Orientation::Collinear
+            && o4 != Orientation::Collinear
+            && o1 != o2
+            && o3 != o4
+    }
+
+    pub fn contains_point(&self, point: &Point) -> bool {
+        on_segment(&self.a, &self.b, point)
     }
 
     pub fn bbox(&self) -> bbox::Bbox {
@@ -189,28 +203,26 @@ impl LineSegment {
         // println!("a{:?}, b{:?}", self.a, self.b);
         return point.distance_squared(&projection);
     }
-
-    pub fn to_line(&self) -> Line {
-        Line {
-            a: self.b.y - self.a.y,
-            b: self.a.x - self.b.x,
-            c: (self.b.x * self.a.y) - (self.a.x * self.b.y),
-        }
-    }
 }
 
 #[derive(Clone)]
 pub struct Polygon {
     vertices: Vec<Point>,
     edges: Vec<LineSegment>,
+    holes: Vec<Polygon>,
     bbox: bbox::Bbox,
 }
 
 impl Polygon {
     pub fn new(vertices: Vec<Point>) -> Polygon {
+        Polygon::with_holes(vertices, Vec::new())
+    }
+
+    pub fn with_holes(vertices: Vec<Point>, holes: Vec<Vec<Point>>) -> Polygon {
         Polygon {
             edges: edges(&vertices),
             bbox: bounding_box(&vertices),
+            holes: holes.into_iter().map(Polygon::new).collect(),
             vertices,
         }
     }
@@ -218,76 +230,124 @@ impl Polygon {
     pub fn bbox(&self) -> &bbox::Bbox {
         &self.bbox
     }
+
+    pub fn exterior(&self) -> &[Point] {
+        &self.vertices
+    }
+
+    pub fn holes(&self) -> &[Polygon] {
+        &self.holes
+    }
+
+    /// Area enclosed by the exterior ring minus the area of the holes.
+    pub fn area(&self) -> f64 {
+        signed_area(&self.vertices).abs()
+            - self
+                .holes
+                .iter()
+                .map(|h| signed_area(&h.vertices).abs())
+                .sum::<f64>()
+    }
+}
+
+/// Shoelace area of a ring, positive when the ring winds counterclockwise.
+pub fn signed_area(ring: &[Point]) -> f64 {
+    let mut sum = 0.0;
+    for i in 0..ring.len() {
+        let a = &ring[i];
+        let b = &ring[(i + 1) % ring.len()];
+        sum += a.x * b.y - b.x * a.y;
+    }
+    sum / 2.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointLocation {
+    Inside,
+    Boundary,
+    Outside,
 }
 
 impl Polygon {
+    /// Points on the boundary count as contained.
     pub fn contains_point(&self, point: &Point) -> bool {
-        if !self.bbox.contains(point) {
-            return false;
+        self.locate_point(point) != PointLocation::Outside
+    }
+
+    /// Classifies the point with a winding number test against the exterior
+    /// ring and each hole. Edges are treated as half-open in y, so a vertex on
+    /// the horizontal ray through the point is counted exactly once and
+    /// horizontal edges are never counted.
+    pub fn locate_point(&self, point: &Point) -> PointLocation {
+        match self.locate_point_in_ring(point) {
+            PointLocation::Inside => {}
+            location => return location,
         }
 
-        let segment = &LineSegment {
-            a: Point {
-                x: self.bbox.a.x - 1.0,
-                y: self.bbox.a.y - 1.0,
-            },
-            b: Point {
-                x: point.x,
-                y: point.y,
-            },
-        };
-        let edges = &self.edges;
-        let collisions = edges
-            .iter()
-            .filter(|e| e.intersects_segment(segment))
-            .collect::<Vec<_>>()
-            .len();
+        for hole in self.holes.iter() {
+            match hole.locate_point_in_ring(point) {
+                PointLocation::Inside => return PointLocation::Outside,
+                PointLocation::Boundary => return PointLocation::Boundary,
+                PointLocation::Outside => {}
+            }
+        }
 
-        collisions > 0 && collisions % 2 == 1
+        PointLocation::Inside
     }
-}
 
-fn edges(vertices: &Vec<Point>) -> Vec<LineSegment> {
-    let mut edges: Vec<LineSegment> = Vec::new();
-    for i in 1..vertices.len() {
-        let segment = LineSegment {
-            a: vertices[i - 1].clone(),
-            b: vertices[i].clone(),
-        };
-        if edges
-            .iter()
-            .find(|e| e.intersects_segment(&segment))
-            .is_none()
-            || true
-        // todo wont work
-        {
-            edges.push(segment);
+    /// Squared distance to the closest point of the polygon, zero for points
+    /// inside it or on its boundary.
+    pub fn distance_squared_to_point(&self, point: &Point) -> f64 {
+        if self.contains_point(point) {
+            return 0.0;
         }
-    }
 
-    loop {
-        let closing = LineSegment {
-            a: vertices.last().unwrap().clone(),
-            b: vertices.first().unwrap().clone(),
-        };
-        if edges
+        self.edges
             .iter()
-            .find(|e| e.intersects_segment(&closing))
-            .is_none()
-            || true
-        // todo wont work
-        {
-            edges.push(closing);
-            break;
-        } else {
-            println!("⚠ Can't close polygon!!!");
-            if edges.pop().is_none() {
-                break;
+            .chain(self.holes.iter().flat_map(|h| h.edges.iter()))
+            .map(|e| e.distance_squared_to_point(point))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    fn locate_point_in_ring(&self, point: &Point) -> PointLocation {
+        if !self.bbox.contains(point) {
+            return PointLocation::Outside;
+        }
+
+        let mut winding = 0;
+        for edge in self.edges.iter() {
+            if edge.contains_point(point) {
+                return PointLocation::Boundary;
+            }
+
+            if edge.a.y <= point.y {
+                if edge.b.y > point.y
+                    && orientation(&edge.a, &edge.b, point) == Orientation::CounterClockwise
+                {
+                    winding += 1;
+                }
+            } else if edge.b.y <= point.y
+                && orientation(&edge.a, &edge.b, point) == Orientation::Clockwise
+            {
+                winding -= 1;
             }
         }
+
+        if winding != 0 {
+            PointLocation::Inside
+        } else {
+            PointLocation::Outside
+        }
     }
+}
 
-    edges
+fn edges(vertices: &[Point]) -> Vec<LineSegment> {
+    (0..vertices.len())
+        .map(|i| LineSegment {
+            a: vertices[i].clone(),
+            b: vertices[(i + 1) % vertices.len()].clone(),
+        })
+        .collect()
 }
 
 fn bounding_box(vertices: &Vec<Point>) -> bbox::Bbox {
@@ -314,3 +374,181 @@ fn bounding_box(vertices: &Vec<Point>) -> bbox::Bbox {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    fn square() -> Polygon {
+        Polygon::new(vec![p(0.0, 0.0), p(4.0, 0.0), p(4.0, 4.0), p(0.0, 4.0)])
+    }
+
+    fn segment(a: (f64, f64), b: (f64, f64)) -> LineSegment {
+        LineSegment {
+            a: p(a.0, a.1),
+            b: p(b.0, b.1),
+        }
+    }
+
+    #[test]
+    fn locates_points_in_square() {
+        let square = square();
+        assert_eq!(square.locate_point(&p(2.0, 2.0)), PointLocation::Inside);
+        assert_eq!(square.locate_point(&p(5.0, 2.0)), PointLocation::Outside);
+        assert_eq!(square.locate_point(&p(-1.0, 2.0)), PointLocation::Outside);
+    }
+
+    #[test]
+    fn points_on_edges_and_vertices_are_boundary() {
+        let square = square();
+        for point in [
+            p(0.0, 0.0),
+            p(4.0, 4.0),
+            p(2.0, 0.0),
+            p(4.0, 2.0),
+            p(2.0, 4.0),
+            p(0.0, 1.0),
+        ] {
+            assert_eq!(square.locate_point(&point), PointLocation::Boundary);
+            assert!(square.contains_point(&point));
+        }
+    }
+
+    #[test]
+    fn ray_through_vertex_is_counted_once() {
+        // The horizontal ray from (1, 2) passes exactly through the vertex
+        // (3, 2) of the diamond and the vertex (−1, 2) behind the point.
+        let diamond = Polygon::new(vec![p(1.0, 0.0), p(3.0, 2.0), p(1.0, 4.0), p(-1.0, 2.0)]);
+        assert_eq!(diamond.locate_point(&p(1.0, 2.0)), PointLocation::Inside);
+        assert_eq!(diamond.locate_point(&p(-2.0, 2.0)), PointLocation::Outside);
+        assert_eq!(diamond.locate_point(&p(3.5, 2.0)), PointLocation::Outside);
+    }
+
+    #[test]
+    fn ray_through_reflex_vertex_is_counted_correctly() {
+        // A "W" shaped top edge where the ray through y = 2 touches the
+        // downward spike at (2, 2) without crossing the boundary.
+        let polygon = Polygon::new(vec![
+            p(0.0, 0.0),
+            p(4.0, 0.0),
+            p(4.0, 4.0),
+            p(2.0, 2.0),
+            p(0.0, 4.0),
+        ]);
+        assert_eq!(polygon.locate_point(&p(1.0, 2.0)), PointLocation::Inside);
+        assert_eq!(polygon.locate_point(&p(3.0, 2.0)), PointLocation::Inside);
+        assert_eq!(polygon.locate_point(&p(2.0, 3.0)), PointLocation::Outside);
+        assert_eq!(polygon.locate_point(&p(2.0, 2.0)), PointLocation::Boundary);
+    }
+
+    #[test]
+    fn ray_along_horizontal_edge() {
+        // The bottom of the notch at y = 2 is a horizontal edge lying on the
+        // ray through the test points.
+        let polygon = Polygon::new(vec![
+            p(0.0, 0.0),
+            p(6.0, 0.0),
+            p(6.0, 4.0),
+            p(4.0, 4.0),
+            p(4.0, 2.0),
+            p(2.0, 2.0),
+            p(2.0, 4.0),
+            p(0.0, 4.0),
+        ]);
+        assert_eq!(polygon.locate_point(&p(1.0, 2.0)), PointLocation::Inside);
+        assert_eq!(polygon.locate_point(&p(5.0, 2.0)), PointLocation::Inside);
+        assert_eq!(polygon.locate_point(&p(3.0, 2.0)), PointLocation::Boundary);
+        assert_eq!(polygon.locate_point(&p(3.0, 3.0)), PointLocation::Outside);
+        assert_eq!(polygon.locate_point(&p(7.0, 2.0)), PointLocation::Outside);
+    }
+
+    #[test]
+    fn collinear_vertices_do_not_change_result() {
+        let polygon = Polygon::new(vec![
+            p(0.0, 0.0),
+            p(2.0, 0.0),
+            p(4.0, 0.0),
+            p(4.0, 2.0),
+            p(4.0, 4.0),
+            p(0.0, 4.0),
+            p(0.0, 2.0),
+        ]);
+        assert_eq!(polygon.locate_point(&p(2.0, 2.0)), PointLocation::Inside);
+        assert_eq!(polygon.locate_point(&p(4.0, 2.0)), PointLocation::Boundary);
+        assert_eq!(polygon.locate_point(&p(5.0, 2.0)), PointLocation::Outside);
+        assert_eq!(polygon.locate_point(&p(-1.0, 2.0)), PointLocation::Outside);
+    }
+
+    #[test]
+    fn closed_rings_and_clockwise_winding() {
+        // GeoJSON rings repeat the first vertex, and winding order varies.
+        let polygon = Polygon::new(vec![
+            p(0.0, 0.0),
+            p(0.0, 4.0),
+            p(4.0, 4.0),
+            p(4.0, 0.0),
+            p(0.0, 0.0),
+        ]);
+        assert_eq!(polygon.locate_point(&p(2.0, 2.0)), PointLocation::Inside);
+        assert_eq!(polygon.locate_point(&p(0.0, 2.0)), PointLocation::Boundary);
+        assert_eq!(polygon.locate_point(&p(2.0, 5.0)), PointLocation::Outside);
+    }
+
+    #[test]
+    fn points_one_ulp_from_diagonal_edge() {
+        let polygon = Polygon::new(vec![p(0.5, 0.5), p(24.0, 24.0), p(0.5, 24.0)]);
+        let step = 12.0 * f64::EPSILON;
+        assert_eq!(
+            polygon.locate_point(&p(12.0, 12.0)),
+            PointLocation::Boundary
+        );
+        assert_eq!(
+            polygon.locate_point(&p(12.0 - step, 12.0)),
+            PointLocation::Inside
+        );
+        assert_eq!(
+            polygon.locate_point(&p(12.0 + step, 12.0)),
+            PointLocation::Outside
+        );
+    }
+
+    #[test]
+    fn segments_touching_at_endpoints_intersect() {
+        let a = segment((0.0, 0.0), (2.0, 2.0));
+        let b = segment((2.0, 2.0), (4.0, 0.0));
+        assert!(a.intersects_segment(&b));
+        assert!(!a.crosses_segment(&b));
+    }
+
+    #[test]
+    fn segment_endpoint_touching_interior_intersects() {
+        let a = segment((0.0, 0.0), (4.0, 0.0));
+        let b = segment((2.0, 0.0), (2.0, 3.0));
+        assert!(a.intersects_segment(&b));
+        assert!(b.intersects_segment(&a));
+        assert!(!a.crosses_segment(&b));
+    }
+
+    #[test]
+    fn collinear_segments() {
+        let a = segment((0.0, 0.0), (2.0, 0.0));
+        assert!(a.intersects_segment(&segment((1.0, 0.0), (3.0, 0.0))));
+        assert!(a.intersects_segment(&segment((2.0, 0.0), (3.0, 0.0))));
+        assert!(!a.intersects_segment(&segment((2.5, 0.0), (3.0, 0.0))));
+        assert!(!a.crosses_segment(&segment((1.0, 0.0), (3.0, 0.0))));
+    }
+
+    #[test]
+    fn crossing_and_disjoint_segments() {
+        let a = segment((0.0, 0.0), (2.0, 2.0));
+        assert!(a.intersects_segment(&segment((0.0, 2.0), (2.0, 0.0))));
+        assert!(a.crosses_segment(&segment((0.0, 2.0), (2.0, 0.0))));
+        assert!(!a.intersects_segment(&segment((0.0, 1.0), (1.0, 2.0))));
+        // Parallel lines that would meet if extended.
+        assert!(!a.intersects_segment(&segment((3.0, 3.0), (4.0, 4.0))));
+    }
+}
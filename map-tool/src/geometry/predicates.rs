@@ -0,0 +1,199 @@
+use super::point::Point;
+
+// Error bound for the fast floating point evaluation of orient2d, from
+// Shewchuk, "Adaptive Precision Floating-Point Arithmetic and Fast Robust
+// Geometric Predicates".
+const EPSILON: f64 = f64::EPSILON * 0.5;
+const CCW_ERROR_BOUND: f64 = (3.0 + 16.0 * EPSILON) * EPSILON;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    CounterClockwise,
+    Clockwise,
+    Collinear,
+}
+
+pub fn orientation(a: &Point, b: &Point, c: &Point) -> Orientation {
+    let det = orient2d(a, b, c);
+    if det > 0.0 {
+        Orientation::CounterClockwise
+    } else if det < 0.0 {
+        Orientation::Clockwise
+    } else {
+        Orientation::Collinear
+    }
+}
+
+/// Twice the signed area of the triangle `abc`. Positive when the points are
+/// in counterclockwise order, negative when clockwise and zero when collinear.
+/// The sign is always exact, the magnitude only when the fast path suffices.
+pub fn orient2d(a: &Point, b: &Point, c: &Point) -> f64 {
+    let left = (a.x - c.x) * (b.y - c.y);
+    let right = (a.y - c.y) * (b.x - c.x);
+    let det = left - right;
+
+    let det_sum = if left > 0.0 {
+        if right <= 0.0 {
+            return det;
+        }
+        left + right
+    } else if left < 0.0 {
+        if right >= 0.0 {
+            return det;
+        }
+        -left - right
+    } else {
+        return det;
+    };
+
+    if det.abs() >= CCW_ERROR_BOUND * det_sum {
+        return det;
+    }
+
+    orient2d_exact(a, b, c)
+}
+
+fn orient2d_exact(a: &Point, b: &Point, c: &Point) -> f64 {
+    // (ax - cx)(by - cy) - (ay - cy)(bx - cx) expanded into products of the
+    // input coordinates, which can all be represented exactly.
+    let products = [
+        two_product(a.x, b.y),
+        two_product(-a.x, c.y),
+        two_product(-c.x, b.y),
+        two_product(-a.y, b.x),
+        two_product(a.y, c.x),
+        two_product(c.y, b.x),
+    ];
+
+    let mut expansion: Vec<f64> = Vec::with_capacity(products.len() * 2);
+    for (high, low) in products {
+        grow_expansion(&mut expansion, low);
+        grow_expansion(&mut expansion, high);
+    }
+
+    // Components are nonoverlapping and ordered by increasing magnitude, so
+    // the sign of the sum is the sign of the largest nonzero component.
+    expansion
+        .iter()
+        .rev()
+        .find(|x| **x != 0.0)
+        .copied()
+        .unwrap_or(0.0)
+}
+
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let x = a + b;
+    let b_virtual = x - a;
+    let a_virtual = x - b_virtual;
+    let b_roundoff = b - b_virtual;
+    let a_roundoff = a - a_virtual;
+    (x, a_roundoff + b_roundoff)
+}
+
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let x = a * b;
+    (x, a.mul_add(b, -x))
+}
+
+fn grow_expansion(expansion: &mut Vec<f64>, b: f64) {
+    let mut q = b;
+    for component in expansion.iter_mut() {
+        let (sum, error) = two_sum(q, *component);
+        *component = error;
+        q = sum;
+    }
+    expansion.push(q);
+}
+
+/// Tells whether `p` lies on the closed segment `ab`.
+pub fn on_segment(a: &Point, b: &Point, p: &Point) -> bool {
+    orientation(a, b, p) == Orientation::Collinear
+        && a.x.min(b.x) <= p.x
+        && p.x <= a.x.max(b.x)
+        && a.y.min(b.y) <= p.y
+        && p.y <= a.y.max(b.y)
+}
+
+/// Positive when `d` lies inside the circle through `a`, `b` and `c`, given
+/// in counterclockwise order, negative outside and zero on the circle. Only
+/// the fast path is evaluated, so points within rounding error of the circle
+/// may land on either side.
+pub fn in_circle(a: &Point, b: &Point, c: &Point, d: &Point) -> f64 {
+    let (adx, ady) = (a.x - d.x, a.y - d.y);
+    let (bdx, bdy) = (b.x - d.x, b.y - d.y);
+    let (cdx, cdy) = (c.x - d.x, c.y - d.y);
+    let alift = adx * adx + ady * ady;
+    let blift = bdx * bdx + bdy * bdy;
+    let clift = cdx * cdx + cdy * cdy;
+    alift * (bdx * cdy - cdx * bdy)
+        + blift * (cdx * ady - adx * cdy)
+        + clift * (adx * bdy - bdx * ady)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn orientation_of_simple_triangles() {
+        assert_eq!(
+            orientation(&p(0.0, 0.0), &p(1.0, 0.0), &p(0.0, 1.0)),
+            Orientation::CounterClockwise
+        );
+        assert_eq!(
+            orientation(&p(0.0, 0.0), &p(0.0, 1.0), &p(1.0, 0.0)),
+            Orientation::Clockwise
+        );
+        assert_eq!(
+            orientation(&p(0.0, 0.0), &p(1.0, 1.0), &p(2.0, 2.0)),
+            Orientation::Collinear
+        );
+    }
+
+    #[test]
+    fn orientation_is_exact_near_collinear() {
+        // Naive evaluation gets these wrong because the products round.
+        let a = p(0.5, 0.5);
+        let b = p(12.0, 12.0);
+        let c = p(24.0, 24.0);
+        for i in 0..256 {
+            let q = p(a.x + i as f64 * f64::EPSILON, a.y);
+            let det = orient2d(&q, &b, &c);
+            if i == 0 {
+                assert_eq!(det, 0.0);
+            } else {
+                assert!(det < 0.0, "wrong sign at {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn orientation_is_consistent_for_geographic_coordinates() {
+        // Three points a few ulps apart, as happens with adjacent vertices
+        // of digitised shorelines.
+        let ulp_x = 23.755 * f64::EPSILON;
+        let ulp_y = 61.481 * f64::EPSILON;
+        let a = p(23.755 + ulp_x, 61.481 + 2.0 * ulp_y);
+        let b = p(23.755 + 3.0 * ulp_x, 61.481 + 6.0 * ulp_y);
+        let c = p(23.755 + 5.0 * ulp_x, 61.481 + 9.0 * ulp_y);
+        let abc = orientation(&a, &b, &c);
+        assert_eq!(abc, orientation(&b, &c, &a));
+        assert_eq!(abc, orientation(&c, &a, &b));
+        assert_eq!(orient2d(&a, &b, &c), -orient2d(&b, &a, &c));
+    }
+
+    #[test]
+    fn on_segment_includes_endpoints() {
+        let a = p(0.0, 0.0);
+        let b = p(2.0, 2.0);
+        assert!(on_segment(&a, &b, &p(0.0, 0.0)));
+        assert!(on_segment(&a, &b, &p(2.0, 2.0)));
+        assert!(on_segment(&a, &b, &p(1.0, 1.0)));
+        assert!(!on_segment(&a, &b, &p(3.0, 3.0)));
+        assert!(!on_segment(&a, &b, &p(1.0, 1.5)));
+    }
+}
@@ -0,0 +1,83 @@
+use super::point::Point;
+
+/// Equirectangular projection around an origin, with the length of a degree
+/// on the WGS84 ellipsoid at the origin's latitude. Over the few kilometres
+/// a map covers the error stays well below a percent, which is plenty for
+/// tolerances and widths given in metres.
+#[derive(Debug, Clone)]
+pub struct LocalProjection {
+    origin: Point,
+    metres_per_degree_x: f64,
+    metres_per_degree_y: f64,
+}
+
+impl LocalProjection {
+    pub fn new(origin: &Point) -> LocalProjection {
+        let latitude = origin.y.to_radians();
+        LocalProjection {
+            origin: origin.clone(),
+            metres_per_degree_x: 111_412.84 * latitude.cos() - 93.5 * (3.0 * latitude).cos()
+                + 0.118 * (5.0 * latitude).cos(),
+            metres_per_degree_y: 111_132.92 - 559.82 * (2.0 * latitude).cos()
+                + 1.175 * (4.0 * latitude).cos()
+                - 0.0023 * (6.0 * latitude).cos(),
+        }
+    }
+
+    pub fn to_metres(&self, point: &Point) -> Point {
+        Point {
+            x: (point.x - self.origin.x) * self.metres_per_degree_x,
+            y: (point.y - self.origin.y) * self.metres_per_degree_y,
+        }
+    }
+
+    pub fn to_degrees(&self, point: &Point) -> Point {
+        Point {
+            x: self.origin.x + point.x / self.metres_per_degree_x,
+            y: self.origin.y + point.y / self.metres_per_degree_y,
+        }
+    }
+
+    pub fn distance(&self, a: &Point, b: &Point) -> f64 {
+        self.to_metres(a).distance(&self.to_metres(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_metres() {
+        let projection = LocalProjection::new(&Point { x: 23.77, y: 61.49 });
+        for (x, y) in [(23.77, 61.49), (23.75, 61.5), (23.8, 61.47), (23.0, 62.0)] {
+            let point = Point { x, y };
+            let back = projection.to_degrees(&projection.to_metres(&point));
+            assert!((back.x - x).abs() < 1e-12 && (back.y - y).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn degrees_have_their_ellipsoid_length_at_61_north() {
+        // Meridian and prime vertical radii of curvature on WGS84.
+        let a = 6_378_137.0;
+        let e2 = 6.694_379_990_14e-3;
+        let latitude: f64 = 61.0;
+        let sin2 = latitude.to_radians().sin().powi(2);
+        let meridian = a * (1.0 - e2) / (1.0 - e2 * sin2).powf(1.5);
+        let prime_vertical = a / (1.0 - e2 * sin2).sqrt();
+        let metres_y = meridian * 1f64.to_radians();
+        let metres_x = prime_vertical * latitude.to_radians().cos() * 1f64.to_radians();
+
+        let projection = LocalProjection::new(&Point {
+            x: 23.0,
+            y: latitude,
+        });
+        // A hundredth of a degree is roughly the size of a map.
+        let north = projection.to_metres(&Point { x: 23.0, y: 61.01 });
+        let east = projection.to_metres(&Point { x: 23.01, y: 61.0 });
+        assert!((north.y - metres_y / 100.0).abs() < 0.01, "{}", north.y);
+        assert!((east.x - metres_x / 100.0).abs() < 0.01, "{}", east.x);
+        assert_eq!((north.x, east.y), (0.0, 0.0));
+    }
+}
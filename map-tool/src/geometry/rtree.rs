@@ -0,0 +1,870 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use super::bbox::Bbox;
+use super::point::{LineSegment, Point};
+
+const DEFAULT_MAX_CHILDREN: usize = 16;
+
+pub trait HasBbox {
+    fn bbox(&self) -> Bbox;
+}
+
+/// Handle to an item stored in an `RTree`, valid until the item is removed.
+/// Slots of removed items are reused, and the generation tells a stale
+/// handle from one to the slot's new item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ItemId {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Leaf(Vec<usize>),
+    Interior(Vec<Node>),
+}
+
+#[derive(Debug)]
+struct Node {
+    bbox: Bbox,
+    kind: NodeKind,
+}
+
+/// R-tree over items with a bounding box. Every item is stored once, leaves
+/// only hold indices into the item storage, and the bounds of the tree
+/// follow the data.
+#[derive(Debug)]
+pub struct RTree<T: HasBbox> {
+    items: Vec<Option<(T, Bbox)>>,
+    /// Generation of each item slot, bumped when its item is removed.
+    generations: Vec<u32>,
+    free: Vec<usize>,
+    root: Node,
+    max_children: usize,
+    min_children: usize,
+    len: usize,
+}
+
+impl<T: HasBbox> Default for RTree<T> {
+    fn default() -> RTree<T> {
+        RTree::new()
+    }
+}
+
+impl<T: HasBbox> RTree<T> {
+    pub fn new() -> RTree<T> {
+        RTree::with_max_children(DEFAULT_MAX_CHILDREN)
+    }
+
+    pub fn with_max_children(max_children: usize) -> RTree<T> {
+        let max_children = max_children.max(4);
+        RTree {
+            items: Vec::new(),
+            generations: Vec::new(),
+            free: Vec::new(),
+            root: Node {
+                bbox: Bbox::empty(),
+                kind: NodeKind::Leaf(Vec::new()),
+            },
+            max_children,
+            min_children: (max_children * 2 / 5).max(2),
+            len: 0,
+        }
+    }
+
+    pub fn bulk_load(items: Vec<T>) -> RTree<T> {
+        RTree::bulk_load_with_max_children(items, DEFAULT_MAX_CHILDREN)
+    }
+
+    /// Builds a packed tree with Sort-Tile-Recursive loading, which is much
+    /// faster than inserting one by one and gives tighter nodes.
+    pub fn bulk_load_with_max_children(items: Vec<T>, max_children: usize) -> RTree<T> {
+        let mut tree = RTree::with_max_children(max_children);
+        tree.len = items.len();
+        tree.generations = vec![0; items.len()];
+        tree.items = items
+            .into_iter()
+            .map(|item| {
+                let bbox = item.bbox();
+                Some((item, bbox))
+            })
+            .collect();
+
+        if tree.len == 0 {
+            return tree;
+        }
+
+        let entries: Vec<(usize, Bbox)> = tree
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (i, item.as_ref().unwrap().1.clone()))
+            .collect();
+        let mut nodes: Vec<Node> = str_pack(entries, tree.max_children, |e| &e.1)
+            .into_iter()
+            .map(|group| Node {
+                bbox: union_all(group.iter().map(|e| &e.1)),
+                kind: NodeKind::Leaf(group.into_iter().map(|e| e.0).collect()),
+            })
+            .collect();
+
+        while nodes.len() > 1 {
+            nodes = str_pack(nodes, tree.max_children, |n| &n.bbox)
+                .into_iter()
+                .map(|group| Node {
+                    bbox: union_all(group.iter().map(|n| &n.bbox)),
+                    kind: NodeKind::Interior(group),
+                })
+                .collect();
+        }
+
+        tree.root = nodes.pop().unwrap();
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bounds of all items, empty when the tree is.
+    pub fn bbox(&self) -> &Bbox {
+        &self.root.bbox
+    }
+
+    pub fn get(&self, id: ItemId) -> Option<&T> {
+        if self.generations.get(id.index) != Some(&id.generation) {
+            return None;
+        }
+        self.items[id.index].as_ref().map(|(item, _)| item)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter().flatten().map(|(item, _)| item)
+    }
+
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (ItemId, &T)> {
+        self.items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| item.as_ref().map(|(item, _)| (self.id(i), item)))
+    }
+
+    pub fn insert(&mut self, item: T) -> ItemId {
+        let bbox = item.bbox();
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.items[index] = Some((item, bbox.clone()));
+                index
+            }
+            None => {
+                self.items.push(Some((item, bbox.clone())));
+                self.generations.push(0);
+                self.items.len() - 1
+            }
+        };
+        self.len += 1;
+        self.insert_index(index, &bbox);
+        self.id(index)
+    }
+
+    fn id(&self, index: usize) -> ItemId {
+        ItemId {
+            index,
+            generation: self.generations[index],
+        }
+    }
+
+    fn insert_index(&mut self, index: usize, bbox: &Bbox) {
+        let items = &self.items;
+        let bbox_of = |i: usize| &items[i].as_ref().unwrap().1;
+        if let Some(sibling) = insert_into(&mut self.root, index, bbox, self.max_children, &bbox_of)
+        {
+            let old_root = std::mem::replace(
+                &mut self.root,
+                Node {
+                    bbox: Bbox::empty(),
+                    kind: NodeKind::Interior(Vec::new()),
+                },
+            );
+            self.root.bbox = old_root.bbox.union(&sibling.bbox);
+            self.root.kind = NodeKind::Interior(vec![old_root, sibling]);
+        }
+    }
+
+    pub fn remove(&mut self, id: ItemId) -> Option<T> {
+        self.get(id)?;
+        let bbox = self.items[id.index].as_ref()?.1.clone();
+
+        let items = &self.items;
+        let bbox_of = |i: usize| &items[i].as_ref().unwrap().1;
+        let mut orphans = Vec::new();
+        if !remove_from(
+            &mut self.root,
+            id.index,
+            &bbox,
+            self.min_children,
+            &bbox_of,
+            &mut orphans,
+        ) {
+            return None;
+        }
+
+        let (item, _) = self.items[id.index].take().unwrap();
+        self.generations[id.index] = self.generations[id.index].wrapping_add(1);
+        self.free.push(id.index);
+        self.len -= 1;
+
+        // Collapse a root left with a single child.
+        loop {
+            let only_child = match &mut self.root.kind {
+                NodeKind::Interior(children) if children.len() == 1 => children.pop().unwrap(),
+                NodeKind::Interior(children) if children.is_empty() => Node {
+                    bbox: Bbox::empty(),
+                    kind: NodeKind::Leaf(Vec::new()),
+                },
+                _ => break,
+            };
+            self.root = only_child;
+        }
+
+        for index in orphans {
+            let bbox = self.items[index].as_ref().unwrap().1.clone();
+            self.insert_index(index, &bbox);
+        }
+
+        Some(item)
+    }
+
+    /// Items whose bounding box overlaps `bbox`.
+    pub fn query_bbox(&self, bbox: &Bbox) -> BboxQuery<'_, T> {
+        let stack = if self.root.bbox.overlaps(bbox) {
+            vec![&self.root]
+        } else {
+            Vec::new()
+        };
+        BboxQuery {
+            tree: self,
+            bbox: bbox.clone(),
+            stack,
+            leaf: [].iter(),
+        }
+    }
+}
+
+impl<T: PointDistance> RTree<T> {
+    /// Items at most `radius` away from the point, in no particular order.
+    pub fn within_radius(&self, point: &Point, radius: f64) -> impl Iterator<Item = &T> {
+        let radius_squared = radius * radius;
+        let bbox = Bbox {
+            a: Point {
+                x: point.x - radius,
+                y: point.y - radius,
+            },
+            b: Point {
+                x: point.x + radius,
+                y: point.y + radius,
+            },
+        };
+        let point = point.clone();
+        self.query_bbox(&bbox)
+            .filter(move |item| item.distance_squared_to_point(&point) <= radius_squared)
+    }
+
+    /// The `k` items closest to the point with their squared distances,
+    /// closest first. Which of several items at the same distance make the
+    /// cut is unspecified.
+    pub fn nearest(&self, point: &Point, k: usize) -> Vec<(&T, f64)> {
+        self.nearest_iter(point).take(k).collect()
+    }
+
+    /// Every item with its squared distance to the point, closest first and
+    /// items at the same distance in no particular order.
+    /// Nodes are only opened once nothing closer is left, so taking a few
+    /// items only visits the part of the tree around the point.
+    pub fn nearest_iter(&self, point: &Point) -> NearestIter<'_, T> {
+        let mut heap = BinaryHeap::new();
+        if !self.is_empty() {
+            heap.push(Candidate {
+                distance_squared: self.root.bbox.distance_squared_to_point(point),
+                entry: Entry::Node(&self.root),
+            });
+        }
+        NearestIter {
+            tree: self,
+            point: point.clone(),
+            heap,
+        }
+    }
+}
+
+/// Items that can measure their exact distance to a point. The distance must
+/// never be smaller than the distance to the item's bounding box.
+pub trait PointDistance: HasBbox {
+    fn distance_squared_to_point(&self, point: &Point) -> f64;
+}
+
+impl HasBbox for LineSegment {
+    fn bbox(&self) -> Bbox {
+        LineSegment::bbox(self)
+    }
+}
+
+impl PointDistance for LineSegment {
+    fn distance_squared_to_point(&self, point: &Point) -> f64 {
+        LineSegment::distance_squared_to_point(self, point)
+    }
+}
+
+impl RTree<LineSegment> {
+    /// Whether the segment crosses one of the segments in the tree at a
+    /// point inside both. Segments passing within `tolerance` of an end of
+    /// `segment` don't count, so that a segment starting on one that it
+    /// was interpolated along doesn't cross it by rounding.
+    pub fn crosses(&self, segment: &LineSegment, tolerance: f64) -> bool {
+        let tolerance_squared = tolerance * tolerance;
+        self.query_bbox(&LineSegment::bbox(segment)).any(|other| {
+            other.crosses_segment(segment)
+                && LineSegment::distance_squared_to_point(other, &segment.a) > tolerance_squared
+                && LineSegment::distance_squared_to_point(other, &segment.b) > tolerance_squared
+        })
+    }
+}
+
+pub struct BboxQuery<'a, T: HasBbox> {
+    tree: &'a RTree<T>,
+    bbox: Bbox,
+    stack: Vec<&'a Node>,
+    leaf: std::slice::Iter<'a, usize>,
+}
+
+impl<'a, T: HasBbox> Iterator for BboxQuery<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            for index in self.leaf.by_ref() {
+                let (item, item_bbox) = self.tree.items[*index].as_ref().unwrap();
+                if item_bbox.overlaps(&self.bbox) {
+                    return Some(item);
+                }
+            }
+
+            match &self.stack.pop()?.kind {
+                NodeKind::Interior(children) => self
+                    .stack
+                    .extend(children.iter().filter(|c| c.bbox.overlaps(&self.bbox))),
+                NodeKind::Leaf(indices) => self.leaf = indices.iter(),
+            }
+        }
+    }
+}
+
+enum Entry<'a> {
+    Node(&'a Node),
+    Item(usize),
+}
+
+struct Candidate<'a> {
+    distance_squared: f64,
+    entry: Entry<'a>,
+}
+
+impl PartialEq for Candidate<'_> {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate<'_> {}
+
+impl PartialOrd for Candidate<'_> {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate<'_> {
+    // Reversed so that the binary heap pops the closest candidate first.
+    // Items win ties against nodes so they are returned as soon as possible.
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        other
+            .distance_squared
+            .total_cmp(&self.distance_squared)
+            .then_with(|| match (&self.entry, &other.entry) {
+                (Entry::Item(_), Entry::Node(_)) => Ordering::Greater,
+                (Entry::Node(_), Entry::Item(_)) => Ordering::Less,
+                _ => Ordering::Equal,
+            })
+    }
+}
+
+pub struct NearestIter<'a, T: PointDistance> {
+    tree: &'a RTree<T>,
+    point: Point,
+    heap: BinaryHeap<Candidate<'a>>,
+}
+
+impl<'a, T: PointDistance> Iterator for NearestIter<'a, T> {
+    type Item = (&'a T, f64);
+
+    fn next(&mut self) -> Option<(&'a T, f64)> {
+        while let Some(Candidate {
+            distance_squared,
+            entry,
+        }) = self.heap.pop()
+        {
+            match entry {
+                Entry::Item(index) => {
+                    let (item, _) = self.tree.items[index].as_ref().unwrap();
+                    return Some((item, distance_squared));
+                }
+                Entry::Node(node) => match &node.kind {
+                    NodeKind::Interior(children) => {
+                        for child in children {
+                            self.heap.push(Candidate {
+                                distance_squared: child.bbox.distance_squared_to_point(&self.point),
+                                entry: Entry::Node(child),
+                            });
+                        }
+                    }
+                    NodeKind::Leaf(indices) => {
+                        for index in indices {
+                            let (item, _) = self.tree.items[*index].as_ref().unwrap();
+                            self.heap.push(Candidate {
+                                distance_squared: item.distance_squared_to_point(&self.point),
+                                entry: Entry::Item(*index),
+                            });
+                        }
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+fn union_all<'a, I: Iterator<Item = &'a Bbox>>(boxes: I) -> Bbox {
+    boxes.fold(Bbox::empty(), |a, b| a.union(b))
+}
+
+fn enlargement(bbox: &Bbox, other: &Bbox) -> f64 {
+    bbox.union(other).area() - bbox.area()
+}
+
+/// Groups entries into runs of at most `max_children`, first into vertical
+/// slices by x and then by y within each slice.
+fn str_pack<E, F: Fn(&E) -> &Bbox>(
+    mut entries: Vec<E>,
+    max_children: usize,
+    bbox: F,
+) -> Vec<Vec<E>> {
+    let node_count = entries.len().div_ceil(max_children);
+    let slice_count = (node_count as f64).sqrt().ceil() as usize;
+    let slice_size = slice_count * max_children;
+
+    entries.sort_unstable_by(|a, b| bbox(a).center().x.total_cmp(&bbox(b).center().x));
+    for slice in entries.chunks_mut(slice_size) {
+        slice.sort_unstable_by(|a, b| bbox(a).center().y.total_cmp(&bbox(b).center().y));
+    }
+
+    // Slices are a whole number of nodes long, so consecutive runs never
+    // straddle two slices.
+    let mut groups = Vec::with_capacity(node_count);
+    let mut entries = entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        groups.push(entries.by_ref().take(max_children).collect());
+    }
+    groups
+}
+
+/// Splits an overfull list in two halves along the axis where the centers
+/// are most spread out.
+fn split_entries<E, F: Fn(&E) -> Point>(mut entries: Vec<E>, center: F) -> (Vec<E>, Vec<E>) {
+    let centers: Vec<Point> = entries.iter().map(&center).collect();
+    let spread = |axis: fn(&Point) -> f64| {
+        let min = centers.iter().map(axis).fold(f64::INFINITY, f64::min);
+        let max = centers.iter().map(axis).fold(-f64::INFINITY, f64::max);
+        max - min
+    };
+    if spread(|c| c.x) >= spread(|c| c.y) {
+        entries.sort_by(|a, b| center(a).x.total_cmp(&center(b).x));
+    } else {
+        entries.sort_by(|a, b| center(a).y.total_cmp(&center(b).y));
+    }
+    let second = entries.split_off(entries.len() / 2);
+    (entries, second)
+}
+
+/// Inserts into the subtree and returns a new sibling if the node had to be
+/// split.
+fn insert_into<'a, F: Fn(usize) -> &'a Bbox>(
+    node: &mut Node,
+    index: usize,
+    bbox: &Bbox,
+    max_children: usize,
+    bbox_of: &F,
+) -> Option<Node> {
+    node.bbox = node.bbox.union(bbox);
+    match &mut node.kind {
+        NodeKind::Leaf(indices) => {
+            indices.push(index);
+            if indices.len() <= max_children {
+                return None;
+            }
+
+            let (first, second) = split_entries(std::mem::take(indices), |i| bbox_of(*i).center());
+            node.bbox = union_all(first.iter().map(|i| bbox_of(*i)));
+            *indices = first;
+            Some(Node {
+                bbox: union_all(second.iter().map(|i| bbox_of(*i))),
+                kind: NodeKind::Leaf(second),
+            })
+        }
+        NodeKind::Interior(children) => {
+            let best = children
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    enlargement(&a.bbox, bbox)
+                        .total_cmp(&enlargement(&b.bbox, bbox))
+                        .then(a.bbox.area().total_cmp(&b.bbox.area()))
+                })
+                .map(|(i, _)| i)
+                .unwrap();
+
+            let sibling = insert_into(&mut children[best], index, bbox, max_children, bbox_of)?;
+            children.push(sibling);
+            if children.len() <= max_children {
+                return None;
+            }
+
+            let (first, second) = split_entries(std::mem::take(children), |n| n.bbox.center());
+            node.bbox = union_all(first.iter().map(|n| &n.bbox));
+            *children = first;
+            Some(Node {
+                bbox: union_all(second.iter().map(|n| &n.bbox)),
+                kind: NodeKind::Interior(second),
+            })
+        }
+    }
+}
+
+/// Removes the index from the subtree. Nodes that drop below the minimum
+/// fill are dissolved and their items collected for reinsertion.
+fn remove_from<'a, F: Fn(usize) -> &'a Bbox>(
+    node: &mut Node,
+    index: usize,
+    bbox: &Bbox,
+    min_children: usize,
+    bbox_of: &F,
+    orphans: &mut Vec<usize>,
+) -> bool {
+    if !node.bbox.contains_bbox(bbox) {
+        return false;
+    }
+
+    let removed = match &mut node.kind {
+        NodeKind::Leaf(indices) => match indices.iter().position(|i| *i == index) {
+            Some(position) => {
+                indices.swap_remove(position);
+                true
+            }
+            None => false,
+        },
+        NodeKind::Interior(children) => {
+            let mut removed = false;
+            for i in 0..children.len() {
+                if remove_from(
+                    &mut children[i],
+                    index,
+                    bbox,
+                    min_children,
+                    bbox_of,
+                    orphans,
+                ) {
+                    if node_size(&children[i]) < min_children {
+                        collect_indices(&children.swap_remove(i), orphans);
+                    }
+                    removed = true;
+                    break;
+                }
+            }
+            removed
+        }
+    };
+
+    if removed {
+        node.bbox = match &node.kind {
+            NodeKind::Leaf(indices) => union_all(indices.iter().map(|i| bbox_of(*i))),
+            NodeKind::Interior(children) => union_all(children.iter().map(|n| &n.bbox)),
+        };
+    }
+    removed
+}
+
+fn node_size(node: &Node) -> usize {
+    match &node.kind {
+        NodeKind::Leaf(indices) => indices.len(),
+        NodeKind::Interior(children) => children.len(),
+    }
+}
+
+fn collect_indices(node: &Node, indices: &mut Vec<usize>) {
+    match &node.kind {
+        NodeKind::Leaf(items) => indices.extend(items.iter()),
+        NodeKind::Interior(children) => {
+            for child in children {
+                collect_indices(child, indices);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct Item {
+        label: usize,
+        bbox: Bbox,
+    }
+
+    impl HasBbox for Item {
+        fn bbox(&self) -> Bbox {
+            self.bbox.clone()
+        }
+    }
+
+    impl PointDistance for Item {
+        fn distance_squared_to_point(&self, point: &Point) -> f64 {
+            self.bbox.distance_squared_to_point(point)
+        }
+    }
+
+    /// Fixed linear congruential generator, numbers in `0..1`.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn item(&mut self, label: usize) -> Item {
+            let (x, y) = (self.next() * 100.0, self.next() * 100.0);
+            let (w, h) = (self.next() * 3.0, self.next() * 3.0);
+            Item {
+                label,
+                bbox: Bbox {
+                    a: Point { x, y },
+                    b: Point { x: x + w, y: y + h },
+                },
+            }
+        }
+
+        fn bbox(&mut self) -> Bbox {
+            let (x, y) = (self.next() * 100.0, self.next() * 100.0);
+            let size = self.next() * 20.0;
+            Bbox {
+                a: Point { x, y },
+                b: Point {
+                    x: x + size,
+                    y: y + size,
+                },
+            }
+        }
+    }
+
+    fn sorted(labels: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut labels: Vec<usize> = labels.collect();
+        labels.sort_unstable();
+        labels
+    }
+
+    /// Compares every query with a scan over the items that should be in
+    /// the tree.
+    fn check_queries(tree: &RTree<Item>, expected: &[Item], random: &mut Random) {
+        assert_eq!(tree.len(), expected.len());
+        assert_eq!(
+            sorted(tree.iter().map(|i| i.label)),
+            sorted(expected.iter().map(|i| i.label))
+        );
+        for _ in 0..50 {
+            let bbox = random.bbox();
+            assert_eq!(
+                sorted(tree.query_bbox(&bbox).map(|i| i.label)),
+                sorted(
+                    expected
+                        .iter()
+                        .filter(|i| i.bbox.overlaps(&bbox))
+                        .map(|i| i.label)
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn inserts_and_removes_match_a_scan() {
+        let mut random = Random(7);
+        let mut tree = RTree::with_max_children(4);
+        let mut present: Vec<(ItemId, Item)> = Vec::new();
+        for round in 0..20 {
+            for label in 0..50 {
+                let item = random.item(round * 50 + label);
+                present.push((tree.insert(item.clone()), item));
+            }
+            for _ in 0..30 {
+                let (id, item) =
+                    present.swap_remove((random.next() * present.len() as f64) as usize);
+                assert_eq!(tree.remove(id).map(|i| i.label), Some(item.label));
+            }
+            let items: Vec<Item> = present.iter().map(|(_, item)| item.clone()).collect();
+            check_queries(&tree, &items, &mut random);
+        }
+        for (id, item) in present.iter() {
+            assert_eq!(tree.get(*id).map(|i| i.label), Some(item.label));
+        }
+    }
+
+    #[test]
+    fn bulk_loaded_tree_matches_a_scan() {
+        let mut random = Random(11);
+        let items: Vec<Item> = (0..1000).map(|label| random.item(label)).collect();
+        let mut tree = RTree::bulk_load_with_max_children(items.clone(), 8);
+        check_queries(&tree, &items, &mut random);
+
+        let ids: Vec<ItemId> = tree.iter_with_ids().map(|(id, _)| id).collect();
+        for id in ids.iter().step_by(3) {
+            tree.remove(*id);
+        }
+        let left: Vec<Item> = items
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 3 != 0)
+            .map(|(_, item)| item.clone())
+            .collect();
+        check_queries(&tree, &left, &mut random);
+        assert!(RTree::<Item>::bulk_load(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn stale_ids_do_not_reach_new_items() {
+        let mut random = Random(3);
+        let mut tree = RTree::new();
+        let old = tree.insert(random.item(1));
+        assert_eq!(tree.remove(old).map(|i| i.label), Some(1));
+        let new = tree.insert(random.item(2));
+
+        assert_ne!(old, new);
+        assert!(tree.get(old).is_none());
+        assert!(tree.remove(old).is_none());
+        assert_eq!(tree.get(new).map(|i| i.label), Some(2));
+        assert_eq!(tree.len(), 1);
+    }
+
+    /// Points on a whole number grid, so that many are equally far from a
+    /// grid point.
+    fn grid_points() -> Vec<Item> {
+        (0..400)
+            .map(|i| {
+                let point = Point {
+                    x: (i % 20) as f64,
+                    y: (i / 20) as f64,
+                };
+                Item {
+                    label: i,
+                    bbox: Bbox::from_point(&point),
+                }
+            })
+            .collect()
+    }
+
+    fn scan_distances(items: &[Item], point: &Point) -> Vec<f64> {
+        let mut distances: Vec<f64> = items
+            .iter()
+            .map(|i| i.distance_squared_to_point(point))
+            .collect();
+        distances.sort_by(f64::total_cmp);
+        distances
+    }
+
+    #[test]
+    fn nearest_items_come_closest_first() {
+        let mut random = Random(5);
+        let items: Vec<Item> = (0..500).map(|label| random.item(label)).collect();
+        let tree = RTree::bulk_load_with_max_children(items.clone(), 6);
+        for _ in 0..50 {
+            let point = Point {
+                x: random.next() * 120.0 - 10.0,
+                y: random.next() * 120.0 - 10.0,
+            };
+            let nearest: Vec<(&Item, f64)> = tree.nearest_iter(&point).collect();
+            assert_eq!(
+                nearest.iter().map(|(_, d)| *d).collect::<Vec<f64>>(),
+                scan_distances(&items, &point)
+            );
+            for (item, distance) in nearest.iter() {
+                assert_eq!(item.distance_squared_to_point(&point), *distance);
+            }
+            assert_eq!(
+                sorted(nearest.iter().map(|(i, _)| i.label)),
+                sorted(items.iter().map(|i| i.label))
+            );
+        }
+    }
+
+    #[test]
+    fn nearest_breaks_ties_without_skipping_closer_items() {
+        let items = grid_points();
+        let mut tree = RTree::with_max_children(4);
+        for item in items.iter() {
+            tree.insert(item.clone());
+        }
+        let point = Point { x: 10.0, y: 10.0 };
+        let distances = scan_distances(&items, &point);
+        for k in [1, 3, 5, 9, 13] {
+            let nearest = tree.nearest(&point, k);
+            assert_eq!(nearest.len(), k);
+            // The four neighbours at distance 1 tie, so only the distances
+            // are fixed.
+            assert_eq!(
+                nearest.iter().map(|(_, d)| *d).collect::<Vec<f64>>(),
+                distances[..k]
+            );
+        }
+        assert_eq!(tree.nearest(&point, 1000).len(), items.len());
+        assert!(RTree::<Item>::new().nearest(&point, 3).is_empty());
+    }
+
+    #[test]
+    fn radius_includes_items_exactly_at_it() {
+        let items = grid_points();
+        let tree = RTree::bulk_load_with_max_children(items.clone(), 5);
+        let point = Point { x: 7.0, y: 4.0 };
+        for radius in [0.0, 1.0, 2.0, 5.0, 2.5] {
+            let expected = sorted(
+                items
+                    .iter()
+                    .filter(|i| i.distance_squared_to_point(&point) <= radius * radius)
+                    .map(|i| i.label),
+            );
+            assert_eq!(
+                sorted(tree.within_radius(&point, radius).map(|i| i.label)),
+                expected
+            );
+        }
+        // Exactly two steps away along each axis, plus the point itself and
+        // everything between.
+        assert_eq!(tree.within_radius(&point, 2.0).count(), 13);
+        assert_eq!(tree.within_radius(&point, 0.0).count(), 1);
+    }
+}
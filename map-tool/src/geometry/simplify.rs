@@ -0,0 +1,340 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+use super::point::{LineSegment, Point, Polygon};
+use super::projection::LocalProjection;
+
+/// Vertex reduction for polylines and rings. Tolerances are in metres.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Simplification {
+    /// Drops vertices closer than `tolerance` to the simplified line.
+    DouglasPeucker { tolerance: f64 },
+    /// Drops vertices whose triangle with their neighbours has an area
+    /// smaller than `tolerance` squared.
+    Visvalingam { tolerance: f64 },
+}
+
+impl Simplification {
+    pub fn apply(&self, points: &[Point]) -> Vec<Point> {
+        match *self {
+            Simplification::DouglasPeucker { tolerance } => douglas_peucker(points, tolerance),
+            Simplification::Visvalingam { tolerance } => visvalingam(points, tolerance),
+        }
+    }
+
+    /// Simplifies a ring, keeping at least a triangle. The ring may or may
+    /// not repeat its first vertex at the end, and the result does the same.
+    pub fn apply_to_ring(&self, ring: &[Point]) -> Vec<Point> {
+        let closed = is_closed(ring);
+        let open = if closed {
+            &ring[..ring.len() - 1]
+        } else {
+            ring
+        };
+        if open.len() <= 3 {
+            return ring.to_vec();
+        }
+
+        // Anchor the ring at its first vertex and the vertex farthest from it
+        // and simplify the two halves as polylines.
+        let projection = LocalProjection::new(&open[0]);
+        let far = (1..open.len())
+            .max_by(|i, j| {
+                let di = projection.distance(&open[0], &open[*i]);
+                let dj = projection.distance(&open[0], &open[*j]);
+                di.partial_cmp(&dj).unwrap_or(Ordering::Equal)
+            })
+            .unwrap();
+        let mut second_half = open[far..].to_vec();
+        second_half.push(open[0].clone());
+
+        let mut result = self.apply(&open[..=far]);
+        result.pop();
+        result.append(&mut self.apply(&second_half));
+        result.pop();
+
+        if result.len() < 3 {
+            return ring.to_vec();
+        }
+        if closed {
+            result.push(result[0].clone());
+        }
+        result
+    }
+}
+
+impl Polygon {
+    pub fn simplified(&self, simplification: &Simplification) -> Polygon {
+        self.map_rings(|r| simplification.apply_to_ring(r))
+    }
+
+    pub fn densified(&self, max_distance: f64) -> Polygon {
+        self.map_rings(|r| densify_ring(r, max_distance))
+    }
+
+    fn map_rings<F: Fn(&[Point]) -> Vec<Point>>(&self, f: F) -> Polygon {
+        Polygon::with_holes(
+            f(self.exterior()),
+            self.holes().iter().map(|h| f(h.exterior())).collect(),
+        )
+    }
+}
+
+fn is_closed(ring: &[Point]) -> bool {
+    ring.len() > 1 && ring[0].x == ring[ring.len() - 1].x && ring[0].y == ring[ring.len() - 1].y
+}
+
+pub fn douglas_peucker(points: &[Point], tolerance: f64) -> Vec<Point> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let projection = LocalProjection::new(&points[0]);
+    let projected: Vec<Point> = points.iter().map(|p| projection.to_metres(p)).collect();
+    let tolerance_squared = tolerance * tolerance;
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let base = LineSegment {
+            a: projected[first].clone(),
+            b: projected[last].clone(),
+        };
+        let farthest = (first + 1..last)
+            .map(|i| (i, base.distance_squared_to_point(&projected[i])))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+
+        if let Some((i, distance_squared)) = farthest {
+            if distance_squared > tolerance_squared {
+                keep[i] = true;
+                stack.push((first, i));
+                stack.push((i, last));
+            }
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, k)| *k)
+        .map(|(p, _)| p.clone())
+        .collect()
+}
+
+struct Candidate {
+    area: f64,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    // Reversed so that the binary heap pops the smallest area first.
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        other
+            .area
+            .partial_cmp(&self.area)
+            .unwrap_or(Ordering::Equal)
+            .then(other.index.cmp(&self.index))
+    }
+}
+
+fn triangle_area(a: &Point, b: &Point, c: &Point) -> f64 {
+    ((b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y)).abs() / 2.0
+}
+
+pub fn visvalingam(points: &[Point], tolerance: f64) -> Vec<Point> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let projection = LocalProjection::new(&points[0]);
+    let projected: Vec<Point> = points.iter().map(|p| projection.to_metres(p)).collect();
+    let threshold = tolerance * tolerance;
+
+    let mut previous: Vec<usize> = (0..points.len()).map(|i| i.wrapping_sub(1)).collect();
+    let mut next: Vec<usize> = (1..=points.len()).collect();
+    let mut areas = vec![f64::INFINITY; points.len()];
+    let mut removed = vec![false; points.len()];
+
+    let mut heap = BinaryHeap::new();
+    for i in 1..points.len() - 1 {
+        areas[i] = triangle_area(&projected[i - 1], &projected[i], &projected[i + 1]);
+        heap.push(Candidate {
+            area: areas[i],
+            index: i,
+        });
+    }
+
+    while let Some(Candidate { area, index }) = heap.pop() {
+        if removed[index] || area != areas[index] {
+            continue;
+        }
+        if area >= threshold {
+            break;
+        }
+
+        removed[index] = true;
+        let (p, n) = (previous[index], next[index]);
+        next[p] = n;
+        previous[n] = p;
+
+        for neighbour in [p, n] {
+            if neighbour == 0 || neighbour == points.len() - 1 {
+                continue;
+            }
+            // A neighbour never gets a smaller area than the removed point,
+            // otherwise it would be removed before points that were
+            // eliminated earlier.
+            let new_area = triangle_area(
+                &projected[previous[neighbour]],
+                &projected[neighbour],
+                &projected[next[neighbour]],
+            )
+            .max(area);
+            areas[neighbour] = new_area;
+            heap.push(Candidate {
+                area: new_area,
+                index: neighbour,
+            });
+        }
+    }
+
+    points
+        .iter()
+        .zip(removed)
+        .filter(|(_, r)| !*r)
+        .map(|(p, _)| p.clone())
+        .collect()
+}
+
+/// Inserts evenly spaced points so that no segment is longer than
+/// `max_distance` metres.
+pub fn densify(points: &[Point], max_distance: f64) -> Vec<Point> {
+    if points.len() < 2 || max_distance <= 0.0 {
+        return points.to_vec();
+    }
+
+    let projection = LocalProjection::new(&points[0]);
+    let mut result = vec![points[0].clone()];
+    for pair in points.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let steps = (projection.distance(a, b) / max_distance).ceil().max(1.0) as usize;
+        for step in 1..steps {
+            let t = step as f64 / steps as f64;
+            result.push(Point {
+                x: a.x + (b.x - a.x) * t,
+                y: a.y + (b.y - a.y) * t,
+            });
+        }
+        result.push(b.clone());
+    }
+    result
+}
+
+fn densify_ring(ring: &[Point], max_distance: f64) -> Vec<Point> {
+    if ring.is_empty() || is_closed(ring) {
+        return densify(ring, max_distance);
+    }
+
+    let mut closed = ring.to_vec();
+    closed.push(ring[0].clone());
+    let mut result = densify(&closed, max_distance);
+    result.pop();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points given in metres around 23° E, 61° N, in degrees.
+    fn line(metres: &[(f64, f64)]) -> (LocalProjection, Vec<Point>) {
+        let projection = LocalProjection::new(&Point { x: 23.0, y: 61.0 });
+        let points = metres
+            .iter()
+            .map(|(x, y)| projection.to_degrees(&Point { x: *x, y: *y }))
+            .collect();
+        (projection, points)
+    }
+
+    fn xy(points: &[Point]) -> Vec<(f64, f64)> {
+        points.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn douglas_peucker_keeps_vertices_beyond_the_tolerance() {
+        let (_, points) = line(&[
+            (0.0, 0.0),
+            (10.0, 0.4),
+            (20.0, -0.3),
+            (30.0, 0.0),
+            (30.3, 10.0),
+            (29.6, 20.0),
+            (30.0, 30.0),
+        ]);
+        let simplified = douglas_peucker(&points, 1.0);
+        let expected = [points[0].clone(), points[3].clone(), points[6].clone()];
+        assert_eq!(xy(&simplified), xy(&expected));
+
+        // Everything stays when the tolerance is below every offset.
+        assert_eq!(xy(&douglas_peucker(&points, 0.1)), xy(&points));
+    }
+
+    #[test]
+    fn douglas_peucker_keeps_the_endpoints() {
+        let (_, points) = line(&[(0.0, 0.0), (1.0, 0.1), (2.0, 0.0), (3.0, -0.1), (4.0, 0.0)]);
+        let simplified = douglas_peucker(&points, 100.0);
+        assert_eq!(xy(&simplified), xy(&[points[0].clone(), points[4].clone()]));
+        assert_eq!(xy(&douglas_peucker(&points[..2], 100.0)), xy(&points[..2]));
+    }
+
+    #[test]
+    fn dropped_vertices_stay_within_the_tolerance() {
+        let metres: Vec<(f64, f64)> = (0..200)
+            .map(|i| {
+                let x = i as f64;
+                (x, 5.0 * (x / 15.0).sin() + 0.5 * (x * 1.7).sin())
+            })
+            .collect();
+        let (projection, points) = line(&metres);
+        let tolerance = 1.0;
+        let simplified: Vec<Point> = douglas_peucker(&points, tolerance)
+            .iter()
+            .map(|p| projection.to_metres(p))
+            .collect();
+        assert!(simplified.len() < points.len() / 4);
+        for point in points.iter().map(|p| projection.to_metres(p)) {
+            let distance = simplified
+                .windows(2)
+                .map(|pair| {
+                    LineSegment {
+                        a: pair[0].clone(),
+                        b: pair[1].clone(),
+                    }
+                    .distance_squared_to_point(&point)
+                })
+                .fold(f64::INFINITY, f64::min)
+                .sqrt();
+            assert!(distance <= tolerance + 1e-6, "{distance}");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+
+use crate::raster::Raster;
+
+/// Terrain-RGB heights are `TERRAIN_RGB_OFFSET + value * TERRAIN_RGB_SCALE`
+/// metres, with the value packed into the red, green and blue bytes.
+pub const TERRAIN_RGB_OFFSET: f64 = -10000.0;
+pub const TERRAIN_RGB_SCALE: f64 = 0.1;
+
+/// Pixel value of missing cells in the gray16 and Terrain-RGB images. Heights
+/// are clamped to start from 1, so a real height never encodes to it.
+pub const HEIGHT_IMAGE_NODATA: u32 = 0;
+
+/// Scale and offset of the 16-bit grayscale height image. A pixel value `v`
+/// is `offset + v * scale` metres, except for `HEIGHT_IMAGE_NODATA`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct HeightImageConfig {
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl Default for HeightImageConfig {
+    fn default() -> HeightImageConfig {
+        // Centimetres from -99.99 m up to 555.35 m.
+        HeightImageConfig {
+            scale: 0.01,
+            offset: -100.0,
+        }
+    }
+}
+
+impl HeightImageConfig {
+    /// Scale and offset with the value after `HEIGHT_IMAGE_NODATA` at `min`
+    /// and the largest value at `max`.
+    pub fn spanning(min: f64, max: f64) -> HeightImageConfig {
+        let scale = (max - min) / (u16::MAX as u32 - HEIGHT_IMAGE_NODATA - 1) as f64;
+        HeightImageConfig {
+            scale,
+            offset: min - (HEIGHT_IMAGE_NODATA + 1) as f64 * scale,
+        }
+    }
+
+    pub fn encode(&self, height: Option<f64>) -> u16 {
+        match height {
+            Some(height) => ((height - self.offset) / self.scale)
+                .round()
+                .clamp((HEIGHT_IMAGE_NODATA + 1) as f64, u16::MAX as f64)
+                as u16,
+            None => HEIGHT_IMAGE_NODATA as u16,
+        }
+    }
+
+    pub fn decode(&self, value: u16) -> Option<f64> {
+        if value as u32 == HEIGHT_IMAGE_NODATA {
+            return None;
+        }
+        Some(self.offset + value as f64 * self.scale)
+    }
+}
+
+/// Georeference written next to a height image.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageGeoreference {
+    /// `gray8`, `gray16`, `rgb8`, `rgba8` or `terrain-rgb`.
+    pub encoding: String,
+    pub width: usize,
+    pub height: usize,
+    pub crs: String,
+    /// West, south, east and north edges of the image in degrees.
+    pub bounds: [f64; 4],
+    /// Width and height of a pixel in degrees. Rows run from north to south.
+    pub pixel_size: [f64; 2],
+    /// Height in metres is `offset + value * scale`, where the value of a
+    /// Terrain-RGB pixel is `r * 65536 + g * 256 + b`.
+    pub scale: f64,
+    pub offset: f64,
+    /// Value of pixels without data, if the encoding reserves one.
+    pub nodata: Option<u32>,
+}
+
+/// Big-endian 16-bit samples in row order, as PNG stores them. Missing
+/// cells are `HEIGHT_IMAGE_NODATA`.
+pub fn encode_gray16(raster: &Raster, config: &HeightImageConfig) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(raster.width() * raster.height() * 2);
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            let value = config.encode(raster.get(x, y));
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    bytes
+}
+
+/// RGB samples in row order. Missing cells are `HEIGHT_IMAGE_NODATA`, which
+/// would otherwise be -10000 m.
+pub fn encode_terrain_rgb(raster: &Raster) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(raster.width() * raster.height() * 3);
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            let value = match raster.get(x, y) {
+                Some(height) => ((height - TERRAIN_RGB_OFFSET) / TERRAIN_RGB_SCALE)
+                    .round()
+                    .clamp((HEIGHT_IMAGE_NODATA + 1) as f64, 16_777_215.0)
+                    as u32,
+                None => HEIGHT_IMAGE_NODATA,
+            };
+            bytes.extend_from_slice(&value.to_be_bytes()[1..]);
+        }
+    }
+    bytes
+}
+
+pub fn decode_terrain_rgb(rgb: [u8; 3]) -> Option<f64> {
+    let value = (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+    if value == HEIGHT_IMAGE_NODATA {
+        return None;
+    }
+    Some(TERRAIN_RGB_OFFSET + value as f64 * TERRAIN_RGB_SCALE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heights() -> Raster {
+        let mut raster = Raster::from_rows(
+            &[
+                vec![-100.0, 0.0, 0.004, 81.25],
+                vec![123.456, 555.35, -3.2, 17.0],
+            ],
+            None,
+        );
+        raster.set(3, 1, None);
+        raster
+    }
+
+    #[test]
+    fn gray16_roundtrip() {
+        let config = HeightImageConfig::default();
+        let raster = heights();
+        let bytes = encode_gray16(&raster, &config);
+        assert_eq!(bytes.len(), 4 * 2 * 2);
+        for (i, pixel) in bytes.chunks(2).enumerate() {
+            let decoded = config.decode(u16::from_be_bytes([pixel[0], pixel[1]]));
+            match raster.get(i % 4, i / 4) {
+                // The lowest height is clamped to the value after nodata.
+                Some(height) => assert!((decoded.unwrap() - height).abs() <= 0.01 + 1e-9),
+                None => assert_eq!(decoded, None),
+            }
+        }
+        // A real 0 m stays apart from a missing cell.
+        assert_ne!(config.encode(Some(0.0)), config.encode(None));
+    }
+
+    #[test]
+    fn spanning_range_keeps_nodata_free() {
+        let config = HeightImageConfig::spanning(-1.0, 1.0);
+        assert_eq!(config.encode(Some(-1.0)), 1);
+        assert_eq!(config.encode(Some(1.0)), u16::MAX);
+        assert!((config.decode(1).unwrap() + 1.0).abs() < 1e-12);
+        assert!((config.decode(u16::MAX).unwrap() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn terrain_rgb_roundtrip() {
+        let raster = heights();
+        let bytes = encode_terrain_rgb(&raster);
+        assert_eq!(bytes.len(), 4 * 2 * 3);
+        for (i, pixel) in bytes.chunks(3).enumerate() {
+            let decoded = decode_terrain_rgb([pixel[0], pixel[1], pixel[2]]);
+            match raster.get(i % 4, i / 4) {
+                Some(height) => assert!((decoded.unwrap() - height).abs() <= 0.05 + 1e-9),
+                None => assert_eq!(decoded, None),
+            }
+        }
+        // 0 m is value 100000, 0x0186a0.
+        assert_eq!(&bytes[3..6], &[0x01, 0x86, 0xa0]);
+    }
+}
@@ -0,0 +1,558 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::raster::Raster;
+
+/// Neighbour offsets in raster cells, counterclockwise from east. Rows run
+/// from north to south, so north is `-1` along y. A D8 direction is an index
+/// into this table.
+pub const NEIGHBOURS: [(i64, i64); 8] = [
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// The eight triangular facets around a cell for D-infinity, each a
+/// cardinal and a diagonal neighbour.
+const FACETS: [(usize, usize); 8] = [
+    (0, 1),
+    (2, 1),
+    (2, 3),
+    (4, 3),
+    (4, 5),
+    (6, 5),
+    (6, 7),
+    (0, 7),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowMethod {
+    /// All flow goes to the steepest of the eight neighbours.
+    #[default]
+    D8,
+    /// Flow follows the steepest downhill direction over triangular facets
+    /// and is split between the two neighbours around it (Tarboton 1997).
+    DInfinity,
+}
+
+/// Options for deriving water flow from the height map.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct HydrologyConfig {
+    /// Flow routing used for the accumulation that streams are cut from.
+    pub flow: FlowMethod,
+    /// Upstream area in square metres a cell needs to start a stream.
+    pub stream_threshold: f64,
+    /// Height in metres each filled cell is raised above the cell it drains
+    /// to, so filled depressions still slope towards their outlet.
+    pub fill_increment: f64,
+}
+
+impl Default for HydrologyConfig {
+    fn default() -> HydrologyConfig {
+        HydrologyConfig {
+            flow: FlowMethod::D8,
+            stream_threshold: 50_000.0,
+            fill_increment: 0.001,
+        }
+    }
+}
+
+/// D-infinity flow out of a cell.
+#[derive(Debug, Clone, Copy)]
+pub struct DinfFlow {
+    /// Flow direction in radians counterclockwise from east.
+    pub angle: f64,
+    /// The two neighbours the flow is split between, as indices into
+    /// `NEIGHBOURS`, and the share each receives.
+    pub targets: [(u8, f64); 2],
+}
+
+pub struct Hydrology {
+    /// Heights with depressions filled.
+    pub filled: Raster,
+    pub d8: Vec<Option<u8>>,
+    pub dinf: Vec<Option<DinfFlow>>,
+    /// Upstream area of each cell in square metres, including the cell,
+    /// with the configured flow method.
+    pub accumulation: Raster,
+    /// Stream lines in raster cells from their upstream end down to a
+    /// junction, a sink or the edge of the raster.
+    pub streams: Vec<Vec<(usize, usize)>>,
+}
+
+/// Routes water over `heights` in metres, rows running from north to south
+/// and `spacing` the cell size in metres. Water ends in `sinks`, which hold
+/// one flag per cell in row order, in missing cells and off the edges.
+pub fn analyse(
+    heights: &Raster,
+    sinks: &[bool],
+    spacing: (f64, f64),
+    config: &HydrologyConfig,
+) -> Hydrology {
+    let filled = fill_depressions(heights, sinks, config.fill_increment);
+    let d8 = d8_directions(&filled, sinks, spacing);
+    let dinf = dinf_directions(&filled, sinks, spacing);
+    let accumulation = match config.flow {
+        FlowMethod::D8 => d8_accumulation(&filled, &d8, spacing),
+        FlowMethod::DInfinity => dinf_accumulation(&filled, &dinf, spacing),
+    };
+    let streams = extract_streams(&accumulation, &d8, sinks, config.stream_threshold);
+    Hydrology {
+        filled,
+        d8,
+        dinf,
+        accumulation,
+        streams,
+    }
+}
+
+#[derive(PartialEq)]
+struct FloodCell {
+    height: f64,
+    order: usize,
+    index: usize,
+}
+
+impl Eq for FloodCell {}
+
+impl Ord for FloodCell {
+    /// Lowest first, ties in the order the cells were queued.
+    fn cmp(&self, other: &FloodCell) -> Ordering {
+        other
+            .height
+            .total_cmp(&self.height)
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &FloodCell) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn neighbour(raster: &Raster, index: usize, direction: usize) -> Option<usize> {
+    let (width, height) = (raster.width() as i64, raster.height() as i64);
+    let (dx, dy) = NEIGHBOURS[direction];
+    let x = (index as i64 % width) + dx;
+    let y = (index as i64 / width) + dy;
+    if x < 0 || y < 0 || x >= width || y >= height {
+        return None;
+    }
+    Some((y * width + x) as usize)
+}
+
+fn value(raster: &Raster, index: usize) -> Option<f64> {
+    raster.get(index % raster.width(), index / raster.width())
+}
+
+fn is_outlet(raster: &Raster, sinks: &[bool], index: usize) -> bool {
+    sinks[index] || value(raster, index).is_none()
+}
+
+/// Distance in metres to the neighbour in `direction`.
+fn step_length(direction: usize, (dx, dy): (f64, f64)) -> f64 {
+    let (ox, oy) = NEIGHBOURS[direction];
+    (ox as f64 * dx).hypot(oy as f64 * dy)
+}
+
+/// Raises every cell that has no downhill path to a sink, a missing cell or
+/// the edge until it has one (priority flood, Barnes et al. 2014). Sinks
+/// and missing cells keep their heights.
+pub fn fill_depressions(heights: &Raster, sinks: &[bool], increment: f64) -> Raster {
+    let (width, height) = (heights.width(), heights.height());
+    let mut filled = heights.clone();
+    let mut queued = vec![false; width * height];
+    let mut queue = BinaryHeap::new();
+    let mut order = 0;
+
+    for (index, is_queued) in queued.iter_mut().enumerate() {
+        let (x, y) = (index % width, index / width);
+        let edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+        if edge || is_outlet(heights, sinks, index) {
+            *is_queued = true;
+            queue.push(FloodCell {
+                height: value(heights, index).unwrap_or(f64::NEG_INFINITY),
+                order,
+                index,
+            });
+            order += 1;
+        }
+    }
+
+    while let Some(cell) = queue.pop() {
+        for direction in 0..NEIGHBOURS.len() {
+            let next = match neighbour(heights, cell.index, direction) {
+                Some(next) if !queued[next] => next,
+                _ => continue,
+            };
+            queued[next] = true;
+            let original = value(heights, next).unwrap_or(f64::NEG_INFINITY);
+            let raised = if cell.height.is_finite() {
+                original.max(cell.height + increment)
+            } else {
+                original
+            };
+            filled.set(next % width, next / width, Some(raised));
+            queue.push(FloodCell {
+                height: raised,
+                order,
+                index: next,
+            });
+            order += 1;
+        }
+    }
+    filled
+}
+
+/// Steepest downhill neighbour of every cell. Sinks, missing cells and edge
+/// cells without a lower neighbour have no direction.
+pub fn d8_directions(filled: &Raster, sinks: &[bool], spacing: (f64, f64)) -> Vec<Option<u8>> {
+    (0..filled.width() * filled.height())
+        .map(|index| {
+            if is_outlet(filled, sinks, index) {
+                return None;
+            }
+            let center = value(filled, index).unwrap();
+            let mut best = None;
+            let mut steepest = 0.0;
+            for direction in 0..NEIGHBOURS.len() {
+                let next = match neighbour(filled, index, direction) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                let drop = match value(filled, next) {
+                    Some(h) => center - h,
+                    // Missing cells take all the water that reaches them.
+                    None => f64::INFINITY,
+                };
+                let slope = drop / step_length(direction, spacing);
+                if slope > steepest {
+                    steepest = slope;
+                    best = Some(direction as u8);
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+/// Unit vector in metres east and north towards a neighbour.
+fn unit(direction: usize, (dx, dy): (f64, f64)) -> (f64, f64) {
+    let (ox, oy) = NEIGHBOURS[direction];
+    let (east, north) = (ox as f64 * dx, -oy as f64 * dy);
+    let length = east.hypot(north);
+    (east / length, north / length)
+}
+
+/// Steepest direction over the eight facets around every cell.
+pub fn dinf_directions(
+    filled: &Raster,
+    sinks: &[bool],
+    spacing: (f64, f64),
+) -> Vec<Option<DinfFlow>> {
+    (0..filled.width() * filled.height())
+        .map(|index| {
+            if is_outlet(filled, sinks, index) {
+                return None;
+            }
+            let center = value(filled, index).unwrap();
+            let mut best: Option<(f64, DinfFlow)> = None;
+            for (cardinal, diagonal) in FACETS {
+                // Facets off the edge or over missing cells are left out,
+                // water next to them drains with the remaining facets.
+                let height =
+                    |direction| neighbour(filled, index, direction).and_then(|n| value(filled, n));
+                let (e1, e2) = match (height(cardinal), height(diagonal)) {
+                    (Some(e1), Some(e2)) => (e1, e2),
+                    _ => continue,
+                };
+
+                // Distances to the cardinal neighbour and from it across to
+                // the diagonal one.
+                let d1 = step_length(cardinal, spacing);
+                let d2 = step_length(diagonal, spacing).powi(2) - d1.powi(2);
+                let d2 = d2.max(0.0).sqrt();
+                let s1 = (center - e1) / d1;
+                let s2 = (e1 - e2) / d2;
+                let max_angle = d2.atan2(d1);
+                let (mut r, mut slope) = (s2.atan2(s1), s1.hypot(s2));
+                if r < 0.0 {
+                    r = 0.0;
+                    slope = s1;
+                } else if r > max_angle {
+                    r = max_angle;
+                    slope = (center - e2) / step_length(diagonal, spacing);
+                }
+                if slope <= 0.0 || best.as_ref().is_some_and(|(s, _)| slope <= *s) {
+                    continue;
+                }
+
+                let (c, g) = (unit(cardinal, spacing), unit(diagonal, spacing));
+                let across = (g.0 * d1.hypot(d2) - c.0 * d1, g.1 * d1.hypot(d2) - c.1 * d1);
+                let across_length = across.0.hypot(across.1);
+                let flow = (
+                    c.0 * r.cos() + across.0 / across_length * r.sin(),
+                    c.1 * r.cos() + across.1 / across_length * r.sin(),
+                );
+                let share = r / max_angle;
+                best = Some((
+                    slope,
+                    DinfFlow {
+                        angle: flow.1.atan2(flow.0).rem_euclid(std::f64::consts::TAU),
+                        targets: [(cardinal as u8, 1.0 - share), (diagonal as u8, share)],
+                    },
+                ));
+            }
+            best.map(|(_, flow)| flow)
+        })
+        .collect()
+}
+
+/// Cells from the highest filled height down, so every cell comes after
+/// all the cells draining into it.
+fn downhill_order(filled: &Raster) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..filled.width() * filled.height())
+        .filter(|i| value(filled, *i).is_some())
+        .collect();
+    order.sort_by(|a, b| {
+        value(filled, *b)
+            .unwrap()
+            .total_cmp(&value(filled, *a).unwrap())
+            .then(a.cmp(b))
+    });
+    order
+}
+
+fn accumulate<F: Fn(usize) -> Vec<(usize, f64)>>(
+    filled: &Raster,
+    spacing: (f64, f64),
+    outflow: F,
+) -> Raster {
+    let cell_area = spacing.0 * spacing.1;
+    let mut area = vec![0.0; filled.width() * filled.height()];
+    for index in downhill_order(filled) {
+        area[index] += cell_area;
+        for (next, share) in outflow(index) {
+            area[next] += area[index] * share;
+        }
+    }
+
+    let mut accumulation = Raster::new(filled.width(), filled.height());
+    for (index, a) in area.iter().enumerate() {
+        if value(filled, index).is_some() {
+            accumulation.set(index % filled.width(), index / filled.width(), Some(*a));
+        }
+    }
+    accumulation
+}
+
+/// Upstream area of every cell in square metres with D8 routing.
+pub fn d8_accumulation(filled: &Raster, directions: &[Option<u8>], spacing: (f64, f64)) -> Raster {
+    accumulate(filled, spacing, |index| {
+        directions[index]
+            .and_then(|d| neighbour(filled, index, d as usize))
+            .map(|next| vec![(next, 1.0)])
+            .unwrap_or_default()
+    })
+}
+
+/// Upstream area of every cell in square metres with D-infinity routing.
+pub fn dinf_accumulation(
+    filled: &Raster,
+    flows: &[Option<DinfFlow>],
+    spacing: (f64, f64),
+) -> Raster {
+    accumulate(filled, spacing, |index| match &flows[index] {
+        Some(flow) => flow
+            .targets
+            .iter()
+            .filter(|(_, share)| *share > 0.0)
+            .filter_map(|(d, share)| neighbour(filled, index, *d as usize).map(|n| (n, *share)))
+            .collect(),
+        None => Vec::new(),
+    })
+}
+
+/// Traces the cells with at least `threshold` upstream area down their D8
+/// directions. Every stream starts at a cell no other stream cell drains
+/// into and ends at the first cell that is a sink, already part of another
+/// stream or has no direction, so tributaries end on the stream they join.
+pub fn extract_streams(
+    accumulation: &Raster,
+    directions: &[Option<u8>],
+    sinks: &[bool],
+    threshold: f64,
+) -> Vec<Vec<(usize, usize)>> {
+    let width = accumulation.width();
+    let count = width * accumulation.height();
+    let is_stream =
+        |index: usize| !sinks[index] && value(accumulation, index).is_some_and(|a| a >= threshold);
+    let downstream =
+        |index: usize| directions[index].and_then(|d| neighbour(accumulation, index, d as usize));
+
+    let mut fed = vec![false; count];
+    for index in (0..count).filter(|i| is_stream(*i)) {
+        if let Some(next) = downstream(index) {
+            fed[next] = true;
+        }
+    }
+
+    let mut visited = vec![false; count];
+    let mut streams = Vec::new();
+    for head in (0..count).filter(|i| is_stream(*i) && !fed[*i]) {
+        let mut line = Vec::new();
+        let mut index = head;
+        loop {
+            line.push((index % width, index / width));
+            if visited[index] || !is_stream(index) {
+                break;
+            }
+            visited[index] = true;
+            match downstream(index) {
+                Some(next) => index = next,
+                None => break,
+            }
+        }
+        if line.len() > 1 {
+            streams.push(line);
+        }
+    }
+    streams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster<F: Fn(usize, usize) -> f64>(width: usize, height: usize, h: F) -> Raster {
+        let rows: Vec<Vec<f64>> = (0..height)
+            .map(|y| (0..width).map(|x| h(x, y)).collect())
+            .collect();
+        Raster::from_rows(&rows, None)
+    }
+
+    #[test]
+    fn pits_are_filled_unless_they_are_sinks() {
+        let heights = raster(5, 5, |x, y| if (x, y) == (2, 2) { 2.0 } else { 10.0 });
+        let sinks = vec![false; 25];
+        let filled = fill_depressions(&heights, &sinks, 0.001);
+        // The pit and the flat around it slope up away from the edge.
+        assert!((filled.get(1, 2).unwrap() - 10.001).abs() < 1e-9);
+        assert!((filled.get(2, 2).unwrap() - 10.002).abs() < 1e-9);
+        assert_eq!(filled.get(0, 2), Some(10.0));
+
+        // Every cell now drains down to the edge.
+        let directions = d8_directions(&filled, &sinks, (1.0, 1.0));
+        let mut index = 12;
+        while let Some(d) = directions[index] {
+            index = neighbour(&filled, index, d as usize).unwrap();
+        }
+        let (x, y) = (index % 5, index / 5);
+        assert!(x == 0 || y == 0 || x == 4 || y == 4);
+
+        let mut sinks = sinks;
+        sinks[12] = true;
+        let filled = fill_depressions(&heights, &sinks, 0.001);
+        assert_eq!(filled.get(2, 2), Some(2.0));
+        assert_eq!(filled.get(1, 2), Some(10.0));
+    }
+
+    #[test]
+    fn d8_follows_a_tilted_plane_downhill() {
+        // Rising one metre per cell east and two per cell south, steepest
+        // towards the north-west.
+        let heights = raster(5, 5, |x, y| x as f64 + 2.0 * y as f64);
+        let directions = d8_directions(&heights, &[false; 25], (1.0, 1.0));
+        for y in 1..5 {
+            for x in 1..5 {
+                assert_eq!(directions[y * 5 + x], Some(3));
+            }
+        }
+        // Wide cells make the drop to the west shallow, so water goes north.
+        let directions = d8_directions(&heights, &[false; 25], (10.0, 1.0));
+        assert_eq!(directions[12], Some(2));
+        // The lowest corner has nowhere to go.
+        assert_eq!(directions[0], None);
+    }
+
+    #[test]
+    fn d_infinity_splits_flow_between_two_neighbours() {
+        // Downhill is half a metre north for every metre west, between the
+        // west and the north-west neighbours.
+        let heights = raster(5, 5, |x, y| x as f64 + 0.5 * y as f64);
+        let flows = dinf_directions(&heights, &[false; 25], (1.0, 1.0));
+        let flow = flows[12].unwrap();
+        let r = 0.5f64.atan();
+        assert!((flow.angle - (std::f64::consts::PI - r)).abs() < 1e-9);
+        let [(west, west_share), (north_west, north_west_share)] = flow.targets;
+        assert_eq!((west, north_west), (4, 3));
+        let share = r / std::f64::consts::FRAC_PI_4;
+        assert!((west_share - (1.0 - share)).abs() < 1e-9);
+        assert!((north_west_share - share).abs() < 1e-9);
+
+        let accumulation = dinf_accumulation(&heights, &flows, (1.0, 1.0));
+        // The cell south-east of the middle sends its shares to the middle
+        // and to the cell north of it.
+        let above = accumulation.get(3, 2).unwrap() - 1.0;
+        assert!(above > 0.0 && above < accumulation.get(3, 3).unwrap());
+    }
+
+    /// A valley along x = 2 sloping down to the north edge, with the sides
+    /// ten times steeper than the floor.
+    fn valley() -> Raster {
+        raster(5, 4, |x, y| 10.0 * x.abs_diff(2) as f64 + y as f64)
+    }
+
+    #[test]
+    fn accumulation_gathers_in_a_valley() {
+        let heights = valley();
+        let sinks = [false; 20];
+        let filled = fill_depressions(&heights, &sinks, 0.001);
+        let directions = d8_directions(&filled, &sinks, (2.0, 2.0));
+        let accumulation = d8_accumulation(&filled, &directions, (2.0, 2.0));
+        for y in 0..4 {
+            let row: Vec<f64> = (0..5).map(|x| accumulation.get(x, y).unwrap()).collect();
+            let floor = 4.0 * 5.0 * (4 - y) as f64;
+            assert_eq!(row, vec![4.0, 8.0, floor, 8.0, 4.0]);
+        }
+    }
+
+    #[test]
+    fn streams_start_where_the_area_reaches_the_threshold() {
+        let config = HydrologyConfig {
+            stream_threshold: 12.0,
+            ..HydrologyConfig::default()
+        };
+        let hydrology = analyse(&valley(), &[false; 20], (1.0, 1.0), &config);
+        // The floor gathers 5, 10, 15 and 20 square metres going north.
+        assert_eq!(hydrology.streams, vec![vec![(2, 1), (2, 0)]]);
+
+        let config = HydrologyConfig {
+            stream_threshold: 2.0,
+            ..config
+        };
+        let hydrology = analyse(&valley(), &[false; 20], (1.0, 1.0), &config);
+        // The side cells next to the floor start streams of their own, and
+        // every cell over the threshold is on one.
+        for stream in hydrology.streams.iter() {
+            let (x, y) = stream[0];
+            assert_eq!(x.abs_diff(2), 1);
+            assert!(hydrology.accumulation.get(x, y).unwrap() >= 2.0);
+        }
+        for y in 0..4 {
+            for x in 1..4 {
+                assert!(hydrology.streams.iter().flatten().any(|c| *c == (x, y)));
+            }
+        }
+    }
+}
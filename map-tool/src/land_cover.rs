@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::map::MapTileClass;
+
+/// Width in metres of line features whose class sets none.
+pub const DEFAULT_LINE_WIDTH: f64 = 2.0;
+
+/// Class a tile can have, with the features it comes from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LandCoverClass {
+    pub id: u16,
+    /// Name used in the class map.
+    pub name: String,
+    /// Red, green and blue.
+    pub colour: [u8; 3],
+    /// Where features of several classes overlap, the tile gets the class
+    /// with the highest priority.
+    pub priority: i32,
+    /// Surface material of tiles of this class, like `asphalt` or `grass`.
+    #[serde(default)]
+    pub material: Option<String>,
+    /// Width in metres of the line features of this class,
+    /// `DEFAULT_LINE_WIDTH` when not set.
+    #[serde(default)]
+    pub line_width: Option<f64>,
+    /// Collections whose features have this class. Empty for classes that
+    /// come from the road, lake and building layers or from the height map.
+    #[serde(default)]
+    pub sources: Vec<ClassSource>,
+}
+
+/// Features of a collection, optionally only those with given `kohdeluokka`
+/// codes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClassSource {
+    pub collection: String,
+    /// Feature class codes of the collection in this class, all features
+    /// when empty.
+    #[serde(default)]
+    pub codes: Vec<i64>,
+}
+
+/// Classes of the map tiles. The classes with the ids of the `MapTileClass`
+/// constants are always present, listing one of them replaces its name,
+/// colour and priority.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LandCoverConfig {
+    pub classes: Vec<LandCoverClass>,
+    /// Fraction of a tile a class has to cover for the tile to get it.
+    pub coverage_threshold: f64,
+}
+
+impl Default for LandCoverConfig {
+    fn default() -> LandCoverConfig {
+        LandCoverConfig {
+            classes: vec![
+                class(10, "FOREST", [60, 120, 60], 10)
+                    .with_material("forest_floor")
+                    .with_sources(&[("metsamaankasvillisuus", &[])]),
+                class(11, "FIELD", [220, 200, 120], 20)
+                    .with_material("soil")
+                    .with_sources(&[("maatalousmaa", &[])]),
+                class(12, "MEADOW", [170, 210, 120], 20)
+                    .with_material("grass")
+                    .with_sources(&[("niitty", &[])]),
+                class(13, "MARSH", [120, 150, 130], 25)
+                    .with_material("peat")
+                    .with_sources(&[("suo", &[35411, 35412, 35421, 35422])]),
+                class(14, "ROCK", [150, 150, 150], 30)
+                    .with_material("rock")
+                    .with_sources(&[("kallioalue", &[])]),
+                class(15, "SAND", [230, 215, 160], 30)
+                    .with_material("sand")
+                    .with_sources(&[("hietikko", &[])]),
+                class(16, "PARK", [120, 190, 100], 35)
+                    .with_material("grass")
+                    .with_sources(&[("puisto", &[])]),
+                class(17, "SPORTS_FIELD", [200, 120, 80], 40)
+                    .with_material("grass")
+                    .with_sources(&[("urheilujavirkistysalue", &[])]),
+                class(18, "PARKING", [110, 110, 120], 45)
+                    .with_material("asphalt")
+                    .with_sources(&[("pysakointialue", &[])]),
+                class(19, "RIVER", [70, 110, 210], 70)
+                    .with_material("water")
+                    .with_line_width(5.0)
+                    .with_sources(&[("virtavesialue", &[]), ("virtavesikapea", &[])]),
+                class(20, "RAILWAY", [90, 60, 60], 75)
+                    .with_material("gravel")
+                    .with_line_width(4.0)
+                    .with_sources(&[("rautatie", &[])]),
+            ],
+            coverage_threshold: 0.5,
+        }
+    }
+}
+
+impl LandCoverConfig {
+    /// Every class, the built-in ones first, ordered by id.
+    pub fn all_classes(&self) -> Vec<LandCoverClass> {
+        let mut classes: HashMap<u16, LandCoverClass> = built_in_classes()
+            .into_iter()
+            .map(|class| (class.id, class))
+            .collect();
+        for class in self.classes.iter() {
+            classes.insert(class.id, class.clone());
+        }
+        let mut classes: Vec<LandCoverClass> = classes.into_values().collect();
+        classes.sort_by_key(|class| class.id);
+        classes
+    }
+
+    /// Collections any class reads features from.
+    pub fn collections(&self) -> Vec<String> {
+        let mut collections: Vec<String> = self
+            .classes
+            .iter()
+            .flat_map(|class| class.sources.iter().map(|s| s.collection.clone()))
+            .collect();
+        collections.sort();
+        collections.dedup();
+        collections
+    }
+
+    /// Class of a feature of `collection` with class code `code`.
+    pub fn classify(&self, collection: &str, code: Option<i64>) -> Option<MapTileClass> {
+        self.classes
+            .iter()
+            .filter(|class| {
+                class.sources.iter().any(|source| {
+                    source.collection == collection
+                        && (source.codes.is_empty()
+                            || code.is_some_and(|code| source.codes.contains(&code)))
+                })
+            })
+            .max_by_key(|class| class.priority)
+            .map(|class| MapTileClass(class.id))
+    }
+}
+
+fn built_in_classes() -> Vec<LandCoverClass> {
+    vec![
+        class(MapTileClass::EMPTY.0, "EMPTY", [0, 0, 0], 0).with_material("grass"),
+        class(MapTileClass::WATER.0, "WATER", [150, 50, 200], 100).with_material("water"),
+        class(MapTileClass::BUILDING.0, "BUILDING", [255, 180, 230], 90).with_material("roof"),
+        class(MapTileClass::ROAD.0, "ROAD", [150, 50, 50], 80)
+            .with_material("asphalt")
+            .with_line_width(6.0),
+        class(MapTileClass::STREAM.0, "STREAM", [60, 120, 220], 50).with_material("water"),
+    ]
+}
+
+/// Class without a material, line width or sources, which the `with_`
+/// methods add.
+fn class(id: u16, name: &str, colour: [u8; 3], priority: i32) -> LandCoverClass {
+    LandCoverClass {
+        id,
+        name: String::from(name),
+        colour,
+        priority,
+        material: None,
+        line_width: None,
+        sources: Vec::new(),
+    }
+}
+
+impl LandCoverClass {
+    fn with_material(mut self, material: &str) -> LandCoverClass {
+        self.material = Some(String::from(material));
+        self
+    }
+
+    fn with_line_width(mut self, width: f64) -> LandCoverClass {
+        self.line_width = Some(width);
+        self
+    }
+
+    fn with_sources(mut self, sources: &[(&str, &[i64])]) -> LandCoverClass {
+        self.sources = sources
+            .iter()
+            .map(|(collection, codes)| ClassSource {
+                collection: String::from(*collection),
+                codes: codes.to_vec(),
+            })
+            .collect();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(collection: &str, codes: &[i64]) -> ClassSource {
+        ClassSource {
+            collection: String::from(collection),
+            codes: codes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn features_take_the_class_with_the_highest_priority() {
+        let mut low = class(30, "LOW", [0, 0, 0], 10).with_material("grass");
+        low.sources = vec![source("suo", &[])];
+        let mut high = class(31, "HIGH", [0, 0, 0], 20).with_material("peat");
+        high.sources = vec![source("suo", &[35411])];
+        let config = LandCoverConfig {
+            classes: vec![high, low],
+            ..LandCoverConfig::default()
+        };
+        assert_eq!(config.classify("suo", Some(35411)), Some(MapTileClass(31)));
+        // Only the class taking every code of the collection matches.
+        assert_eq!(config.classify("suo", Some(35412)), Some(MapTileClass(30)));
+        assert_eq!(config.classify("suo", None), Some(MapTileClass(30)));
+        assert_eq!(config.classify("niitty", Some(35411)), None);
+        assert_eq!(config.collections(), vec![String::from("suo")]);
+    }
+
+    #[test]
+    fn configured_classes_replace_built_in_ones() {
+        let config = LandCoverConfig {
+            classes: vec![
+                class(MapTileClass::WATER.0, "LAKE", [0, 0, 255], 5).with_material("water"),
+                class(12, "MEADOW", [0, 255, 0], 20).with_material("grass"),
+            ],
+            ..LandCoverConfig::default()
+        };
+        let classes = config.all_classes();
+        let ids: Vec<u16> = classes.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 12]);
+        let water = &classes[1];
+        assert_eq!((water.name.as_str(), water.priority), ("LAKE", 5));
+        assert_eq!(classes[3].name, "ROAD");
+    }
+}
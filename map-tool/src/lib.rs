@@ -0,0 +1,26 @@
+pub mod bathymetry;
+pub mod conditioning;
+pub mod config;
+pub mod contour;
+pub mod coverage;
+pub mod data;
+pub mod geojson;
+pub mod geometry;
+pub mod height_image;
+pub mod hydrology;
+pub mod land_cover;
+pub mod map;
+pub mod pyramid;
+pub mod quantized_mesh;
+pub mod raster;
+pub mod road_graph;
+pub mod road_mesh;
+pub mod roads;
+pub mod rtin;
+pub mod terrain;
+pub mod terrain_analysis;
+pub mod texture;
+pub mod tile_layers;
+pub mod ui;
+pub mod visibility;
+pub mod water;
@@ -1,29 +1,82 @@
-mod data;
-mod geojson;
-mod geometry;
-mod map;
-mod ui;
-
 use std::fs;
 
-use data::download_collection;
-use data::read_height_data_from_file;
-use data::write_building_models;
-use data::write_building_normals;
-use data::write_surface_model;
-use data::write_surface_normals;
-use geometry::bbox;
-use geometry::point;
-use map::*;
+use map_tool::data::read_collection_from_files;
+use map_tool::data::read_height_data_from_file;
+use map_tool::data::write_adaptive_surface_model;
+use map_tool::data::write_building_models;
+use map_tool::data::write_building_normals;
+use map_tool::data::write_contours;
+use map_tool::data::write_coverage_masks;
+use map_tool::data::write_height_png;
+use map_tool::data::write_lake_bed_png;
+use map_tool::data::write_land_cover;
+use map_tool::data::write_quantized_mesh;
+use map_tool::data::write_road_graph;
+use map_tool::data::write_road_model;
+use map_tool::data::write_roads;
+use map_tool::data::write_streams;
+use map_tool::data::write_surface_model;
+use map_tool::data::write_surface_normals;
+use map_tool::data::write_terrain_analysis;
+use map_tool::data::write_terrain_rgb_png;
+use map_tool::data::write_terrain_textures;
+use map_tool::data::write_terrain_tiles;
+use map_tool::data::write_tile_layers;
+use map_tool::data::write_viewshed;
+use map_tool::data::write_water_model;
+use map_tool::data::{read_config_from_file, read_geojson_from_file, write_height_map};
+use map_tool::geometry::point::Point;
+use map_tool::map::*;
+use map_tool::visibility::ViewshedConfig;
+
+const USAGE: &str = "usage: map-tool [viewshed <longitude> <latitude> [--eye-height <m>] \
+[--target-height <m>] [--max-distance <m>] [--no-buildings] [--output <path>]]";
+
+enum Command {
+    /// Build the map and write every output.
+    Build,
+    /// Write the viewshed from an observer.
+    Viewshed { observer: Point, output: String },
+}
+
+/// Reads the subcommand from the arguments. Viewshed options override the
+/// ones in the config.
+fn parse_command(config: &mut ViewshedConfig) -> Result<Command, Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => return Ok(Command::Build),
+        Some("viewshed") => {}
+        Some(_) => return Err(USAGE.into()),
+    }
 
-use crate::data::{read_geojson_from_file, write_height_map};
+    let number = |value: Option<String>| -> Result<f64, Box<dyn std::error::Error>> {
+        Ok(value.ok_or(USAGE)?.parse::<f64>()?)
+    };
+    let observer = Point {
+        x: number(args.next())?,
+        y: number(args.next())?,
+    };
+    let mut output = String::from("output/viewshed.png");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--eye-height" => config.eye_height = number(args.next())?,
+            "--target-height" => config.target_height = number(args.next())?,
+            "--max-distance" => config.max_distance = Some(number(args.next())?),
+            "--no-buildings" => config.buildings = false,
+            "--output" => output = args.next().ok_or(USAGE)?,
+            _ => return Err(USAGE.into()),
+        }
+    }
+    Ok(Command::Viewshed { observer, output })
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = read_config_from_file("./config.json")?;
+    let command = parse_command(&mut config.viewshed)?;
     // let resp = read_geojson_from_file("./data/heightdata.json")?;
-    // let resp_depth = read_geojson_from_file("./data/depthdata.json")?;
     // let mut resp_building = download_collection("rakennus", "23.75,61.47,23.8,61.52").await?;
-    let mut resp_building = vec![
+    let resp_building = vec![
         read_geojson_from_file("./data/rakennus-1.json")?,
         read_geojson_from_file("./data/rakennus-2.json")?,
         read_geojson_from_file("./data/rakennus-3.json")?,
@@ -32,28 +85,76 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     ];
 
     // let mut resp_road = download_collection("tieviiva", "23.75,61.47,23.8,61.52").await?;
-    let mut resp_road = vec![
+    let resp_road = vec![
         read_geojson_from_file("./data/tieviiva-1.json")?,
         read_geojson_from_file("./data/tieviiva-2.json")?,
         read_geojson_from_file("./data/tieviiva-3.json")?,
     ];
     // let resp_road = read_geojson_from_file("./data/tiedata.json")?;
     // let mut resp_lake = download_collection("jarvi", "23.7,61.4,23.9,61.6").await?;
-    let mut resp_lake = vec![read_geojson_from_file("./data/jarvi-1.json")?];
+    let resp_lake = vec![read_geojson_from_file("./data/jarvi-1.json")?];
+    let resp_depth = vec![read_geojson_from_file("./data/depthdata.json")?];
+    let resp_land_cover = config
+        .land_cover
+        .collections()
+        .into_iter()
+        .map(|collection| {
+            let pages = read_collection_from_files("./data", &collection)?;
+            Ok((collection, pages))
+        })
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
     let height_data = read_height_data_from_file("./data/heightgrid.txt")?;
     // let respBuild = read_geojson_from_file("./buildingdata.json")?;
 
     // println!("{:#?}", bbox);
 
-    let smooth_rows = Map::new(height_data, resp_road, resp_lake, resp_building);
-    // let smooth_rows = smooth_rows.smooth_map();
-    // let smooth_rows = smooth_map(smooth_rows);
-    // let smooth_rows = smooth_map(smooth_rows);
-    // let smooth_rows = blur_dark_spots(smooth_rows);
+    let smooth_rows = Map::new(
+        height_data,
+        resp_road,
+        resp_lake,
+        resp_depth,
+        resp_building,
+        resp_land_cover,
+        &config,
+    );
     fs::create_dir_all("output/")?;
+
+    if let Command::Viewshed { observer, output } = command {
+        let viewshed = smooth_rows.viewshed(&observer, &config.viewshed);
+        write_viewshed(output, &smooth_rows, &viewshed)?;
+        return Ok(());
+    }
+
     write_height_map("output/map.json", &smooth_rows)?;
+    write_land_cover("output/land_cover.png", &smooth_rows)?;
+    write_tile_layers("output/layers", &smooth_rows)?;
+    write_coverage_masks("output/masks", &smooth_rows)?;
+    write_terrain_textures(
+        "output/textures",
+        &smooth_rows,
+        &config.textures,
+        &config.analysis,
+    )?;
     write_surface_model("output/height_model.json", &smooth_rows, 2)?;
     write_surface_normals("output/height_normals.json", &smooth_rows, 2)?;
+    write_adaptive_surface_model(
+        "output/height_model_adaptive.json",
+        "output/height_normals_adaptive.json",
+        &smooth_rows,
+        config.mesh.max_error,
+    )?;
+    write_road_model("output/road_model.json", &smooth_rows, &config.road_mesh)?;
+    write_water_model("output/water_model.json", &smooth_rows, &config.water)?;
+    write_terrain_tiles("output/tiles", &smooth_rows, &config.tiles)?;
+    write_quantized_mesh("output/terrain", &smooth_rows, &config.quantized_mesh)?;
+    write_height_png("output/height.png", &smooth_rows, &config.height_image)?;
+    write_terrain_rgb_png("output/height_terrain_rgb.png", &smooth_rows)?;
+    write_lake_bed_png("output/lake_bed.png", &smooth_rows, &config.height_image)?;
+    write_terrain_analysis("output/analysis", &smooth_rows, &config.analysis)?;
+    write_roads("output/roads.geojson", &smooth_rows)?;
+    write_road_graph("output/road_graph.json", &smooth_rows)?;
+    write_streams("output/streams.geojson", &smooth_rows)?;
+    write_contours("output/contours.geojson", &smooth_rows, &config.contours)?;
     write_building_models("output/building_models.json", &smooth_rows)?;
     write_building_normals("output/building_normals.json", &smooth_rows)?;
     Ok(())
@@ -1,16 +1,35 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::io::stdout;
 use std::io::Write;
 
-use crate::bbox;
-use crate::bbox::BboxTree;
-use crate::bbox::HasCoords;
-use crate::geojson;
+use crate::bathymetry::{BathymetryConfig, DepthContour, Lake, LakeBed};
+use crate::conditioning::{self, ConditioningConfig};
+use crate::config::{Config, LayerConfig};
+use crate::contour::{self, ContourConfig, ContourLabel};
+use crate::coverage::{polygon_coverage, segment_coverage, Coverage};
 use crate::geojson::Coordinates;
 use crate::geojson::GeoJSON;
+use crate::geojson::GeoJSONFeatures;
+use crate::geometry::bbox;
 use crate::geometry::point::LineSegment;
+use crate::geometry::point::Point;
 use crate::geometry::point::Polygon;
-use crate::point::Point;
+use crate::geometry::projection::LocalProjection;
+use crate::hydrology::{self, Hydrology, HydrologyConfig};
+use crate::land_cover::{LandCoverClass, LandCoverConfig, DEFAULT_LINE_WIDTH};
+use crate::pyramid::TileMesh;
+use crate::raster::Raster;
+use crate::raster::Resampler;
+use crate::road_graph::{RoadGraph, Route};
+use crate::road_mesh::{self, Junction, Ribbon, RoadMeshConfig};
+use crate::roads::{Road, RoadConfig, RoadDirection, RoadSurface};
+use crate::terrain::{apply_filters, fill_laplacian, TerrainFilter};
+use crate::texture;
+use crate::tile_layers::{coverage_layer_name, TileLayer, TileLayers};
 use crate::ui;
+use crate::visibility::{Surface, ViewshedConfig};
+use crate::water::{water_surface, WaterConfig, WaterSurface};
 
 const MAP_SIZE: i64 = 512;
 const ZOOM: f64 = 0.5;
@@ -18,116 +37,77 @@ const LAT_MAX: f64 = 61.515 - (0.05 - MAP_SIZE_D) * 0.5;
 const LONG_MIN: f64 = 23.745 + (0.05 - MAP_SIZE_D) * 0.5;
 const MAP_SIZE_D: f64 = 0.05 * ZOOM;
 
-#[derive(Debug)]
-pub struct HeightPoint {
-    pub height: i64,
-    pub coords: Point,
-}
-
-impl HasCoords for HeightPoint {
-    fn fits_into(self: &HeightPoint, bbox: &bbox::Bbox) -> bool {
-        bbox.contains(&self.coords)
-    }
-}
-
-impl Clone for HeightPoint {
-    fn clone(self: &HeightPoint) -> HeightPoint {
-        HeightPoint {
-            height: self.height,
-            coords: Point {
-                x: self.coords.x,
-                y: self.coords.y,
-            },
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct ClassPoint {
-    pub class: MapTileClass,
-    pub coords: Point,
-}
-
-impl HasCoords for ClassPoint {
-    fn fits_into(&self, bbox: &bbox::Bbox) -> bool {
-        bbox.contains(&self.coords)
-    }
-}
-
+// Extent of the height grid in ETRS-TM35FIN metres. Grid rows run from
+// south to north and columns from west to east.
+const HEIGHT_GRID_NORTH_MIN: f64 = 6819455.0;
+const HEIGHT_GRID_NORTH_MAX: f64 = 6824888.0;
+const HEIGHT_GRID_EAST_MIN: f64 = 326874.0;
+const HEIGHT_GRID_EAST_MAX: f64 = 329810.0;
+const HEIGHT_GRID_NODATA: f64 = -9999.0;
+// Relaxation passes over the missing tiles under the road ribbons.
+const ROAD_HEIGHT_FILL_ITERATIONS: usize = 200;
+
+/// Tile heights are stored in integer units of the height grid metres.
+pub const TILE_HEIGHT_PER_METRE: f64 = 350.0 / 2.0;
+/// `height_map` divides tile heights down to the scale the client draws.
+pub const HEIGHT_MAP_PER_METRE: f64 = TILE_HEIGHT_PER_METRE / 10_000.0;
+
+/// Feature geometry with its class and the index of the feature in its
+/// layer.
 #[derive(Clone)]
 enum Shape {
-    LINE_SEGMENT(LineSegment, MapTileClass),
-    POLYGON(Polygon, MapTileClass),
+    LINE_SEGMENT(LineSegment, MapTileClass, usize),
+    POLYGON(Polygon, MapTileClass, usize),
 }
 
-impl HasCoords for Shape {
-    fn fits_into(&self, bbox: &bbox::Bbox) -> bool {
-        let b = match self {
-            Shape::LINE_SEGMENT(x, ..) => x.bbox(),
-            Shape::POLYGON(x, ..) => x.bbox().clone(),
-        };
-
-        bbox.overlaps(&b)
-    }
+/// Id of a land cover class. The constants are the classes the map builds
+/// itself, the rest come from `LandCoverConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MapTileClass(pub u16);
+
+impl MapTileClass {
+    pub const EMPTY: MapTileClass = MapTileClass(0);
+    pub const WATER: MapTileClass = MapTileClass(1);
+    pub const BUILDING: MapTileClass = MapTileClass(2);
+    pub const ROAD: MapTileClass = MapTileClass(3);
+    pub const STREAM: MapTileClass = MapTileClass(4);
 }
 
-impl BboxTree<HeightPoint> {
-    pub fn max_height(self: &BboxTree<HeightPoint>) -> i64 {
-        match self {
-            BboxTree::Interior { ref children, .. } => *[
-                (*children.0).max_height(),
-                (*children.1).max_height(),
-                (*children.2).max_height(),
-                (*children.3).max_height(),
-            ]
-            .iter()
-            .max()
-            .unwrap_or(&0),
-            BboxTree::Leaf { ref items, .. } => items.iter().map(|h| h.height).max().unwrap_or(0),
-        }
-    }
-
-    pub fn average_height(self: &BboxTree<HeightPoint>) -> i64 {
-        match self {
-            BboxTree::Interior { ref children, .. } => {
-                [
-                    (*children.0).average_height(),
-                    (*children.1).average_height(),
-                    (*children.2).average_height(),
-                    (*children.3).average_height(),
-                ]
-                .iter()
-                .filter(|x| **x > 0)
-                .sum::<i64>()
-                    / 4
-            }
-            BboxTree::Leaf { ref items, .. } => {
-                if items.len() == 0 {
-                    return 0;
-                }
-
-                items.iter().map(|h| h.height).sum::<i64>() / items.len() as i64
-            }
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub enum MapTileClass {
-    WATER,
-    BUILDING,
-    ROAD,
-    EMPTY,
+/// Contour line in global coordinates.
+pub struct ContourLine {
+    pub elevation: f64,
+    pub points: Vec<Point>,
+    pub closed: bool,
 }
 
 pub struct MapTile {
+    /// Height of the surface, the water surface on lakes as the height data
+    /// has it.
     pub height: i64,
     pub class: MapTileClass,
+    /// Level of the lake on lake tiles, in the units of `height`.
+    pub water_surface: Option<i64>,
+    /// Height of the lake bed under `water_surface`.
+    pub bed_height: Option<i64>,
 }
 
 pub struct Map {
     pub tiles: Vec<Vec<MapTile>>,
     pub buildings: Vec<Polygon>,
+    /// Floor count of each of `buildings` where the building data has one.
+    pub building_storeys: Vec<Option<i64>>,
+    /// Roads reaching into the map, unclipped.
+    pub roads: Vec<Road>,
+    /// Network of `roads`, edges referring to them by index.
+    pub road_graph: RoadGraph,
+    /// Lakes reaching into the map, unclipped.
+    pub lakes: Vec<Lake>,
+    /// Stream lines derived from the height map, from upstream down.
+    pub streams: Vec<Vec<Point>>,
+    /// Every class the tiles can have.
+    pub classes: Vec<LandCoverClass>,
+    /// Attributes of the tiles besides height and class.
+    pub layers: TileLayers,
 }
 
 impl Map {
@@ -135,92 +115,22 @@ impl Map {
         heightData: Vec<Vec<f64>>,
         roads: Vec<GeoJSON>,
         water: Vec<GeoJSON>,
+        depths: Vec<GeoJSON>,
         buildings: Vec<GeoJSON>,
+        land_cover: Vec<(String, Vec<GeoJSON>)>,
+        config: &Config,
     ) -> Map {
-        let mut bbox: BboxTree<HeightPoint> = BboxTree::Leaf {
-            bbox: bbox::Bbox {
-                // a: bbox::Point { x: 23.37, y: 61.19 },
-                // b: bbox::Point { x: 23.47, y: 61.39 }
-                a: Point { x: 23.0, y: 60.0 },
-                b: Point { x: 24.0, y: 62.0 },
-            },
-            items: Vec::new(),
-        };
-
-        let mut bboxClass: BboxTree<ClassPoint> = BboxTree::Leaf {
-            bbox: bbox::Bbox {
-                // a: bbox::Point { x: 23.37, y: 61.19 },
-                // b: bbox::Point { x: 23.47, y: 61.39 }
-                a: Point { x: 23.0, y: 60.0 },
-                b: Point { x: 24.0, y: 62.0 },
-            },
-            items: Vec::new(),
-        };
-        let mut stdout = stdout();
-
-        let roadData = GeoJSON {
-            features: roads.into_iter().flat_map(|d| d.features).collect(),
-            links: None,
-        };
+        let classes = config.land_cover.all_classes();
 
         println!("Adding roads");
-        let mut roadSegments = Vec::new();
-        let mut segment_counter = 0;
-        let points = roadData
-            .features
+        let fallback_width = classes
             .iter()
-            .map(|f| {
-                (
-                    f,
-                    match (&f.geometry.coordinates) {
-                        geojson::Coordinates::points(x) => x.clone(),
-                        geojson::Coordinates::polygon(x) => {
-                            // separate to features?!
-                            x.iter().flat_map(|x| x.clone()).collect::<Vec<_>>()
-                        }
-                    },
-                )
-            })
-            .map(|(f, coords)| {
-                coords
-                    .iter()
-                    .map(|c| HeightPoint {
-                        height: f
-                            .properties
-                            .korkeusarvo
-                            .or(f.properties.syvyysarvo.map(|x| x * 100))
-                            .or(f.properties.pohjankorkeus)
-                            .unwrap_or(10_000),
-                        coords: Point { x: c.0, y: c.1 },
-                    })
-                    .collect::<Vec<_>>()
-            });
-        for line_segment in points {
-            segment_counter += 1;
-            for ii in 0..(line_segment.len() - 1) {
-                let point_a = &line_segment[ii];
-                let point_b = &line_segment[ii + 1];
-
-                roadSegments.push(LineSegment {
-                    a: Point {
-                        x: point_a.coords.x,
-                        y: point_a.coords.y,
-                    },
-                    b: Point {
-                        x: point_b.coords.x,
-                        y: point_b.coords.y,
-                    },
-                });
-
-                if ii % 5 == 0 {
-                    let progress = segment_counter as f64 / roadData.features.len() as f64
-                        + (ii as f64 / (line_segment.len() as f64).powf(2.0));
-                    ui::print_progress_bar(progress);
-                    stdout.flush().unwrap();
-                }
-            }
-        }
-        ui::print_progress_bar_completed();
+            .find(|class| class.id == MapTileClass::ROAD.0)
+            .and_then(|class| class.line_width)
+            .unwrap_or(DEFAULT_LINE_WIDTH);
+        let roads = roads_from_features(roads, &config.layers.roads, &config.roads, fallback_width);
+        println!("Building road graph");
+        let road_graph = RoadGraph::new(&roads, config.roads.snap_tolerance);
 
         // println!("Adding lake outline");
         // let mut segment_counter = 0;
@@ -289,93 +199,370 @@ impl Map {
         // ui::print_progress_bar_completed();
 
         println!("Adding lakes");
-        let lakePolys = water
-            .into_iter()
-            .flat_map(|d| d.features)
-            .map(|f| {
-                if let Coordinates::polygon(points) = f.geometry.coordinates {
-                    return Some(
-                        points
-                            .iter()
-                            .map(|p| {
-                                Polygon::new(
-                                    p.iter()
-                                        // .flat_map(|x| x)
-                                        .map(|p| Point { x: p.0, y: p.1 })
-                                        .collect(),
-                                )
-                            })
-                            .collect::<Vec<_>>(),
-                    );
-                }
-                None
-            })
-            .filter_map(|x| x)
-            .flat_map(|x| x)
-            .collect::<Vec<_>>();
+        let mut lakes = lakes_from_features(water, &config.layers.lakes, &config.bathymetry);
+        let lakePolys: Vec<Polygon> = lakes
+            .iter()
+            .flat_map(|l| l.polygon.clip_to_bbox(&map_bbox()))
+            .collect();
+        let depth_contours = depth_contours_from_features(depths, &config.bathymetry);
 
         println!("Adding buildings");
-        let buildingPolys = buildings
-            .into_iter()
-            .flat_map(|d| d.features)
-            .map(|f| {
-                if let Coordinates::polygon(points) = f.geometry.coordinates {
-                    return Some(
-                        points
-                            .iter()
-                            .map(|p| {
-                                Polygon::new(
-                                    p.iter()
-                                        // .flat_map(|x| x)
-                                        .map(|p| Point { x: p.0, y: p.1 })
-                                        .collect(),
-                                )
-                            })
-                            .collect::<Vec<_>>(),
-                    );
-                }
-                None
-            })
-            .filter_map(|x| x)
-            .flat_map(|x| x)
-            .collect::<Vec<_>>();
-
-        println!("Adding height data points");
-        for x in 0..1468 {
-            for y in 0..2716 {
-                bbox = bbox.add_item(
-                    &HeightPoint {
-                        height: (heightData[y][x] * 350.0 / 2.0) as i64,
-                        coords: finnish_coords_to_global(
-                            6819455.0 + ((y as f64) / 2716.0) * (6824888.0 - 6819455.0),
-                            326874.0 + ((x as f64) / 1468.0) * (329810.0 - 326874.0),
-                        ),
-                    },
-                    0,
-                );
-            }
-            ui::print_progress_bar((x as f64) / 1468.0);
-        }
-        ui::print_progress_bar_completed();
+        let (buildingPolys, building_storeys) =
+            buildings_from_features(buildings, &config.layers.buildings);
+
+        println!("Adding land cover");
+        let land_cover_shapes = shapes_from_features(land_cover, &config.land_cover);
+
+        let height_grid = Raster::from_rows(&heightData, Some(HEIGHT_GRID_NODATA));
 
-        let heightRows = build_height_map(&bbox);
-        let classRows = build_class_map(&bboxClass, &lakePolys, &roadSegments, &buildingPolys);
+        let heightRows = build_height_map(&height_grid, &config.height.resampling);
+        let (classRows, layers) = build_class_map(
+            &lakePolys,
+            &roads,
+            &buildingPolys,
+            land_cover_shapes,
+            &classes,
+            config.land_cover.coverage_threshold,
+        );
 
         let mut tiles = Vec::new();
         for y in 0..(MAP_SIZE as usize) {
             tiles.push(Vec::new());
             for x in 0..(MAP_SIZE as usize) {
                 tiles[y].push(MapTile {
-                    class: classRows[y][x].clone(),
+                    class: classRows[y][x],
                     height: heightRows[y][x],
+                    water_surface: None,
+                    bed_height: None,
                 });
             }
         }
 
-        Map {
+        let mut map = Map {
             tiles,
             buildings: buildingPolys,
+            building_storeys,
+            roads,
+            road_graph,
+            lakes: Vec::new(),
+            streams: Vec::new(),
+            classes,
+            layers,
+        };
+        map.filter_terrain(&config.height.filters);
+        map.condition_terrain(&config.conditioning);
+        map.build_lake_beds(&mut lakes, &depth_contours);
+        map.lakes = lakes;
+        map.extract_streams(&config.hydrology);
+        map.add_contour_layer(&config.contours);
+        map
+    }
+
+    /// Tile heights as a raster. Tiles without height data have height zero
+    /// and are missing from the raster.
+    pub fn height_raster(&self) -> Raster {
+        let mut raster = Raster::new(MAP_SIZE as usize, MAP_SIZE as usize);
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if tile.height != 0 {
+                    raster.set(x, y, Some(tile.height as f64));
+                }
+            }
+        }
+        raster
+    }
+
+    /// Runs the filters over the tile heights. Tiles the filters leave
+    /// missing get height zero.
+    pub fn filter_terrain(&mut self, filters: &[TerrainFilter]) {
+        if filters.is_empty() {
+            return;
+        }
+
+        println!("Filtering terrain");
+        let raster = apply_filters(&self.height_raster(), filters);
+        for (y, row) in self.tiles.iter_mut().enumerate() {
+            for (x, tile) in row.iter_mut().enumerate() {
+                tile.height = raster.get(x, y).map(|h| h.round() as i64).unwrap_or(0);
+            }
+        }
+    }
+
+    /// Levels pads under the buildings and the roads on the ground across
+    /// their width. Lake tiles and tiles without height data are left as
+    /// they are.
+    pub fn condition_terrain(&mut self, config: &ConditioningConfig) {
+        println!("Conditioning terrain");
+        let mut heights = self.height_raster();
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if tile.class == MapTileClass::WATER {
+                    heights.set(x, y, None);
+                }
+            }
+        }
+
+        let ring = |points: &[Point]| {
+            points
+                .iter()
+                .map(|p| global_to_tile_coords(p.x, p.y))
+                .collect::<Vec<_>>()
+        };
+        let buildings: Vec<Vec<Vec<(f64, f64)>>> = self
+            .buildings
+            .iter()
+            .map(|building| {
+                std::iter::once(ring(building.exterior()))
+                    .chain(building.holes().iter().map(|hole| ring(hole.exterior())))
+                    .collect()
+            })
+            .collect();
+        let roads: Vec<Ribbon> = self
+            .roads
+            .iter()
+            .filter(|road| road.level == 0)
+            .map(|road| Ribbon {
+                points: ring(&road.points),
+                width: road.width,
+            })
+            .collect();
+
+        let conditioned = conditioning::condition_terrain(
+            &heights,
+            &buildings,
+            &roads,
+            self.tile_size_in_metres(),
+            config,
+        );
+        for (y, row) in self.tiles.iter_mut().enumerate() {
+            for (x, tile) in row.iter_mut().enumerate() {
+                if heights.get(x, y).is_some() {
+                    if let Some(height) = conditioned.get(x, y) {
+                        tile.height = height.round() as i64;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets the water surface and lake bed heights of the lake tiles. The
+    /// surface is the lake's mean water level, or the median tile height
+    /// where the lake has none, which is then kept as the lake's `surface`
+    /// so that the water meshes lie at the same level.
+    pub fn build_lake_beds(&mut self, lakes: &mut [Lake], contours: &[DepthContour]) {
+        println!("Building lake beds");
+        for lake in lakes.iter_mut() {
+            let bbox = lake.polygon.bbox();
+            let (x0, y0) = self.to_tile_coords(bbox.a.x, bbox.b.y);
+            let (x1, y1) = self.to_tile_coords(bbox.b.x, bbox.a.y);
+            let clamp = |v: f64| v.clamp(0.0, (MAP_SIZE - 1) as f64) as usize;
+            let mut tiles = Vec::new();
+            for y in clamp(y0.floor())..=clamp(y1.ceil()) {
+                for x in clamp(x0.floor())..=clamp(x1.ceil()) {
+                    if matches!(self.tiles[y][x].class, MapTileClass::WATER)
+                        && lake
+                            .polygon
+                            .contains_point(&tile_coords_to_global(x as f64, y as f64))
+                    {
+                        tiles.push((x, y));
+                    }
+                }
+            }
+
+            let mut heights: Vec<i64> = tiles
+                .iter()
+                .map(|(x, y)| self.tiles[*y][*x].height)
+                .filter(|h| *h != 0)
+                .collect();
+            heights.sort_unstable();
+            let surface = match lake.surface {
+                Some(surface) => surface,
+                None if !heights.is_empty() => {
+                    heights[heights.len() / 2] as f64 / TILE_HEIGHT_PER_METRE
+                }
+                None => continue,
+            };
+            lake.surface = Some(surface);
+
+            let bed = LakeBed::new(&lake.polygon, contours);
+            for (x, y) in tiles {
+                let depth = bed
+                    .depth(&tile_coords_to_global(x as f64, y as f64))
+                    .unwrap_or(0.0);
+                let tile = &mut self.tiles[y][x];
+                tile.water_surface = Some((surface * TILE_HEIGHT_PER_METRE).round() as i64);
+                tile.bed_height = Some(((surface - depth) * TILE_HEIGHT_PER_METRE).round() as i64);
+            }
+        }
+    }
+
+    /// Lake bed heights in metres as a raster, missing outside lakes.
+    pub fn lake_bed_raster_in_metres(&self) -> Raster {
+        let mut raster = Raster::new(MAP_SIZE as usize, MAP_SIZE as usize);
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                raster.set(
+                    x,
+                    y,
+                    tile.bed_height.map(|h| h as f64 / TILE_HEIGHT_PER_METRE),
+                );
+            }
+        }
+        raster
+    }
+
+    /// Tile heights in metres as a raster, rows from north to south.
+    pub fn height_raster_in_metres(&self) -> Raster {
+        let tiles = self.height_raster();
+        let mut raster = Raster::new(tiles.width(), tiles.height());
+        for y in 0..tiles.height() {
+            for x in 0..tiles.width() {
+                raster.set(x, y, tiles.get(x, y).map(|h| h / TILE_HEIGHT_PER_METRE));
+            }
+        }
+        raster
+    }
+
+    /// Adds the `contour` tile layer with the elevation of the contour
+    /// through each tile.
+    pub fn add_contour_layer(&mut self, config: &ContourConfig) {
+        let heights = self.height_raster_in_metres();
+        let lines = contour::contours(&heights, config);
+        self.layers.insert(TileLayer {
+            name: String::from("contour"),
+            values: contour::layer(&lines, heights.width(), heights.height()),
+            labels: None,
+        });
+    }
+
+    /// Contour lines and their labels in global coordinates.
+    pub fn contours(&self, config: &ContourConfig) -> (Vec<ContourLine>, Vec<ContourLabel>) {
+        let heights = self.height_raster_in_metres();
+        let lines = contour::contours(&heights, config);
+        let labels = config
+            .label_spacing
+            .map(|spacing| contour::labels(&lines, spacing, self.tile_size_in_metres()))
+            .unwrap_or_default();
+
+        let to_global = |(x, y): (f64, f64)| tile_coords_to_global(x, y);
+        (
+            lines
+                .into_iter()
+                .map(|line| ContourLine {
+                    elevation: line.elevation,
+                    points: line.points.into_iter().map(to_global).collect(),
+                    closed: line.closed,
+                })
+                .collect(),
+            labels
+                .into_iter()
+                .map(|label| {
+                    let position = to_global(label.position);
+                    ContourLabel {
+                        position: (position.x, position.y),
+                        ..label
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Terrain in metres with buildings standing on their footprint tiles
+    /// when `config.buildings` is set. A building is `storey_height` per
+    /// floor tall, or `building_height` without a floor count.
+    pub fn visibility_surface(&self, config: &ViewshedConfig) -> Surface {
+        let building_ids = self.layers.get("building_id").map(|layer| &layer.values);
+        let obstacles = config.buildings.then(|| {
+            let mut obstacles = Raster::new(MAP_SIZE as usize, MAP_SIZE as usize);
+            for (y, row) in self.tiles.iter().enumerate() {
+                for (x, tile) in row.iter().enumerate() {
+                    if matches!(tile.class, MapTileClass::BUILDING) {
+                        let storeys = building_ids
+                            .and_then(|ids| ids.get(x, y))
+                            .and_then(|id| self.building_storeys.get(id as usize).copied())
+                            .flatten();
+                        let height = storeys
+                            .map(|n| n as f64 * config.storey_height)
+                            .unwrap_or(config.building_height);
+                        obstacles.set(x, y, Some(height));
+                    }
+                }
+            }
+            obstacles
+        });
+        Surface::new(
+            self.height_raster_in_metres(),
+            obstacles,
+            self.tile_size_in_metres(),
+        )
+    }
+
+    /// Whether an eye `config.eye_height` metres above the surface at `a`
+    /// sees the point `config.target_height` metres above the surface at `b`.
+    pub fn line_of_sight(&self, a: &Point, b: &Point, config: &ViewshedConfig) -> bool {
+        self.visibility_surface(config).line_of_sight(
+            self.to_tile_coords(a.x, a.y),
+            config.eye_height,
+            self.to_tile_coords(b.x, b.y),
+            config.target_height,
+        )
+    }
+
+    /// Tiles seen from `observer`, see `Surface::viewshed`.
+    pub fn viewshed(&self, observer: &Point, config: &ViewshedConfig) -> Raster {
+        self.visibility_surface(config)
+            .viewshed(self.to_tile_coords(observer.x, observer.y), config)
+    }
+
+    /// Routes water over the height map with lakes as sinks.
+    pub fn flow(&self, config: &HydrologyConfig) -> Hydrology {
+        let sinks: Vec<bool> = self
+            .tiles
+            .iter()
+            .flatten()
+            .map(|tile| matches!(tile.class, MapTileClass::WATER))
+            .collect();
+        hydrology::analyse(
+            &self.height_raster_in_metres(),
+            &sinks,
+            self.tile_size_in_metres(),
+            config,
+        )
+    }
+
+    /// Replaces the streams with ones cut from the flow accumulation. Tiles
+    /// along them whose class has a lower priority become streams.
+    pub fn extract_streams(&mut self, config: &HydrologyConfig) {
+        println!("Extracting streams");
+        let flow = self.flow(config);
+        let priorities = class_priorities(&self.classes);
+        let name = self.class_name(MapTileClass::STREAM);
+        let mut coverage = Raster::filled(MAP_SIZE as usize, MAP_SIZE as usize, 0.0);
+        for line in flow.streams.iter() {
+            for (x, y) in line.iter() {
+                let tile = &mut self.tiles[*y][*x];
+                if class_priority(&priorities, &MapTileClass::STREAM)
+                    > class_priority(&priorities, &tile.class)
+                {
+                    tile.class = MapTileClass::STREAM;
+                }
+                coverage.set(*x, *y, Some(1.0));
+            }
         }
+        self.layers.insert(TileLayer {
+            name: coverage_layer_name(&name),
+            values: coverage,
+            labels: None,
+        });
+        self.streams = flow
+            .streams
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|(x, y)| tile_coords_to_global(*x as f64, *y as f64))
+                    .collect()
+            })
+            .collect();
     }
 
     pub fn height_map(&self) -> Vec<Vec<f64>> {
@@ -389,217 +576,357 @@ impl Map {
             .collect()
     }
 
+    /// Class names of the tiles, see `class_name`.
     pub fn class_map(&self) -> Vec<Vec<String>> {
+        let names: HashMap<MapTileClass, &String> = self
+            .classes
+            .iter()
+            .map(|class| (MapTileClass(class.id), &class.name))
+            .collect();
         self.tiles
             .iter()
             .map(|row| {
                 row.iter()
-                    .map(|tile| match tile.class {
-                        MapTileClass::BUILDING => String::from("BUILDING"),
-                        MapTileClass::WATER => String::from("WATER"),
-                        MapTileClass::ROAD => String::from("ROAD"),
-                        MapTileClass::EMPTY => String::from("EMPTY"),
+                    .map(|tile| match names.get(&tile.class) {
+                        Some(name) => (*name).clone(),
+                        None => tile.class.0.to_string(),
                     })
                     .collect()
             })
             .collect()
     }
 
+    /// Name of a class, or its id for classes missing from `classes`.
+    pub fn class_name(&self, class: MapTileClass) -> String {
+        self.classes
+            .iter()
+            .find(|c| c.id == class.0)
+            .map(|c| c.name.clone())
+            .unwrap_or_else(|| class.0.to_string())
+    }
+
+    /// Class ids of the tiles as a raster.
+    pub fn class_raster(&self) -> Raster {
+        let mut raster = Raster::new(MAP_SIZE as usize, MAP_SIZE as usize);
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                raster.set(x, y, Some(tile.class.0 as f64));
+            }
+        }
+        raster
+    }
+
+    /// Width and height of a map tile in metres at the middle of the map.
+    pub fn tile_size_in_metres(&self) -> (f64, f64) {
+        tile_size()
+    }
+
+    /// Area covered by the map in degrees.
+    pub fn extent(&self) -> bbox::Bbox {
+        map_bbox()
+    }
+
+    /// Road ribbons at their widths draped on the height map, with patches
+    /// where roads meet. Roads are clipped to the map, and the heights of
+    /// missing tiles are filled in from the tiles around them. Positions are
+    /// in tile coordinates with heights in metres.
+    pub fn road_mesh(&self, config: &RoadMeshConfig) -> TileMesh {
+        let tile = |p: &Point| self.to_tile_coords(p.x, p.y);
+        let extent = map_bbox();
+        let ribbons: Vec<Ribbon> = self
+            .roads
+            .iter()
+            .flat_map(|road| {
+                extent
+                    .clip_line(&road.points)
+                    .into_iter()
+                    .map(|part| Ribbon {
+                        points: part.iter().map(tile).collect(),
+                        width: road.width,
+                    })
+            })
+            .collect();
+
+        let graph = &self.road_graph;
+        let mut arms = vec![Vec::new(); graph.nodes.len()];
+        for edge in graph.edges.iter() {
+            let width = self.roads[edge.road].width;
+            let last = edge.points.len() - 1;
+            arms[edge.from].push((tile(&edge.points[1]), width));
+            arms[edge.to].push((tile(&edge.points[last - 1]), width));
+        }
+        let junctions: Vec<Junction> = graph
+            .nodes
+            .iter()
+            .zip(arms)
+            .filter(|(node, arms)| arms.len() >= 2 && extent.contains(&node.point))
+            .map(|(node, arms)| Junction {
+                center: tile(&node.point),
+                arms,
+            })
+            .collect();
+
+        let surface = Surface::new(
+            fill_laplacian(&self.height_raster_in_metres(), ROAD_HEIGHT_FILL_ITERATIONS),
+            None,
+            self.tile_size_in_metres(),
+        );
+        let mut mesh = road_mesh::road_mesh(
+            &ribbons,
+            &junctions,
+            &surface,
+            self.tile_size_in_metres(),
+            config,
+        );
+        mesh.set_texture_coords((MAP_SIZE as usize, MAP_SIZE as usize));
+        mesh
+    }
+
+    /// Texture coordinates of a point in tiles on the map wide textures.
+    pub fn texture_coords(&self, x: f64, y: f64) -> [f64; 2] {
+        texture::texture_coords(x, y, (MAP_SIZE as usize, MAP_SIZE as usize))
+    }
+
+    /// Flat water surfaces of the lakes inside the map, with points in tile
+    /// coordinates, at the water level `build_lake_beds` gave the tiles.
+    /// Lakes without a level are left out.
+    pub fn water_meshes(&self, config: &WaterConfig) -> Vec<WaterSurface> {
+        let mut surfaces = Vec::new();
+        for lake in self.lakes.iter() {
+            let Some(level) = lake.surface else {
+                continue;
+            };
+            for area in lake.polygon.clip_to_bbox(&map_bbox()) {
+                let mut water = water_surface(&area, &lake.polygon, level, config.spacing);
+                water.points = water
+                    .points
+                    .iter()
+                    .map(|p| {
+                        let (x, y) = self.to_tile_coords(p.x, p.y);
+                        Point { x, y }
+                    })
+                    .collect();
+                // Tile rows run southwards, which mirrors the triangles.
+                for triangle in water.triangles.iter_mut() {
+                    triangle.swap(1, 2);
+                }
+                surfaces.push(water);
+            }
+        }
+        surfaces
+    }
+
+    /// Shortest route along the roads between the road nodes closest to two
+    /// points given in tile coordinates.
+    pub fn route(&self, from: (f64, f64), to: (f64, f64)) -> Option<Route> {
+        self.road_graph.shortest_path(
+            &tile_coords_to_global(from.0, from.1),
+            &tile_coords_to_global(to.0, to.1),
+        )
+    }
+
     pub fn to_tile_coords(&self, x: f64, y: f64) -> (f64, f64) {
-        // let longitude = ((x - LONG_MIN) / MAP_SIZE_D) * MAP_SIZE as f64;
-        // let latitude = ((y - LAT_MAX + MAP_SIZE_D) / MAP_SIZE_D) * MAP_SIZE as f64;
-
-        let longitude = ((x - LONG_MIN) / MAP_SIZE_D) * MAP_SIZE as f64;
-        let latitude = ((y - LAT_MAX) / (-MAP_SIZE_D)) * MAP_SIZE as f64;
-        (longitude, latitude)
-    }
-
-    //     pub fn smooth_map(self) -> Map {
-    //         let mut stdout = stdout();
-    //         let rows: Vec<Vec<_>> = self
-    //             .tiles
-    //             .into_iter()
-    //             .map(|row| row.iter().map(|tile| tile.height).collect())
-    //             .collect();
-    //         println!("Smoothing cliffs");
-
-    //         let mut smooth_rows: Vec<Vec<i64>> = Vec::new();
-    //         for i in 0..MAP_SIZE {
-    //             smooth_rows.push(Vec::new());
-    //             for ii in 0..MAP_SIZE {
-    //                 if rows[i as usize][ii as usize] < 100 {
-    //                     let mut x_search = 1;
-    //                     let mut y_search = 1;
-    //                     'smoothLoop: loop {
-    //                         for x in -(x_search)..(x_search) {
-    //                             for y in [-y_search, y_search] {
-    //                                 if (ii + x) < 0
-    //                                     || (i + y) < 0
-    //                                     || (ii + x) >= MAP_SIZE
-    //                                     || (i + y) >= MAP_SIZE
-    //                                 {
-    //                                     continue;
-    //                                 }
-
-    //                                 if rows[(i + y) as usize][(ii + x) as usize] > 0 {
-    //                                     let dampening = x_search as f64 / 10.0;
-    //                                     let dampening = if dampening < 1.0 { 1.0 } else { dampening };
-    //                                     smooth_rows[i as usize].push(
-    //                                         ((rows[(i + y) as usize][(ii + x) as usize]) as f64
-    //                                             / dampening)
-    //                                             as i64,
-    //                                     );
-    //                                     break 'smoothLoop;
-    //                                 }
-    //                             }
-    //                         }
-    //                         for y in -(y_search)..(y_search) {
-    //                             for x in [-x_search, x_search] {
-    //                                 if (ii + x) < 0
-    //                                     || (i + y) < 0
-    //                                     || (ii + x) >= MAP_SIZE
-    //                                     || (i + y) >= MAP_SIZE
-    //                                 {
-    //                                     continue;
-    //                                 }
-
-    //                                 if rows[(i + y) as usize][(ii + x) as usize] > 0 {
-    //                                     let dampening = x_search as f64 / 10.0;
-    //                                     let dampening = if dampening < 1.0 { 1.0 } else { dampening };
-    //                                     smooth_rows[i as usize].push(
-    //                                         ((rows[(i + y) as usize][(ii + x) as usize]) as f64
-    //                                             / dampening)
-    //                                             as i64,
-    //                                     );
-    //                                     break 'smoothLoop;
-    //                                 }
-    //                             }
-    //                         }
-
-    //                         x_search += 1;
-    //                         y_search += 1;
-    //                         if x_search > 20 {
-    //                             smooth_rows[i as usize].push(rows[i as usize][ii as usize]);
-    //                             break 'smoothLoop;
-    //                         }
-    //                     }
-    //                 } else {
-    //                     smooth_rows[i as usize].push(rows[i as usize][ii as usize]);
-    //                 }
-    //                 if ii % 10 == 0 {
-    //                     let progress =
-    //                         i as f64 / MAP_SIZE as f64 + (ii as f64 / (MAP_SIZE as f64).powf(2.0));
-    //                     ui::print_progress_bar(progress);
-    //                     stdout.flush().unwrap();
-    //                 }
-    //             }
-    //         }
-    //         ui::print_progress_bar_completed();
-
-    //         Map {
-    //             tiles: smooth_rows
-    //                 .into_iter()
-    //                 .map(|row| row.into_iter().map(|h| MapTile { height: h }).collect())
-    //                 .collect(),
-    //         }
-    //     }
-
-    //     pub fn blur_dark_spots(self) -> Map {
-    //         let mut stdout = stdout();
-    //         let rows: Vec<Vec<_>> = self
-    //             .tiles
-    //             .into_iter()
-    //             .map(|row| row.iter().map(|tile| tile.height).collect())
-    //             .collect();
-    //         println!("Blurring dark spots");
-
-    //         let mut smooth_rows: Vec<Vec<i64>> = Vec::new();
-    //         for i in 0..MAP_SIZE {
-    //             smooth_rows.push(Vec::new());
-    //             for ii in 0..MAP_SIZE {
-    //                 if rows[i as usize][ii as usize] < 50000 {
-    //                     let mut sum = 0;
-    //                     for x in -8..8 {
-    //                         for y in -8..8 {
-    //                             if (ii + x) < 0
-    //                                 || (i + y) < 0
-    //                                 || (ii + x) >= MAP_SIZE
-    //                                 || (i + y) >= MAP_SIZE
-    //                             {
-    //                                 continue;
-    //                             }
-
-    //                             sum += rows[(i + y) as usize][(ii + x) as usize];
-    //                         }
-    //                     }
-    //                     smooth_rows[i as usize].push(sum / 16_i64.pow(2))
-    //                 } else {
-    //                     smooth_rows[i as usize].push(rows[i as usize][ii as usize]);
-    //                 }
-    //                 if ii % 10 == 0 {
-    //                     let progress =
-    //                         i as f64 / MAP_SIZE as f64 + (ii as f64 / (MAP_SIZE as f64).powf(2.0));
-    //                     ui::print_progress_bar(progress);
-    //                     stdout.flush().unwrap();
-    //                 }
-    //             }
-    //         }
-    //         ui::print_progress_bar_completed();
-
-    //         Map {
-    //             tiles: smooth_rows
-    //                 .into_iter()
-    //                 .map(|row| row.into_iter().map(|h| MapTile { height: h }).collect())
-    //                 .collect(),
-    //         }
-    //     }
+        global_to_tile_coords(x, y)
+    }
+}
+
+fn global_to_tile_coords(x: f64, y: f64) -> (f64, f64) {
+    // let longitude = ((x - LONG_MIN) / MAP_SIZE_D) * MAP_SIZE as f64;
+    // let latitude = ((y - LAT_MAX + MAP_SIZE_D) / MAP_SIZE_D) * MAP_SIZE as f64;
+
+    let longitude = ((x - LONG_MIN) / MAP_SIZE_D) * MAP_SIZE as f64;
+    let latitude = ((y - LAT_MAX) / (-MAP_SIZE_D)) * MAP_SIZE as f64;
+    (longitude, latitude)
 }
 
-fn build_height_map(bbox: &BboxTree<HeightPoint>) -> Vec<Vec<i64>> {
+/// Width and height of a map tile in metres at the middle of the map.
+fn tile_size() -> (f64, f64) {
+    let extent = map_bbox();
+    let center = extent.center();
+    let step = MAP_SIZE_D / MAP_SIZE as f64;
+    let corner = LocalProjection::new(&center).to_metres(&Point {
+        x: center.x + step,
+        y: center.y + step,
+    });
+    (corner.x, corner.y)
+}
+
+/// Global coordinates of a position in tiles, whole numbers being the
+/// corners the map tiles are sampled at.
+fn tile_coords_to_global(x: f64, y: f64) -> Point {
+    Point {
+        x: LONG_MIN + MAP_SIZE_D * (x / MAP_SIZE as f64),
+        y: LAT_MAX - MAP_SIZE_D * (y / MAP_SIZE as f64),
+    }
+}
+
+fn map_bbox() -> bbox::Bbox {
+    bbox::Bbox {
+        a: Point {
+            x: LONG_MIN,
+            y: LAT_MAX - MAP_SIZE_D,
+        },
+        b: Point {
+            x: LONG_MIN + MAP_SIZE_D,
+            y: LAT_MAX,
+        },
+    }
+}
+
+/// Building polygons clipped to the map extent with the floor count of the
+/// feature each came from.
+fn buildings_from_features(
+    data: Vec<GeoJSON>,
+    layer: &LayerConfig,
+) -> (Vec<Polygon>, Vec<Option<i64>>) {
+    let extent = map_bbox();
+    data.into_iter()
+        .flat_map(|d| d.features)
+        .filter_map(|f| {
+            let storeys = f.properties.kerrosluku.filter(|n| *n > 0);
+            Some((polygon_from_feature(f, layer)?, storeys))
+        })
+        .flat_map(|(p, storeys)| {
+            p.clip_to_bbox(&extent)
+                .into_iter()
+                .map(move |piece| (piece, storeys))
+        })
+        .unzip()
+}
+
+/// Polygon of a feature. The first ring is the exterior and the rest are
+/// holes.
+fn polygon_from_feature(feature: GeoJSONFeatures, layer: &LayerConfig) -> Option<Polygon> {
+    if let Coordinates::polygon(rings) = feature.geometry.coordinates {
+        let mut rings = rings
+            .into_iter()
+            .map(|r| r.into_iter().map(|p| Point { x: p.0, y: p.1 }).collect());
+        let exterior = rings.next()?;
+        return Some(layer.apply_to_polygon(Polygon::with_holes(exterior, rings.collect())));
+    }
+    None
+}
+
+/// Whole lakes that reach into the map extent. They are not clipped, so
+/// that the shore stays the only place where the lake bed meets the surface.
+fn lakes_from_features(
+    data: Vec<GeoJSON>,
+    layer: &LayerConfig,
+    config: &BathymetryConfig,
+) -> Vec<Lake> {
+    let extent = map_bbox();
+    data.into_iter()
+        .flat_map(|d| d.features)
+        .filter_map(|f| {
+            let surface = f
+                .properties
+                .keskikorkeus
+                .map(|h| h as f64 * config.value_scale);
+            let polygon = polygon_from_feature(f, layer)?;
+            Some(Lake { polygon, surface })
+        })
+        .filter(|lake| lake.polygon.bbox().overlaps(&extent))
+        .collect()
+}
+
+/// Road features reaching into the map extent, with their widths from the
+/// road classes or `fallback_width` metres.
+fn roads_from_features(
+    data: Vec<GeoJSON>,
+    layer: &LayerConfig,
+    config: &RoadConfig,
+    fallback_width: f64,
+) -> Vec<Road> {
+    let extent = map_bbox();
+    let mut polygons = 0;
+    let roads = data
+        .into_iter()
+        .flat_map(|d| d.features)
+        .filter_map(|f| {
+            let points: Vec<Point> = match f.geometry.coordinates {
+                Coordinates::points(points) => points,
+                Coordinates::polygon(_) => {
+                    polygons += 1;
+                    return None;
+                }
+            }
+            .into_iter()
+            .map(|p| Point { x: p.0, y: p.1 })
+            .collect();
+            let points = layer.apply_to_line(points);
+            let bbox = points.iter().fold(bbox::Bbox::empty(), |bbox, p| {
+                bbox.union(&bbox::Bbox::from_point(p))
+            });
+            if points.len() < 2 || !bbox.overlaps(&extent) {
+                return None;
+            }
+            let properties = f.properties;
+            let class = config.classify(properties.kohdeluokka);
+            Some(Road {
+                points,
+                class: class.map(|class| class.name.clone()),
+                code: properties.kohdeluokka,
+                surface: RoadSurface::from_code(properties.paallyste),
+                direction: RoadDirection::from_code(properties.yksisuuntaisuus),
+                width: class.map_or(fallback_width, |class| class.width),
+                level: properties.tasosijainti.unwrap_or(0),
+                name: properties.nimi_suomi,
+            })
+        })
+        .collect();
+    if polygons > 0 {
+        println!("Left out {} road features with polygon geometry", polygons);
+    }
+    roads
+}
+
+/// Depth contours of the line features with a depth value.
+fn depth_contours_from_features(
+    data: Vec<GeoJSON>,
+    config: &BathymetryConfig,
+) -> Vec<DepthContour> {
+    data.into_iter()
+        .flat_map(|d| d.features)
+        .filter_map(
+            |f| match (f.properties.syvyysarvo, f.geometry.coordinates) {
+                (Some(depth), Coordinates::points(points)) => Some(DepthContour {
+                    depth: depth as f64 * config.value_scale,
+                    points: points
+                        .into_iter()
+                        .map(|p| Point { x: p.0, y: p.1 })
+                        .collect(),
+                }),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+fn build_height_map(grid: &Raster, resampler: &Resampler) -> Vec<Vec<i64>> {
     println!("Building height map");
     let mut stdout = stdout();
     let mut rows = Vec::new();
-    // let average_height = bbox.average_height();
+    let mut missing = 0;
     for y in 0..MAP_SIZE {
         let mut row = Vec::new();
         for x in 0..MAP_SIZE {
-            // 61.4559276,23.6617841 61.4982935,23.7746761
             let longitude = LONG_MIN + MAP_SIZE_D * (x as f64 / MAP_SIZE as f64);
             let latitude = LAT_MAX - MAP_SIZE_D * (y as f64 / MAP_SIZE as f64);
-            // let longitude = 23.7746761 - 0.001 + 0.002 * (x as f64 / MAP_SIZE as f64);
-            // let latitude = 61.4982935 + 0.001 - 0.002 * (y as f64 / MAP_SIZE as f64);
-            let searchArea = 0.00003;
-            let searchBox = &bbox::Bbox {
-                a: Point {
-                    x: longitude - searchArea,
-                    y: latitude - searchArea,
-                },
-                b: Point {
-                    x: longitude + searchArea,
-                    y: latitude + searchArea,
-                },
-            };
-            let found: Vec<_> = bbox
-                .find_boxes_overlapping(searchBox)
-                .iter()
-                .flat_map(|b| {
-                    if let BboxTree::Leaf { items, .. } = b {
-                        items.clone()
-                    } else {
-                        Vec::new()
-                    }
-                })
-                .collect();
-            if found.len() > 0 {
-                let average = found
-                    .iter()
-                    .map(|h| h.height)
-                    .map(|x| x as i64)
-                    .sum::<i64>()
-                    / found.len() as i64;
-                row.push(average);
-            } else {
-                row.push(0);
+
+            // The tile footprint in grid cells, measured from the next tile
+            // to the right and below.
+            let step = MAP_SIZE_D / MAP_SIZE as f64;
+            let (gx, gy) = global_coords_to_grid(longitude, latitude);
+            let (rx, ry) = global_coords_to_grid(longitude + step, latitude);
+            let (bx, by) = global_coords_to_grid(longitude, latitude - step);
+            let footprint = ((rx - gx).hypot(ry - gy), (bx - gx).hypot(by - gy));
+
+            match resampler.sample(grid, gx, gy, footprint) {
+                Some(height) => row.push((height * TILE_HEIGHT_PER_METRE) as i64),
+                None => {
+                    missing += 1;
+                    row.push(0);
+                }
             }
 
             if x % 10 == 0 {
@@ -622,227 +949,686 @@ fn build_height_map(bbox: &BboxTree<HeightPoint>) -> Vec<Vec<i64>> {
         "with max height of {}",
         rows.iter().flat_map(|r| r).max().unwrap()
     );
-    println!("Had {} data points", bbox.count_points());
-    println!("with max height of {}", bbox.max_height());
+    println!(
+        "Sampled a {}x{} grid, {} tiles without data",
+        grid.width(),
+        grid.height(),
+        missing
+    );
 
     rows
 }
 
+/// Coverage of the tiles by each class, summed over the areas of a class
+/// and the largest of its lines, and the road and building covering most of
+/// each tile.
+struct ClassCoverage {
+    areas: HashMap<MapTileClass, Raster>,
+    lines: HashMap<MapTileClass, Raster>,
+    /// Id of the feature covering most of each tile and how much it covers.
+    features: HashMap<MapTileClass, (Raster, Raster)>,
+}
+
+impl ClassCoverage {
+    fn new() -> ClassCoverage {
+        let size = MAP_SIZE as usize;
+        let features = [MapTileClass::ROAD, MapTileClass::BUILDING]
+            .into_iter()
+            .map(|class| {
+                (
+                    class,
+                    (Raster::new(size, size), Raster::filled(size, size, 0.0)),
+                )
+            })
+            .collect();
+        ClassCoverage {
+            areas: HashMap::new(),
+            lines: HashMap::new(),
+            features,
+        }
+    }
+
+    fn add(&mut self, class: MapTileClass, id: usize, coverage: &Coverage, line: bool) {
+        let size = MAP_SIZE as usize;
+        let values = if line {
+            &mut self.lines
+        } else {
+            &mut self.areas
+        }
+        .entry(class)
+        .or_insert_with(|| Raster::filled(size, size, 0.0));
+        for (x, y, v) in coverage.cells() {
+            let old = values.get(x, y).unwrap_or(0.0);
+            values.set(x, y, Some(if line { old.max(v) } else { old + v }));
+        }
+        if let Some((ids, best)) = self.features.get_mut(&class) {
+            for (x, y, v) in coverage.cells() {
+                if v > best.get(x, y).unwrap_or(0.0) {
+                    best.set(x, y, Some(v));
+                    ids.set(x, y, Some(id as f64));
+                }
+            }
+        }
+    }
+
+    fn add_polygon(&mut self, polygon: &Polygon, class: MapTileClass, id: usize) {
+        let ring = |points: &[Point]| {
+            points
+                .iter()
+                .map(|p| global_to_tile_coords(p.x, p.y))
+                .collect::<Vec<_>>()
+        };
+        let rings: Vec<Vec<(f64, f64)>> = std::iter::once(ring(polygon.exterior()))
+            .chain(polygon.holes().iter().map(|hole| ring(hole.exterior())))
+            .collect();
+        let size = MAP_SIZE as usize;
+        self.add(class, id, &polygon_coverage(&rings, size, size), false);
+    }
+
+    fn add_segment(&mut self, segment: &LineSegment, width: f64, class: MapTileClass, id: usize) {
+        let size = MAP_SIZE as usize;
+        let coverage = segment_coverage(
+            global_to_tile_coords(segment.a.x, segment.a.y),
+            global_to_tile_coords(segment.b.x, segment.b.y),
+            width,
+            tile_size(),
+            size,
+            size,
+        );
+        self.add(class, id, &coverage, true);
+    }
+
+    /// Coverage of a class, at most one.
+    fn get(&self, class: &MapTileClass, x: usize, y: usize) -> f64 {
+        let area = self.areas.get(class).and_then(|r| r.get(x, y));
+        let line = self.lines.get(class).and_then(|r| r.get(x, y));
+        (area.unwrap_or(0.0) + line.unwrap_or(0.0)).min(1.0)
+    }
+}
+
+/// Classes of the tiles and their layers. Polygons cover a tile by the area
+/// they share with it and lines by their buffered width. A tile gets the
+/// class with the highest priority among those covering at least
+/// `threshold` of it.
 fn build_class_map(
-    bbox: &BboxTree<ClassPoint>,
-    lakes: &Vec<Polygon>,
-    roads: &Vec<LineSegment>,
-    buildings: &Vec<Polygon>,
-) -> Vec<Vec<MapTileClass>> {
+    lakes: &[Polygon],
+    roads: &[Road],
+    buildings: &[Polygon],
+    land_cover: Vec<Shape>,
+    classes: &[LandCoverClass],
+    threshold: f64,
+) -> (Vec<Vec<MapTileClass>>, TileLayers) {
     println!("Building class map");
     let mut stdout = stdout();
-    let mut rows = Vec::new();
-
-    let mut shapeBox: BboxTree<Shape> = BboxTree::Leaf {
-        bbox: bbox::Bbox {
-            // a: bbox::Point { x: 23.37, y: 61.19 },
-            // b: bbox::Point { x: 23.47, y: 61.39 }
-            a: Point { x: 23.0, y: 60.0 },
-            b: Point { x: 24.0, y: 62.0 },
-        },
-        items: Vec::new(),
+    let priorities = class_priorities(classes);
+    let line_widths: HashMap<MapTileClass, f64> = classes
+        .iter()
+        .map(|c| {
+            (
+                MapTileClass(c.id),
+                c.line_width.unwrap_or(DEFAULT_LINE_WIDTH),
+            )
+        })
+        .collect();
+    let size = MAP_SIZE as usize;
+
+    let mut coverage = ClassCoverage::new();
+    let shape_count = lakes.len() + roads.len() + buildings.len() + land_cover.len();
+    let mut progress = |i: usize| {
+        if i.is_multiple_of(100) {
+            ui::print_progress_bar(i as f64 / shape_count as f64);
+            stdout.flush().unwrap();
+        }
     };
+    for (id, lake) in lakes.iter().enumerate() {
+        coverage.add_polygon(lake, MapTileClass::WATER, id);
+        progress(id);
+    }
+    for (id, road) in roads.iter().enumerate() {
+        for segment in road.segments() {
+            coverage.add_segment(&segment, road.width, MapTileClass::ROAD, id);
+        }
+        progress(lakes.len() + id);
+    }
+    for (id, building) in buildings.iter().enumerate() {
+        coverage.add_polygon(building, MapTileClass::BUILDING, id);
+        progress(lakes.len() + roads.len() + id);
+    }
+    for (i, shape) in land_cover.iter().enumerate() {
+        match shape {
+            Shape::POLYGON(polygon, class, id) => coverage.add_polygon(polygon, *class, *id),
+            Shape::LINE_SEGMENT(segment, class, id) => {
+                coverage.add_segment(segment, line_widths[class], *class, *id)
+            }
+        }
+        progress(lakes.len() + roads.len() + buildings.len() + i);
+    }
+    ui::print_progress_bar_completed();
 
-    for road in roads {
-        shapeBox = shapeBox.add_item(&Shape::LINE_SEGMENT(road.clone(), MapTileClass::ROAD), 0);
+    let mut materials: Vec<String> = classes.iter().filter_map(|c| c.material.clone()).collect();
+    materials.sort();
+    materials.dedup();
+    let material_of: HashMap<MapTileClass, usize> = classes
+        .iter()
+        .filter_map(|c| {
+            let material = c.material.as_ref()?;
+            Some((
+                MapTileClass(c.id),
+                materials.iter().position(|m| m == material)?,
+            ))
+        })
+        .collect();
+
+    let mut present: Vec<MapTileClass> = coverage
+        .areas
+        .keys()
+        .chain(coverage.lines.keys())
+        .copied()
+        .collect();
+    // Classes of equal priority go by id, so that ties don't depend on the
+    // order of the hash maps.
+    present.sort_by_key(|class| (Reverse(class_priority(&priorities, class)), class.0));
+    present.dedup();
+
+    let mut layers = TileLayers::new();
+    let mut class_layers: HashMap<MapTileClass, Raster> = present
+        .iter()
+        .map(|class| (*class, Raster::filled(size, size, 0.0)))
+        .collect();
+    let mut material = Raster::new(size, size);
+    let mut rows = Vec::new();
+    for y in 0..size {
+        let mut row = Vec::new();
+        for x in 0..size {
+            let mut covering = Vec::new();
+            for class in present.iter() {
+                let fraction = coverage.get(class, x, y);
+                class_layers
+                    .get_mut(class)
+                    .unwrap()
+                    .set(x, y, Some(fraction));
+                if fraction >= threshold {
+                    covering.push(*class);
+                }
+            }
+
+            let class = covering.first().copied().unwrap_or(MapTileClass::EMPTY);
+            let tile_material = std::iter::once(class)
+                .chain(covering)
+                .find_map(|class| material_of.get(&class));
+            material.set(x, y, tile_material.map(|m| *m as f64));
+            row.push(class);
+        }
+        rows.push(row);
     }
 
-    for building in buildings {
-        shapeBox = shapeBox.add_item(&Shape::POLYGON(building.clone(), MapTileClass::BUILDING), 0);
+    for class in classes {
+        if let Some(values) = class_layers.remove(&MapTileClass(class.id)) {
+            layers.insert(TileLayer {
+                name: coverage_layer_name(&class.name),
+                values,
+                labels: None,
+            });
+        }
     }
+    let mut features = coverage.features;
+    let (road_ids, _) = features.remove(&MapTileClass::ROAD).unwrap();
+    let (building_ids, _) = features.remove(&MapTileClass::BUILDING).unwrap();
+    layers.insert(TileLayer {
+        name: String::from("road_id"),
+        values: road_ids,
+        labels: None,
+    });
+    layers.insert(TileLayer {
+        name: String::from("building_id"),
+        values: building_ids,
+        labels: None,
+    });
+    layers.insert(TileLayer {
+        name: String::from("material"),
+        values: material,
+        labels: Some(materials),
+    });
+
+    (rows, layers)
+}
 
-    // let average_height = bbox.average_height();
-    for y in 0..MAP_SIZE {
-        let mut row = Vec::new();
-        for x in 0..MAP_SIZE {
-            // 61.4559276,23.6617841 61.4982935,23.7746761
-            let longitude = LONG_MIN + MAP_SIZE_D * (x as f64 / MAP_SIZE as f64);
-            let latitude = LAT_MAX - MAP_SIZE_D * (y as f64 / MAP_SIZE as f64);
-            // let longitude = 23.7746761 - 0.001 + 0.002 * (x as f64 / MAP_SIZE as f64);
-            // let latitude = 61.4982935 + 0.001 - 0.002 * (y as f64 / MAP_SIZE as f64);
-
-            let searchArea = 0.00001;
-            let searchBox = &bbox::Bbox {
-                a: Point {
-                    x: longitude - searchArea,
-                    y: latitude - searchArea,
-                },
-                b: Point {
-                    x: longitude + searchArea,
-                    y: latitude + searchArea,
-                },
-            };
-            let found = bbox.find_boxes_overlapping(searchBox);
-            let roads = shapeBox
-                .find_boxes_overlapping(searchBox)
-                .iter()
-                .filter_map(|x| {
-                    if let bbox::BboxTree::Leaf { items, .. } = x {
-                        Some(items.iter().filter_map(|x| {
-                            if let Shape::LINE_SEGMENT(l, MapTileClass::ROAD) = x {
-                                Some(l)
-                            } else {
-                                None
-                            }
-                        }))
-                    } else {
-                        None
-                    }
-                })
-                .flatten()
-                .collect::<Vec<_>>();
-            let buildings = shapeBox
-                .find_boxes_overlapping(searchBox)
-                .iter()
-                .filter_map(|x| {
-                    if let bbox::BboxTree::Leaf { items, .. } = x {
-                        Some(items.iter().filter_map(|x| {
-                            if let Shape::POLYGON(l, MapTileClass::BUILDING) = x {
-                                Some(l)
-                            } else {
-                                None
-                            }
-                        }))
-                    } else {
-                        None
-                    }
-                })
-                .flatten()
-                .collect::<Vec<_>>();
-            if lakes
-                .iter()
-                .find(|l| {
-                    l.contains_point(&Point {
-                        x: longitude,
-                        y: latitude,
-                    })
-                })
-                .is_some()
-            {
-                row.push(MapTileClass::WATER);
-            } else if buildings
-                .iter()
-                .find(|r| {
-                    r.contains_point(&Point {
-                        x: longitude,
-                        y: latitude,
-                    })
-                })
-                .is_some()
-            {
-                row.push(MapTileClass::BUILDING);
-            } else if roads
-                .iter()
-                .find(|r| {
-                    r.distance_squared_to_point(&Point {
-                        x: longitude,
-                        y: latitude,
-                    }) < 0.00001_f64.powf(2.0)
-                })
-                .is_some()
-            {
-                row.push(MapTileClass::ROAD);
-            } else {
-                let point = &Point {
-                    x: longitude,
-                    y: latitude,
-                };
-                let items = found.iter().flat_map(|x| {
-                    if let bbox::BboxTree::Leaf { items, .. } = x {
-                        items.clone()
-                    } else {
-                        Vec::new()
-                    }
-                });
+fn class_priorities(classes: &[LandCoverClass]) -> HashMap<MapTileClass, i32> {
+    classes
+        .iter()
+        .map(|class| (MapTileClass(class.id), class.priority))
+        .collect()
+}
 
-                let mut class = items
-                    .filter(|p| {
-                        p.coords.distance_squared(&Point {
-                            x: longitude,
-                            y: latitude,
-                        }) < 0.0000001
-                    })
-                    .collect::<Vec<_>>();
-                class.sort_by(|a, b| {
-                    a.coords
-                        .distance_squared(point)
-                        .partial_cmp(&b.coords.distance_squared(point))
-                        .unwrap()
-                });
-                let class = class.first();
-                row.push(
-                    class
-                        .map(|c| &c.class)
-                        .unwrap_or(&MapTileClass::EMPTY)
-                        .clone(),
-                );
-            }
+/// Priority of a class, below every other for classes missing from the
+/// priorities.
+fn class_priority(priorities: &HashMap<MapTileClass, i32>, class: &MapTileClass) -> i32 {
+    priorities.get(class).copied().unwrap_or(i32::MIN)
+}
 
-            if x % 10 == 0 {
-                let progress =
-                    y as f64 / MAP_SIZE as f64 + (x as f64 / (MAP_SIZE as f64).powf(2.0));
-                ui::print_progress_bar(progress);
-                stdout.flush().unwrap();
+/// Shapes of the land cover features with their classes. Lines become
+/// their segments and polygons are clipped to the map extent.
+fn shapes_from_features(
+    collections: Vec<(String, Vec<GeoJSON>)>,
+    config: &LandCoverConfig,
+) -> Vec<Shape> {
+    let extent = map_bbox();
+    let layer = LayerConfig::default();
+    let mut shapes = Vec::new();
+    for (collection, data) in collections {
+        for (id, feature) in data.into_iter().flat_map(|d| d.features).enumerate() {
+            let class = match config.classify(&collection, feature.properties.kohdeluokka) {
+                Some(class) => class,
+                None => continue,
+            };
+            match feature.geometry.coordinates {
+                Coordinates::points(points) => {
+                    shapes.extend(points.windows(2).map(|pair| {
+                        Shape::LINE_SEGMENT(
+                            LineSegment {
+                                a: Point {
+                                    x: pair[0].0,
+                                    y: pair[0].1,
+                                },
+                                b: Point {
+                                    x: pair[1].0,
+                                    y: pair[1].1,
+                                },
+                            },
+                            class,
+                            id,
+                        )
+                    }));
+                }
+                Coordinates::polygon(_) => {
+                    if let Some(polygon) = polygon_from_feature(feature, &layer) {
+                        shapes.extend(
+                            polygon
+                                .clip_to_bbox(&extent)
+                                .into_iter()
+                                .map(|p| Shape::POLYGON(p, class, id)),
+                        );
+                    }
+                }
             }
         }
-        rows.push(row);
     }
-    ui::print_progress_bar_completed();
+    shapes
+}
 
-    rows
+/// Position in height grid cells of a global coordinate.
+fn global_coords_to_grid(longitude: f64, latitude: f64) -> (f64, f64) {
+    let (north, east) = global_coords_to_finnish(longitude, latitude);
+    let columns = 1468.0;
+    let rows = 2716.0;
+    (
+        (east - HEIGHT_GRID_EAST_MIN) / (HEIGHT_GRID_EAST_MAX - HEIGHT_GRID_EAST_MIN) * columns,
+        (north - HEIGHT_GRID_NORTH_MIN) / (HEIGHT_GRID_NORTH_MAX - HEIGHT_GRID_NORTH_MIN) * rows,
+    )
 }
 
-fn finnish_coords_to_global(N: f64, E: f64) -> Point {
+/// ETRS-TM35FIN coordinates (N, E) of a global coordinate.
+fn global_coords_to_finnish(longitude: f64, latitude: f64) -> (f64, f64) {
     let f = 1.0 / 298.257222101; // Ellipsoidin litistyssuhde
     let a = 6378137.0; // Isoakselin puolikas
     let lambda_nolla = 0.471238898; // Keskimeridiaani (rad), 27 astetta
     let k_nolla = 0.9996; // Mittakaavakerroin
-    let E_nolla = 500000.0; // It�koordinaatti
+    let e_nolla = 500000.0; // Itäkoordinaatti
 
     // Kaavat
     let n: f64 = f / (2.0 - f);
-    let A1 = (a / (1.0 + n)) * (1.0 + (n.powf(2.0) / 4.0) + (n.powf(4.0) / 64.0));
-    let e_toiseen = (2.0 * f) - f.powf(2.0);
-    let h1 = (1.0 / 2.0) * n - (2.0 / 3.0) * n.powf(2.0) + (37.0 / 96.0) * n.powf(3.0)
-        - (1.0 / 360.0) * n.powf(4.0);
+    let a1 = (a / (1.0 + n)) * (1.0 + (n.powf(2.0) / 4.0) + (n.powf(4.0) / 64.0));
+    let e = ((2.0 * f) - f.powf(2.0)).sqrt();
+    let h1 = (1.0 / 2.0) * n - (2.0 / 3.0) * n.powf(2.0)
+        + (5.0 / 16.0) * n.powf(3.0)
+        + (41.0 / 180.0) * n.powf(4.0);
     let h2 =
-        (1.0 / 48.0) * n.powf(2.0) + (1.0 / 15.0) * n.powf(3.0) - (437.0 / 1440.0) * n.powf(4.0);
-    let h3 = (17.0 / 480.0) * n.powf(3.0) - (37.0 / 840.0) * n.powf(4.0);
-    let h4 = (4397.0 / 161280.0) * n.powf(4.0);
-    let zeeta = N / (A1 * k_nolla);
-    let eeta = (E - E_nolla) / (A1 * k_nolla);
-    let zeeta1_pilkku = h1 * (2.0 * zeeta).sin() * (2.0 * eeta).cosh();
-    let zeeta2_pilkku = h2 * (4.0 * zeeta).sin() * (4.0 * eeta).cosh();
-    let zeeta3_pilkku = h3 * (6.0 * zeeta).sin() * (6.0 * eeta).cosh();
-    let zeeta4_pilkku = h4 * (8.0 * zeeta).sin() * (8.0 * eeta).cosh();
-    let eeta1_pilkku = h1 * (2.0 * zeeta).cos() * (2.0 * eeta).sinh();
-    let eeta2_pilkku = h2 * (4.0 * zeeta).cos() * (4.0 * eeta).sinh();
-    let eeta3_pilkku = h3 * (6.0 * zeeta).cos() * (6.0 * eeta).sinh();
-    let eeta4_pilkku = h4 * (8.0 * zeeta).cos() * (8.0 * eeta).sinh();
-    let zeeta_pilkku = zeeta - (zeeta1_pilkku + zeeta2_pilkku + zeeta3_pilkku + zeeta4_pilkku);
-    let eeta_pilkku = eeta - (eeta1_pilkku + eeta2_pilkku + eeta3_pilkku + eeta4_pilkku);
-    let beeta = ((1.0 / (eeta_pilkku).cosh() * (zeeta_pilkku).sin()) as f64).asin();
-    let l = ((eeta_pilkku).tanh() / ((beeta).cos())).asin();
-    let Q = ((beeta).tan()).asinh();
-    let mut Q_pilkku = Q + (e_toiseen).sqrt() * ((e_toiseen).sqrt() * (Q).tanh()).atanh();
-
-    for kierros in 1..5 {
-        Q_pilkku = Q + (e_toiseen).sqrt() * ((e_toiseen).sqrt().atanh() * (Q_pilkku).tanh());
-    }
-
-    // Tulos radiaaneina
-    let fii = ((Q_pilkku).sinh()).atan();
-    let lambda = lambda_nolla + l;
-
-    // Tulos asteina
-    let fii = fii * 57.2957795;
-    let lambda = lambda * 57.2957795;
-
-    // let array = array ('lev' => $fii, 'pit' => $lambda);
-    Point { x: lambda, y: fii }
+        (13.0 / 48.0) * n.powf(2.0) - (3.0 / 5.0) * n.powf(3.0) + (557.0 / 1440.0) * n.powf(4.0);
+    let h3 = (61.0 / 240.0) * n.powf(3.0) - (103.0 / 140.0) * n.powf(4.0);
+    let h4 = (49561.0 / 161280.0) * n.powf(4.0);
+
+    let fii = latitude / 57.2957795;
+    let lambda = longitude / 57.2957795;
+    let q = fii.tan().asinh() - e * (e * fii.sin()).atanh();
+    let l = lambda - lambda_nolla;
+    let beeta = q.sinh().atan();
+    let eeta_pilkku = (beeta.cos() * l.sin()).atanh();
+    let zeeta_pilkku = (beeta.sin() * eeta_pilkku.cosh()).asin();
+    let zeeta = zeeta_pilkku
+        + h1 * (2.0 * zeeta_pilkku).sin() * (2.0 * eeta_pilkku).cosh()
+        + h2 * (4.0 * zeeta_pilkku).sin() * (4.0 * eeta_pilkku).cosh()
+        + h3 * (6.0 * zeeta_pilkku).sin() * (6.0 * eeta_pilkku).cosh()
+        + h4 * (8.0 * zeeta_pilkku).sin() * (8.0 * eeta_pilkku).cosh();
+    let eeta = eeta_pilkku
+        + h1 * (2.0 * zeeta_pilkku).cos() * (2.0 * eeta_pilkku).sinh()
+        + h2 * (4.0 * zeeta_pilkku).cos() * (4.0 * eeta_pilkku).sinh()
+        + h3 * (6.0 * zeeta_pilkku).cos() * (6.0 * eeta_pilkku).sinh()
+        + h4 * (8.0 * zeeta_pilkku).cos() * (8.0 * eeta_pilkku).sinh();
+
+    (a1 * zeeta * k_nolla, a1 * eeta * k_nolla + e_nolla)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_map() -> Map {
+        Map::new(
+            vec![vec![0.0; 2]; 2],
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            &Config::default(),
+        )
+    }
+
+    #[test]
+    fn buildings_are_as_tall_as_their_storeys() {
+        let mut map = empty_map();
+        let mut ids = Raster::new(MAP_SIZE as usize, MAP_SIZE as usize);
+        for (id, x) in [(0, 20), (1, 30)] {
+            map.tiles[10][x].class = MapTileClass::BUILDING;
+            ids.set(x, 10, Some(id as f64));
+        }
+        map.layers.insert(TileLayer {
+            name: String::from("building_id"),
+            values: ids,
+            labels: None,
+        });
+        map.building_storeys = vec![Some(4), None];
+
+        let config = ViewshedConfig::default();
+        let surface = map.visibility_surface(&config);
+        let ground = map.visibility_surface(&ViewshedConfig {
+            buildings: false,
+            ..config
+        });
+        let above = |x: f64| surface.height(x, 10.0) - ground.height(x, 10.0);
+        assert_eq!(above(20.0), 4.0 * config.storey_height);
+        assert_eq!(above(30.0), config.building_height);
+        assert_eq!(above(25.0), 0.0);
+
+        let (a, b) = (
+            tile_coords_to_global(15.0, 10.0),
+            tile_coords_to_global(35.0, 10.0),
+        );
+        assert!(!map.line_of_sight(&a, &b, &config));
+        let tall = ViewshedConfig {
+            eye_height: 40.0,
+            ..config
+        };
+        assert!(map.line_of_sight(&a, &b, &tall));
+    }
+
+    #[test]
+    fn unknown_classes_are_named_by_their_id() {
+        let mut map = empty_map();
+        map.tiles[3][4].class = MapTileClass(999);
+        let names = map.class_map();
+        assert_eq!(names[3][4], "999");
+        assert_eq!(names[3][5], "EMPTY");
+    }
+
+    /// Rectangle in tile coordinates as a global polygon.
+    fn rectangle(x0: f64, y0: f64, x1: f64, y1: f64) -> Polygon {
+        Polygon::new(
+            [(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+                .into_iter()
+                .map(|(x, y)| tile_coords_to_global(x, y))
+                .collect(),
+        )
+    }
+
+    fn road(from: (f64, f64), to: (f64, f64)) -> Road {
+        Road {
+            points: vec![
+                tile_coords_to_global(from.0, from.1),
+                tile_coords_to_global(to.0, to.1),
+            ],
+            class: None,
+            code: None,
+            surface: RoadSurface::Unknown,
+            direction: RoadDirection::Both,
+            width: 8.0,
+            level: 0,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn road_mesh_stays_on_the_map_and_spans_missing_heights() {
+        let mut map = empty_map();
+        for (y, row) in map.tiles.iter_mut().enumerate() {
+            for (x, tile) in row.iter_mut().enumerate() {
+                if !(200..210).contains(&x) || !(95..105).contains(&y) {
+                    tile.height = (10.0 * TILE_HEIGHT_PER_METRE) as i64;
+                }
+            }
+        }
+        map.roads = vec![road((-300.0, 100.0), (300.0, 100.0))];
+        map.road_graph = RoadGraph::new(&map.roads, 1.0);
+
+        let config = RoadMeshConfig::default();
+        let mesh = map.road_mesh(&config);
+        assert!(!mesh.indices.is_empty());
+        // The road is 8 m wide and tiles are more than 2 m across.
+        assert!(mesh.positions.iter().all(|p| p[0] > -2.0 && p[0] < 301.0));
+        assert!(mesh
+            .positions
+            .iter()
+            .all(|p| (p[2] - 10.0 - config.lift).abs() < 1e-6));
+    }
+
+    #[test]
+    fn lake_beds_and_water_meshes_share_the_water_level() {
+        let mut map = empty_map();
+        // Lake tiles from 4 m to 8 m, with the shore around them at 1 m.
+        for y in 8..=22 {
+            for x in 8..=22 {
+                let tile = &mut map.tiles[y][x];
+                if (10..=20).contains(&x) && (10..=20).contains(&y) {
+                    tile.class = MapTileClass::WATER;
+                    tile.height = ((4 + (x + y) % 5) as f64 * TILE_HEIGHT_PER_METRE) as i64;
+                } else {
+                    tile.height = TILE_HEIGHT_PER_METRE as i64;
+                }
+            }
+        }
+        let mut lakes = vec![Lake {
+            polygon: rectangle(9.5, 9.5, 20.5, 20.5),
+            surface: None,
+        }];
+        map.build_lake_beds(&mut lakes, &[]);
+        map.lakes = lakes;
+
+        let water_surface = map.tiles[15][15].water_surface.unwrap();
+        assert_eq!(water_surface, (6.0 * TILE_HEIGHT_PER_METRE) as i64);
+        let meshes = map.water_meshes(&WaterConfig::default());
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(
+            meshes[0].level,
+            water_surface as f64 / TILE_HEIGHT_PER_METRE
+        );
+    }
+
+    #[test]
+    fn roads_keep_their_attributes() {
+        let features = r#"{
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": [[23.76, 61.49], [23.765, 61.4905], [23.77, 61.49]]
+                    },
+                    "properties": {
+                        "mtk_id": 1,
+                        "kohdeluokka": 12121,
+                        "paallyste": 2,
+                        "yksisuuntaisuus": 1,
+                        "tasosijainti": 1,
+                        "nimi_suomi": "Hämeenkatu"
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": [[23.77, 61.49], [23.77, 61.495]]
+                    },
+                    "properties": { "kohdeluokka": 12999, "yksisuuntaisuus": 2 }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[23.76, 61.49], [23.77, 61.49], [23.77, 61.495]]]
+                    },
+                    "properties": { "kohdeluokka": 12131 }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": [[23.9, 61.49], [23.91, 61.49]]
+                    },
+                    "properties": { "kohdeluokka": 12131 }
+                }
+            ]
+        }"#;
+        let data: GeoJSON = serde_json::from_str(features).unwrap();
+        let roads = roads_from_features(
+            vec![data],
+            &LayerConfig::default(),
+            &RoadConfig::default(),
+            6.0,
+        );
+        // The polygon and the road outside the map are left out.
+        assert_eq!(roads.len(), 2);
+
+        let road = &roads[0];
+        assert_eq!(road.points.len(), 3);
+        assert_eq!(road.class.as_deref(), Some("road"));
+        assert_eq!((road.code, road.width), (Some(12121), 8.0));
+        assert_eq!(road.surface, RoadSurface::Paved);
+        assert_eq!(road.direction, RoadDirection::Forward);
+        assert_eq!(road.level, 1);
+        assert_eq!(road.name.as_deref(), Some("Hämeenkatu"));
+
+        // A code in no class gets the fallback width.
+        let road = &roads[1];
+        assert_eq!((road.class.clone(), road.width), (None, 6.0));
+        assert_eq!(road.surface, RoadSurface::Unknown);
+        assert_eq!(road.direction, RoadDirection::Backward);
+        assert_eq!((road.level, road.name.clone()), (0, None));
+    }
+
+    #[test]
+    fn layers_keep_ids_and_materials_under_the_class() {
+        let mut config = LandCoverConfig::default();
+        let mut plaza = config.classes[0].clone();
+        (plaza.id, plaza.name, plaza.priority, plaza.material) =
+            (30, String::from("PLAZA"), 95, None);
+        config.classes.push(plaza);
+        let classes = config.all_classes();
+        let (forest, plaza) = (MapTileClass(10), MapTileClass(30));
+
+        let roads = [
+            road((5.0, 20.0), (15.0, 20.0)),
+            road((10.0, 22.0), (10.0, 40.0)),
+        ];
+        let buildings = [
+            rectangle(29.5, 29.5, 32.5, 32.5),
+            rectangle(39.5, 29.5, 42.5, 32.5),
+        ];
+        let shapes = vec![
+            Shape::POLYGON(rectangle(49.5, 49.5, 55.5, 55.5), forest, 0),
+            Shape::POLYGON(rectangle(49.5, 49.5, 60.5, 55.5), plaza, 0),
+        ];
+        let (rows, layers) = build_class_map(&[], &roads, &buildings, shapes, &classes, 0.5);
+
+        let value = |layer: &str, x: usize, y: usize| layers.get(layer).unwrap().values.get(x, y);
+        assert_eq!(value("road_id", 6, 20), Some(0.0));
+        assert_eq!(value("road_id", 10, 30), Some(1.0));
+        assert_eq!(value("road_id", 6, 30), None);
+        assert_eq!(value("building_id", 31, 31), Some(0.0));
+        assert_eq!(value("building_id", 41, 30), Some(1.0));
+        assert_eq!(value("building_id", 36, 31), None);
+        assert_eq!(rows[31][31], MapTileClass::BUILDING);
+
+        // The plaza has no material of its own and shows the forest under
+        // it, or nothing where there is none.
+        let material = layers.get("material").unwrap();
+        let name = |x: usize, y: usize| {
+            let labels = material.labels.as_ref().unwrap();
+            material
+                .values
+                .get(x, y)
+                .map(|m| labels[m as usize].as_str())
+        };
+        assert_eq!(rows[52][52], plaza);
+        assert_eq!(name(52, 52), Some("forest_floor"));
+        assert_eq!(rows[52][58], plaza);
+        assert_eq!(name(58, 52), None);
+        assert_eq!(name(31, 31), Some("roof"));
+        assert_eq!(name(6, 20), Some("asphalt"));
+        // Empty tiles are covered by no class at all.
+        assert_eq!(name(100, 100), Some("grass"));
+    }
+
+    #[test]
+    fn tied_classes_go_by_id() {
+        let mut config = LandCoverConfig::default();
+        for id in [31, 30] {
+            let mut class = config.classes[0].clone();
+            (class.id, class.name, class.priority) = (id, format!("TIED_{id}"), 60);
+            config.classes.push(class);
+        }
+        let classes = config.all_classes();
+        let (a, b) = (MapTileClass(30), MapTileClass(31));
+        // Both classes cover the tiles, one of them from a polygon and a line.
+        let line = LineSegment {
+            a: tile_coords_to_global(0.0, 12.0),
+            b: tile_coords_to_global(30.0, 12.0),
+        };
+        for shapes in [
+            vec![
+                Shape::POLYGON(rectangle(9.5, 9.5, 20.5, 20.5), b, 0),
+                Shape::LINE_SEGMENT(line.clone(), a, 1),
+                Shape::POLYGON(rectangle(9.5, 9.5, 20.5, 20.5), a, 2),
+            ],
+            vec![
+                Shape::POLYGON(rectangle(9.5, 9.5, 20.5, 20.5), a, 0),
+                Shape::POLYGON(rectangle(9.5, 9.5, 20.5, 20.5), b, 1),
+                Shape::LINE_SEGMENT(line.clone(), b, 2),
+            ],
+        ] {
+            let (rows, layers) = build_class_map(&[], &[], &[], shapes, &classes, 0.5);
+            assert_eq!(rows[12][15], a);
+            assert_eq!(rows[15][15], a);
+            let coverage = |name: &str| layers.get(name).unwrap().values.get(15, 15);
+            assert_eq!(coverage("coverage_tied_30"), Some(1.0));
+            assert_eq!(coverage("coverage_tied_31"), Some(1.0));
+        }
+    }
+
+    #[test]
+    fn classes_need_the_threshold_of_a_tile() {
+        let classes = LandCoverConfig::default().all_classes();
+        let forest = MapTileClass(10);
+        let shapes = || {
+            // Forest on 40 % of tile (10, 10).
+            vec![Shape::POLYGON(rectangle(9.5, 9.5, 9.9, 10.5), forest, 0)]
+        };
+        let (rows, layers) = build_class_map(&[], &[], &[], shapes(), &classes, 0.5);
+        assert_eq!(rows[10][10], MapTileClass::EMPTY);
+        let coverage = layers.get("coverage_forest").unwrap();
+        assert!((coverage.values.get(10, 10).unwrap() - 0.4).abs() < 1e-6);
+
+        let (rows, _) = build_class_map(&[], &[], &[], shapes(), &classes, 0.3);
+        assert_eq!(rows[10][10], forest);
+        assert_eq!(rows[10][11], MapTileClass::EMPTY);
+    }
 }
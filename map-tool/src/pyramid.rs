@@ -0,0 +1,374 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::raster::Raster;
+use crate::texture::texture_coords;
+
+/// Options for cutting the height raster into a tile pyramid.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct PyramidConfig {
+    /// Quads along each side of a tile mesh, rounded up to a power of two.
+    pub tile_size: usize,
+    /// Skirt depth added to the geometric error of a tile, in height units.
+    pub skirt_depth: f64,
+}
+
+impl Default for PyramidConfig {
+    fn default() -> PyramidConfig {
+        PyramidConfig {
+            tile_size: 32,
+            skirt_depth: 0.01,
+        }
+    }
+}
+
+/// Axis aligned box and enclosing sphere of a tile in map coordinates,
+/// x and y in tiles and z in height units.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BoundingVolume {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+    pub center: [f64; 3],
+    pub radius: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TileMesh {
+    pub positions: Vec<[f64; 3]>,
+    /// Triangles wind counterclockwise in map coordinates, so the surface
+    /// normals point up along z and skirt normals point out of the tile.
+    pub indices: Vec<u32>,
+    /// Texture coordinates of the positions on the map wide textures.
+    #[serde(default)]
+    pub uvs: Vec<[f64; 2]>,
+}
+
+impl TileMesh {
+    /// Sets the texture coordinates from the positions, which are in tiles
+    /// of a map `size` tiles across.
+    pub fn set_texture_coords(&mut self, size: (usize, usize)) {
+        self.uvs = self
+            .positions
+            .iter()
+            .map(|p| texture_coords(p[0], p[1], size))
+            .collect();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TerrainTile {
+    pub level: usize,
+    pub x: usize,
+    pub y: usize,
+    /// Largest height difference between this mesh and the full resolution
+    /// raster inside the tile.
+    pub geometric_error: f64,
+    pub bounds: BoundingVolume,
+    pub mesh: TileMesh,
+}
+
+/// Quadtree of terrain tiles. Level 0 is one tile over the whole raster and
+/// every level splits each tile in four, down to the level where a mesh quad
+/// is one raster cell.
+pub struct TilePyramid {
+    /// Quads along each side of every tile mesh.
+    pub tile_size: usize,
+    pub levels: Vec<Vec<TerrainTile>>,
+}
+
+impl TilePyramid {
+    /// The raster must be square with a power of two side. Missing cells are
+    /// treated as height zero, like map tiles without data.
+    pub fn build(raster: &Raster, config: &PyramidConfig) -> Result<TilePyramid, Box<dyn Error>> {
+        let size = raster.width();
+        if raster.height() != size || !size.is_power_of_two() {
+            return Err(format!(
+                "Terrain tiles need a square raster with a power of two side, not {}x{}",
+                raster.width(),
+                raster.height()
+            )
+            .into());
+        }
+        let tile_size = config.tile_size.next_power_of_two().min(size);
+
+        let mut levels = Vec::new();
+        let mut tiles_per_side = 1;
+        loop {
+            let cells_per_tile = size / tiles_per_side;
+            let stride = (cells_per_tile / tile_size).max(1);
+            let mut level = Vec::new();
+            for y in 0..tiles_per_side {
+                for x in 0..tiles_per_side {
+                    level.push(build_tile(
+                        raster,
+                        levels.len(),
+                        (x, y),
+                        cells_per_tile,
+                        stride,
+                        config.skirt_depth,
+                    ));
+                }
+            }
+            levels.push(level);
+
+            if stride == 1 || cells_per_tile < 2 {
+                break;
+            }
+            tiles_per_side *= 2;
+        }
+
+        Ok(TilePyramid { tile_size, levels })
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = &TerrainTile> {
+        self.levels.iter().flatten()
+    }
+}
+
+fn height_at(raster: &Raster, x: usize, y: usize) -> f64 {
+    let x = x.min(raster.width() - 1);
+    let y = y.min(raster.height() - 1);
+    raster.get(x, y).unwrap_or(0.0)
+}
+
+fn build_tile(
+    raster: &Raster,
+    level: usize,
+    (tile_x, tile_y): (usize, usize),
+    cells_per_tile: usize,
+    stride: usize,
+    skirt_depth: f64,
+) -> TerrainTile {
+    let x0 = tile_x * cells_per_tile;
+    let y0 = tile_y * cells_per_tile;
+    let quads = cells_per_tile / stride;
+    let side = quads + 1;
+
+    // Vertices sit on raster cells, the last row and column reuse the first
+    // cells of the next tile so that neighbours share their edges exactly.
+    let mut mesh = TileMesh::default();
+    for j in 0..side {
+        for i in 0..side {
+            let x = x0 + i * stride;
+            let y = y0 + j * stride;
+            mesh.positions
+                .push([x as f64, y as f64, height_at(raster, x, y)]);
+        }
+    }
+    for j in 0..quads {
+        for i in 0..quads {
+            let a = (j * side + i) as u32;
+            let b = a + 1;
+            let c = a + side as u32;
+            let d = c + 1;
+            mesh.indices.extend([a, b, c, b, d, c]);
+        }
+    }
+
+    let geometric_error = geometric_error(raster, &mesh, (x0, y0), cells_per_tile, stride);
+    add_skirts(&mut mesh, side, geometric_error + skirt_depth);
+    mesh.set_texture_coords((raster.width(), raster.height()));
+
+    TerrainTile {
+        level,
+        x: tile_x,
+        y: tile_y,
+        geometric_error,
+        bounds: bounding_volume(&mesh.positions),
+        mesh,
+    }
+}
+
+/// Compares the triangles of the mesh, each quad split along its `b`-`c`
+/// diagonal, against every raster cell they cover.
+fn geometric_error(
+    raster: &Raster,
+    mesh: &TileMesh,
+    (x0, y0): (usize, usize),
+    cells_per_tile: usize,
+    stride: usize,
+) -> f64 {
+    if stride == 1 {
+        return 0.0;
+    }
+
+    let side = cells_per_tile / stride + 1;
+    let mut error: f64 = 0.0;
+    for dy in 0..=cells_per_tile {
+        for dx in 0..=cells_per_tile {
+            let (i, j) = ((dx / stride).min(side - 2), (dy / stride).min(side - 2));
+            let tx = (dx - i * stride) as f64 / stride as f64;
+            let ty = (dy - j * stride) as f64 / stride as f64;
+            let h = |i: usize, j: usize| mesh.positions[j * side + i][2];
+            let (a, b, c, d) = (h(i, j), h(i + 1, j), h(i, j + 1), h(i + 1, j + 1));
+            let approximation = if tx + ty <= 1.0 {
+                a + (b - a) * tx + (c - a) * ty
+            } else {
+                d + (c - d) * (1.0 - tx) + (b - d) * (1.0 - ty)
+            };
+            let actual = height_at(raster, x0 + dx, y0 + dy);
+            error = error.max((actual - approximation).abs());
+        }
+    }
+    error
+}
+
+/// Hangs a vertical strip below each tile edge. Where a neighbour is drawn
+/// at another level the edges don't line up, and the skirts cover the gap.
+fn add_skirts(mesh: &mut TileMesh, side: usize, depth: f64) {
+    let border: Vec<usize> = (0..side)
+        .chain((1..side).map(|j| j * side + side - 1))
+        .chain((0..side - 1).rev().map(|i| (side - 1) * side + i))
+        .chain((1..side - 1).rev().map(|j| j * side))
+        .collect();
+
+    let first_skirt = mesh.positions.len() as u32;
+    for index in border.iter() {
+        let [x, y, z] = mesh.positions[*index];
+        mesh.positions.push([x, y, z - depth]);
+    }
+
+    // The border runs counterclockwise around the tile.
+    for k in 0..border.len() {
+        let next = (k + 1) % border.len();
+        let (a, b) = (border[k] as u32, border[next] as u32);
+        let (c, d) = (first_skirt + k as u32, first_skirt + next as u32);
+        mesh.indices.extend([a, c, b, b, c, d]);
+    }
+}
+
+fn bounding_volume(positions: &[[f64; 3]]) -> BoundingVolume {
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [-f64::INFINITY; 3];
+    for p in positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(p[axis]);
+            max[axis] = max[axis].max(p[axis]);
+        }
+    }
+    let center = [
+        (min[0] + max[0]) / 2.0,
+        (min[1] + max[1]) / 2.0,
+        (min[2] + max[2]) / 2.0,
+    ];
+    let radius = positions
+        .iter()
+        .map(|p| {
+            ((p[0] - center[0]).powi(2) + (p[1] - center[1]).powi(2) + (p[2] - center[2]).powi(2))
+                .sqrt()
+        })
+        .fold(0.0, f64::max);
+    BoundingVolume {
+        min,
+        max,
+        center,
+        radius,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster(width: usize, height: usize) -> Raster {
+        let rows: Vec<Vec<f64>> = (0..height)
+            .map(|y| (0..width).map(|x| ((x * 7 + y * 13) % 11) as f64).collect())
+            .collect();
+        Raster::from_rows(&rows, None)
+    }
+
+    fn config(tile_size: usize) -> PyramidConfig {
+        PyramidConfig {
+            tile_size,
+            skirt_depth: 0.5,
+        }
+    }
+
+    #[test]
+    fn every_level_splits_the_tiles_in_four() {
+        let pyramid = TilePyramid::build(&raster(64, 64), &config(16)).unwrap();
+        let counts: Vec<usize> = pyramid.levels.iter().map(|l| l.len()).collect();
+        assert_eq!(counts, vec![1, 4, 16]);
+        assert_eq!(pyramid.tile_size, 16);
+        // The last level is at full resolution.
+        assert!(pyramid.levels[2].iter().all(|t| t.geometric_error == 0.0));
+        assert!(pyramid.levels[0][0].geometric_error > 0.0);
+    }
+
+    #[test]
+    fn neighbours_share_their_border_heights() {
+        let raster = raster(64, 64);
+        let pyramid = TilePyramid::build(&raster, &config(8)).unwrap();
+        let side = pyramid.tile_size + 1;
+        for level in pyramid.levels.iter() {
+            let mut heights = std::collections::HashMap::new();
+            for tile in level {
+                // The surface vertices come before the skirts.
+                for p in tile.mesh.positions[..side * side].iter() {
+                    let expected = height_at(&raster, p[0] as usize, p[1] as usize);
+                    assert_eq!(p[2], expected);
+                    let previous = heights.insert((p[0] as usize, p[1] as usize), p[2]);
+                    assert!(previous.is_none_or(|h| h == p[2]));
+                }
+            }
+            // Every tile corner of the level is present once the tiles are
+            // put together.
+            let tiles_per_side = (level.len() as f64).sqrt() as usize;
+            let step = 64 / tiles_per_side;
+            for y in (0..=64).step_by(step) {
+                for x in (0..=64).step_by(step) {
+                    assert!(heights.contains_key(&(x, y)));
+                }
+            }
+        }
+    }
+
+    /// Height of the mesh surface at a point, from the triangle under it.
+    fn mesh_height(mesh: &TileMesh, x: f64, y: f64) -> Option<f64> {
+        mesh.indices.chunks(3).find_map(|t| {
+            let [p, q, r] = [t[0], t[1], t[2]].map(|i| mesh.positions[i as usize]);
+            let area = (q[0] - p[0]) * (r[1] - p[1]) - (q[1] - p[1]) * (r[0] - p[0]);
+            if area == 0.0 {
+                return None;
+            }
+            let u = ((q[0] - x) * (r[1] - y) - (q[1] - y) * (r[0] - x)) / area;
+            let v = ((r[0] - x) * (p[1] - y) - (r[1] - y) * (p[0] - x)) / area;
+            let w = 1.0 - u - v;
+            (u >= -1e-9 && v >= -1e-9 && w >= -1e-9).then(|| u * p[2] + v * q[2] + w * r[2])
+        })
+    }
+
+    #[test]
+    fn errors_are_measured_on_the_mesh_triangles() {
+        // A saddle: the diagonal of the quad runs along the ridge, where a
+        // bilinear patch would dip to halfway.
+        let saddle = Raster::from_rows(&[vec![0.0, 10.0], vec![10.0, 0.0]], None);
+        let pyramid = TilePyramid::build(&saddle, &config(1)).unwrap();
+        assert_eq!(pyramid.levels[0][0].geometric_error, 10.0);
+
+        let raster = raster(32, 32);
+        let pyramid = TilePyramid::build(&raster, &config(4)).unwrap();
+        for tile in pyramid.tiles() {
+            let cells = 32 >> tile.level;
+            let (x0, y0) = (tile.x * cells, tile.y * cells);
+            let mut error: f64 = 0.0;
+            for y in y0..=y0 + cells {
+                for x in x0..=x0 + cells {
+                    let surface = mesh_height(&tile.mesh, x as f64, y as f64).unwrap();
+                    error = error.max((height_at(&raster, x, y) - surface).abs());
+                }
+            }
+            assert!((tile.geometric_error - error).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn rejects_rasters_that_are_not_square_powers_of_two() {
+        for (width, height) in [(64, 32), (48, 48), (0, 0)] {
+            assert!(TilePyramid::build(&raster(width, height), &config(16)).is_err());
+        }
+        assert!(TilePyramid::build(&raster(1, 1), &config(16)).is_ok());
+    }
+}
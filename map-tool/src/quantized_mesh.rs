@@ -0,0 +1,763 @@
+use serde::{Deserialize, Serialize};
+
+use crate::data::normal_from_neighbours;
+use crate::geometry::bbox::Bbox;
+use crate::geometry::point::{Point, Vec3};
+use crate::geometry::projection::LocalProjection;
+use crate::raster::Raster;
+use crate::rtin::Rtin;
+
+const WGS84_A: f64 = 6378137.0;
+const WGS84_B: f64 = 6356752.314245179;
+const WGS84_E2: f64 = 0.0066943799901413165;
+
+/// Horizon occlusion point distance in Earth radii for tiles too large to
+/// have one.
+const HORIZON_FALLBACK_MAGNITUDE: f64 = 1.0e6;
+
+/// Largest quantized vertex coordinate, u and v run from the west and south
+/// tile edges at 0 to the east and north edges at this value.
+pub const QUANTIZED_MAX: u16 = 32767;
+
+/// Extension id of the oct-encoded vertex normals.
+pub const EXTENSION_VERTEX_NORMALS: u8 = 1;
+
+/// Options for the quantized-mesh terrain tiles.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct QuantizedMeshConfig {
+    /// Deepest level written. Levels from 0 down to this one cover the map.
+    pub max_level: u32,
+    /// Height samples along each side of a tile before simplification,
+    /// rounded up to `2^k + 1`.
+    pub grid_size: usize,
+    /// Largest vertical distance in metres between a tile mesh and its
+    /// height samples.
+    pub max_error: f64,
+}
+
+impl Default for QuantizedMeshConfig {
+    fn default() -> QuantizedMeshConfig {
+        QuantizedMeshConfig {
+            max_level: 16,
+            grid_size: 65,
+            max_error: 0.25,
+        }
+    }
+}
+
+/// Tile in the geographic TMS scheme used by quantized-mesh. Level 0 has two
+/// tiles, west and east of the prime meridian, every level below splits each
+/// tile in four and y counts up from the south.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileId {
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    /// Width and height of the tiles on a level in degrees.
+    pub fn size(level: u32) -> f64 {
+        180.0 / (1u64 << level) as f64
+    }
+
+    /// Tile bounds in degrees, `a` at the south west corner.
+    pub fn bounds(&self) -> Bbox {
+        let size = TileId::size(self.level);
+        Bbox {
+            a: Point {
+                x: -180.0 + self.x as f64 * size,
+                y: -90.0 + self.y as f64 * size,
+            },
+            b: Point {
+                x: -180.0 + (self.x + 1) as f64 * size,
+                y: -90.0 + (self.y + 1) as f64 * size,
+            },
+        }
+    }
+
+    /// First and last tile along x and y on `level` that touch the extent.
+    /// Level 0 always lists both of its tiles, as clients load them before
+    /// anything else.
+    pub fn range(extent: &Bbox, level: u32) -> ((u32, u32), (u32, u32)) {
+        let columns = 2u32 << level;
+        let rows = 1u32 << level;
+        if level == 0 {
+            return ((0, columns - 1), (0, rows - 1));
+        }
+        let size = TileId::size(level);
+        let index = |degrees: f64, count: u32| {
+            (degrees / size).floor().clamp(0.0, (count - 1) as f64) as u32
+        };
+        (
+            (
+                index(extent.a.x + 180.0, columns),
+                index(extent.b.x + 180.0, columns),
+            ),
+            (
+                index(extent.a.y + 90.0, rows),
+                index(extent.b.y + 90.0, rows),
+            ),
+        )
+    }
+}
+
+/// Triangle mesh of one terrain tile before encoding.
+#[derive(Debug, Clone)]
+pub struct QuantizedMesh {
+    pub tile: TileId,
+    /// Longitude and latitude in degrees and height in metres.
+    pub vertices: Vec<[f64; 3]>,
+    /// Triangles wind counterclockwise seen from above.
+    pub indices: Vec<u32>,
+    /// Unit normals in Earth-centered, Earth-fixed coordinates.
+    pub normals: Vec<Vec3>,
+}
+
+impl QuantizedMesh {
+    /// Samples `height(longitude, latitude)` on a regular grid over the tile
+    /// and simplifies it to `max_error`. Points without height get zero.
+    /// Normals come from the same ring of neighbours `normal_for_point`
+    /// uses, one grid step apart, so they continue across tile edges.
+    pub fn build<F: Fn(f64, f64) -> Option<f64>>(
+        tile: TileId,
+        height: F,
+        config: &QuantizedMeshConfig,
+    ) -> QuantizedMesh {
+        let side = (config.grid_size.max(3) - 1).next_power_of_two() + 1;
+        let bounds = tile.bounds();
+        let step = (
+            (bounds.b.x - bounds.a.x) / (side - 1) as f64,
+            (bounds.b.y - bounds.a.y) / (side - 1) as f64,
+        );
+        let height = |lon: f64, lat: f64| height(lon, lat).unwrap_or(0.0);
+
+        // Rows run from south to north, so the grid has the same orientation
+        // as u and v.
+        let rows: Vec<Vec<f64>> = (0..side)
+            .map(|j| {
+                (0..side)
+                    .map(|i| {
+                        height(
+                            bounds.a.x + i as f64 * step.0,
+                            bounds.a.y + j as f64 * step.1,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        let grid = Rtin::new(&Raster::from_rows(&rows, None)).mesh(config.max_error);
+
+        let mut vertices = Vec::with_capacity(grid.positions.len());
+        let mut normals = Vec::with_capacity(grid.positions.len());
+        for [i, j, h] in grid.positions {
+            let lon = bounds.a.x + i * step.0;
+            let lat = bounds.a.y + j * step.1;
+            let origin = Point { x: lon, y: lat };
+            let spacing = LocalProjection::new(&origin).to_metres(&Point {
+                x: lon + step.0,
+                y: lat + step.1,
+            });
+            let normal = normal_from_neighbours(
+                |dx, dy| height(lon + dx as f64 * step.0, lat + dy as f64 * step.1),
+                (spacing.x, spacing.y),
+            );
+            vertices.push([lon, lat, h]);
+            normals.push(enu_to_ecef(&normal, lon, lat).normalize());
+        }
+
+        QuantizedMesh {
+            tile,
+            vertices,
+            indices: grid.indices,
+            normals,
+        }
+    }
+
+    /// Encodes the tile as quantized-mesh-1.0 with the vertex normals
+    /// extension. The output is not gzipped.
+    pub fn encode(&self) -> Vec<u8> {
+        // High water mark encoding needs vertices in order of first use.
+        let mut order = Vec::with_capacity(self.vertices.len());
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        for index in self.indices.iter() {
+            if remap[*index as usize] == u32::MAX {
+                remap[*index as usize] = order.len() as u32;
+                order.push(*index as usize);
+            }
+        }
+        let indices: Vec<u32> = self.indices.iter().map(|i| remap[*i as usize]).collect();
+        let vertices: Vec<[f64; 3]> = order.iter().map(|i| self.vertices[*i]).collect();
+        let normals: Vec<&Vec3> = order.iter().map(|i| &self.normals[*i]).collect();
+
+        let bounds = self.tile.bounds();
+        let min_height = vertices.iter().map(|v| v[2]).fold(f64::INFINITY, f64::min);
+        let max_height = vertices.iter().map(|v| v[2]).fold(-f64::INFINITY, f64::max);
+        let (min_height, max_height) = if vertices.is_empty() {
+            (0.0, 0.0)
+        } else {
+            (min_height, max_height)
+        };
+        let ecef: Vec<[f64; 3]> = vertices
+            .iter()
+            .map(|v| geodetic_to_ecef(v[0], v[1], v[2]))
+            .collect();
+
+        let mut out = Vec::new();
+
+        let center = geodetic_to_ecef(
+            (bounds.a.x + bounds.b.x) / 2.0,
+            (bounds.a.y + bounds.b.y) / 2.0,
+            (min_height + max_height) / 2.0,
+        );
+        let (sphere_center, radius) = bounding_sphere(&ecef);
+        let horizon = horizon_occlusion_point(&center, &ecef);
+        for value in center {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&(min_height as f32).to_le_bytes());
+        out.extend_from_slice(&(max_height as f32).to_le_bytes());
+        for value in sphere_center
+            .iter()
+            .chain([radius].iter())
+            .chain(horizon.iter())
+        {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let quantize = |value: f64, min: f64, max: f64| {
+            if max > min {
+                ((value - min) / (max - min) * QUANTIZED_MAX as f64)
+                    .round()
+                    .clamp(0.0, QUANTIZED_MAX as f64) as u16
+            } else {
+                0
+            }
+        };
+        let u: Vec<u16> = vertices
+            .iter()
+            .map(|v| quantize(v[0], bounds.a.x, bounds.b.x))
+            .collect();
+        let v: Vec<u16> = vertices
+            .iter()
+            .map(|v| quantize(v[1], bounds.a.y, bounds.b.y))
+            .collect();
+        let h: Vec<u16> = vertices
+            .iter()
+            .map(|v| quantize(v[2], min_height, max_height))
+            .collect();
+
+        out.extend_from_slice(&(vertices.len() as u32).to_le_bytes());
+        for values in [&u, &v, &h] {
+            let mut previous = 0i32;
+            for value in values.iter() {
+                let delta = *value as i32 - previous;
+                out.extend_from_slice(&zigzag(delta).to_le_bytes());
+                previous = *value as i32;
+            }
+        }
+
+        let wide = vertices.len() > 65536;
+        let write_index = |out: &mut Vec<u8>, index: u32| {
+            if wide {
+                out.extend_from_slice(&index.to_le_bytes());
+            } else {
+                out.extend_from_slice(&(index as u16).to_le_bytes());
+            }
+        };
+
+        let alignment = if wide { 4 } else { 2 };
+        while !out.len().is_multiple_of(alignment) {
+            out.push(0);
+        }
+        out.extend_from_slice(&((indices.len() / 3) as u32).to_le_bytes());
+        let mut highest = 0;
+        for index in indices.iter() {
+            write_index(&mut out, highest - index);
+            if *index == highest {
+                highest += 1;
+            }
+        }
+
+        let edge = |on_edge: &dyn Fn(usize) -> bool| -> Vec<u32> {
+            (0..vertices.len())
+                .filter(|i| on_edge(*i))
+                .map(|i| i as u32)
+                .collect()
+        };
+        let edges = [
+            edge(&|i| u[i] == 0),
+            edge(&|i| v[i] == 0),
+            edge(&|i| u[i] == QUANTIZED_MAX),
+            edge(&|i| v[i] == QUANTIZED_MAX),
+        ];
+        for edge in edges.iter() {
+            out.extend_from_slice(&(edge.len() as u32).to_le_bytes());
+            for index in edge {
+                write_index(&mut out, *index);
+            }
+        }
+
+        out.push(EXTENSION_VERTEX_NORMALS);
+        out.extend_from_slice(&(vertices.len() as u32 * 2).to_le_bytes());
+        for normal in normals {
+            out.extend_from_slice(&oct_encode(normal));
+        }
+        out
+    }
+}
+
+/// Earth-centered, Earth-fixed coordinates of a WGS84 position.
+pub fn geodetic_to_ecef(longitude: f64, latitude: f64, height: f64) -> [f64; 3] {
+    let (lon, lat) = (longitude.to_radians(), latitude.to_radians());
+    let n = WGS84_A / (1.0 - WGS84_E2 * lat.sin().powi(2)).sqrt();
+    [
+        (n + height) * lat.cos() * lon.cos(),
+        (n + height) * lat.cos() * lon.sin(),
+        (n * (1.0 - WGS84_E2) + height) * lat.sin(),
+    ]
+}
+
+/// Turns a vector in local east, north, up axes into ECEF axes.
+fn enu_to_ecef(v: &Vec3, longitude: f64, latitude: f64) -> Vec3 {
+    let (lon, lat) = (longitude.to_radians(), latitude.to_radians());
+    let east = [-lon.sin(), lon.cos(), 0.0];
+    let north = [-lat.sin() * lon.cos(), -lat.sin() * lon.sin(), lat.cos()];
+    let up = [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()];
+    let axis = |k: usize| v.x * east[k] + v.y * north[k] + v.z * up[k];
+    Vec3 {
+        x: axis(0),
+        y: axis(1),
+        z: axis(2),
+    }
+}
+
+fn bounding_sphere(points: &[[f64; 3]]) -> ([f64; 3], f64) {
+    if points.is_empty() {
+        return ([0.0; 3], 0.0);
+    }
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [-f64::INFINITY; 3];
+    for p in points {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(p[axis]);
+            max[axis] = max[axis].max(p[axis]);
+        }
+    }
+    let center = [
+        (min[0] + max[0]) / 2.0,
+        (min[1] + max[1]) / 2.0,
+        (min[2] + max[2]) / 2.0,
+    ];
+    let radius = points
+        .iter()
+        .map(|p| distance(p, &center))
+        .fold(0.0, f64::max);
+    (center, radius)
+}
+
+fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Point in ellipsoid-scaled space along the direction of `center` from
+/// which the whole tile is hidden behind the horizon once the point is.
+fn horizon_occlusion_point(center: &[f64; 3], points: &[[f64; 3]]) -> [f64; 3] {
+    let radii = [WGS84_A, WGS84_A, WGS84_B];
+    let scale = |p: &[f64; 3]| [p[0] / radii[0], p[1] / radii[1], p[2] / radii[2]];
+    let length = |p: &[f64; 3]| distance(p, &[0.0; 3]);
+    let unit = |p: [f64; 3]| {
+        let l = length(&p);
+        [p[0] / l, p[1] / l, p[2] / l]
+    };
+
+    let direction = unit(scale(center));
+    let mut magnitude: f64 = 0.0;
+    for p in points {
+        let scaled = scale(p);
+        let point_magnitude = length(&scaled).max(1.0);
+        let point_direction = unit(scaled);
+        let cos_alpha = point_direction[0] * direction[0]
+            + point_direction[1] * direction[1]
+            + point_direction[2] * direction[2];
+        let sin_alpha = (1.0 - cos_alpha.powi(2)).max(0.0).sqrt();
+        let cos_beta = 1.0 / point_magnitude;
+        let sin_beta = (point_magnitude.powi(2) - 1.0).sqrt() * cos_beta;
+        let denominator = cos_alpha * cos_beta - sin_alpha * sin_beta;
+        if denominator <= 0.0 {
+            // Part of the tile is on the far side of the globe from every
+            // point along the direction, such as on the level 0 tiles. A
+            // far away point keeps the tile from being culled.
+            magnitude = HORIZON_FALLBACK_MAGNITUDE;
+            break;
+        }
+        magnitude = magnitude.max(1.0 / denominator);
+    }
+    [
+        direction[0] * magnitude,
+        direction[1] * magnitude,
+        direction[2] * magnitude,
+    ]
+}
+
+fn zigzag(value: i32) -> u16 {
+    ((value << 1) ^ (value >> 31)) as u16
+}
+
+/// Octahedron encoding of a unit vector into two bytes.
+fn oct_encode(v: &Vec3) -> [u8; 2] {
+    let sum = v.x.abs() + v.y.abs() + v.z.abs();
+    let (mut x, mut y) = (v.x / sum, v.y / sum);
+    if v.z < 0.0 {
+        let sign = |a: f64| if a < 0.0 { -1.0 } else { 1.0 };
+        (x, y) = ((1.0 - y.abs()) * sign(x), (1.0 - x.abs()) * sign(y));
+    }
+    let snorm = |a: f64| ((a.clamp(-1.0, 1.0) * 0.5 + 0.5) * 255.0).round() as u8;
+    [snorm(x), snorm(y)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::write_quantized_mesh_tiles;
+    use crate::raster::Resampler;
+
+    struct Decoded {
+        min_height: f32,
+        max_height: f32,
+        u: Vec<u16>,
+        v: Vec<u16>,
+        h: Vec<u16>,
+        indices: Vec<u32>,
+        edges: Vec<Vec<u32>>,
+        normals: Vec<[u8; 2]>,
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        offset: usize,
+    }
+
+    impl Reader<'_> {
+        fn take(&mut self, count: usize) -> &[u8] {
+            let slice = &self.bytes[self.offset..self.offset + count];
+            self.offset += count;
+            slice
+        }
+        fn u16(&mut self) -> u16 {
+            u16::from_le_bytes(self.take(2).try_into().unwrap())
+        }
+        fn u32(&mut self) -> u32 {
+            u32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+        fn f32(&mut self) -> f32 {
+            f32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+        fn index(&mut self, wide: bool) -> u32 {
+            if wide {
+                self.u32()
+            } else {
+                self.u16() as u32
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Decoded {
+        let mut reader = Reader { bytes, offset: 24 };
+        let min_height = reader.f32();
+        let max_height = reader.f32();
+        reader.offset = 88;
+
+        let vertex_count = reader.u32() as usize;
+        let mut values = Vec::new();
+        for _ in 0..3 {
+            let mut value = 0i32;
+            let mut decoded = Vec::with_capacity(vertex_count);
+            for _ in 0..vertex_count {
+                let zigzag = reader.u16() as i32;
+                value += (zigzag >> 1) ^ -(zigzag & 1);
+                decoded.push(value as u16);
+            }
+            values.push(decoded);
+        }
+
+        let wide = vertex_count > 65536;
+        let alignment = if wide { 4 } else { 2 };
+        while !reader.offset.is_multiple_of(alignment) {
+            reader.offset += 1;
+        }
+        let triangle_count = reader.u32() as usize;
+        let mut highest = 0;
+        let mut indices = Vec::with_capacity(triangle_count * 3);
+        for _ in 0..triangle_count * 3 {
+            let code = reader.index(wide);
+            let index = highest - code;
+            if code == 0 {
+                highest += 1;
+            }
+            indices.push(index);
+        }
+
+        let edges = (0..4)
+            .map(|_| {
+                let count = reader.u32() as usize;
+                (0..count).map(|_| reader.index(wide)).collect()
+            })
+            .collect();
+
+        let mut normals = Vec::new();
+        while reader.offset < bytes.len() {
+            let id = reader.take(1)[0];
+            let length = reader.u32() as usize;
+            let data = reader.take(length).to_vec();
+            if id == EXTENSION_VERTEX_NORMALS {
+                normals = data.chunks(2).map(|c| [c[0], c[1]]).collect();
+            }
+        }
+
+        let h = values.pop().unwrap();
+        let v = values.pop().unwrap();
+        let u = values.pop().unwrap();
+        Decoded {
+            min_height,
+            max_height,
+            u,
+            v,
+            h,
+            indices,
+            edges,
+            normals,
+        }
+    }
+
+    impl Decoded {
+        fn height(&self, i: usize) -> f64 {
+            let range = (self.max_height - self.min_height) as f64;
+            self.min_height as f64 + self.h[i] as f64 / QUANTIZED_MAX as f64 * range
+        }
+
+        /// Position of a vertex snapped back onto the `side` x `side`
+        /// sampling grid it came from.
+        fn position(&self, tile: &TileId, side: usize, i: usize) -> (f64, f64) {
+            let bounds = tile.bounds();
+            let snap = |q: u16| {
+                (q as f64 / QUANTIZED_MAX as f64 * (side - 1) as f64).round() / (side - 1) as f64
+            };
+            (
+                bounds.a.x + snap(self.u[i]) * (bounds.b.x - bounds.a.x),
+                bounds.a.y + snap(self.v[i]) * (bounds.b.y - bounds.a.y),
+            )
+        }
+
+        /// Height of the mesh surface at quantized coordinates.
+        fn interpolate(&self, u: f64, v: f64) -> Option<f64> {
+            for t in self.indices.chunks(3) {
+                let p = |k: usize| (self.u[t[k] as usize] as f64, self.v[t[k] as usize] as f64);
+                let (a, b, c) = (p(0), p(1), p(2));
+                let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+                let wa = ((b.0 - u) * (c.1 - v) - (c.0 - u) * (b.1 - v)) / area;
+                let wb = ((c.0 - u) * (a.1 - v) - (a.0 - u) * (c.1 - v)) / area;
+                let wc = 1.0 - wa - wb;
+                if wa >= -1e-9 && wb >= -1e-9 && wc >= -1e-9 {
+                    let h = |k: usize| self.height(t[k] as usize);
+                    return Some(wa * h(0) + wb * h(1) + wc * h(2));
+                }
+            }
+            None
+        }
+    }
+
+    fn extent() -> Bbox {
+        Bbox {
+            a: Point { x: 23.75, y: 61.49 },
+            b: Point { x: 23.78, y: 61.52 },
+        }
+    }
+
+    /// Rolling terrain on a 64x64 raster over `extent`, sampled bilinearly.
+    fn source() -> impl Fn(f64, f64) -> Option<f64> {
+        let rows: Vec<Vec<f64>> = (0..64)
+            .map(|y| {
+                (0..64)
+                    .map(|x| 100.0 + 10.0 * (x as f64 / 7.0).sin() + 5.0 * (y as f64 / 5.0).cos())
+                    .collect()
+            })
+            .collect();
+        let raster = Raster::from_rows(&rows, None);
+        let resampler = Resampler::default();
+        let extent = extent();
+        move |lon, lat| {
+            if !extent.contains(&Point { x: lon, y: lat }) {
+                return None;
+            }
+            let x = (lon - extent.a.x) / (extent.b.x - extent.a.x) * 63.0;
+            let y = (lat - extent.a.y) / (extent.b.y - extent.a.y) * 63.0;
+            resampler.sample(&raster, x, y, (1.0, 1.0))
+        }
+    }
+
+    fn tolerance(decoded: &Decoded) -> f64 {
+        (decoded.max_height - decoded.min_height) as f64 / QUANTIZED_MAX as f64 + 0.001
+    }
+
+    #[test]
+    fn written_tiles_decode_to_source_heights() {
+        let path = std::env::temp_dir().join(format!("map-tool-terrain-{}", std::process::id()));
+        let config = QuantizedMeshConfig {
+            max_level: 13,
+            grid_size: 33,
+            max_error: 0.1,
+        };
+        let height = source();
+        write_quantized_mesh_tiles(&path, &height, &extent(), &config).unwrap();
+
+        let layer: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path.join("layer.json")).unwrap())
+                .unwrap();
+        assert_eq!(layer["format"], "quantized-mesh-1.0");
+        let available = layer["available"].as_array().unwrap();
+        assert_eq!(available.len(), 14);
+
+        let mut tiles = 0;
+        for (level, ranges) in available.iter().enumerate() {
+            let range = &ranges[0];
+            let coordinate = |key: &str| range[key].as_u64().unwrap() as u32;
+            for x in coordinate("startX")..=coordinate("endX") {
+                for y in coordinate("startY")..=coordinate("endY") {
+                    let tile = TileId {
+                        level: level as u32,
+                        x,
+                        y,
+                    };
+                    let file = path
+                        .join(level.to_string())
+                        .join(x.to_string())
+                        .join(format!("{}.terrain", y));
+                    let decoded = decode(&std::fs::read(file).unwrap());
+                    assert_eq!(decoded.normals.len(), decoded.u.len());
+                    for i in 0..decoded.u.len() {
+                        let (lon, lat) = decoded.position(&tile, 33, i);
+                        let expected = height(lon, lat).unwrap_or(0.0);
+                        assert!(
+                            (decoded.height(i) - expected).abs() <= tolerance(&decoded),
+                            "tile {:?} vertex {}: {} != {}",
+                            tile,
+                            i,
+                            decoded.height(i),
+                            expected
+                        );
+                    }
+                    tiles += 1;
+                }
+            }
+        }
+        assert!(tiles > 14);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn mesh_stays_within_max_error() {
+        let height = source();
+        let config = QuantizedMeshConfig {
+            max_level: 14,
+            grid_size: 33,
+            max_error: 0.2,
+        };
+        let tile = TileId {
+            level: 14,
+            x: 18546,
+            y: 13790,
+        };
+        let bounds = tile.bounds();
+        assert!(extent().contains(&bounds.a) && extent().contains(&bounds.b));
+
+        let decoded = decode(&QuantizedMesh::build(tile, &height, &config).encode());
+        assert!(decoded.indices.len() / 3 < 32 * 32 * 2);
+        for j in 0..=32 {
+            for i in 0..=32 {
+                let (u, v) = (i as f64 / 32.0, j as f64 / 32.0);
+                let lon = bounds.a.x + u * (bounds.b.x - bounds.a.x);
+                let lat = bounds.a.y + v * (bounds.b.y - bounds.a.y);
+                let surface = decoded
+                    .interpolate(u * QUANTIZED_MAX as f64, v * QUANTIZED_MAX as f64)
+                    .unwrap();
+                let error = (surface - height(lon, lat).unwrap()).abs();
+                assert!(
+                    error <= config.max_error + tolerance(&decoded),
+                    "{} at {} {}",
+                    error,
+                    i,
+                    j
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn flat_tile_has_edges_winding_and_up_normals() {
+        let config = QuantizedMeshConfig::default();
+        let tile = TileId {
+            level: 12,
+            x: 6213,
+            y: 2843,
+        };
+        let decoded = decode(&QuantizedMesh::build(tile, |_, _| Some(50.0), &config).encode());
+        assert_eq!(decoded.indices.len(), 6);
+        assert_eq!(decoded.min_height, 50.0);
+
+        let expected_edges: Vec<Vec<u32>> = [
+            |u: u16, _: u16| u == 0,
+            |_: u16, v: u16| v == 0,
+            |u: u16, _: u16| u == QUANTIZED_MAX,
+            |_: u16, v: u16| v == QUANTIZED_MAX,
+        ]
+        .iter()
+        .map(|on_edge| {
+            (0..decoded.u.len() as u32)
+                .filter(|i| on_edge(decoded.u[*i as usize], decoded.v[*i as usize]))
+                .collect()
+        })
+        .collect();
+        assert_eq!(decoded.edges, expected_edges);
+        assert!(decoded.edges.iter().all(|edge| edge.len() == 2));
+
+        for t in decoded.indices.chunks(3) {
+            let p = |k: usize| {
+                (
+                    decoded.u[t[k] as usize] as f64,
+                    decoded.v[t[k] as usize] as f64,
+                )
+            };
+            let (a, b, c) = (p(0), p(1), p(2));
+            assert!((b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1) > 0.0);
+        }
+
+        for (i, normal) in decoded.normals.iter().enumerate() {
+            let (lon, lat) = decoded.position(&tile, 65, i);
+            let up = enu_to_ecef(
+                &Vec3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 1.0,
+                },
+                lon,
+                lat,
+            );
+            let snorm = |b: u8| b as f64 / 255.0 * 2.0 - 1.0;
+            let (x, y) = (snorm(normal[0]), snorm(normal[1]));
+            let mut n = Vec3 {
+                x,
+                y,
+                z: 1.0 - x.abs() - y.abs(),
+            };
+            if n.z < 0.0 {
+                let sign = |a: f64| if a < 0.0 { -1.0 } else { 1.0 };
+                (n.x, n.y) = ((1.0 - y.abs()) * sign(x), (1.0 - x.abs()) * sign(y));
+            }
+            let n = n.normalize();
+            let dot = n.x * up.x + n.y * up.y + n.z * up.z;
+            assert!(dot > 0.999, "normal {} points off up by {}", i, dot);
+        }
+    }
+}
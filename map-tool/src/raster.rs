@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+
+/// Row-major grid of cell values with missing cells. Pixel coordinates put
+/// the center of cell (x, y) at (x, y), so a cell covers half a cell in every
+/// direction around its index.
+#[derive(Debug, Clone)]
+pub struct Raster {
+    width: usize,
+    height: usize,
+    values: Vec<Option<f64>>,
+}
+
+impl Raster {
+    pub fn new(width: usize, height: usize) -> Raster {
+        Raster {
+            width,
+            height,
+            values: vec![None; width * height],
+        }
+    }
+
+    /// Raster with every cell set to `value`.
+    pub fn filled(width: usize, height: usize, value: f64) -> Raster {
+        Raster {
+            width,
+            height,
+            values: vec![Some(value); width * height],
+        }
+    }
+
+    /// Builds a raster from rows of equal length. Cells equal to `nodata`
+    /// become missing.
+    pub fn from_rows(rows: &[Vec<f64>], nodata: Option<f64>) -> Raster {
+        let height = rows.len();
+        let width = rows.first().map(|r| r.len()).unwrap_or(0);
+        let values = rows
+            .iter()
+            .flat_map(|r| r.iter().take(width))
+            .map(|v| match nodata {
+                Some(n) if *v == n => None,
+                _ => Some(*v),
+            })
+            .collect();
+        Raster {
+            width,
+            height,
+            values,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width and height.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<f64> {
+        self.values[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: Option<f64>) {
+        self.values[y * self.width + x] = value;
+    }
+
+    /// Cell value with coordinates clamped to the grid.
+    fn get_clamped(&self, x: i64, y: i64) -> Option<f64> {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.get(x, y)
+    }
+
+    fn covers(&self, x: f64, y: f64) -> bool {
+        x >= -0.5 && y >= -0.5 && x <= self.width as f64 - 0.5 && y <= self.height as f64 - 0.5
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Resampling {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Catmull-Rom spline through the surrounding 4x4 cells.
+    Bicubic,
+    /// Average of the cells under the sample footprint, weighted by overlap.
+    Area,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NodataPolicy {
+    /// A sample touching any missing cell is missing.
+    Propagate,
+    /// Missing cells are left out of the kernel while the valid ones carry at
+    /// least half of its weight. Otherwise the closest valid cell within
+    /// `fill_distance` cells is used.
+    #[default]
+    Fill,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Resampler {
+    pub method: Resampling,
+    pub nodata: NodataPolicy,
+    /// Search distance in cells when filling missing samples.
+    pub fill_distance: usize,
+}
+
+impl Default for Resampler {
+    fn default() -> Resampler {
+        Resampler {
+            method: Resampling::default(),
+            nodata: NodataPolicy::default(),
+            fill_distance: 32,
+        }
+    }
+}
+
+impl Resampler {
+    /// Samples the raster at pixel coordinates. `footprint` is the size of
+    /// the output cell in source cells and is only used by the area filter.
+    /// The result only depends on the raster contents, so the same inputs
+    /// always give the same value.
+    pub fn sample(&self, raster: &Raster, x: f64, y: f64, footprint: (f64, f64)) -> Option<f64> {
+        if raster.width == 0 || raster.height == 0 || !raster.covers(x, y) {
+            return None;
+        }
+
+        let weights = match self.method {
+            Resampling::Nearest => vec![(x.round() as i64, y.round() as i64, 1.0)],
+            Resampling::Bilinear => bilinear_weights(x, y),
+            Resampling::Bicubic => bicubic_weights(x, y),
+            Resampling::Area => area_weights(x, y, footprint),
+        };
+
+        let mut sum = 0.0;
+        let mut total_weight = 0.0;
+        let mut missing = false;
+        for (cx, cy, weight) in weights {
+            if weight == 0.0 {
+                continue;
+            }
+            match raster.get_clamped(cx, cy) {
+                Some(value) => {
+                    sum += value * weight;
+                    total_weight += weight;
+                }
+                None => missing = true,
+            }
+        }
+
+        if !missing {
+            return Some(sum / total_weight);
+        }
+        match self.nodata {
+            NodataPolicy::Propagate => None,
+            // Bicubic weights can be negative, so a small remaining total
+            // would blow the value up.
+            NodataPolicy::Fill if total_weight > 0.5 => Some(sum / total_weight),
+            NodataPolicy::Fill => self.closest_valid(raster, x, y),
+        }
+    }
+
+    /// Searches square rings of growing size around the point. A ring can
+    /// hold cells farther away than the next ring's nearest cells, so the
+    /// search keeps going until no closer cell is possible. Ties go to the
+    /// first cell in row-major order.
+    fn closest_valid(&self, raster: &Raster, x: f64, y: f64) -> Option<f64> {
+        let (cx, cy) = (x.round() as i64, y.round() as i64);
+        let mut best: Option<(f64, i64, i64, f64)> = None;
+        for ring in 0..=self.fill_distance as i64 {
+            if let Some((distance, ..)) = best {
+                if (ring as f64 - 0.5).max(0.0).powi(2) > distance {
+                    break;
+                }
+            }
+
+            for ny in cy - ring..=cy + ring {
+                for nx in cx - ring..=cx + ring {
+                    let on_ring = (ny - cy).abs() == ring || (nx - cx).abs() == ring;
+                    if !on_ring
+                        || nx < 0
+                        || ny < 0
+                        || nx >= raster.width as i64
+                        || ny >= raster.height as i64
+                    {
+                        continue;
+                    }
+                    let value = match raster.get(nx as usize, ny as usize) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let distance = (nx as f64 - x).powi(2) + (ny as f64 - y).powi(2);
+                    let closer = match best {
+                        None => true,
+                        Some((d, by, bx, _)) => {
+                            distance < d || (distance == d && (ny, nx) < (by, bx))
+                        }
+                    };
+                    if closer {
+                        best = Some((distance, ny, nx, value));
+                    }
+                }
+            }
+        }
+        best.map(|(.., value)| value)
+    }
+}
+
+fn bilinear_weights(x: f64, y: f64) -> Vec<(i64, i64, f64)> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    vec![
+        (x0, y0, (1.0 - tx) * (1.0 - ty)),
+        (x0 + 1, y0, tx * (1.0 - ty)),
+        (x0, y0 + 1, (1.0 - tx) * ty),
+        (x0 + 1, y0 + 1, tx * ty),
+    ]
+}
+
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+fn bicubic_weights(x: f64, y: f64) -> Vec<(i64, i64, f64)> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let wx = catmull_rom(x - x0);
+    let wy = catmull_rom(y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let mut weights = Vec::with_capacity(16);
+    for (j, wy) in wy.iter().enumerate() {
+        for (i, wx) in wx.iter().enumerate() {
+            weights.push((x0 + i as i64 - 1, y0 + j as i64 - 1, wx * wy));
+        }
+    }
+    weights
+}
+
+/// Overlap of the footprint with each cell. A footprint smaller than a cell
+/// is widened to one cell, which makes the filter fall back to bilinear.
+fn area_weights(x: f64, y: f64, footprint: (f64, f64)) -> Vec<(i64, i64, f64)> {
+    let half_width = footprint.0.abs().max(1.0) / 2.0;
+    let half_height = footprint.1.abs().max(1.0) / 2.0;
+    let (left, right) = (x - half_width, x + half_width);
+    let (top, bottom) = (y - half_height, y + half_height);
+
+    let mut weights = Vec::new();
+    for cy in (top + 0.5).floor() as i64..=(bottom + 0.5).floor() as i64 {
+        let overlap_y = (bottom.min(cy as f64 + 0.5) - top.max(cy as f64 - 0.5)).max(0.0);
+        for cx in (left + 0.5).floor() as i64..=(right + 0.5).floor() as i64 {
+            let overlap_x = (right.min(cx as f64 + 0.5) - left.max(cx as f64 - 0.5)).max(0.0);
+            weights.push((cx, cy, overlap_x * overlap_y));
+        }
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3x3 raster with `x + 10 y` in each cell, which bilinear sampling
+    /// reproduces exactly between cell middles.
+    fn plane() -> Raster {
+        let rows: Vec<Vec<f64>> = (0..3)
+            .map(|y| (0..3).map(|x| (x + 10 * y) as f64).collect())
+            .collect();
+        Raster::from_rows(&rows, None)
+    }
+
+    fn resampler(method: Resampling, nodata: NodataPolicy) -> Resampler {
+        Resampler {
+            method,
+            nodata,
+            fill_distance: 2,
+        }
+    }
+
+    #[test]
+    fn nearest_takes_the_cell_under_the_point() {
+        let raster = plane();
+        let nearest = resampler(Resampling::Nearest, NodataPolicy::Propagate);
+        let sample = |x, y| nearest.sample(&raster, x, y, (1.0, 1.0));
+        assert_eq!(sample(0.0, 0.0), Some(0.0));
+        assert_eq!(sample(2.0, 2.0), Some(22.0));
+        assert_eq!(sample(1.4, 0.6), Some(11.0));
+        // Halfway between cells rounds away from the origin.
+        assert_eq!(sample(0.5, 1.5), Some(21.0));
+        // The outer halves of the edge cells are still covered.
+        assert_eq!(sample(-0.5, -0.5), Some(0.0));
+        assert_eq!(sample(2.5, 2.5), Some(22.0));
+        assert_eq!(sample(-0.6, 1.0), None);
+        assert_eq!(sample(1.0, 2.6), None);
+    }
+
+    #[test]
+    fn bilinear_interpolates_and_holds_the_edges() {
+        let raster = plane();
+        let bilinear = resampler(Resampling::Bilinear, NodataPolicy::Propagate);
+        let sample = |x, y| bilinear.sample(&raster, x, y, (1.0, 1.0)).unwrap();
+        assert_eq!(sample(0.0, 0.0), 0.0);
+        assert_eq!(sample(2.0, 0.0), 2.0);
+        assert_eq!(sample(0.0, 2.0), 20.0);
+        assert_eq!(sample(2.0, 2.0), 22.0);
+        assert!((sample(1.25, 0.75) - 8.75).abs() < 1e-12);
+        assert!((sample(0.5, 1.5) - 15.5).abs() < 1e-12);
+        // Beyond the last cell middle the edge value carries on.
+        assert_eq!(sample(2.4, 1.0), 12.0);
+        assert_eq!(sample(-0.4, -0.4), 0.0);
+        assert!((sample(2.0, 1.5) - 17.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn missing_cells_propagate_or_get_filled() {
+        let mut raster = plane();
+        raster.set(1, 1, None);
+        let sample =
+            |method, nodata, x, y| resampler(method, nodata).sample(&raster, x, y, (1.0, 1.0));
+
+        assert_eq!(
+            sample(Resampling::Bilinear, NodataPolicy::Propagate, 0.5, 0.5),
+            None
+        );
+        assert_eq!(
+            sample(Resampling::Nearest, NodataPolicy::Propagate, 1.0, 1.0),
+            None
+        );
+        // Cells away from the hole are unaffected.
+        assert_eq!(
+            sample(Resampling::Bilinear, NodataPolicy::Propagate, 2.0, 0.0),
+            Some(2.0)
+        );
+
+        // Three valid cells carry three quarters of the weight.
+        let filled = sample(Resampling::Bilinear, NodataPolicy::Fill, 0.5, 0.5).unwrap();
+        assert!((filled - 11.0 / 3.0).abs() < 1e-12);
+        // The four neighbours of the hole are equally close, and the first
+        // in row-major order wins every time.
+        for _ in 0..3 {
+            assert_eq!(
+                sample(Resampling::Nearest, NodataPolicy::Fill, 1.0, 1.0),
+                Some(1.0)
+            );
+        }
+
+        let empty = Raster::new(3, 3);
+        let fill = resampler(Resampling::Nearest, NodataPolicy::Fill);
+        assert_eq!(fill.sample(&empty, 1.0, 1.0, (1.0, 1.0)), None);
+    }
+}
@@ -0,0 +1,610 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::geometry::bbox::Bbox;
+use crate::geometry::point::Point;
+use crate::geometry::projection::LocalProjection;
+use crate::geometry::rtree::{HasBbox, PointDistance, RTree};
+use crate::roads::{Road, RoadDirection};
+
+/// Road junction or dead end.
+#[derive(Debug, Clone)]
+pub struct RoadNode {
+    pub point: Point,
+}
+
+/// Stretch of one road between two nodes. One-way edges run in the
+/// direction they can be travelled.
+#[derive(Debug, Clone)]
+pub struct RoadEdge {
+    pub from: usize,
+    pub to: usize,
+    /// Index of the road in the road list the graph was built from.
+    pub road: usize,
+    /// Line from `from` to `to`, both included.
+    pub points: Vec<Point>,
+    /// Length in metres.
+    pub length: f64,
+    pub one_way: bool,
+}
+
+/// Shortest path through the road graph.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub nodes: Vec<usize>,
+    pub edges: Vec<usize>,
+    /// Length in metres.
+    pub length: f64,
+    pub points: Vec<Point>,
+}
+
+/// Road network with a node wherever roads meet, cross or end. Road ends
+/// and vertices closer than the snap tolerance become one node, and roads
+/// crossing on the same level are split at the crossing. Roads on
+/// different levels, like a bridge over a street, cross without a node.
+pub struct RoadGraph {
+    pub nodes: Vec<RoadNode>,
+    pub edges: Vec<RoadEdge>,
+    projection: LocalProjection,
+    /// Node positions in metres.
+    positions: Vec<Point>,
+    /// Node positions indexed for the nearest node queries.
+    index: RTree<Indexed>,
+    /// Edges leaving each node with the node they lead to.
+    outgoing: Vec<Vec<(usize, usize)>>,
+}
+
+/// Point or segment in an R-tree with its index in the list it came from.
+/// Distances are measured to the bounding box, which is exact for points.
+struct Indexed {
+    index: usize,
+    bbox: Bbox,
+}
+
+impl Indexed {
+    fn point(index: usize, point: &Point) -> Indexed {
+        Indexed {
+            index,
+            bbox: Bbox::from_point(point),
+        }
+    }
+}
+
+impl HasBbox for Indexed {
+    fn bbox(&self) -> Bbox {
+        self.bbox.clone()
+    }
+}
+
+impl PointDistance for Indexed {
+    fn distance_squared_to_point(&self, point: &Point) -> f64 {
+        self.bbox.distance_squared_to_point(point)
+    }
+}
+
+/// Road vertex in metres, `junction` where the road was split at a
+/// crossing.
+struct Vertex {
+    point: Point,
+    junction: bool,
+}
+
+impl RoadGraph {
+    pub fn new(roads: &[Road], snap_tolerance: f64) -> RoadGraph {
+        let bbox = roads
+            .iter()
+            .flat_map(|road| road.points.iter())
+            .fold(Bbox::empty(), |bbox, p| bbox.union(&Bbox::from_point(p)));
+        let origin = if roads.is_empty() {
+            Point { x: 0.0, y: 0.0 }
+        } else {
+            bbox.center()
+        };
+        let projection = LocalProjection::new(&origin);
+        let lines: Vec<Vec<Point>> = roads
+            .iter()
+            .map(|road| {
+                road.points
+                    .iter()
+                    .map(|p| projection.to_metres(p))
+                    .collect()
+            })
+            .collect();
+        let levels: Vec<i64> = roads.iter().map(|road| road.level).collect();
+        let lines = split_at_crossings(&lines, &levels, snap_tolerance);
+
+        // Vertices closer than the tolerance share a cluster, the first one
+        // giving its position.
+        let mut snapper = Snapper::new(snap_tolerance);
+        let clustered: Vec<Vec<(usize, bool)>> = lines
+            .iter()
+            .map(|line| {
+                let mut vertices: Vec<(usize, bool)> = Vec::new();
+                for vertex in line {
+                    let cluster = snapper.snap(&vertex.point);
+                    match vertices.last_mut() {
+                        Some(last) if last.0 == cluster => last.1 |= vertex.junction,
+                        _ => vertices.push((cluster, vertex.junction)),
+                    }
+                }
+                vertices
+            })
+            .collect();
+
+        let mut uses = vec![0; snapper.points.len()];
+        for vertices in clustered.iter() {
+            for (cluster, _) in vertices {
+                uses[*cluster] += 1;
+            }
+        }
+        let mut node_of: HashMap<usize, usize> = HashMap::new();
+        let mut graph = RoadGraph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            projection,
+            positions: Vec::new(),
+            index: RTree::new(),
+            outgoing: Vec::new(),
+        };
+        let mut node = |graph: &mut RoadGraph, cluster: usize| {
+            *node_of.entry(cluster).or_insert_with(|| {
+                let position = snapper.points[cluster].clone();
+                graph.nodes.push(RoadNode {
+                    point: graph.projection.to_degrees(&position),
+                });
+                graph.positions.push(position);
+                graph.outgoing.push(Vec::new());
+                graph.nodes.len() - 1
+            })
+        };
+
+        for (road, vertices) in clustered.iter().enumerate() {
+            if vertices.len() < 2 {
+                continue;
+            }
+            let mut start = 0;
+            for i in 1..vertices.len() {
+                let (cluster, junction) = vertices[i];
+                if i < vertices.len() - 1 && !junction && uses[cluster] < 2 {
+                    continue;
+                }
+                let from = node(&mut graph, vertices[start].0);
+                let to = node(&mut graph, cluster);
+                let points: Vec<Point> = vertices[start..=i]
+                    .iter()
+                    .map(|(c, _)| snapper.points[*c].clone())
+                    .collect();
+                graph.add_edge(from, to, road, points, roads[road].direction);
+                start = i;
+            }
+        }
+        graph.index = RTree::bulk_load(
+            graph
+                .positions
+                .iter()
+                .enumerate()
+                .map(|(i, p)| Indexed::point(i, p))
+                .collect(),
+        );
+        graph
+    }
+
+    fn add_edge(
+        &mut self,
+        from: usize,
+        to: usize,
+        road: usize,
+        mut points: Vec<Point>,
+        direction: RoadDirection,
+    ) {
+        let length = points.windows(2).map(|p| p[0].distance(&p[1])).sum();
+        let (from, to) = if direction == RoadDirection::Backward {
+            points.reverse();
+            (to, from)
+        } else {
+            (from, to)
+        };
+        let one_way = direction != RoadDirection::Both;
+        let edge = self.edges.len();
+        self.outgoing[from].push((edge, to));
+        if !one_way {
+            self.outgoing[to].push((edge, from));
+        }
+        self.edges.push(RoadEdge {
+            from,
+            to,
+            road,
+            points: points
+                .iter()
+                .map(|p| self.projection.to_degrees(p))
+                .collect(),
+            length,
+            one_way,
+        });
+    }
+
+    /// Node closest to a point.
+    pub fn nearest_node(&self, point: &Point) -> Option<usize> {
+        let point = self.projection.to_metres(point);
+        self.index
+            .nearest(&point, 1)
+            .first()
+            .map(|(node, _)| node.index)
+    }
+
+    /// Shortest route between the nodes closest to two points, found with
+    /// A* using the straight line distance as the estimate. `None` when no
+    /// road leads from one to the other.
+    pub fn shortest_path(&self, from: &Point, to: &Point) -> Option<Route> {
+        let start = self.nearest_node(from)?;
+        let goal = self.nearest_node(to)?;
+        let estimate = |node: usize| self.positions[node].distance(&self.positions[goal]);
+
+        let mut distances = vec![f64::INFINITY; self.nodes.len()];
+        let mut previous: Vec<Option<(usize, usize)>> = vec![None; self.nodes.len()];
+        let mut queue = BinaryHeap::new();
+        distances[start] = 0.0;
+        queue.push(QueuedNode {
+            cost: estimate(start),
+            node: start,
+        });
+        while let Some(QueuedNode { cost, node }) = queue.pop() {
+            if node == goal {
+                break;
+            }
+            if cost > distances[node] + estimate(node) {
+                continue;
+            }
+            for (edge, next) in self.outgoing[node].iter() {
+                let distance = distances[node] + self.edges[*edge].length;
+                if distance < distances[*next] {
+                    distances[*next] = distance;
+                    previous[*next] = Some((*edge, node));
+                    queue.push(QueuedNode {
+                        cost: distance + estimate(*next),
+                        node: *next,
+                    });
+                }
+            }
+        }
+        if distances[goal].is_infinite() {
+            return None;
+        }
+
+        let mut nodes = vec![goal];
+        let mut edges = Vec::new();
+        while let Some((edge, node)) = previous[*nodes.last().unwrap()] {
+            edges.push(edge);
+            nodes.push(node);
+        }
+        nodes.reverse();
+        edges.reverse();
+
+        let mut points = vec![self.nodes[start].point.clone()];
+        for (edge, node) in edges.iter().zip(nodes.iter()) {
+            let edge = &self.edges[*edge];
+            if edge.from == *node {
+                points.extend(edge.points.iter().skip(1).cloned());
+            } else {
+                points.extend(edge.points.iter().rev().skip(1).cloned());
+            }
+        }
+        Some(Route {
+            nodes,
+            edges,
+            length: distances[goal],
+            points,
+        })
+    }
+}
+
+#[derive(PartialEq)]
+struct QueuedNode {
+    cost: f64,
+    node: usize,
+}
+
+impl Eq for QueuedNode {}
+
+impl Ord for QueuedNode {
+    /// Lowest cost first.
+    fn cmp(&self, other: &QueuedNode) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then(other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for QueuedNode {
+    fn partial_cmp(&self, other: &QueuedNode) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Merges points closer than a tolerance into the closest earlier point,
+/// the earliest one among equally close points.
+struct Snapper {
+    tolerance: f64,
+    points: Vec<Point>,
+    index: RTree<Indexed>,
+}
+
+impl Snapper {
+    fn new(tolerance: f64) -> Snapper {
+        Snapper {
+            tolerance: tolerance.max(1e-9),
+            points: Vec::new(),
+            index: RTree::new(),
+        }
+    }
+
+    fn snap(&mut self, point: &Point) -> usize {
+        let closest = self
+            .index
+            .within_radius(point, self.tolerance)
+            .map(|p| (p.index, p.distance_squared_to_point(point)))
+            .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        if let Some((i, _)) = closest {
+            return i;
+        }
+        let i = self.points.len();
+        self.points.push(point.clone());
+        self.index.insert(Indexed::point(i, point));
+        i
+    }
+}
+
+/// Road lines with a junction vertex added where a segment crosses or ends
+/// on a segment of another road, or of the same road, on the same level.
+/// Ends that stop short of a segment or overshoot it by less than the
+/// tolerance count as meeting it.
+fn split_at_crossings(lines: &[Vec<Point>], levels: &[i64], tolerance: f64) -> Vec<Vec<Vertex>> {
+    let segments: Vec<(usize, usize)> = lines
+        .iter()
+        .enumerate()
+        .flat_map(|(road, line)| (0..line.len().saturating_sub(1)).map(move |i| (road, i)))
+        .collect();
+    // Segment boxes grown by the tolerance, so that ends short of another
+    // segment still find it.
+    let boxes: Vec<Bbox> = segments
+        .iter()
+        .map(|(road, i)| {
+            let (a, b) = (&lines[*road][*i], &lines[*road][*i + 1]);
+            Bbox {
+                a: Point {
+                    x: a.x.min(b.x) - tolerance,
+                    y: a.y.min(b.y) - tolerance,
+                },
+                b: Point {
+                    x: a.x.max(b.x) + tolerance,
+                    y: a.y.max(b.y) + tolerance,
+                },
+            }
+        })
+        .collect();
+    let index = RTree::bulk_load(
+        boxes
+            .iter()
+            .enumerate()
+            .map(|(index, bbox)| Indexed {
+                index,
+                bbox: bbox.clone(),
+            })
+            .collect(),
+    );
+
+    // Split positions along each segment, as fractions of its length.
+    let mut splits: HashMap<usize, Vec<f64>> = HashMap::new();
+    for (s, bbox) in boxes.iter().enumerate() {
+        for t in index.query_bbox(bbox).map(|t| t.index).filter(|t| *t > s) {
+            let ((road_s, i), (road_t, j)) = (segments[s], segments[t]);
+            if levels[road_s] != levels[road_t] || (road_s == road_t && i.abs_diff(j) < 2) {
+                continue;
+            }
+            let first = (&lines[road_s][i], &lines[road_s][i + 1]);
+            let second = (&lines[road_t][j], &lines[road_t][j + 1]);
+            if let Some((u, v)) = intersection(first, second, tolerance) {
+                splits.entry(s).or_default().push(u);
+                splits.entry(t).or_default().push(v);
+            }
+        }
+    }
+
+    let mut lines: Vec<Vec<Vertex>> = lines
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|p| Vertex {
+                    point: p.clone(),
+                    junction: false,
+                })
+                .collect()
+        })
+        .collect();
+    let mut by_road: HashMap<usize, Vec<(usize, Vec<f64>)>> = HashMap::new();
+    for (s, mut at) in splits {
+        at.sort_by(f64::total_cmp);
+        let (road, i) = segments[s];
+        by_road.entry(road).or_default().push((i, at));
+    }
+    for (road, mut segment_splits) in by_road {
+        // Insert from the end so that earlier segment indices stay valid.
+        segment_splits.sort_by_key(|(i, _)| std::cmp::Reverse(*i));
+        let line = &mut lines[road];
+        for (i, at) in segment_splits {
+            let (a, b) = (line[i].point.clone(), line[i + 1].point.clone());
+            let length = a.distance(&b);
+            let mut inserted = Vec::new();
+            for u in at {
+                let point = Point {
+                    x: a.x + (b.x - a.x) * u,
+                    y: a.y + (b.y - a.y) * u,
+                };
+                if u * length <= tolerance {
+                    line[i].junction = true;
+                } else if (1.0 - u) * length <= tolerance {
+                    line[i + 1].junction = true;
+                } else {
+                    inserted.push(Vertex {
+                        point,
+                        junction: true,
+                    });
+                }
+            }
+            line.splice(i + 1..i + 1, inserted);
+        }
+    }
+    lines
+}
+
+/// Where two segments meet, as the fraction along each, allowing their
+/// ends to reach `tolerance` beyond the other segment. `None` for parallel
+/// segments.
+fn intersection(
+    first: (&Point, &Point),
+    second: (&Point, &Point),
+    tolerance: f64,
+) -> Option<(f64, f64)> {
+    let (a, b) = first;
+    let (c, d) = second;
+    let r = (b.x - a.x, b.y - a.y);
+    let s = (d.x - c.x, d.y - c.y);
+    let denominator = r.0 * s.1 - r.1 * s.0;
+    let (first_length, second_length) = (r.0.hypot(r.1), s.0.hypot(s.1));
+    if denominator.abs() <= 1e-12 * first_length * second_length {
+        return None;
+    }
+    let ac = (c.x - a.x, c.y - a.y);
+    let u = (ac.0 * s.1 - ac.1 * s.0) / denominator;
+    let v = (ac.0 * r.1 - ac.1 * r.0) / denominator;
+    let within = |t: f64, length: f64| t * length >= -tolerance && (t - 1.0) * length <= tolerance;
+    if within(u, first_length) && within(v, second_length) {
+        Some((u.clamp(0.0, 1.0), v.clamp(0.0, 1.0)))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roads::RoadSurface;
+
+    /// Road through points given in metres east and north of 23° E, 61° N.
+    fn road(points: &[(f64, f64)], direction: RoadDirection, level: i64) -> Road {
+        let projection = LocalProjection::new(&Point { x: 23.0, y: 61.0 });
+        Road {
+            points: points
+                .iter()
+                .map(|(x, y)| projection.to_degrees(&Point { x: *x, y: *y }))
+                .collect(),
+            class: None,
+            code: None,
+            surface: RoadSurface::Unknown,
+            direction,
+            width: 5.0,
+            level,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn roads_are_split_where_they_meet() {
+        let roads = vec![
+            road(&[(0.0, 0.0), (100.0, 0.0)], RoadDirection::Both, 0),
+            road(&[(50.0, -50.0), (50.0, 50.0)], RoadDirection::Both, 0),
+            // Stops half a metre short of the first road.
+            road(&[(20.0, 30.0), (20.0, 0.5)], RoadDirection::Both, 0),
+            // A bridge crossing both lower roads.
+            road(&[(0.0, 20.0), (100.0, -20.0)], RoadDirection::Both, 1),
+        ];
+        let graph = RoadGraph::new(&roads, 1.0);
+        // Ends of the four roads, the crossing and the T-junction.
+        assert_eq!(graph.nodes.len(), 9);
+        assert_eq!(graph.edges.len(), 7);
+        let total: f64 = graph.edges.iter().map(|e| e.length).sum();
+        let expected = 100.0 + 100.0 + 30.0 + (100.0f64.powi(2) + 40.0f64.powi(2)).sqrt();
+        assert!((total - expected).abs() < 0.1);
+    }
+
+    #[test]
+    fn snapping_and_nearest_nodes_match_a_scan() {
+        let mut seed: u64 = 7;
+        let mut random = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as f64 / (1u64 << 31) as f64 * 100.0
+        };
+        let points: Vec<Point> = (0..300)
+            .map(|_| Point {
+                x: random(),
+                y: random(),
+            })
+            .collect();
+
+        let mut snapper = Snapper::new(3.0);
+        for point in points.iter() {
+            let before = snapper.points.clone();
+            let cluster = snapper.snap(point);
+            let closest = before
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.distance(point) <= 3.0)
+                .min_by(|a, b| a.1.distance(point).total_cmp(&b.1.distance(point)))
+                .map(|(i, _)| i);
+            assert_eq!(cluster, closest.unwrap_or(before.len()));
+        }
+
+        let roads: Vec<Road> = points
+            .chunks(2)
+            .map(|p| {
+                road(
+                    &[(p[0].x, p[0].y), (p[1].x, p[1].y)],
+                    RoadDirection::Both,
+                    0,
+                )
+            })
+            .collect();
+        let graph = RoadGraph::new(&roads, 1.0);
+        for _ in 0..50 {
+            let point = graph.projection.to_degrees(&Point {
+                x: random(),
+                y: random(),
+            });
+            let metres = graph.projection.to_metres(&point);
+            let node = graph.nearest_node(&point).unwrap();
+            let best = graph
+                .positions
+                .iter()
+                .map(|p| p.distance(&metres))
+                .fold(f64::INFINITY, f64::min);
+            assert_eq!(graph.positions[node].distance(&metres), best);
+        }
+    }
+
+    #[test]
+    fn shortest_path_follows_one_way_roads() {
+        let roads = vec![
+            road(&[(0.0, 0.0), (100.0, 0.0)], RoadDirection::Forward, 0),
+            road(
+                &[(100.0, 0.0), (100.0, 100.0), (0.0, 100.0), (0.0, 0.0)],
+                RoadDirection::Both,
+                0,
+            ),
+        ];
+        let graph = RoadGraph::new(&roads, 1.0);
+        let start = &roads[0].points[0];
+        let end = &roads[0].points[1];
+
+        let forward = graph.shortest_path(start, end).unwrap();
+        assert!((forward.length - 100.0).abs() < 0.1);
+        assert_eq!(forward.edges.len(), 1);
+
+        let back = graph.shortest_path(end, start).unwrap();
+        assert!((back.length - 300.0).abs() < 0.1);
+        assert_eq!(back.points.len(), 4);
+        assert_eq!(back.nodes.first(), graph.nearest_node(end).as_ref());
+    }
+}
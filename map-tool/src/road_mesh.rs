@@ -0,0 +1,481 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::point::Point;
+use crate::geometry::predicates::orient2d;
+use crate::pyramid::TileMesh;
+use crate::visibility::Surface;
+
+/// Options for the road ribbon meshes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct RoadMeshConfig {
+    /// Height in metres of the ribbons above the terrain, so that they
+    /// don't fight with the height mesh. Junction patches sit twice as high.
+    pub lift: f64,
+    /// Longest distance in metres between ribbon cross sections, so that
+    /// the ribbon follows the terrain between the road's own vertices.
+    pub spacing: f64,
+    /// Longest miter at a bend as a multiple of the half width. Sharper
+    /// bends get a bevel instead.
+    pub miter_limit: f64,
+}
+
+impl Default for RoadMeshConfig {
+    fn default() -> RoadMeshConfig {
+        RoadMeshConfig {
+            lift: 0.05,
+            spacing: 2.0,
+            miter_limit: 2.0,
+        }
+    }
+}
+
+/// Road centre line in tile coordinates with its width in metres.
+#[derive(Debug, Clone)]
+pub struct Ribbon {
+    pub points: Vec<(f64, f64)>,
+    pub width: f64,
+}
+
+/// Place where road ribbons meet, in tile coordinates. Each arm is the next
+/// point along one of the roads leaving the junction and that road's width
+/// in metres.
+#[derive(Debug, Clone)]
+pub struct Junction {
+    pub center: (f64, f64),
+    pub arms: Vec<((f64, f64), f64)>,
+}
+
+/// Triangle mesh of road ribbons draped on the surface and patches covering
+/// the junctions. Positions are in tile coordinates with heights in metres,
+/// and triangles wind counterclockwise like the height mesh.
+pub fn road_mesh(
+    ribbons: &[Ribbon],
+    junctions: &[Junction],
+    surface: &Surface,
+    tile_size: (f64, f64),
+    config: &RoadMeshConfig,
+) -> TileMesh {
+    let mut mesh = MeshBuilder {
+        mesh: TileMesh::default(),
+        surface,
+        tile_size,
+    };
+    for ribbon in ribbons {
+        let points: Vec<Point> = ribbon.points.iter().map(|p| mesh.to_metres(*p)).collect();
+        let points = without_repeats(&points);
+        let joins = cross_sections(&points, ribbon.width / 2.0, config.miter_limit);
+        mesh.add_strip(&resample(&points, joins, config.spacing), config.lift);
+    }
+    for junction in junctions {
+        let outline = junction_outline(junction, &mesh);
+        mesh.add_fan(
+            &mesh.to_metres(junction.center),
+            &outline,
+            2.0 * config.lift,
+        );
+    }
+    mesh.mesh
+}
+
+struct MeshBuilder<'a> {
+    mesh: TileMesh,
+    surface: &'a Surface,
+    tile_size: (f64, f64),
+}
+
+impl MeshBuilder<'_> {
+    fn to_metres(&self, p: (f64, f64)) -> Point {
+        Point {
+            x: p.0 * self.tile_size.0,
+            y: p.1 * self.tile_size.1,
+        }
+    }
+
+    fn vertex(&mut self, p: &Point, lift: f64) -> u32 {
+        let (x, y) = (p.x / self.tile_size.0, p.y / self.tile_size.1);
+        self.mesh
+            .positions
+            .push([x, y, self.surface.height(x, y) + lift]);
+        (self.mesh.positions.len() - 1) as u32
+    }
+
+    /// Adds a counterclockwise triangle, leaving out ones without area
+    /// where two corners of a bevel coincide.
+    fn triangle(&mut self, corners: [(u32, &Point); 3]) {
+        let [(a, pa), (b, pb), (c, pc)] = corners;
+        let area = orient2d(pa, pb, pc);
+        debug_assert!(area > -1e-9, "Road mesh triangle winds clockwise");
+        if area > 1e-9 {
+            self.mesh.indices.extend([a, b, c]);
+        }
+    }
+
+    /// Quads between consecutive cross sections, each section being the
+    /// left and right edge of the ribbon.
+    fn add_strip(&mut self, sections: &[(Point, Point)], lift: f64) {
+        let mut previous: Option<(u32, u32)> = None;
+        for (i, (left, right)) in sections.iter().enumerate() {
+            let current = (self.vertex(left, lift), self.vertex(right, lift));
+            if let Some((l0, r0)) = previous {
+                let (pl, pr) = &sections[i - 1];
+                self.triangle([(l0, pl), (r0, pr), (current.1, right)]);
+                self.triangle([(l0, pl), (current.1, right), (current.0, left)]);
+            }
+            previous = Some(current);
+        }
+    }
+
+    /// Fan from the center over a counterclockwise outline.
+    fn add_fan(&mut self, center: &Point, outline: &[Point], lift: f64) {
+        if outline.len() < 3 {
+            return;
+        }
+        let c = self.vertex(center, lift);
+        let indices: Vec<u32> = outline.iter().map(|p| self.vertex(p, lift)).collect();
+        for i in 0..outline.len() {
+            let j = (i + 1) % outline.len();
+            self.triangle([
+                (c, center),
+                (indices[i], &outline[i]),
+                (indices[j], &outline[j]),
+            ]);
+        }
+    }
+}
+
+/// Line with repeated points left out.
+fn without_repeats(points: &[Point]) -> Vec<Point> {
+    let mut kept: Vec<Point> = Vec::new();
+    for point in points {
+        if kept.last().is_none_or(|last| last.distance(point) >= 1e-6) {
+            kept.push(point.clone());
+        }
+    }
+    kept
+}
+
+/// Cross sections of the whole ribbon from the sections at each point, with
+/// sections added along each segment so that no two are more than
+/// `spacing` apart.
+fn resample(
+    points: &[Point],
+    joins: Vec<Vec<(Point, Point)>>,
+    spacing: f64,
+) -> Vec<(Point, Point)> {
+    let mut sections: Vec<(Point, Point)> = Vec::new();
+    for (i, join) in joins.into_iter().enumerate() {
+        if let Some((left, right)) = sections.last().cloned() {
+            let (next_left, next_right) = &join[0];
+            let steps = (points[i - 1].distance(&points[i]) / spacing.max(1e-3)).ceil() as usize;
+            for step in 1..steps {
+                let t = step as f64 / steps as f64;
+                sections.push((lerp(&left, next_left, t), lerp(&right, next_right, t)));
+            }
+        }
+        sections.extend(join);
+    }
+    sections
+}
+
+fn lerp(a: &Point, b: &Point, t: f64) -> Point {
+    Point {
+        x: a.x + (b.x - a.x) * t,
+        y: a.y + (b.y - a.y) * t,
+    }
+}
+
+/// Unit normal to the left of the direction from `a` to `b`.
+fn normal(a: &Point, b: &Point) -> Point {
+    let length = a.distance(b);
+    Point {
+        x: -(b.y - a.y) / length,
+        y: (b.x - a.x) / length,
+    }
+}
+
+fn offset(p: &Point, direction: &Point, distance: f64) -> Point {
+    Point {
+        x: p.x + direction.x * distance,
+        y: p.y + direction.y * distance,
+    }
+}
+
+/// Left and right edge of the ribbon at each point. Bends get a miter
+/// join, or two sections sharing the inner corner when the miter would be
+/// longer than `miter_limit` half widths, which bevels the outer corner.
+/// Corners reach at most halfway along the segments next to them, so that
+/// the inner edge never folds back where the segments are short.
+fn cross_sections(points: &[Point], half_width: f64, miter_limit: f64) -> Vec<Vec<(Point, Point)>> {
+    let mut sections = Vec::new();
+    if points.len() < 2 {
+        return sections;
+    }
+    let last = points.len() - 1;
+    for (i, p) in points.iter().enumerate() {
+        if i == 0 || i == last {
+            let n = if i == 0 {
+                normal(&points[0], &points[1])
+            } else {
+                normal(&points[last - 1], &points[last])
+            };
+            sections.push(vec![(
+                offset(p, &n, half_width),
+                offset(p, &n, -half_width),
+            )]);
+            continue;
+        }
+
+        let n1 = normal(&points[i - 1], p);
+        let n2 = normal(p, &points[i + 1]);
+        let sum = Point {
+            x: n1.x + n2.x,
+            y: n1.y + n2.y,
+        };
+        let sum_length = sum.x.hypot(sum.y);
+        if sum_length <= 1e-9 {
+            // The road turns straight back, so both sides end square here.
+            sections.push(vec![
+                (offset(p, &n1, half_width), offset(p, &n1, -half_width)),
+                (offset(p, &n2, half_width), offset(p, &n2, -half_width)),
+            ]);
+            continue;
+        }
+        let miter = Point {
+            x: sum.x / sum_length,
+            y: sum.y / sum_length,
+        };
+        let cos = miter.x * n1.x + miter.y * n1.y;
+        // A corner `length` out along the miter lies `length * sin` along
+        // both segments.
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let reach = points[i - 1].distance(p).min(p.distance(&points[i + 1])) / 2.0;
+        let corner = |length: f64| {
+            if sin > 1e-9 {
+                length.min(reach / sin)
+            } else {
+                length
+            }
+        };
+        if 1.0 / cos <= miter_limit {
+            let length = corner(half_width / cos);
+            sections.push(vec![(
+                offset(p, &miter, length),
+                offset(p, &miter, -length),
+            )]);
+            continue;
+        }
+
+        // The inner corner is where the inner edges meet, kept within the
+        // miter limit for hairpin bends.
+        let inner_length = corner((half_width / cos).min(miter_limit * half_width));
+        if orient2d(&points[i - 1], p, &points[i + 1]) > 0.0 {
+            let inner = offset(p, &miter, inner_length);
+            sections.push(vec![
+                (inner.clone(), offset(p, &n1, -half_width)),
+                (inner, offset(p, &n2, -half_width)),
+            ]);
+        } else {
+            let inner = offset(p, &miter, -inner_length);
+            sections.push(vec![
+                (offset(p, &n1, half_width), inner.clone()),
+                (offset(p, &n2, half_width), inner),
+            ]);
+        }
+    }
+    sections
+}
+
+/// Convex outline over the ribbon ends at a junction. Each arm covers its
+/// full width from the center out to the widest arm's half width, which
+/// is as far as the other ribbons reach over it.
+fn junction_outline(junction: &Junction, mesh: &MeshBuilder) -> Vec<Point> {
+    let center = mesh.to_metres(junction.center);
+    let reach = junction
+        .arms
+        .iter()
+        .map(|(_, width)| width / 2.0)
+        .fold(0.0, f64::max);
+    let mut corners = Vec::new();
+    for (next, width) in junction.arms.iter() {
+        let next = mesh.to_metres(*next);
+        if center.distance(&next) < 1e-6 {
+            continue;
+        }
+        let n = normal(&center, &next);
+        let along = Point { x: n.y, y: -n.x };
+        let end = offset(&center, &along, reach);
+        for side in [-1.0, 1.0] {
+            corners.push(offset(&center, &n, side * width / 2.0));
+            corners.push(offset(&end, &n, side * width / 2.0));
+        }
+    }
+    convex_hull(corners)
+}
+
+/// Counterclockwise convex hull (monotone chain).
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    if points.len() < 3 {
+        return points;
+    }
+    let mut hull: Vec<Point> = Vec::new();
+    for pass in 0..2 {
+        let start = hull.len();
+        let ordered: Box<dyn Iterator<Item = &Point>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+        for p in ordered {
+            while hull.len() >= start + 2
+                && orient2d(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(p.clone());
+        }
+        hull.pop();
+    }
+    hull
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::Raster;
+
+    fn flat_surface(height: f64) -> Surface {
+        Surface::new(Raster::filled(100, 100, height), None, (1.0, 1.0))
+    }
+
+    fn area(mesh: &TileMesh) -> f64 {
+        mesh.indices
+            .chunks(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| {
+                    let p = mesh.positions[i as usize];
+                    Point { x: p[0], y: p[1] }
+                });
+                assert!(orient2d(&a, &b, &c) > 0.0);
+                orient2d(&a, &b, &c) / 2.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn ribbons_have_the_road_width_and_sit_above_the_terrain() {
+        let config = RoadMeshConfig::default();
+        let ribbon = Ribbon {
+            points: vec![(10.0, 10.0), (50.0, 10.0)],
+            width: 6.0,
+        };
+        let mesh = road_mesh(&[ribbon], &[], &flat_surface(3.0), (1.0, 1.0), &config);
+        assert!((area(&mesh) - 40.0 * 6.0).abs() < 1e-9);
+        // Resampled every two metres.
+        assert_eq!(mesh.positions.len(), 2 * 21);
+        assert!(mesh.positions.iter().all(|p| p[2] == 3.0 + config.lift));
+    }
+
+    #[test]
+    fn bends_are_joined_without_gaps_or_overlaps() {
+        let config = RoadMeshConfig {
+            spacing: 100.0,
+            ..RoadMeshConfig::default()
+        };
+        // A right angle gets a miter, the hairpin a bevel.
+        let right_angle = Ribbon {
+            points: vec![(10.0, 10.0), (40.0, 10.0), (40.0, 40.0)],
+            width: 4.0,
+        };
+        let mesh = road_mesh(&[right_angle], &[], &flat_surface(0.0), (1.0, 1.0), &config);
+        assert!((area(&mesh) - 60.0 * 4.0).abs() < 1e-9);
+
+        let hairpin = Ribbon {
+            points: vec![(10.0, 10.0), (40.0, 10.0), (10.0, 20.0)],
+            width: 4.0,
+        };
+        let mesh = road_mesh(&[hairpin], &[], &flat_surface(0.0), (1.0, 1.0), &config);
+        assert_eq!(mesh.indices.len(), 3 * 5);
+    }
+
+    #[test]
+    fn wide_roads_bend_without_holes_at_the_default_spacing() {
+        let config = RoadMeshConfig::default();
+        // Sixty and ninety degree bends on a 12 m road, both 60 m past the
+        // bend.
+        let rise = 60.0 * 60f64.to_radians().sin();
+        for end in [(50.0 + 30.0, 10.0 + rise), (50.0, 70.0)] {
+            let ribbon = Ribbon {
+                points: vec![(10.0, 10.0), (50.0, 10.0), end],
+                width: 12.0,
+            };
+            let mesh = road_mesh(&[ribbon], &[], &flat_surface(0.0), (1.0, 1.0), &config);
+            assert!((area(&mesh) - (40.0 + 60.0) * 12.0).abs() < 1e-6);
+            let sections = mesh.positions.len() / 2;
+            assert_eq!(mesh.indices.len(), 3 * 2 * (sections - 1));
+        }
+
+        // A tight curve of one metre segments, narrower inside than the
+        // half width, keeps every triangle counterclockwise.
+        let curve: Vec<(f64, f64)> = (0..=20)
+            .map(|i| {
+                let angle = i as f64 * 0.2;
+                (50.0 + 5.0 * angle.cos(), 50.0 + 5.0 * angle.sin())
+            })
+            .collect();
+        let ribbon = Ribbon {
+            points: curve,
+            width: 12.0,
+        };
+        let mesh = road_mesh(&[ribbon], &[], &flat_surface(0.0), (1.0, 1.0), &config);
+        assert!(area(&mesh) > 0.0);
+    }
+
+    #[test]
+    fn roads_turning_straight_back_end_square() {
+        let points = [
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 10.0, y: 0.0 },
+            Point { x: 0.0, y: 0.0 },
+        ];
+        let sections = cross_sections(&points, 2.0, 2.0).concat();
+        assert_eq!(sections.len(), 4);
+        assert!(sections
+            .iter()
+            .all(|(l, r)| [l.x, l.y, r.x, r.y].iter().all(|c| c.is_finite())));
+        assert_eq!((sections[1].0.y, sections[1].1.y), (2.0, -2.0));
+        assert_eq!((sections[2].0.y, sections[2].1.y), (-2.0, 2.0));
+
+        let config = RoadMeshConfig {
+            spacing: 100.0,
+            ..RoadMeshConfig::default()
+        };
+        let reversal = Ribbon {
+            points: vec![(10.0, 10.0), (20.0, 10.0), (10.0, 10.0)],
+            width: 4.0,
+        };
+        let mesh = road_mesh(&[reversal], &[], &flat_surface(0.0), (1.0, 1.0), &config);
+        assert!(mesh.positions.iter().flatten().all(|c| c.is_finite()));
+        assert!((area(&mesh) - 2.0 * 10.0 * 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn junction_patches_cover_crossings() {
+        let junction = Junction {
+            center: (20.0, 20.0),
+            arms: vec![
+                ((30.0, 20.0), 6.0),
+                ((10.0, 20.0), 6.0),
+                ((20.0, 30.0), 4.0),
+                ((20.0, 10.0), 4.0),
+            ],
+        };
+        let config = RoadMeshConfig::default();
+        let mesh = road_mesh(&[], &[junction], &flat_surface(0.0), (1.0, 1.0), &config);
+        // The wider road across the narrower one, whose ends reach 3 m
+        // from the center.
+        assert!((area(&mesh) - 6.0 * 6.0).abs() < 1e-9);
+        assert!(mesh.positions.iter().all(|p| p[2] == 2.0 * config.lift));
+    }
+}
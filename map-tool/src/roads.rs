@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::point::{LineSegment, Point};
+
+/// Road widths by road class and how the road graph joins roads.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RoadConfig {
+    pub classes: Vec<RoadClass>,
+    /// Distance in metres within which road vertices become one graph node.
+    pub snap_tolerance: f64,
+}
+
+/// Roads with any of the `kohdeluokka` codes of a class are `width` metres
+/// wide.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoadClass {
+    pub name: String,
+    pub codes: Vec<i64>,
+    pub width: f64,
+}
+
+impl Default for RoadConfig {
+    fn default() -> RoadConfig {
+        RoadConfig {
+            classes: vec![
+                road_class("motorway", &[12111, 12112], 12.0),
+                road_class("road", &[12121, 12122], 8.0),
+                road_class("street", &[12131, 12132, 12141], 5.0),
+                road_class("path", &[12312, 12313, 12314, 12316], 2.0),
+            ],
+            snap_tolerance: 1.0,
+        }
+    }
+}
+
+fn road_class(name: &str, codes: &[i64], width: f64) -> RoadClass {
+    RoadClass {
+        name: String::from(name),
+        codes: codes.to_vec(),
+        width,
+    }
+}
+
+impl RoadConfig {
+    /// Class of a road with class code `code`.
+    pub fn classify(&self, code: Option<i64>) -> Option<&RoadClass> {
+        let code = code?;
+        self.classes
+            .iter()
+            .find(|class| class.codes.contains(&code))
+    }
+}
+
+/// Surface of a road from its `paallyste` code.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoadSurface {
+    Unknown,
+    Unpaved,
+    Paved,
+}
+
+impl RoadSurface {
+    pub fn from_code(code: Option<i64>) -> RoadSurface {
+        match code {
+            Some(1) => RoadSurface::Unpaved,
+            Some(2) => RoadSurface::Paved,
+            _ => RoadSurface::Unknown,
+        }
+    }
+}
+
+/// Directions a road can be travelled in, from its `yksisuuntaisuus` code.
+/// `Forward` is the direction the road's points run in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoadDirection {
+    Both,
+    Forward,
+    Backward,
+}
+
+impl RoadDirection {
+    pub fn from_code(code: Option<i64>) -> RoadDirection {
+        match code {
+            Some(1) => RoadDirection::Forward,
+            Some(2) => RoadDirection::Backward,
+            _ => RoadDirection::Both,
+        }
+    }
+}
+
+/// One road feature as a polyline with its attributes.
+#[derive(Debug, Clone)]
+pub struct Road {
+    pub points: Vec<Point>,
+    /// Name of the road class, `None` when the class code is in no class.
+    pub class: Option<String>,
+    /// Feature class code `kohdeluokka`.
+    pub code: Option<i64>,
+    pub surface: RoadSurface,
+    pub direction: RoadDirection,
+    /// Width in metres.
+    pub width: f64,
+    /// Vertical level `tasosijainti`, 0 on the ground, above it on bridges
+    /// and below it in tunnels.
+    pub level: i64,
+    pub name: Option<String>,
+}
+
+impl Road {
+    pub fn segments(&self) -> impl Iterator<Item = LineSegment> + '_ {
+        self.points.windows(2).map(|pair| LineSegment {
+            a: pair[0].clone(),
+            b: pair[1].clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_map_to_surfaces_and_directions() {
+        assert_eq!(RoadSurface::from_code(Some(1)), RoadSurface::Unpaved);
+        assert_eq!(RoadSurface::from_code(Some(2)), RoadSurface::Paved);
+        assert_eq!(RoadSurface::from_code(Some(0)), RoadSurface::Unknown);
+        assert_eq!(RoadSurface::from_code(None), RoadSurface::Unknown);
+        assert_eq!(RoadDirection::from_code(Some(1)), RoadDirection::Forward);
+        assert_eq!(RoadDirection::from_code(Some(2)), RoadDirection::Backward);
+        assert_eq!(RoadDirection::from_code(Some(0)), RoadDirection::Both);
+        assert_eq!(RoadDirection::from_code(None), RoadDirection::Both);
+    }
+
+    #[test]
+    fn classes_give_the_width_of_their_codes() {
+        let config = RoadConfig::default();
+        let width = |code| config.classify(code).map(|class| class.width);
+        assert_eq!(width(Some(12111)), Some(12.0));
+        assert_eq!(width(Some(12132)), Some(5.0));
+        assert_eq!(width(Some(12316)), Some(2.0));
+        assert_eq!(width(Some(12999)), None);
+        assert_eq!(width(None), None);
+        assert_eq!(config.classify(Some(12122)).unwrap().name, "road");
+    }
+}
@@ -0,0 +1,307 @@
+use crate::pyramid::TileMesh;
+use crate::raster::Raster;
+
+/// Right-triangulated irregular network over a height raster.
+///
+/// The raster is covered by two right triangles which are recursively split
+/// at the middle of their hypotenuse. Every split point stores the largest
+/// vertical error of any triangle in the hierarchy that is split there, so
+/// a mesh cut at a threshold never has a triangle whose plane is farther
+/// than the threshold from a raster cell it covers. Triangles sharing a
+/// hypotenuse share its split point, so the mesh has no cracks.
+pub struct Rtin {
+    /// Width and height of the raster before padding.
+    size: (usize, usize),
+    grid_size: usize,
+    heights: Vec<f64>,
+    errors: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MeshReport {
+    pub max_error: f64,
+    pub triangles: usize,
+    pub vertices: usize,
+    /// Triangles in the full resolution grid mesh.
+    pub grid_triangles: usize,
+}
+
+type GridPoint = (usize, usize);
+
+/// Corners of a right triangle, right angle at the last one.
+type Triangle = (GridPoint, GridPoint, GridPoint);
+
+impl Rtin {
+    /// Builds the error hierarchy. Unless the raster already is a `2^k + 1`
+    /// grid it is padded to one by repeating its last row and column. Missing
+    /// cells count as height zero like map tiles without data.
+    pub fn new(raster: &Raster) -> Rtin {
+        let size = raster.width().max(raster.height()).max(2);
+        let grid_size = (size - 1).next_power_of_two() + 1;
+        let mut heights = Vec::with_capacity(grid_size * grid_size);
+        for y in 0..grid_size {
+            for x in 0..grid_size {
+                let x = x.min(raster.width().saturating_sub(1));
+                let y = y.min(raster.height().saturating_sub(1));
+                let height = if raster.width() == 0 || raster.height() == 0 {
+                    0.0
+                } else {
+                    raster.get(x, y).unwrap_or(0.0)
+                };
+                heights.push(height);
+            }
+        }
+
+        let mut rtin = Rtin {
+            size: (raster.width(), raster.height()),
+            grid_size,
+            heights,
+            errors: vec![0.0; grid_size * grid_size],
+        };
+        rtin.compute_errors();
+        rtin
+    }
+
+    fn height(&self, (x, y): GridPoint) -> f64 {
+        self.heights[y * self.grid_size + x]
+    }
+
+    /// Goes through the hierarchy one level at a time from the smallest
+    /// triangles up. A split point is shared by the triangles on both sides
+    /// of a hypotenuse, so every level has to be finished before the level
+    /// above reads its errors.
+    fn compute_errors(&mut self) {
+        let last = self.grid_size - 1;
+        let mut levels: Vec<Vec<Triangle>> = vec![vec![
+            ((0, 0), (last, last), (last, 0)),
+            ((last, last), (0, 0), (0, last)),
+        ]];
+        loop {
+            let children: Vec<Triangle> = levels
+                .last()
+                .unwrap()
+                .iter()
+                .filter(|(a, _, c)| can_split(*a, *c))
+                .flat_map(|(a, b, c)| {
+                    let m = midpoint(*a, *b);
+                    [(*c, *a, m), (*b, *c, m)]
+                })
+                .collect();
+            if children.is_empty() {
+                break;
+            }
+            levels.push(children);
+        }
+
+        for level in levels.iter().rev() {
+            for (a, b, c) in level.iter().filter(|(a, _, c)| can_split(*a, *c)) {
+                let m = midpoint(*a, *b);
+                let mut error = self.triangle_error(*a, *b, *c);
+                for (child_a, child_b, child_c) in [(*c, *a, m), (*b, *c, m)] {
+                    if can_split(child_a, child_c) {
+                        error = error.max(self.error_at(midpoint(child_a, child_b)));
+                    }
+                }
+                let index = m.1 * self.grid_size + m.0;
+                self.errors[index] = self.errors[index].max(error);
+            }
+        }
+    }
+
+    fn error_at(&self, (x, y): GridPoint) -> f64 {
+        self.errors[y * self.grid_size + x]
+    }
+
+    /// Largest distance along z between the triangle's plane and the grid
+    /// points inside it.
+    fn triangle_error(&self, a: GridPoint, b: GridPoint, c: GridPoint) -> f64 {
+        let (ax, ay) = (a.0 as f64, a.1 as f64);
+        let (bx, by) = (b.0 as f64, b.1 as f64);
+        let (cx, cy) = (c.0 as f64, c.1 as f64);
+        let area = (bx - ax) * (cy - ay) - (cx - ax) * (by - ay);
+        let (ha, hb, hc) = (self.height(a), self.height(b), self.height(c));
+
+        let mut error: f64 = 0.0;
+        for y in a.1.min(b.1).min(c.1)..=a.1.max(b.1).max(c.1) {
+            for x in a.0.min(b.0).min(c.0)..=a.0.max(b.0).max(c.0) {
+                let (px, py) = (x as f64, y as f64);
+                let wa = ((bx - px) * (cy - py) - (cx - px) * (by - py)) / area;
+                let wb = ((cx - px) * (ay - py) - (ax - px) * (cy - py)) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let plane = wa * ha + wb * hb + wc * hc;
+                error = error.max((self.height((x, y)) - plane).abs());
+            }
+        }
+        error
+    }
+
+    /// Mesh whose surface stays within `max_error` of every raster cell.
+    /// Positions are in raster cells with the height as z.
+    pub fn mesh(&self, max_error: f64) -> TileMesh {
+        let mut mesh = TileMesh::default();
+        let mut vertex_index = vec![u32::MAX; self.grid_size * self.grid_size];
+        let last = self.grid_size - 1;
+        self.add_triangles(
+            &mut mesh,
+            &mut vertex_index,
+            max_error,
+            ((0, 0), (last, last), (last, 0)),
+        );
+        self.add_triangles(
+            &mut mesh,
+            &mut vertex_index,
+            max_error,
+            ((last, last), (0, 0), (0, last)),
+        );
+        mesh.set_texture_coords(self.size);
+        mesh
+    }
+
+    fn add_triangles(
+        &self,
+        mesh: &mut TileMesh,
+        vertex_index: &mut [u32],
+        max_error: f64,
+        (a, b, c): Triangle,
+    ) {
+        let m = midpoint(a, b);
+        if can_split(a, c) && self.error_at(m) > max_error {
+            self.add_triangles(mesh, vertex_index, max_error, (c, a, m));
+            self.add_triangles(mesh, vertex_index, max_error, (b, c, m));
+            return;
+        }
+
+        let mut triangle = [a, b, c];
+        // Same winding as the tile meshes, counterclockwise in map
+        // coordinates.
+        let cross = (b.0 as f64 - a.0 as f64) * (c.1 as f64 - a.1 as f64)
+            - (c.0 as f64 - a.0 as f64) * (b.1 as f64 - a.1 as f64);
+        if cross < 0.0 {
+            triangle.swap(1, 2);
+        }
+        for point in triangle {
+            let index = point.1 * self.grid_size + point.0;
+            if vertex_index[index] == u32::MAX {
+                vertex_index[index] = mesh.positions.len() as u32;
+                mesh.positions
+                    .push([point.0 as f64, point.1 as f64, self.height(point)]);
+            }
+            mesh.indices.push(vertex_index[index]);
+        }
+    }
+
+    pub fn report(&self, max_error: f64) -> MeshReport {
+        let mesh = self.mesh(max_error);
+        MeshReport {
+            max_error,
+            triangles: mesh.indices.len() / 3,
+            vertices: mesh.positions.len(),
+            grid_triangles: (self.grid_size - 1).pow(2) * 2,
+        }
+    }
+}
+
+fn midpoint(a: GridPoint, b: GridPoint) -> GridPoint {
+    ((a.0 + b.0) / 2, (a.1 + b.1) / 2)
+}
+
+/// The smallest triangles have legs one cell long and no grid point in the
+/// middle of their hypotenuse.
+fn can_split(a: GridPoint, c: GridPoint) -> bool {
+    a.0.abs_diff(c.0) + a.1.abs_diff(c.1) > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Rolling hills with some noise, so every level of the hierarchy has
+    /// errors of its own.
+    fn hills(width: usize, height: usize) -> Raster {
+        let mut state = 7u64;
+        let rows: Vec<Vec<f64>> = (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        state = state
+                            .wrapping_mul(6_364_136_223_846_793_005)
+                            .wrapping_add(1_442_695_040_888_963_407);
+                        let noise = (state >> 11) as f64 / (1u64 << 53) as f64;
+                        let (x, y) = (x as f64, y as f64);
+                        20.0 * (x / 5.0).sin() * (y / 7.0).cos() + 3.0 * noise
+                    })
+                    .collect()
+            })
+            .collect();
+        Raster::from_rows(&rows, None)
+    }
+
+    fn triangles(mesh: &TileMesh) -> impl Iterator<Item = [[f64; 3]; 3]> + '_ {
+        mesh.indices
+            .chunks(3)
+            .map(|t| [0, 1, 2].map(|i| mesh.positions[t[i] as usize]))
+    }
+
+    #[test]
+    fn every_cell_is_within_the_error_of_the_mesh() {
+        for (width, height) in [(33, 33), (20, 27)] {
+            let raster = hills(width, height);
+            let rtin = Rtin::new(&raster);
+            for max_error in [0.0, 0.5, 2.0, 10.0] {
+                let mesh = rtin.mesh(max_error);
+                let mut covered = vec![false; width * height];
+                for [a, b, c] in triangles(&mesh) {
+                    let area = (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1]);
+                    assert!(area > 0.0, "triangles wind counterclockwise");
+                    for y in 0..height {
+                        for x in 0..width {
+                            let (px, py) = (x as f64, y as f64);
+                            let wa = ((b[0] - px) * (c[1] - py) - (c[0] - px) * (b[1] - py)) / area;
+                            let wb = ((c[0] - px) * (a[1] - py) - (a[0] - px) * (c[1] - py)) / area;
+                            let wc = 1.0 - wa - wb;
+                            if wa < -1e-12 || wb < -1e-12 || wc < -1e-12 {
+                                continue;
+                            }
+                            let surface = wa * a[2] + wb * b[2] + wc * c[2];
+                            let cell = raster.get(x, y).unwrap();
+                            assert!(
+                                (surface - cell).abs() <= max_error + 1e-9,
+                                "cell ({x}, {y}) is {} from the mesh at error {max_error}",
+                                (surface - cell).abs()
+                            );
+                            covered[y * width + x] = true;
+                        }
+                    }
+                }
+                assert!(covered.iter().all(|c| *c));
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_share_whole_edges() {
+        let rtin = Rtin::new(&hills(33, 33));
+        let last = 32.0;
+        for max_error in [0.5, 2.0, 10.0] {
+            let mesh = rtin.mesh(max_error);
+            let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+            for t in mesh.indices.chunks(3) {
+                for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                    *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+                }
+            }
+            // An edge with a vertex of the neighbour in its middle would be
+            // used by one triangle only, like the edges on the grid border.
+            for ((a, b), count) in edges {
+                let (a, b) = (mesh.positions[a as usize], mesh.positions[b as usize]);
+                let border = (0..2).any(|i| a[i] == b[i] && (a[i] == 0.0 || a[i] == last));
+                assert_eq!(count, if border { 1 } else { 2 }, "edge {a:?} {b:?}");
+            }
+        }
+    }
+}
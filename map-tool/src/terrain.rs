@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+
+use crate::raster::Raster;
+
+/// Post-processing step for a height raster. Sizes are in cells and height
+/// differences in the units of the raster.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "filter", rename_all = "snake_case")]
+pub enum TerrainFilter {
+    /// Separable Gaussian blur. Missing cells are left out of the kernel and
+    /// stay missing.
+    Gaussian { sigma: f64 },
+    /// Gaussian blur that also weighs neighbours by how close their height
+    /// is, which smooths noise but keeps steep edges.
+    Bilateral {
+        sigma_spatial: f64,
+        sigma_range: f64,
+    },
+    /// Replaces cells that differ from the median of their window by more
+    /// than `threshold`, or every cell without a threshold.
+    Median {
+        radius: usize,
+        #[serde(default)]
+        threshold: Option<f64>,
+    },
+    /// Fills missing cells with the inverse distance weighted average of the
+    /// valid cells within `radius`.
+    FillIdw { radius: usize, power: f64 },
+    /// Fills missing cells with the smooth surface that meets the valid
+    /// cells around each hole, by relaxing the Laplace equation.
+    FillLaplacian { iterations: usize },
+}
+
+impl TerrainFilter {
+    pub fn apply(&self, raster: &Raster) -> Raster {
+        match *self {
+            TerrainFilter::Gaussian { sigma } => gaussian(raster, sigma),
+            TerrainFilter::Bilateral {
+                sigma_spatial,
+                sigma_range,
+            } => bilateral(raster, sigma_spatial, sigma_range),
+            TerrainFilter::Median { radius, threshold } => median(raster, radius, threshold),
+            TerrainFilter::FillIdw { radius, power } => fill_idw(raster, radius, power),
+            TerrainFilter::FillLaplacian { iterations } => fill_laplacian(raster, iterations),
+        }
+    }
+}
+
+/// Runs the filters in order.
+pub fn apply_filters(raster: &Raster, filters: &[TerrainFilter]) -> Raster {
+    filters
+        .iter()
+        .fold(raster.clone(), |raster, filter| filter.apply(&raster))
+}
+
+fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let radius = (sigma * 3.0).ceil().max(0.0) as i64;
+    (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect()
+}
+
+pub fn gaussian(raster: &Raster, sigma: f64) -> Raster {
+    if sigma <= 0.0 {
+        return raster.clone();
+    }
+
+    let kernel = gaussian_kernel(sigma);
+    let horizontal = convolve_1d(raster, &kernel, (1, 0));
+    convolve_1d(&horizontal, &kernel, (0, 1))
+}
+
+/// Weighted average along one axis over the valid cells. Missing cells are
+/// skipped and stay missing.
+fn convolve_1d(raster: &Raster, kernel: &[f64], direction: (i64, i64)) -> Raster {
+    let radius = (kernel.len() / 2) as i64;
+    let mut result = Raster::new(raster.width(), raster.height());
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            if raster.get(x, y).is_none() {
+                continue;
+            }
+
+            let mut sum = 0.0;
+            let mut total_weight = 0.0;
+            for (k, weight) in kernel.iter().enumerate() {
+                let offset = k as i64 - radius;
+                let nx = x as i64 + offset * direction.0;
+                let ny = y as i64 + offset * direction.1;
+                if let Some(value) = get(raster, nx, ny) {
+                    sum += value * weight;
+                    total_weight += weight;
+                }
+            }
+            if total_weight > 0.0 {
+                result.set(x, y, Some(sum / total_weight));
+            }
+        }
+    }
+    result
+}
+
+pub fn bilateral(raster: &Raster, sigma_spatial: f64, sigma_range: f64) -> Raster {
+    if sigma_spatial <= 0.0 || sigma_range <= 0.0 {
+        return raster.clone();
+    }
+
+    let radius = (sigma_spatial * 3.0).ceil() as i64;
+    let mut result = Raster::new(raster.width(), raster.height());
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            let center = match raster.get(x, y) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let mut sum = 0.0;
+            let mut total_weight = 0.0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let value = match get(raster, x as i64 + dx, y as i64 + dy) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let spatial = (dx * dx + dy * dy) as f64 / (2.0 * sigma_spatial.powi(2));
+                    let range = (value - center).powi(2) / (2.0 * sigma_range.powi(2));
+                    let weight = (-spatial - range).exp();
+                    sum += value * weight;
+                    total_weight += weight;
+                }
+            }
+            result.set(x, y, Some(sum / total_weight));
+        }
+    }
+    result
+}
+
+pub fn median(raster: &Raster, radius: usize, threshold: Option<f64>) -> Raster {
+    let radius = radius as i64;
+    let mut result = raster.clone();
+    let mut window = Vec::new();
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            let center = match raster.get(x, y) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            window.clear();
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if let Some(value) = get(raster, x as i64 + dx, y as i64 + dy) {
+                        window.push(value);
+                    }
+                }
+            }
+            window.sort_by(f64::total_cmp);
+            let middle = window.len() / 2;
+            let median = if window.len() % 2 == 0 {
+                (window[middle - 1] + window[middle]) / 2.0
+            } else {
+                window[middle]
+            };
+
+            if threshold
+                .map(|t| (center - median).abs() > t)
+                .unwrap_or(true)
+            {
+                result.set(x, y, Some(median));
+            }
+        }
+    }
+    result
+}
+
+pub fn fill_idw(raster: &Raster, radius: usize, power: f64) -> Raster {
+    let radius = radius as i64;
+    let mut result = raster.clone();
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            if raster.get(x, y).is_some() {
+                continue;
+            }
+
+            let mut sum = 0.0;
+            let mut total_weight = 0.0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let distance_squared = (dx * dx + dy * dy) as f64;
+                    if distance_squared > (radius * radius) as f64 {
+                        continue;
+                    }
+                    if let Some(value) = get(raster, x as i64 + dx, y as i64 + dy) {
+                        let weight = distance_squared.powf(-power / 2.0);
+                        sum += value * weight;
+                        total_weight += weight;
+                    }
+                }
+            }
+            if total_weight > 0.0 {
+                result.set(x, y, Some(sum / total_weight));
+            }
+        }
+    }
+    result
+}
+
+/// Gauss-Seidel relaxation over the missing cells, starting from the mean of
+/// the valid cells. Cells at the raster edge only use the neighbours that
+/// exist. Holes that touch no valid cell at all stay missing.
+pub fn fill_laplacian(raster: &Raster, iterations: usize) -> Raster {
+    let holes: Vec<(usize, usize)> = (0..raster.height())
+        .flat_map(|y| (0..raster.width()).map(move |x| (x, y)))
+        .filter(|(x, y)| raster.get(*x, *y).is_none())
+        .collect();
+    if holes.is_empty() {
+        return raster.clone();
+    }
+
+    let reachable = reachable_holes(raster);
+    let (sum, count) = (0..raster.height())
+        .flat_map(|y| (0..raster.width()).map(move |x| (x, y)))
+        .filter_map(|(x, y)| raster.get(x, y))
+        .fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    if count == 0 {
+        return raster.clone();
+    }
+
+    let mut result = raster.clone();
+    for (x, y) in holes.iter() {
+        if reachable.get(*x, *y).is_some() {
+            result.set(*x, *y, Some(sum / count as f64));
+        }
+    }
+
+    for _ in 0..iterations {
+        let mut change: f64 = 0.0;
+        for (x, y) in holes.iter() {
+            if reachable.get(*x, *y).is_none() {
+                continue;
+            }
+
+            let (mut sum, mut count) = (0.0, 0);
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                if let Some(value) = get(&result, *x as i64 + dx, *y as i64 + dy) {
+                    sum += value;
+                    count += 1;
+                }
+            }
+            let value = sum / count as f64;
+            change = change.max((value - result.get(*x, *y).unwrap()).abs());
+            result.set(*x, *y, Some(value));
+        }
+        if change < 1e-9 {
+            break;
+        }
+    }
+    result
+}
+
+/// Marks the missing cells connected to at least one valid cell.
+fn reachable_holes(raster: &Raster) -> Raster {
+    let mut reachable = Raster::new(raster.width(), raster.height());
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            if raster.get(x, y).is_some() {
+                stack.push((x, y));
+            }
+        }
+    }
+    while let Some((x, y)) = stack.pop() {
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            if !inside(raster, nx, ny) {
+                continue;
+            }
+            let (nx, ny) = (nx as usize, ny as usize);
+            if raster.get(nx, ny).is_none() && reachable.get(nx, ny).is_none() {
+                reachable.set(nx, ny, Some(1.0));
+                stack.push((nx, ny));
+            }
+        }
+    }
+    reachable
+}
+
+fn inside(raster: &Raster, x: i64, y: i64) -> bool {
+    x >= 0 && y >= 0 && x < raster.width() as i64 && y < raster.height() as i64
+}
+
+fn get(raster: &Raster, x: i64, y: i64) -> Option<f64> {
+    if inside(raster, x, y) {
+        raster.get(x as usize, y as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster<F: Fn(usize, usize) -> f64>(width: usize, height: usize, f: F) -> Raster {
+        let rows: Vec<Vec<f64>> = (0..height)
+            .map(|y| (0..width).map(|x| f(x, y)).collect())
+            .collect();
+        Raster::from_rows(&rows, None)
+    }
+
+    fn with_hole(raster: &Raster, x0: usize, y0: usize, size: usize) -> Raster {
+        let mut raster = raster.clone();
+        for y in y0..y0 + size {
+            for x in x0..x0 + size {
+                raster.set(x, y, None);
+            }
+        }
+        raster
+    }
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn gaussian_keeps_constant_surface() {
+        let flat = with_hole(&raster(12, 10, |_, _| 5.0), 4, 4, 2);
+        let smoothed = gaussian(&flat, 1.5);
+        for y in 0..10 {
+            for x in 0..12 {
+                assert_eq!(smoothed.get(x, y).is_none(), flat.get(x, y).is_none());
+                if let Some(value) = smoothed.get(x, y) {
+                    assert_close(value, 5.0, 1e-12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gaussian_spreads_spike() {
+        let spike = raster(11, 11, |x, y| if (x, y) == (5, 5) { 100.0 } else { 0.0 });
+        let smoothed = gaussian(&spike, 1.0);
+        let center = smoothed.get(5, 5).unwrap();
+        assert!(center < 100.0 && center > 0.0);
+        assert!(smoothed.get(4, 5).unwrap() > smoothed.get(3, 5).unwrap());
+        assert_close(
+            smoothed.get(4, 5).unwrap(),
+            smoothed.get(5, 6).unwrap(),
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn bilateral_keeps_step_edge() {
+        let step = raster(10, 10, |x, _| if x < 5 { 0.0 } else { 100.0 });
+        let smoothed = bilateral(&step, 2.0, 5.0);
+        assert_close(smoothed.get(4, 5).unwrap(), 0.0, 1e-6);
+        assert_close(smoothed.get(5, 5).unwrap(), 100.0, 1e-6);
+
+        let blurred = gaussian(&step, 2.0);
+        assert!(blurred.get(4, 5).unwrap() > 10.0);
+    }
+
+    #[test]
+    fn bilateral_smooths_small_noise() {
+        let noisy = raster(9, 9, |x, y| if (x + y) % 2 == 0 { 1.0 } else { -1.0 });
+        let smoothed = bilateral(&noisy, 1.5, 10.0);
+        assert!(smoothed.get(4, 4).unwrap().abs() < 0.2);
+    }
+
+    #[test]
+    fn median_removes_speckle() {
+        let speckled = raster(7, 7, |x, y| match (x, y) {
+            (3, 3) => 50.0,
+            _ => x as f64,
+        });
+        let cleaned = median(&speckled, 1, Some(10.0));
+        assert_eq!(cleaned.get(3, 3), Some(3.0));
+        for x in 0..7 {
+            assert_eq!(cleaned.get(x, 1), Some(x as f64));
+        }
+
+        let everything = median(&speckled, 1, None);
+        assert_eq!(everything.get(3, 3), Some(3.0));
+        assert_eq!(everything.get(0, 0), Some(0.5));
+    }
+
+    #[test]
+    fn idw_fills_holes_within_radius() {
+        let plane = raster(10, 10, |_, _| 7.0);
+        let filled = fill_idw(&with_hole(&plane, 3, 3, 3), 3, 2.0);
+        for y in 3..6 {
+            for x in 3..6 {
+                assert_close(filled.get(x, y).unwrap(), 7.0, 1e-12);
+            }
+        }
+
+        let wide = with_hole(&plane, 0, 0, 9);
+        assert_eq!(fill_idw(&wide, 2, 2.0).get(0, 0), None);
+    }
+
+    #[test]
+    fn idw_weights_closer_cells_more() {
+        let ramp = raster(10, 1, |x, _| x as f64);
+        let filled = fill_idw(&with_hole(&ramp, 2, 0, 1), 5, 2.0);
+        let value = filled.get(2, 0).unwrap();
+        assert!(value > 2.0 && value < 4.0);
+    }
+
+    #[test]
+    fn laplacian_fills_plane_exactly() {
+        let plane = raster(12, 12, |x, y| 2.0 * x as f64 - y as f64 + 10.0);
+        let filled = fill_laplacian(&with_hole(&plane, 3, 4, 5), 2000);
+        for y in 4..9 {
+            for x in 3..8 {
+                assert_close(filled.get(x, y).unwrap(), plane.get(x, y).unwrap(), 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn laplacian_leaves_empty_raster_missing() {
+        let empty = Raster::new(4, 4);
+        assert_eq!(fill_laplacian(&empty, 10).get(1, 1), None);
+    }
+
+    #[test]
+    fn filters_chain_from_config() {
+        let filters: Vec<TerrainFilter> = serde_json::from_str(
+            r#"[
+                { "filter": "fill_laplacian", "iterations": 500 },
+                { "filter": "median", "radius": 1, "threshold": 10.0 },
+                { "filter": "gaussian", "sigma": 1.0 }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            filters[1],
+            TerrainFilter::Median {
+                radius: 1,
+                threshold: Some(10.0)
+            }
+        );
+
+        let mut input = with_hole(&raster(10, 10, |_, _| 3.0), 2, 2, 2);
+        input.set(7, 7, Some(90.0));
+        let output = apply_filters(&input, &filters);
+        for y in 0..10 {
+            for x in 0..10 {
+                assert_close(output.get(x, y).unwrap(), 3.0, 1e-6);
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+
+use crate::data::normal_from_neighbours;
+use crate::geometry::point::Vec3;
+use crate::raster::Raster;
+
+/// Options for the terrain derivatives.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    /// Directions of the hillshade lights in degrees clockwise from north.
+    pub azimuths: Vec<f64>,
+    /// Height of the lights above the horizon in degrees.
+    pub altitude: f64,
+    /// Vertical exaggeration used for hillshading.
+    pub z_factor: f64,
+    /// Curvature in 1/m that maps to the ends of the curvature images.
+    pub curvature_range: f64,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> AnalysisConfig {
+        AnalysisConfig {
+            azimuths: vec![225.0, 270.0, 315.0, 360.0],
+            altitude: 45.0,
+            z_factor: 1.0,
+            curvature_range: 0.05,
+        }
+    }
+}
+
+/// Per cell derivatives of a height raster. Every raster has the size and
+/// orientation of the heights, and cells with missing height are missing.
+pub struct TerrainDerivatives {
+    /// Steepness in degrees from horizontal.
+    pub slope: Raster,
+    /// Downslope direction in degrees clockwise from north. Missing on flat
+    /// cells.
+    pub aspect: Raster,
+    /// Illumination from 0 to 1.
+    pub hillshade: Raster,
+    /// Second derivative across the slope along the contour in 1/m. Positive
+    /// in hollows where flow converges, negative on spurs.
+    pub plan_curvature: Raster,
+    /// Second derivative down the slope in 1/m. Positive where the slope
+    /// flattens out, negative where it gets steeper.
+    pub profile_curvature: Raster,
+}
+
+/// Computes the derivatives of `heights` in metres, with rows running from
+/// north to south and `spacing` the cell size in metres along x and y.
+/// Missing neighbours take the height of the center cell, and cells past
+/// the edge repeat the edge.
+pub fn analyse(
+    heights: &Raster,
+    spacing: (f64, f64),
+    config: &AnalysisConfig,
+) -> TerrainDerivatives {
+    let (width, height) = (heights.width(), heights.height());
+    let mut derivatives = TerrainDerivatives {
+        slope: Raster::new(width, height),
+        aspect: Raster::new(width, height),
+        hillshade: Raster::new(width, height),
+        plan_curvature: Raster::new(width, height),
+        profile_curvature: Raster::new(width, height),
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = match heights.get(x, y) {
+                Some(h) => h,
+                None => continue,
+            };
+            // Neighbour `dx` cells east and `dy` cells north.
+            let z = |dx: i64, dy: i64| {
+                let nx = (x as i64 + dx).clamp(0, width as i64 - 1) as usize;
+                let ny = (y as i64 - dy).clamp(0, height as i64 - 1) as usize;
+                heights.get(nx, ny).unwrap_or(center)
+            };
+
+            let normal = normal_from_neighbours(z, spacing).normalize();
+            let slope = normal.z.clamp(-1.0, 1.0).acos();
+            derivatives.slope.set(x, y, Some(slope.to_degrees()));
+
+            let aspect = aspect(&normal);
+            derivatives.aspect.set(x, y, aspect.map(f64::to_degrees));
+
+            let shading_normal = Vec3 {
+                x: normal.x * config.z_factor,
+                y: normal.y * config.z_factor,
+                z: normal.z,
+            }
+            .normalize();
+            derivatives
+                .hillshade
+                .set(x, y, Some(hillshade(&shading_normal, aspect, config)));
+
+            let (plan, profile) = curvature(&z, spacing);
+            derivatives.plan_curvature.set(x, y, Some(plan));
+            derivatives.profile_curvature.set(x, y, Some(profile));
+        }
+    }
+    derivatives
+}
+
+/// Downslope direction of an upward normal in radians clockwise from north.
+fn aspect(normal: &Vec3) -> Option<f64> {
+    if normal.x.hypot(normal.y) < 1e-12 {
+        return None;
+    }
+    Some(normal.x.atan2(normal.y).rem_euclid(std::f64::consts::TAU))
+}
+
+/// Lights from every configured azimuth, each weighted by how square it
+/// falls across the aspect (Mark 1992), so relief is lit from the side
+/// whichever way it faces. Flat cells weigh all lights equally.
+fn hillshade(normal: &Vec3, aspect: Option<f64>, config: &AnalysisConfig) -> f64 {
+    let altitude = config.altitude.to_radians();
+    let mut shade = 0.0;
+    let mut total_weight = 0.0;
+    for azimuth in config.azimuths.iter().map(|a| a.to_radians()) {
+        let light = Vec3 {
+            x: azimuth.sin() * altitude.cos(),
+            y: azimuth.cos() * altitude.cos(),
+            z: altitude.sin(),
+        };
+        let lit = (normal.x * light.x + normal.y * light.y + normal.z * light.z).max(0.0);
+        let weight = aspect.map(|a| (a - azimuth).sin().powi(2)).unwrap_or(1.0);
+        shade += weight * lit;
+        total_weight += weight;
+    }
+    if total_weight > 0.0 {
+        shade / total_weight
+    } else {
+        0.0
+    }
+}
+
+/// Plan and profile curvature from the second order polynomial through the
+/// 3x3 neighbourhood (Zevenbergen and Thorne 1987).
+fn curvature<F: Fn(i64, i64) -> f64>(z: &F, (dx, dy): (f64, f64)) -> (f64, f64) {
+    let p = (z(1, 0) - z(-1, 0)) / (2.0 * dx);
+    let q = (z(0, 1) - z(0, -1)) / (2.0 * dy);
+    let gradient = p * p + q * q;
+    if gradient < 1e-12 {
+        return (0.0, 0.0);
+    }
+
+    let zxx = (z(-1, 0) - 2.0 * z(0, 0) + z(1, 0)) / (dx * dx);
+    let zyy = (z(0, -1) - 2.0 * z(0, 0) + z(0, 1)) / (dy * dy);
+    let zxy = (z(1, 1) - z(-1, 1) - z(1, -1) + z(-1, -1)) / (4.0 * dx * dy);
+
+    let plan = (zxx * q * q - 2.0 * zxy * p * q + zyy * p * p) / gradient;
+    let profile = (zxx * p * p + 2.0 * zxy * p * q + zyy * q * q) / gradient;
+    (plan, profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACING: (f64, f64) = (2.0, 3.0);
+
+    /// Raster of `height(east, north)` in metres around the middle cell,
+    /// rows from north to south.
+    fn surface<F: Fn(f64, f64) -> f64>(size: usize, height: F) -> Raster {
+        let middle = (size / 2) as f64;
+        let rows: Vec<Vec<f64>> = (0..size)
+            .map(|y| {
+                (0..size)
+                    .map(|x| {
+                        height(
+                            (x as f64 - middle) * SPACING.0,
+                            (middle - y as f64) * SPACING.1,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        Raster::from_rows(&rows, None)
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} is not {b}");
+    }
+
+    #[test]
+    fn slope_and_aspect_of_a_tilted_plane() {
+        // Rising 0.3 m per metre to the east and falling 0.4 m to the north,
+        // so water runs north-west and the slope is atan(0.5).
+        let heights = surface(7, |east, north| 0.3 * east - 0.4 * north);
+        let derivatives = analyse(&heights, SPACING, &AnalysisConfig::default());
+        for y in 1..6 {
+            for x in 1..6 {
+                assert_close(
+                    derivatives.slope.get(x, y).unwrap(),
+                    0.5f64.atan().to_degrees(),
+                );
+                let aspect = 360.0 - 0.75f64.atan().to_degrees();
+                assert_close(derivatives.aspect.get(x, y).unwrap(), aspect);
+                assert_close(derivatives.plan_curvature.get(x, y).unwrap(), 0.0);
+                assert_close(derivatives.profile_curvature.get(x, y).unwrap(), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn curvature_of_paraboloids() {
+        let config = AnalysisConfig::default();
+        // A bowl curves up both along and across the slope.
+        let bowl = analyse(
+            &surface(9, |east, north| 0.01 * (east * east + north * north)),
+            SPACING,
+            &config,
+        );
+        // A saddle seen from the east curves up down the slope and down
+        // across it.
+        let saddle = analyse(
+            &surface(9, |east, north| 0.01 * (east * east - north * north)),
+            SPACING,
+            &config,
+        );
+        for (x, y) in [(6, 4), (2, 2), (5, 7)] {
+            assert_close(bowl.plan_curvature.get(x, y).unwrap(), 0.02);
+            assert_close(bowl.profile_curvature.get(x, y).unwrap(), 0.02);
+        }
+        assert_close(saddle.plan_curvature.get(6, 4).unwrap(), -0.02);
+        assert_close(saddle.profile_curvature.get(6, 4).unwrap(), 0.02);
+        // No gradient at the bottom of the bowl.
+        assert_close(bowl.plan_curvature.get(4, 4).unwrap(), 0.0);
+        assert_eq!(bowl.aspect.get(4, 4), None);
+    }
+
+    #[test]
+    fn flat_surface_is_lit_from_every_light() {
+        let mut heights = Raster::filled(4, 4, 12.0);
+        heights.set(3, 3, None);
+        let config = AnalysisConfig {
+            altitude: 30.0,
+            ..AnalysisConfig::default()
+        };
+        let derivatives = analyse(&heights, SPACING, &config);
+        for y in 0..4 {
+            for x in 0..4 {
+                if (x, y) == (3, 3) {
+                    assert_eq!(derivatives.hillshade.get(x, y), None);
+                    assert_eq!(derivatives.slope.get(x, y), None);
+                    continue;
+                }
+                assert_close(derivatives.hillshade.get(x, y).unwrap(), 0.5);
+                assert_close(derivatives.slope.get(x, y).unwrap(), 0.0);
+                assert_eq!(derivatives.aspect.get(x, y), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::raster::Raster;
+
+/// Resolution and shading of the baked terrain textures.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct TextureConfig {
+    /// Texture pixels along one side of a map tile.
+    pub texels_per_tile: usize,
+    /// How much the hillshade darkens the class colours, from 0 for flat
+    /// colours to 1 for black where the hillshade is 0.
+    pub hillshade: f64,
+}
+
+impl Default for TextureConfig {
+    fn default() -> TextureConfig {
+        TextureConfig {
+            texels_per_tile: 4,
+            hillshade: 0.6,
+        }
+    }
+}
+
+/// 8-bit image with interleaved channels, rows from north to south.
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Texture coordinates of a point in tiles on a map `size` tiles across.
+/// The texture covers the map like its images, with tile `(x, y)` in the
+/// middle of pixel `(x, y)` at one pixel per tile, and `v` runs from the
+/// north edge down like the image rows.
+pub fn texture_coords(x: f64, y: f64, size: (usize, usize)) -> [f64; 2] {
+    [(x + 0.5) / size.0 as f64, (y + 0.5) / size.1 as f64]
+}
+
+/// Tile colours interpolated between the tile middles and darkened by the
+/// hillshade, as RGB. Tiles take the colour of their class id in `classes`,
+/// black for unknown classes, and are not shaded where the hillshade is
+/// missing.
+pub fn bake_colour(
+    classes: &Raster,
+    colours: &HashMap<u16, [u8; 3]>,
+    hillshade: &Raster,
+    config: &TextureConfig,
+) -> Texture {
+    let colour = |x: usize, y: usize| -> [f64; 3] {
+        let id = classes.get(x, y).map(|id| id as u16);
+        let rgb = id.and_then(|id| colours.get(&id)).unwrap_or(&[0, 0, 0]);
+        rgb.map(|c| c as f64)
+    };
+    bake(classes, 3, config, |x, y, data| {
+        let mut rgb = [0.0; 3];
+        let mut shade = 0.0;
+        for (tx, ty, weight) in bilinear(classes, x, y) {
+            let c = colour(tx, ty);
+            for channel in 0..3 {
+                rgb[channel] += weight * c[channel];
+            }
+            shade += weight * hillshade.get(tx, ty).unwrap_or(1.0);
+        }
+        let factor = 1.0 - config.hillshade * (1.0 - shade.clamp(0.0, 1.0));
+        data.extend(rgb.map(|c| (c * factor).round().clamp(0.0, 255.0) as u8));
+    })
+}
+
+/// Weights of up to four classes in the red, green, blue and alpha bytes,
+/// 255 for full cover, interpolated between the tile middles from the
+/// class coverage rasters. Missing coverage is 0 and unused channels stay 0.
+pub fn bake_splat(coverage: &[&Raster], config: &TextureConfig) -> Texture {
+    assert!(
+        !coverage.is_empty() && coverage.len() <= 4,
+        "A splat map holds one to four classes"
+    );
+    bake(coverage[0], 4, config, |x, y, data| {
+        let mut rgba = [0u8; 4];
+        for (channel, raster) in coverage.iter().enumerate() {
+            let weight: f64 = bilinear(raster, x, y)
+                .into_iter()
+                .map(|(tx, ty, w)| w * raster.get(tx, ty).unwrap_or(0.0))
+                .sum();
+            rgba[channel] = (weight.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        data.extend(rgba);
+    })
+}
+
+/// Texture `texels_per_tile` times the size of `raster`, with `texel`
+/// adding the channels of the pixel whose middle is at a point in tiles.
+fn bake<F: FnMut(f64, f64, &mut Vec<u8>)>(
+    raster: &Raster,
+    channels: usize,
+    config: &TextureConfig,
+    mut texel: F,
+) -> Texture {
+    let scale = config.texels_per_tile.max(1);
+    let (width, height) = (raster.width() * scale, raster.height() * scale);
+    let mut data = Vec::with_capacity(width * height * channels);
+    for j in 0..height {
+        for i in 0..width {
+            texel(texel_to_tile(i, scale), texel_to_tile(j, scale), &mut data);
+        }
+    }
+    Texture {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Middle of texture pixel `i` in tiles.
+fn texel_to_tile(i: usize, texels_per_tile: usize) -> f64 {
+    (i as f64 + 0.5) / texels_per_tile as f64 - 0.5
+}
+
+/// Cells around a point with their bilinear weights, the point clamped to
+/// the middles of the edge cells.
+fn bilinear(raster: &Raster, x: f64, y: f64) -> [(usize, usize, f64); 4] {
+    let (last_x, last_y) = (raster.width() - 1, raster.height() - 1);
+    let x = x.clamp(0.0, last_x as f64);
+    let y = y.clamp(0.0, last_y as f64);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(last_x), (y0 + 1).min(last_y));
+    let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+    [
+        (x0, y0, (1.0 - tx) * (1.0 - ty)),
+        (x1, y0, tx * (1.0 - ty)),
+        (x0, y1, (1.0 - tx) * ty),
+        (x1, y1, tx * ty),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texels_line_up_with_texture_coordinates() {
+        let config = TextureConfig {
+            texels_per_tile: 4,
+            hillshade: 0.0,
+        };
+        let size = (8, 8);
+        for i in 0..size.0 * config.texels_per_tile {
+            let [u, _] = texture_coords(texel_to_tile(i, config.texels_per_tile), 0.0, size);
+            let texel = u * (size.0 * config.texels_per_tile) as f64;
+            assert!((texel - (i as f64 + 0.5)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn colours_blend_between_tiles_and_darken_with_the_hillshade() {
+        let mut classes = Raster::new(2, 1);
+        classes.set(0, 0, Some(1.0));
+        classes.set(1, 0, Some(2.0));
+        let colours = HashMap::from([(1, [0, 0, 0]), (2, [200, 100, 0])]);
+        let mut hillshade = Raster::filled(2, 1, 1.0);
+        hillshade.set(1, 0, Some(0.5));
+        let config = TextureConfig {
+            texels_per_tile: 2,
+            hillshade: 1.0,
+        };
+
+        let texture = bake_colour(&classes, &colours, &hillshade, &config);
+        assert_eq!((texture.width, texture.height), (4, 2));
+        let red: Vec<u8> = texture.data[..12].iter().step_by(3).copied().collect();
+        // Texels left of the first tile middle and right of the last one
+        // take the edge tile; between them the colour and shade blend.
+        assert_eq!(red[0], 0);
+        assert_eq!(red[1], f64::round(0.25 * 200.0 * (1.0 - 0.25 * 0.5)) as u8);
+        assert_eq!(red[2], f64::round(0.75 * 200.0 * (1.0 - 0.75 * 0.5)) as u8);
+        assert_eq!(red[3], 100);
+
+        let coverage = Raster::filled(2, 1, 0.5);
+        let splat = bake_splat(&[&coverage, &classes], &config);
+        assert_eq!(splat.data.len(), 4 * 2 * 4);
+        assert_eq!(&splat.data[..4], &[128, 255, 0, 0]);
+    }
+}
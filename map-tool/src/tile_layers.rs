@@ -0,0 +1,81 @@
+use crate::raster::Raster;
+
+/// Named attribute of every map tile, kept next to the tile class so that
+/// overlapping features all leave a trace.
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    /// Value of each tile, missing where the layer has nothing.
+    pub values: Raster,
+    /// Names of the values of a categorical layer, value `i` meaning
+    /// `labels[i]`.
+    pub labels: Option<Vec<String>>,
+}
+
+/// Layers of the map tiles by name, in the order they were added.
+#[derive(Debug, Clone, Default)]
+pub struct TileLayers {
+    layers: Vec<TileLayer>,
+}
+
+impl TileLayers {
+    pub fn new() -> TileLayers {
+        TileLayers::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TileLayer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut TileLayer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    /// Adds a layer, replacing one with the same name.
+    pub fn insert(&mut self, layer: TileLayer) {
+        match self.get_mut(&layer.name) {
+            Some(existing) => *existing = layer,
+            None => self.layers.push(layer),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.layers
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TileLayer> {
+        self.layers.iter()
+    }
+}
+
+/// Name of the layer holding the fraction of each tile covered by a class.
+pub fn coverage_layer_name(class_name: &str) -> String {
+    format!("coverage_{}", class_name.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(name: &str, value: f64) -> TileLayer {
+        TileLayer {
+            name: String::from(name),
+            values: Raster::filled(2, 2, value),
+            labels: None,
+        }
+    }
+
+    #[test]
+    fn layers_are_replaced_by_name_in_place() {
+        let mut layers = TileLayers::new();
+        layers.insert(layer("road_id", 1.0));
+        layers.insert(layer(&coverage_layer_name("FOREST"), 0.5));
+        layers.insert(layer("road_id", 2.0));
+        assert_eq!(layers.names(), vec!["road_id", "coverage_forest"]);
+        assert_eq!(layers.get("road_id").unwrap().values.get(1, 1), Some(2.0));
+        assert!(layers.get("building_id").is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+
+use crate::raster::Raster;
+
+/// Options for line of sight and viewshed queries.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ViewshedConfig {
+    /// Height of the observer's eye above the surface in metres.
+    pub eye_height: f64,
+    /// Height above the surface in metres that has to be seen for a target
+    /// to count as visible.
+    pub target_height: f64,
+    /// Whether building footprints block the view.
+    pub buildings: bool,
+    /// Height of a storey in metres, for buildings with a floor count.
+    pub storey_height: f64,
+    /// Height in metres given to buildings without a floor count.
+    pub building_height: f64,
+    /// Cells farther than this many metres from the observer are left out
+    /// of the viewshed.
+    pub max_distance: Option<f64>,
+}
+
+impl Default for ViewshedConfig {
+    fn default() -> ViewshedConfig {
+        ViewshedConfig {
+            eye_height: 1.7,
+            target_height: 0.0,
+            buildings: true,
+            storey_height: 3.0,
+            building_height: 8.0,
+            max_distance: None,
+        }
+    }
+}
+
+/// Height field the sight lines are tested against. Terrain is bilinear
+/// between cells and obstacles stand on whole cells, so a building blocks
+/// the view across its full footprint.
+pub struct Surface {
+    terrain: Raster,
+    obstacles: Option<Raster>,
+    spacing: (f64, f64),
+}
+
+impl Surface {
+    /// `terrain` holds heights in metres with missing cells at height zero,
+    /// `obstacles` heights to add on top of it and `spacing` the cell size
+    /// in metres. Positions are in raster cells with cell centers on whole
+    /// numbers.
+    pub fn new(terrain: Raster, obstacles: Option<Raster>, spacing: (f64, f64)) -> Surface {
+        Surface {
+            terrain,
+            obstacles,
+            spacing,
+        }
+    }
+
+    fn terrain_height(&self, x: f64, y: f64) -> f64 {
+        if self.terrain.width() == 0 || self.terrain.height() == 0 {
+            return 0.0;
+        }
+        let max_x = (self.terrain.width() - 1) as f64;
+        let max_y = (self.terrain.height() - 1) as f64;
+        let (x, y) = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(max_x as usize), (y0 + 1).min(max_y as usize));
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+        let h = |x, y| self.terrain.get(x, y).unwrap_or(0.0);
+        h(x0, y0) * (1.0 - tx) * (1.0 - ty)
+            + h(x1, y0) * tx * (1.0 - ty)
+            + h(x0, y1) * (1.0 - tx) * ty
+            + h(x1, y1) * tx * ty
+    }
+
+    /// Terrain and obstacle height at a position.
+    pub fn height(&self, x: f64, y: f64) -> f64 {
+        let obstacle = self.obstacles.as_ref().map_or(0.0, |obstacles| {
+            if obstacles.width() == 0 || obstacles.height() == 0 {
+                return 0.0;
+            }
+            let cx = x.round().clamp(0.0, (obstacles.width() - 1) as f64) as usize;
+            let cy = y.round().clamp(0.0, (obstacles.height() - 1) as f64) as usize;
+            obstacles.get(cx, cy).unwrap_or(0.0)
+        });
+        self.terrain_height(x, y) + obstacle
+    }
+
+    /// Distance in metres between two positions.
+    pub fn distance(&self, a: (f64, f64), b: (f64, f64)) -> f64 {
+        ((b.0 - a.0) * self.spacing.0).hypot((b.1 - a.1) * self.spacing.1)
+    }
+
+    /// Whether an eye `eye_height` above the surface at `a` sees the point
+    /// `target_height` above the surface at `b`. The surface is sampled
+    /// every half cell between them, the cells under the end points don't
+    /// block the view.
+    pub fn line_of_sight(
+        &self,
+        a: (f64, f64),
+        eye_height: f64,
+        b: (f64, f64),
+        target_height: f64,
+    ) -> bool {
+        let from = self.height(a.0, a.1) + eye_height;
+        let to = self.height(b.0, b.1) + target_height;
+        let cells = (b.0 - a.0).abs().max((b.1 - a.1).abs());
+        let steps = (cells * 2.0).ceil() as usize;
+        let (start, end) = ((a.0.round(), a.1.round()), (b.0.round(), b.1.round()));
+        for step in 1..steps {
+            let t = step as f64 / steps as f64;
+            let (x, y) = (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+            let cell = (x.round(), y.round());
+            if cell == start || cell == end {
+                continue;
+            }
+            if self.height(x, y) > from + (to - from) * t {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Visibility of every cell from an observer at `observer`: 1 where the
+    /// cell is seen, 0 where it is hidden and missing past `max_distance`.
+    pub fn viewshed(&self, observer: (f64, f64), config: &ViewshedConfig) -> Raster {
+        let (width, height) = (self.terrain.width(), self.terrain.height());
+        let mut visible = Raster::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let target = (x as f64, y as f64);
+                if config
+                    .max_distance
+                    .is_some_and(|max| self.distance(observer, target) > max)
+                {
+                    continue;
+                }
+                let seen =
+                    self.line_of_sight(observer, config.eye_height, target, config.target_height);
+                visible.set(x, y, Some(if seen { 1.0 } else { 0.0 }));
+            }
+        }
+        visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flat terrain at height zero with a ten metre ridge along column 5.
+    fn ridge(width: usize, height: usize) -> Raster {
+        let mut terrain = Raster::filled(width, height, 0.0);
+        for y in 0..height {
+            terrain.set(5, y, Some(10.0));
+        }
+        terrain
+    }
+
+    #[test]
+    fn ridges_block_the_view_unless_seen_over() {
+        let surface = Surface::new(ridge(11, 1), None, (1.0, 1.0));
+        assert!(!surface.line_of_sight((0.0, 0.0), 1.7, (10.0, 0.0), 0.0));
+        assert!(surface.line_of_sight((0.0, 0.0), 25.0, (10.0, 0.0), 0.0));
+        assert!(surface.line_of_sight((0.0, 0.0), 1.7, (10.0, 0.0), 25.0));
+        // The ridge itself is seen, and it doesn't block its own view.
+        assert!(surface.line_of_sight((0.0, 0.0), 1.7, (5.0, 0.0), 0.0));
+        assert!(surface.line_of_sight((5.0, 0.0), 0.0, (10.0, 0.0), 0.0));
+        assert!(surface.line_of_sight((0.0, 0.0), 1.7, (4.0, 0.0), 0.0));
+    }
+
+    #[test]
+    fn obstacles_stand_on_the_terrain() {
+        let terrain = Raster::filled(11, 1, 3.0);
+        let mut obstacles = Raster::new(11, 1);
+        obstacles.set(5, 0, Some(8.0));
+        let surface = Surface::new(terrain.clone(), Some(obstacles), (1.0, 1.0));
+        assert_eq!(surface.height(5.2, 0.0), 11.0);
+        assert_eq!(surface.height(4.0, 0.0), 3.0);
+        assert!(!surface.line_of_sight((0.0, 0.0), 1.7, (10.0, 0.0), 0.0));
+        let open = Surface::new(terrain, None, (1.0, 1.0));
+        assert!(open.line_of_sight((0.0, 0.0), 1.7, (10.0, 0.0), 0.0));
+    }
+
+    #[test]
+    fn viewshed_ends_at_the_ridge_and_the_distance() {
+        let surface = Surface::new(ridge(9, 9), None, (2.0, 2.0));
+        let config = ViewshedConfig::default();
+        let viewshed = surface.viewshed((2.0, 4.0), &config);
+        for y in 0..9 {
+            for x in 0..9 {
+                let seen = if x <= 5 { 1.0 } else { 0.0 };
+                assert_eq!(viewshed.get(x, y), Some(seen), "cell ({x}, {y})");
+            }
+        }
+
+        let config = ViewshedConfig {
+            max_distance: Some(6.0),
+            ..config
+        };
+        let viewshed = surface.viewshed((2.0, 4.0), &config);
+        assert_eq!(viewshed.get(5, 4), Some(1.0));
+        assert_eq!(viewshed.get(6, 4), None);
+        assert_eq!(viewshed.get(2, 7), Some(1.0));
+        assert_eq!(viewshed.get(4, 7), None);
+    }
+
+    #[test]
+    fn empty_rasters_have_no_height() {
+        let surface = Surface::new(Raster::new(0, 0), Some(Raster::new(0, 0)), (1.0, 1.0));
+        assert_eq!(surface.height(3.0, 4.0), 0.0);
+        assert!(surface.line_of_sight((0.0, 0.0), 1.7, (3.0, 4.0), 0.0));
+        assert_eq!(
+            surface
+                .viewshed((0.0, 0.0), &ViewshedConfig::default())
+                .size(),
+            (0, 0)
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::delaunay::Triangulation;
+use crate::geometry::point::{LineSegment, Point, Polygon};
+use crate::geometry::projection::LocalProjection;
+use crate::geometry::rtree::RTree;
+
+/// Options for the lake water meshes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct WaterConfig {
+    /// Distance in metres between the mesh vertices, along the shore and
+    /// across the open water.
+    pub spacing: f64,
+}
+
+impl Default for WaterConfig {
+    fn default() -> WaterConfig {
+        WaterConfig { spacing: 10.0 }
+    }
+}
+
+/// Distance in metres within which a triangle corner lies on a ring.
+const ON_RING: f64 = 1e-6;
+
+/// Flat water surface of a lake at `level` metres, with the distance in
+/// metres from each vertex to the shore.
+pub struct WaterSurface {
+    pub level: f64,
+    pub points: Vec<Point>,
+    pub shore_distances: Vec<f64>,
+    /// Counterclockwise triangles of `points`.
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// Triangulates `area`, which may be the part of a lake inside the map, as
+/// a Delaunay triangulation of its rings and a grid of points inside it,
+/// keeping the triangles whose middle is on the water and whose edges don't
+/// cross a ring, so that none spans a spit or an inlet. Shore distances are
+/// measured to `shore`, the whole lake, so that a cut through the lake is
+/// not taken for its shore.
+pub fn water_surface(area: &Polygon, shore: &Polygon, level: f64, spacing: f64) -> WaterSurface {
+    let projection = LocalProjection::new(&area.bbox().center());
+    let to_metres =
+        |ring: &[Point]| -> Vec<Point> { ring.iter().map(|p| projection.to_metres(p)).collect() };
+    let area = Polygon::with_holes(
+        to_metres(area.exterior()),
+        area.holes()
+            .iter()
+            .map(|h| to_metres(h.exterior()))
+            .collect(),
+    );
+    let shore = ring_segments(
+        std::iter::once(shore.exterior())
+            .chain(shore.holes().iter().map(|h| h.exterior()))
+            .map(to_metres)
+            .collect(),
+    );
+    let edges = ring_segments(
+        std::iter::once(area.exterior())
+            .chain(area.holes().iter().map(|h| h.exterior()))
+            .map(|ring| ring.to_vec())
+            .collect(),
+    );
+
+    let mut points: Vec<Point> = Vec::new();
+    for ring in std::iter::once(area.exterior()).chain(area.holes().iter().map(|h| h.exterior())) {
+        for (a, b) in ring_edges(ring) {
+            let steps = (a.distance(b) / spacing).ceil() as usize;
+            for step in 0..steps {
+                let t = step as f64 / steps as f64;
+                points.push(Point {
+                    x: a.x + (b.x - a.x) * t,
+                    y: a.y + (b.y - a.y) * t,
+                });
+            }
+        }
+    }
+    // Grid points closer to a ring than half the spacing would make slivers.
+    let bbox = area.bbox();
+    let mut y = (bbox.a.y / spacing).ceil() * spacing;
+    while y < bbox.b.y {
+        let mut x = (bbox.a.x / spacing).ceil() * spacing;
+        while x < bbox.b.x {
+            let p = Point { x, y };
+            if area.contains_point(&p) && distance(&edges, &p) > spacing / 2.0 {
+                points.push(p);
+            }
+            x += spacing;
+        }
+        y += spacing;
+    }
+
+    let triangles = Triangulation::new(&points)
+        .triangles()
+        .into_iter()
+        .filter(|t| {
+            let [a, b, c] = t.map(|v| &points[v]);
+            area.contains_point(&Point {
+                x: (a.x + b.x + c.x) / 3.0,
+                y: (a.y + b.y + c.y) / 3.0,
+            }) && !crosses_ring(&edges, [a, b, c])
+        })
+        .collect();
+    WaterSurface {
+        level,
+        shore_distances: points.iter().map(|p| distance(&shore, p)).collect(),
+        points: points.iter().map(|p| projection.to_degrees(p)).collect(),
+        triangles,
+    }
+}
+
+/// Edges of a ring, closing it if its last point is not the first.
+fn ring_edges(ring: &[Point]) -> impl Iterator<Item = (&Point, &Point)> {
+    (0..ring.len())
+        .map(move |i| (&ring[i], &ring[(i + 1) % ring.len()]))
+        .filter(|(a, b)| a.x != b.x || a.y != b.y)
+}
+
+/// Edges of the rings, bulk loaded into an R-tree.
+fn ring_segments(rings: Vec<Vec<Point>>) -> RTree<LineSegment> {
+    RTree::bulk_load(
+        rings
+            .iter()
+            .flat_map(|ring| ring_edges(ring))
+            .map(|(a, b)| LineSegment {
+                a: a.clone(),
+                b: b.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// Whether an edge of the triangle crosses one of the ring segments.
+fn crosses_ring(segments: &RTree<LineSegment>, [a, b, c]: [&Point; 3]) -> bool {
+    [(a, b), (b, c), (c, a)].into_iter().any(|(a, b)| {
+        segments.crosses(
+            &LineSegment {
+                a: a.clone(),
+                b: b.clone(),
+            },
+            ON_RING,
+        )
+    })
+}
+
+/// Distance to the nearest of the segments, 0 when there are none.
+fn distance(segments: &RTree<LineSegment>, p: &Point) -> f64 {
+    segments
+        .nearest(p, 1)
+        .first()
+        .map_or(0.0, |(_, distance_squared)| distance_squared.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Square lake `size` metres across with a square island, around
+    /// 23° E, 61° N.
+    fn lake(size: f64, island: f64) -> Polygon {
+        let projection = LocalProjection::new(&Point { x: 23.0, y: 61.0 });
+        let ring = |half: f64, ccw: bool| {
+            let mut ring: Vec<Point> = [(-half, -half), (half, -half), (half, half), (-half, half)]
+                .iter()
+                .map(|(x, y)| projection.to_degrees(&Point { x: *x, y: *y }))
+                .collect();
+            if !ccw {
+                ring.reverse();
+            }
+            ring
+        };
+        Polygon::with_holes(ring(size / 2.0, true), vec![ring(island / 2.0, false)])
+    }
+
+    #[test]
+    fn water_covers_the_lake_around_its_islands() {
+        let lake = lake(200.0, 40.0);
+        let water = water_surface(&lake, &lake, 95.0, 10.0);
+        let projection = LocalProjection::new(&lake.bbox().center());
+        let area: f64 = water
+            .triangles
+            .iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|v| projection.to_metres(&water.points[v]));
+                let doubled = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+                assert!(doubled > 0.0);
+                doubled / 2.0
+            })
+            .sum();
+        assert!((area - (200.0 * 200.0 - 40.0 * 40.0)).abs() < 1.0);
+        assert_eq!(water.level, 95.0);
+    }
+
+    #[test]
+    fn water_does_not_span_a_narrow_spit() {
+        // A spit 2 m wide, narrower than the spacing, slanting from the
+        // north shore to the middle of a square lake 200 m across.
+        let projection = LocalProjection::new(&Point { x: 23.0, y: 61.0 });
+        let lake = Polygon::new(
+            [
+                (-100.0, -100.0),
+                (100.0, -100.0),
+                (100.0, 100.0),
+                (85.3, 100.0),
+                (1.3, 0.0),
+                (-1.3, 0.0),
+                (82.7, 100.0),
+                (-100.0, 100.0),
+            ]
+            .iter()
+            .map(|(x, y)| projection.to_degrees(&Point { x: *x, y: *y }))
+            .collect(),
+        );
+        let water = water_surface(&lake, &lake, 0.0, 10.0);
+        let triangles: Vec<[Point; 3]> = water
+            .triangles
+            .iter()
+            .map(|t| t.map(|v| projection.to_metres(&water.points[v])))
+            .collect();
+        let inside = |p: &Point, [a, b, c]: &[Point; 3]| {
+            let side = |a: &Point, b: &Point| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+            side(a, b) > 0.0 && side(b, c) > 0.0 && side(c, a) > 0.0
+        };
+        for y in 1..100 {
+            let p = Point {
+                x: 0.84 * y as f64,
+                y: y as f64,
+            };
+            assert!(triangles.iter().all(|t| !inside(&p, t)), "water at {y} m");
+        }
+        let area: f64 = triangles
+            .iter()
+            .map(|[a, b, c]| ((b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)) / 2.0)
+            .sum();
+        let lake_area = 200.0 * 200.0 - 2.6 * 100.0;
+        assert!(area < lake_area + 1.0 && area > 0.97 * lake_area, "{area}");
+    }
+
+    #[test]
+    fn shore_distance_grows_away_from_the_shore() {
+        let lake = lake(200.0, 40.0);
+        let water = water_surface(&lake, &lake, 0.0, 10.0);
+        let projection = LocalProjection::new(&lake.bbox().center());
+        for (point, distance) in water.points.iter().zip(water.shore_distances.iter()) {
+            let p = projection.to_metres(point);
+            let outer = 100.0 - p.x.abs().max(p.y.abs());
+            let inner = (p.x.abs() - 20.0)
+                .max(0.0)
+                .hypot((p.y.abs() - 20.0).max(0.0));
+            assert!((distance - outer.min(inner)).abs() < 0.5);
+        }
+        let farthest = water.shore_distances.iter().copied().fold(0.0, f64::max);
+        assert!(farthest > 35.0);
+    }
+}